@@ -0,0 +1,368 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Assign cells to donors using germline variants seen in their VDJ contigs, as requested by
+// DONOR_DEMUX=k.  This allows libraries made from pooled donors to be deconvolved without
+// genotyping the donors.
+//
+// 1. For each contig, we compare the V segment (excluding its last ref_v_trim bases) to the
+//    reference, and record the base seen at each reference position.  Contigs whose V segment
+//    alignment has an indel are ignored.  Constant regions are not used, because only the start
+//    of C on the contig is known, and not the reference position at which its alignment starts
+//    or whether it has indels.
+// 2. A reference position is a variant site if a particular non-reference base is seen in at
+//    least MIN_FRAC of the cells covering the position, and in cells having at least MIN_CDR3S
+//    distinct sets of CDR3 sequences, and the reference base is also seen in at least MIN_FRAC
+//    of the cells.  The CDR3 requirement prevents a single expanded clone from defining a site
+//    via a somatic mutation.
+// 3. Each cell is represented by the alleles (reference or alternate) that it shows at the
+//    variant sites, and we fit a mixture of k independent Bernoulli distributions to the cells
+//    by expectation maximization, from several deterministic random starts, keeping the fit
+//    of highest likelihood.
+// 4. Each cell having at least one allele is assigned to the donor of highest posterior
+//    probability, and that probability is recorded as its confidence, in the per-cell variable
+//    donor_demux_conf.  Donors are named donor1, ..., donork, in decreasing order of number of
+//    cells.  Cells having no alleles are not assigned to a donor.
+
+use vdj_ann::refx;
+
+use self::refx::RefData;
+use enclone_core::defs::{EncloneControl, TigData};
+use rayon::prelude::*;
+use std::cmp::min;
+use std::collections::HashMap;
+use vector_utils::{bin_position, reverse_sort, unique_sort};
+
+const MIN_FRAC: f64 = 0.05;
+const MIN_CDR3S: usize = 5;
+const RESTARTS: usize = 10;
+const ITERATIONS: usize = 200;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Find the bases in a contig that are aligned to V reference positions, as
+// (ref id, ref position, base).
+
+fn aligned_bases(ctl: &EncloneControl, refdata: &RefData, x: &TigData) -> Vec<(usize, usize, u8)> {
+    let mut bases = Vec::<(usize, usize, u8)>::new();
+    if x.annv.len() == 1 && x.annv[0].3 == 0 {
+        let r = &refdata.refs[x.v_ref_id];
+        let len = min(
+            x.annv[0].1 as usize,
+            r.len().saturating_sub(ctl.heur.ref_v_trim),
+        );
+        let seq = x.seq();
+        for p in 0..min(len, seq.len()) {
+            bases.push((x.v_ref_id, p, seq[p]));
+        }
+    }
+    bases
+}
+
+// A simple deterministic pseudorandom number generator (xorshift64).
+
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// Fit a mixture of k Bernoulli distributions to cells, each given by a list of (site, allele)
+// pairs, starting from a random assignment of cells to components.  Return the log likelihood
+// and the posterior probabilities of the components for each cell.
+
+fn bernoulli_mixture(
+    cells: &Vec<Vec<(usize, bool)>>,
+    nsites: usize,
+    k: usize,
+    seed: u64,
+) -> (f64, Vec<Vec<f64>>) {
+    let n = cells.len();
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+    let mut post = vec![vec![0.0; k]; n];
+    for i in 0..n {
+        post[i][(next_random(&mut state) % k as u64) as usize] = 1.0;
+    }
+    let mut loglik = f64::NEG_INFINITY;
+    for _ in 0..ITERATIONS {
+        // Estimate component weights and allele frequencies, with a uniform prior.
+
+        let mut weight = vec![1.0; k];
+        let mut alt = vec![vec![1.0; nsites]; k];
+        let mut total = vec![vec![2.0; nsites]; k];
+        for i in 0..n {
+            for c in 0..k {
+                weight[c] += post[i][c];
+                for &(s, a) in cells[i].iter() {
+                    total[c][s] += post[i][c];
+                    if a {
+                        alt[c][s] += post[i][c];
+                    }
+                }
+            }
+        }
+        let wsum = weight.iter().sum::<f64>();
+        let lw = weight.iter().map(|w| (w / wsum).ln()).collect::<Vec<_>>();
+        let mut lp = vec![vec![(0.0, 0.0); nsites]; k];
+        for c in 0..k {
+            for s in 0..nsites {
+                let f = alt[c][s] / total[c][s];
+                lp[c][s] = (f.ln(), (1.0 - f).ln());
+            }
+        }
+
+        // Compute posterior probabilities.
+
+        let results = cells
+            .par_iter()
+            .map(|x| {
+                let mut l = lw.clone();
+                for c in 0..k {
+                    for &(s, a) in x.iter() {
+                        l[c] += if a { lp[c][s].0 } else { lp[c][s].1 };
+                    }
+                }
+                let m = l.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+                let z = l.iter().map(|v| (v - m).exp()).sum::<f64>();
+                let p = l.iter().map(|v| (v - m).exp() / z).collect::<Vec<_>>();
+                (m + z.ln(), p)
+            })
+            .collect::<Vec<_>>();
+        let new_loglik = results.iter().map(|r| r.0).sum::<f64>();
+        post = results.into_iter().map(|r| r.1).collect();
+        let done = (new_loglik - loglik).abs() < 1.0e-6 * new_loglik.abs().max(1.0);
+        loglik = new_loglik;
+        if done {
+            break;
+        }
+    }
+    (loglik, post)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Assign cells to donors, setting the donor index of their contigs, and recording the
+// assignments in donor_for_bc and the confidences in alt_bc_fields.
+
+pub fn donor_demux(
+    ctl: &mut EncloneControl,
+    refdata: &RefData,
+    tig_bc: &mut Vec<Vec<TigData>>,
+) -> Result<(), String> {
+    let k = ctl.gen_opt.donor_demux;
+
+    // Find the bases seen in each cell, ignoring positions where its contigs disagree.
+
+    let cell_bases = tig_bc
+        .par_iter()
+        .map(|tigs| {
+            let mut b = Vec::<(usize, usize, u8)>::new();
+            for x in tigs.iter() {
+                b.append(&mut aligned_bases(ctl, refdata, x));
+            }
+            unique_sort(&mut b);
+            let mut c = Vec::<(usize, usize, u8)>::new();
+            for i in 0..b.len() {
+                if (i == 0 || (b[i].0, b[i].1) != (b[i - 1].0, b[i - 1].1))
+                    && (i + 1 == b.len() || (b[i].0, b[i].1) != (b[i + 1].0, b[i + 1].1))
+                {
+                    c.push(b[i]);
+                }
+            }
+            c
+        })
+        .collect::<Vec<_>>();
+
+    // Count cells and CDR3 sequences supporting each base at each reference position.
+
+    let refs = (0..refdata.refs.len())
+        .map(|id| refdata.refs[id].to_ascii_vec())
+        .collect::<Vec<Vec<u8>>>();
+    let mut counts = HashMap::<(usize, usize), [usize; 4]>::new();
+    let mut alts = Vec::<(usize, usize, u8, String)>::new();
+    let to_index = |b: u8| match b {
+        b'A' => Some(0),
+        b'C' => Some(1),
+        b'G' => Some(2),
+        b'T' => Some(3),
+        _ => None,
+    };
+    for (i, bases) in cell_bases.iter().enumerate() {
+        for &(id, p, b) in bases.iter() {
+            if let Some(j) = to_index(b) {
+                counts.entry((id, p)).or_insert([0; 4])[j] += 1;
+                if p < refs[id].len() && b != refs[id][p] {
+                    let cdr3s = tig_bc[i]
+                        .iter()
+                        .map(|x| x.cdr3_dna.as_str())
+                        .collect::<Vec<_>>();
+                    alts.push((id, p, b, cdr3s.join(":")));
+                }
+            }
+        }
+    }
+    unique_sort(&mut alts);
+
+    // Find the variant sites.
+
+    let mut sites = Vec::<(usize, usize, u8)>::new();
+    let mut i = 0;
+    while i < alts.len() {
+        let mut j = i + 1;
+        while j < alts.len()
+            && (alts[j].0, alts[j].1, alts[j].2) == (alts[i].0, alts[i].1, alts[i].2)
+        {
+            j += 1;
+        }
+        let (id, p, b) = (alts[i].0, alts[i].1, alts[i].2);
+        let c = &counts[&(id, p)];
+        let cov = c.iter().sum::<usize>() as f64;
+        let nref = to_index(refs[id][p]).map(|r| c[r]).unwrap_or(0) as f64;
+        let nalt = c[to_index(b).unwrap()] as f64;
+        if j - i >= MIN_CDR3S && nalt >= MIN_FRAC * cov && nref >= MIN_FRAC * cov {
+            sites.push((id, p, b));
+        }
+        i = j;
+    }
+    if sites.is_empty() {
+        return Err(
+            "\nDONOR_DEMUX was specified, but no variant positions were found in the V \
+             segments of the contigs, so cells cannot be assigned to donors.\n"
+                .to_string(),
+        );
+    }
+    let site_pos = sites.iter().map(|x| (x.0, x.1)).collect::<Vec<_>>();
+
+    // Find the alleles of each cell.
+
+    let mut cells = Vec::<Vec<(usize, bool)>>::new();
+    let mut to_cell = Vec::<Option<usize>>::new();
+    for bases in cell_bases.iter() {
+        let mut x = Vec::<(usize, bool)>::new();
+        for &(id, p, b) in bases.iter() {
+            let s = bin_position(&site_pos, &(id, p));
+            if s >= 0 {
+                let s = s as usize;
+                if b == sites[s].2 {
+                    x.push((s, true));
+                } else if b == refs[id][p] {
+                    x.push((s, false));
+                }
+            }
+        }
+        if x.is_empty() {
+            to_cell.push(None);
+        } else {
+            to_cell.push(Some(cells.len()));
+            cells.push(x);
+        }
+    }
+
+    // Fit the mixture model, and name the donors in decreasing order of size.
+
+    let mut fits = (0..RESTARTS)
+        .into_par_iter()
+        .map(|seed| bernoulli_mixture(&cells, sites.len(), k, seed as u64 + 1))
+        .collect::<Vec<_>>();
+    let mut best = 0;
+    for r in 1..fits.len() {
+        if fits[r].0 > fits[best].0 {
+            best = r;
+        }
+    }
+    let post = fits.swap_remove(best).1;
+    let calls = post
+        .iter()
+        .map(|p| {
+            let mut c = 0;
+            for j in 1..k {
+                if p[j] > p[c] {
+                    c = j;
+                }
+            }
+            (c, p[c])
+        })
+        .collect::<Vec<_>>();
+    let mut sizes = (0..k)
+        .map(|c| (calls.iter().filter(|x| x.0 == c).count(), c))
+        .collect::<Vec<_>>();
+    reverse_sort(&mut sizes);
+    let mut name = vec![String::new(); k];
+    for (j, s) in sizes.iter().enumerate() {
+        name[s.1] = format!("donor{}", j + 1);
+    }
+
+    // Record the assignments.
+
+    for (i, tigs) in tig_bc.iter_mut().enumerate() {
+        let li = tigs[0].dataset_index;
+        let conf_field = ctl.origin_info.alt_bc_fields[li]
+            .iter()
+            .position(|x| x.0 == "donor_demux_conf")
+            .unwrap();
+        let bc = tigs[0].barcode.clone();
+        let donor = match to_cell[i] {
+            Some(m) => {
+                let (c, conf) = calls[m];
+                ctl.origin_info.donor_for_bc[li].insert(bc.clone(), name[c].clone());
+                ctl.origin_info.alt_bc_fields[li][conf_field]
+                    .1
+                    .insert(bc, format!("{:.3}", conf));
+                let d = bin_position(&ctl.origin_info.donor_list, &name[c]);
+                Some(d as usize)
+            }
+            None => None,
+        };
+        for x in tigs.iter_mut() {
+            x.donor_index = donor;
+        }
+    }
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Simulate two donors that differ at every site, and cells that each cover a few random
+    // sites, with occasional errors.  The mixture should separate the donors.
+
+    #[test]
+    fn test_bernoulli_mixture() {
+        let (nsites, ncells) = (30, 200);
+        let mut state = 12345;
+        let genotype = (0..nsites)
+            .map(|_| next_random(&mut state) % 2 == 0)
+            .collect::<Vec<bool>>();
+        let mut cells = Vec::<Vec<(usize, bool)>>::new();
+        for i in 0..ncells {
+            let donor = i % 2 == 1;
+            let mut x = Vec::<(usize, bool)>::new();
+            while x.len() < 4 {
+                let s = (next_random(&mut state) % nsites as u64) as usize;
+                if x.iter().all(|y| y.0 != s) {
+                    let error = next_random(&mut state) % 50 == 0;
+                    x.push((s, genotype[s] ^ donor ^ error));
+                }
+            }
+            cells.push(x);
+        }
+        let (loglik, post) = (1..=RESTARTS as u64)
+            .map(|seed| bernoulli_mixture(&cells, nsites, 2, seed))
+            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+            .unwrap();
+        assert!(loglik.is_finite() && loglik < 0.0);
+        let calls = post
+            .iter()
+            .map(|p| {
+                assert!((p[0] + p[1] - 1.0).abs() < 1.0e-9);
+                p[1] > p[0]
+            })
+            .collect::<Vec<bool>>();
+        let correct = (0..ncells).filter(|&i| calls[i] == (i % 2 == 1)).count();
+        let correct = correct.max(ncells - correct);
+        assert!(correct >= ncells * 95 / 100);
+    }
+}
@@ -1,7 +1,11 @@
 // Copyright (c) 2021 10X Genomics, Inc. All rights reserved.
 
-// This file provides the single function join_exacts, which computes the equivalence relation
-// on exact subclonotypes.
+// This file provides the function join_exacts, which computes the equivalence relation on exact
+// subclonotypes, and the function join_by_assignment, which instead derives it from a given
+// assignment of cells to clonotypes.
+//
+// If a cached join state is in use (see join_state.rs), prior gives the stable clonotype id of
+// each info entry that was seen before, and pairs of such entries are not compared.
 //
 // Note that in principle the specificity of joining might be increased by using nonproductive
 // contigs that represent the sequence of the "other" allele.  This does not look easy to
@@ -15,6 +19,7 @@ use crate::join2::finish_join;
 use crate::join_core::join_core;
 use debruijn::dna_string::DnaString;
 use enclone_core::defs::{CloneInfo, EncloneControl, ExactClonotype, PotentialJoin};
+use enclone_core::join_score::join_scorer;
 use enclone_proto::types::DonorReferenceItem;
 use equiv::EquivRel;
 use io_utils::{fwrite, fwriteln};
@@ -38,6 +43,7 @@ pub fn join_exacts(
     raw_joins: &mut Vec<(i32, i32)>,
     sr: &Vec<Vec<Double>>,
     dref: &Vec<DonorReferenceItem>,
+    prior: &Vec<Option<usize>>,
 ) -> EquivRel {
     //
     // Run special option for joining by barcode identity.
@@ -109,6 +115,10 @@ pub fn join_exacts(
     ctl.perf_stats(&timer1, "join setup");
     let timer2 = Instant::now();
 
+    // The scorer name was validated when arguments were parsed.
+
+    let scorer = join_scorer(&ctl.join_alg_opt.scorer).unwrap();
+
     let joinf = |r: &mut (
         usize,
         usize,
@@ -140,6 +150,8 @@ pub fn join_exacts(
             &mut pot,
             &refdata,
             dref,
+            prior,
+            &*scorer,
         );
 
         // Run two passes.
@@ -185,6 +197,7 @@ pub fn join_exacts(
                     && ctl.join_alg_opt.basic_h.is_none()
                     && ctl.join_alg_opt.basic.is_none()
                     && !ctl.join_alg_opt.basicx
+                    && ctl.join_alg_opt.scorer == "default"
                 {
                     let (k1, k2) = (x[0] as usize + i, x[1] as usize + i);
                     let k = min(k1, k2);
@@ -607,5 +620,52 @@ pub fn join_exacts(
             raw_joins.push((results[l].5[j].0 as i32, results[l].5[j].1 as i32));
         }
     }
-    finish_join(ctl, info, &results, join_info)
+    let mut eq = finish_join(ctl, info, &results, join_info);
+
+    // Join info entries that were in the same clonotype in the cached join state, if any.
+
+    let mut first = HashMap::<usize, usize>::new();
+    for i in 0..prior.len() {
+        if let Some(id) = prior[i] {
+            match first.get(&id) {
+                Some(j) => eq.join(*j as i32, i as i32),
+                None => {
+                    first.insert(id, i);
+                }
+            }
+        }
+    }
+    eq
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Compute the equivalence relation on exact subclonotypes from an assignment of barcodes to
+// clonotypes, as provided by PROTO_IN, rather than by comparing sequences.  An exact subclonotype
+// is assigned to the clonotype of its first cell, and is left alone if that cell has no
+// assignment.  The raw joins are the pairs that are joined.
+
+pub fn join_by_assignment(
+    exact_clonotypes: &Vec<ExactClonotype>,
+    info: &Vec<CloneInfo>,
+    clonotype_of: &HashMap<String, usize>,
+    raw_joins: &mut Vec<(i32, i32)>,
+) -> EquivRel {
+    let mut eq: EquivRel = EquivRel::new(info.len() as i32);
+    let mut first = HashMap::<usize, usize>::new();
+    for i in 0..info.len() {
+        let ex = &exact_clonotypes[info[i].clonotype_index];
+        if let Some(c) = clonotype_of.get(&ex.clones[0][0].barcode) {
+            match first.get(c) {
+                Some(j) => {
+                    eq.join(*j as i32, i as i32);
+                    raw_joins.push((*j as i32, i as i32));
+                }
+                None => {
+                    first.insert(*c, i);
+                }
+            }
+        }
+    }
+    eq
 }
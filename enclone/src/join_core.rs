@@ -1,7 +1,7 @@
 // Copyright (c) 2021 10X Genomics, Inc. All rights reserved.
 
 use enclone_core::defs::{CloneInfo, EncloneControl, ExactClonotype, PotentialJoin};
-use enclone_core::join_one::join_one;
+use enclone_core::join_score::JoinScorer;
 use enclone_proto::types::DonorReferenceItem;
 use equiv::EquivRel;
 use qd::Double;
@@ -20,10 +20,35 @@ pub fn join_core(
     pot: &mut Vec<PotentialJoin>,
     refdata: &RefData,
     dref: &Vec<DonorReferenceItem>,
+    prior: &Vec<Option<usize>>,
+    scorer: &dyn JoinScorer,
 ) {
     let mut eq: EquivRel = EquivRel::new((j - i) as i32);
+
+    // Seed the equivalence relation with the joins from cached join state, so that a new entry
+    // that joins one member of a previous clonotype is not compared to the other members.
+
+    if !prior.is_empty() {
+        let mut first = HashMap::<usize, usize>::new();
+        for k in i..j {
+            if let Some(id) = prior[k] {
+                match first.get(&id) {
+                    Some(&f) => eq.join((f - i) as i32, (k - i) as i32),
+                    None => {
+                        first.insert(id, k);
+                    }
+                }
+            }
+        }
+    }
     for k1 in i..j {
         for k2 in k1 + 1..j {
+            // Do nothing if both were seen in a previous run, as recorded in cached join state.
+
+            if !prior.is_empty() && prior[k1].is_some() && prior[k2].is_some() {
+                continue;
+            }
+
             // Do nothing if join could have no effect on equivalence relation.
             // For certain samples, this hugely reduces run time.  That is the purpose of
             // having the equivalence relation.  Observed on MALT samples including 83808.
@@ -33,7 +58,7 @@ pub fn join_core(
             if !ctl.force && (eq.class_id((k1 - i) as i32) == eq.class_id((k2 - i) as i32)) {
                 continue;
             }
-            if join_one(
+            if scorer.join(
                 is_bcr,
                 k1,
                 k2,
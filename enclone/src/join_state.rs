@@ -0,0 +1,533 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+
+// Cached join state, for incremental clonotyping.  When datasets are added to a cohort, the
+// cached state from the previous run may be supplied via JOIN_STATE_IN, and then exact
+// subclonotypes that were seen before keep their joins, and only comparisons that involve at
+// least one new exact subclonotype are made.  The donor reference is also taken from the cached
+// state, for the donors that it covers.
+//
+// Each clonotype has a stable integer id.  A clonotype that contains exact subclonotypes from the
+// previous run keeps the smallest id that those had, and so previously reported ids remain valid,
+// except that when new data cause two previous clonotypes to merge, the larger id is retired.
+// A clonotype consisting only of new exact subclonotypes gets a new id.  The ids are written by
+// JOIN_ID_MAP, and are also available as the parseable variable join_id.  They are assigned just
+// after joining, so the exact subclonotypes in a printed clonotype may have different ids, if
+// later steps change clonotypes.
+//
+// Exact subclonotypes are identified by their chain types and V..J sequences.  They are
+// identified across runs only if their sequences are the same, so the cached state should only be
+// used with the same reference and the same clonotyping options.  Exact subclonotypes in the
+// cached state that are not seen in the current run are retained, so that a later run that
+// includes them again gets their ids back.
+
+use debruijn::dna_string::DnaString;
+use enclone_core::defs::{CloneInfo, EncloneControl, ExactClonotype};
+use equiv::EquivRel;
+use io_utils::{fwriteln, open_for_write_new, path_exists};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use vdj_ann::refx::RefData;
+
+// The key for an exact subclonotype: its chain types and V..J sequences.
+
+pub type ExactKey = Vec<(String, String)>;
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct JoinState {
+    pub exacts: Vec<ExactKey>,          // the exact subclonotypes
+    pub clonotype_ids: Vec<usize>,      // stable clonotype id of each exact subclonotype
+    pub raw_joins: Vec<(usize, usize)>, // raw joins, as indices into exacts
+    pub join_info: Vec<(usize, usize, bool, Vec<u8>)>, // join_info, as indices into exacts
+    pub donors: Vec<String>,            // donors whose alt_refs are included
+    // alt_refs, with donor and reference segment given by name: {(donor, ref name, ref id,
+    // alt seq, support, is_ref)}
+    pub alt_refs: Vec<(String, String, usize, String, usize, bool)>,
+    pub next_id: usize, // next clonotype id to assign
+}
+
+pub fn exact_key(ex: &ExactClonotype) -> ExactKey {
+    ex.share
+        .iter()
+        .map(|x| {
+            (
+                x.chain_type.clone(),
+                String::from_utf8_lossy(&x.seq).to_string(),
+            )
+        })
+        .collect()
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Load cached join state.
+
+pub fn load_join_state(path: &str) -> Result<JoinState, String> {
+    if !path_exists(path) {
+        return Err(format!(
+            "\nThe JOIN_STATE_IN file\n{}\ndoes not exist.\n",
+            path
+        ));
+    }
+    let f = File::open(path).map_err(|e| {
+        format!(
+            "\nThe JOIN_STATE_IN file\n{}\ncould not be opened: {}.\n",
+            path, e
+        )
+    })?;
+    let state: JoinState = bincode::deserialize_from(BufReader::new(f)).map_err(|_| {
+        format!(
+            "\nThe JOIN_STATE_IN file\n{}\ncould not be read.  Perhaps it is not a file \
+             written by JOIN_STATE_OUT, or it was written by a different version of enclone.\n",
+            path
+        )
+    })?;
+    if state.clonotype_ids.len() != state.exacts.len() {
+        return Err(format!(
+            "\nThe JOIN_STATE_IN file\n{}\nappears to be corrupted.\n",
+            path
+        ));
+    }
+    Ok(state)
+}
+
+// Save join state.
+
+pub fn save_join_state(state: &JoinState, path: &str) -> Result<(), String> {
+    let f = File::create(path).map_err(|e| {
+        format!(
+            "\nThe JOIN_STATE_OUT file\n{}\ncould not be created: {}.\n",
+            path, e
+        )
+    })?;
+    let mut f = BufWriter::new(f);
+    bincode::serialize_into(&mut f, state).map_err(|e| {
+        format!(
+            "\nThe JOIN_STATE_OUT file\n{}\ncould not be written: {}.\n",
+            path, e
+        )
+    })?;
+    f.flush().map_err(|e| {
+        format!(
+            "\nThe JOIN_STATE_OUT file\n{}\ncould not be written: {}.\n",
+            path, e
+        )
+    })
+}
+
+// Determine if the cached state covers all donors in this run, in which case allele finding is
+// not needed.
+
+pub fn covers_all_donors(state: &JoinState, ctl: &EncloneControl) -> bool {
+    ctl.origin_info
+        .donor_list
+        .iter()
+        .all(|d| state.donors.contains(d))
+}
+
+// Replace the alt_refs for donors that are covered by the cached state.  Entries whose reference
+// segment does not match this run's reference are dropped.
+
+pub fn merge_alt_refs(
+    state: &JoinState,
+    refdata: &RefData,
+    ctl: &EncloneControl,
+    alt_refs: &mut Vec<(usize, usize, DnaString, usize, bool)>,
+) {
+    let donor_index = |d: &String| ctl.origin_info.donor_list.iter().position(|x| x == d);
+    let mut covered = vec![false; ctl.origin_info.donor_list.len()];
+    for d in state.donors.iter() {
+        if let Some(donor) = donor_index(d) {
+            covered[donor] = true;
+        }
+    }
+    let mut prior = Vec::<(usize, usize, DnaString, usize, bool)>::new();
+    for x in state.alt_refs.iter() {
+        if let Some(donor) = donor_index(&x.0) {
+            if x.2 < refdata.name.len() && refdata.name[x.2] == x.1 {
+                prior.push((donor, x.2, DnaString::from_dna_string(&x.3), x.4, x.5));
+            }
+        }
+    }
+    alt_refs.retain(|x| !covered[x.0]);
+    alt_refs.append(&mut prior);
+    alt_refs.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+}
+
+// Find the stable clonotype id, if any, of each info entry, and restore the raw joins and
+// join_info of the cached state, for the exact subclonotypes that are present in this run.
+
+pub fn restore_joins(
+    state: &JoinState,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    info: &Vec<CloneInfo>,
+    raw_joins: &mut Vec<(i32, i32)>,
+    join_info: &mut Vec<(usize, usize, bool, Vec<u8>)>,
+) -> Vec<Option<usize>> {
+    let mut to_state = HashMap::<ExactKey, usize>::new();
+    for (i, x) in state.exacts.iter().enumerate() {
+        to_state.entry(x.clone()).or_insert(i);
+    }
+    let mut to_exact = vec![None; state.exacts.len()];
+    for (u, ex) in exact_clonotypes.iter().enumerate() {
+        if let Some(i) = to_state.get(&exact_key(ex)) {
+            if to_exact[*i].is_none() {
+                to_exact[*i] = Some(u);
+            }
+        }
+    }
+    let mut to_info = vec![None; exact_clonotypes.len()];
+    for i in (0..info.len()).rev() {
+        to_info[info[i].clonotype_index] = Some(i);
+    }
+    let mut prior = vec![None; info.len()];
+    for i in 0..info.len() {
+        if let Some(s) = to_state.get(&exact_key(&exact_clonotypes[info[i].clonotype_index])) {
+            prior[i] = Some(state.clonotype_ids[*s]);
+        }
+    }
+    for (s1, s2) in state.raw_joins.iter() {
+        if let (Some(u1), Some(u2)) = (to_exact[*s1], to_exact[*s2]) {
+            if let (Some(i1), Some(i2)) = (to_info[u1], to_info[u2]) {
+                raw_joins.push((i1 as i32, i2 as i32));
+            }
+        }
+    }
+    for x in state.join_info.iter() {
+        if let (Some(u1), Some(u2)) = (to_exact[x.0], to_exact[x.1]) {
+            join_info.push((u1, u2, x.2, x.3.clone()));
+        }
+    }
+    prior
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Assign a stable clonotype id to each orbit, given as a list of info indices, where prior is the
+// id from the cached state, if any, of each info entry.  An orbit keeps the smallest of its prior
+// ids, and otherwise gets a new id.  Also return, for each id, the prior ids that were merged
+// into it.
+
+fn assign_ids(
+    orbits: &[Vec<usize>],
+    prior: &[Option<usize>],
+    next_id: &mut usize,
+) -> (Vec<usize>, HashMap<usize, Vec<usize>>) {
+    let mut ids = Vec::<usize>::new();
+    let mut merged = HashMap::<usize, Vec<usize>>::new();
+    for o in orbits.iter() {
+        let mut old = o
+            .iter()
+            .filter_map(|i| prior.get(*i).cloned().flatten())
+            .collect::<Vec<usize>>();
+        old.sort_unstable();
+        old.dedup();
+        let id;
+        if old.is_empty() {
+            id = *next_id;
+            *next_id += 1;
+        } else {
+            id = old[0];
+        }
+        merged.insert(id, old);
+        ids.push(id);
+    }
+    (ids, merged)
+}
+
+// Record in state the exact subclonotypes that have ids, given their keys, and then those from
+// the prior state that were not seen, carrying over their joins.  Ids of the latter that were
+// merged in this run are replaced by the id they were merged into.  Return the index in
+// state.exacts of each exact subclonotype.
+
+fn record_exacts(
+    state: &mut JoinState,
+    prior_state: Option<&JoinState>,
+    keys: &[ExactKey],
+    ids: &[Option<usize>],
+    merged: &HashMap<usize, Vec<usize>>,
+) -> Vec<Option<usize>> {
+    let mut to_state = vec![None; keys.len()];
+    let mut seen = HashMap::<ExactKey, usize>::new();
+    for (u, key) in keys.iter().enumerate() {
+        if let Some(id) = ids[u] {
+            if !seen.contains_key(key) {
+                seen.insert(key.clone(), state.exacts.len());
+                to_state[u] = Some(state.exacts.len());
+                state.exacts.push(key.clone());
+                state.clonotype_ids.push(id);
+            }
+        }
+    }
+    if let Some(p) = prior_state {
+        let mut renamed = HashMap::<usize, usize>::new();
+        for (id, old) in merged.iter() {
+            for x in old.iter() {
+                renamed.insert(*x, *id);
+            }
+        }
+        let mut unseen = vec![None; p.exacts.len()];
+        for (i, key) in p.exacts.iter().enumerate() {
+            if !seen.contains_key(key) {
+                let id = p.clonotype_ids[i];
+                let id = *renamed.get(&id).unwrap_or(&id);
+                unseen[i] = Some(state.exacts.len());
+                state.exacts.push(key.clone());
+                state.clonotype_ids.push(id);
+            }
+        }
+        for (s1, s2) in p.raw_joins.iter() {
+            if let (Some(t1), Some(t2)) = (unseen[*s1], unseen[*s2]) {
+                state.raw_joins.push((t1, t2));
+            }
+        }
+        for x in p.join_info.iter() {
+            if let (Some(t1), Some(t2)) = (unseen[x.0], unseen[x.1]) {
+                state.join_info.push((t1, t2, x.2, x.3.clone()));
+            }
+        }
+    }
+    to_state
+}
+
+// Assign stable clonotype ids after joining, and make the join state for this run.  Also
+// return the stable id of each exact subclonotype, and for each stable id, the previous ids that
+// were merged into it.
+
+pub fn make_join_state(
+    prior_state: Option<&JoinState>,
+    prior: &Vec<Option<usize>>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    info: &Vec<CloneInfo>,
+    eq: &EquivRel,
+    raw_joins: &Vec<(i32, i32)>,
+    join_info: &Vec<(usize, usize, bool, Vec<u8>)>,
+    alt_refs: &Vec<(usize, usize, DnaString, usize, bool)>,
+    refdata: &RefData,
+    ctl: &EncloneControl,
+) -> (JoinState, Vec<Option<usize>>, HashMap<usize, Vec<usize>>) {
+    let mut state = JoinState::default();
+    if let Some(p) = prior_state {
+        state.next_id = p.next_id;
+    }
+
+    // Assign ids to orbits.
+
+    let mut ids = vec![None; exact_clonotypes.len()];
+    let mut reps = Vec::<i32>::new();
+    eq.orbit_reps(&mut reps);
+    let mut orbits = Vec::<Vec<usize>>::new();
+    for r in reps.iter() {
+        let mut o = Vec::<i32>::new();
+        eq.orbit(*r, &mut o);
+        orbits.push(o.iter().map(|i| *i as usize).collect());
+    }
+    let (orbit_ids, merged) = assign_ids(&orbits, prior, &mut state.next_id);
+    for (o, id) in orbits.iter().zip(orbit_ids.iter()) {
+        for i in o.iter() {
+            ids[info[*i].clonotype_index] = Some(*id);
+        }
+    }
+
+    // Record exact subclonotypes, then those from the prior state that were not seen.
+
+    let keys = exact_clonotypes.iter().map(exact_key).collect::<Vec<_>>();
+    let to_state = record_exacts(&mut state, prior_state, &keys, &ids, &merged);
+
+    // Record joins.
+
+    for (i1, i2) in raw_joins.iter() {
+        let u1 = to_state[info[*i1 as usize].clonotype_index];
+        let u2 = to_state[info[*i2 as usize].clonotype_index];
+        if let (Some(t1), Some(t2)) = (u1, u2) {
+            state.raw_joins.push((t1, t2));
+        }
+    }
+    for x in join_info.iter() {
+        if let (Some(t1), Some(t2)) = (to_state[x.0], to_state[x.1]) {
+            state.join_info.push((t1, t2, x.2, x.3.clone()));
+        }
+    }
+
+    // Record donors and alt_refs.
+
+    state.donors = ctl.origin_info.donor_list.clone();
+    for x in alt_refs.iter() {
+        state.alt_refs.push((
+            ctl.origin_info.donor_list[x.0].clone(),
+            refdata.name[x.1].clone(),
+            x.1,
+            x.2.to_string(),
+            x.3,
+            x.4,
+        ));
+    }
+    if let Some(p) = prior_state {
+        for d in p.donors.iter() {
+            if !state.donors.contains(d) {
+                state.donors.push(d.clone());
+            }
+        }
+        for x in p.alt_refs.iter() {
+            if !ctl.origin_info.donor_list.contains(&x.0) {
+                state.alt_refs.push(x.clone());
+            }
+        }
+    }
+    (state, ids, merged)
+}
+
+// Write the stable clonotype id of each cell, as a TSV file having fields dataset, barcode,
+// clonotype_id and merged_ids, the last being the previous ids that were merged into the id, if
+// any.
+
+pub fn write_join_id_map(
+    path: &str,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    ids: &Vec<Option<usize>>,
+    merged: &HashMap<usize, Vec<usize>>,
+    ctl: &EncloneControl,
+) {
+    let mut f = open_for_write_new![&path];
+    fwriteln!(f, "dataset\tbarcode\tclonotype_id\tmerged_ids");
+    for (u, ex) in exact_clonotypes.iter().enumerate() {
+        if let Some(id) = ids[u] {
+            let others = merged[&id]
+                .iter()
+                .filter(|x| **x != id)
+                .map(|x| x.to_string())
+                .collect::<Vec<String>>();
+            for clone in ex.clones.iter() {
+                fwriteln!(
+                    f,
+                    "{}\t{}\t{}\t{}",
+                    ctl.origin_info.dataset_id[clone[0].dataset_index],
+                    clone[0].barcode,
+                    id,
+                    others.join(",")
+                );
+            }
+        }
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(seq: &str) -> ExactKey {
+        vec![("IGH".to_string(), seq.to_string())]
+    }
+
+    #[test]
+    fn test_assign_new_ids() {
+        let orbits = vec![vec![0, 2], vec![1], vec![3]];
+        let mut next_id = 0;
+        let (ids, merged) = assign_ids(&orbits, &[None; 4], &mut next_id);
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert_eq!(next_id, 3);
+        assert!(merged.values().all(|x| x.is_empty()));
+    }
+
+    #[test]
+    fn test_reuse_cached_ids() {
+        // Orbit 0 contains an entry seen before with id 5, and orbit 1 is new.
+
+        let orbits = vec![vec![0, 1], vec![2]];
+        let prior = vec![None, Some(5), None];
+        let mut next_id = 8;
+        let (ids, merged) = assign_ids(&orbits, &prior, &mut next_id);
+        assert_eq!(ids, vec![5, 8]);
+        assert_eq!(next_id, 9);
+        assert_eq!(merged[&5], vec![5]);
+
+        // A clonotype seen again, without new data, keeps its ids.
+
+        let prior_state = JoinState {
+            exacts: vec![key("AAA"), key("CCC")],
+            clonotype_ids: vec![3, 4],
+            next_id: 5,
+            ..Default::default()
+        };
+        let keys = vec![key("CCC"), key("AAA")];
+        let prior = vec![Some(4), Some(3)];
+        let mut state = JoinState {
+            next_id: prior_state.next_id,
+            ..Default::default()
+        };
+        let orbits = vec![vec![0], vec![1]];
+        let (orbit_ids, merged) = assign_ids(&orbits, &prior, &mut state.next_id);
+        assert_eq!(orbit_ids, vec![4, 3]);
+        assert_eq!(state.next_id, 5);
+        let ids = vec![Some(4), Some(3)];
+        let to_state = record_exacts(&mut state, Some(&prior_state), &keys, &ids, &merged);
+        assert_eq!(to_state, vec![Some(0), Some(1)]);
+        assert_eq!(state.exacts, keys);
+        assert_eq!(state.clonotype_ids, vec![4, 3]);
+    }
+
+    #[test]
+    fn test_retire_ids() {
+        // The cached state has clonotypes 3 = {AAA}, 7 = {CCC, GGG} and 9 = {TTT}, with a join
+        // between CCC and GGG.  In this run, AAA and CCC are joined, so 7 is retired in favor of
+        // 3.  GGG and TTT are not seen, and are retained, GGG under its new id.
+
+        let prior_state = JoinState {
+            exacts: vec![key("AAA"), key("CCC"), key("GGG"), key("TTT")],
+            clonotype_ids: vec![3, 7, 7, 9],
+            raw_joins: vec![(1, 2)],
+            next_id: 10,
+            ..Default::default()
+        };
+        let keys = vec![key("AAA"), key("CCC")];
+        let prior = vec![Some(3), Some(7)];
+        let mut state = JoinState {
+            next_id: prior_state.next_id,
+            ..Default::default()
+        };
+        let (orbit_ids, merged) = assign_ids(&[vec![0, 1]], &prior, &mut state.next_id);
+        assert_eq!(orbit_ids, vec![3]);
+        assert_eq!(merged[&3], vec![3, 7]);
+        let ids = vec![Some(3), Some(3)];
+        let to_state = record_exacts(&mut state, Some(&prior_state), &keys, &ids, &merged);
+        assert_eq!(to_state, vec![Some(0), Some(1)]);
+        assert_eq!(
+            state.exacts,
+            vec![key("AAA"), key("CCC"), key("GGG"), key("TTT")]
+        );
+        assert_eq!(state.clonotype_ids, vec![3, 3, 3, 9]);
+        assert!(!state.clonotype_ids.contains(&7));
+
+        // The join between CCC and GGG is not carried over, because CCC was seen.
+
+        assert!(state.raw_joins.is_empty());
+        assert_eq!(state.next_id, 10);
+    }
+
+    #[test]
+    fn test_save_load_join_state() {
+        let path = std::env::temp_dir().join(format!(
+            "enclone_test_join_state_{}.bin",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        let state = JoinState {
+            exacts: vec![key("AAA"), key("CCC")],
+            clonotype_ids: vec![0, 1],
+            raw_joins: vec![(0, 1)],
+            next_id: 2,
+            ..Default::default()
+        };
+        save_join_state(&state, path).unwrap();
+        let loaded = load_join_state(path).unwrap();
+        assert_eq!(loaded.exacts, state.exacts);
+        assert_eq!(loaded.clonotype_ids, state.clonotype_ids);
+        assert_eq!(loaded.raw_joins, state.raw_joins);
+        assert_eq!(loaded.next_id, 2);
+        std::fs::remove_file(path).unwrap();
+        assert!(save_join_state(&state, "/nonexistent_dir/join_state.bin").is_err());
+    }
+}
@@ -1,12 +1,14 @@
 // Copyright (c) 2021 10x Genomics, Inc. All rights reserved.
 
 pub mod allele;
+pub mod donor_demux;
 pub mod graph_filter;
 pub mod info;
 pub mod innate;
 pub mod join;
 pub mod join2;
 pub mod join_core;
+pub mod join_state;
 pub mod misc1;
 pub mod misc2;
 pub mod misc3;
@@ -0,0 +1,365 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Gene expression values corrected for ambient RNA, as requested by AMBIENT.
+//
+// Ambient RNA, for example from lysed plasma cells, contributes transcripts to every droplet, and
+// so can make cells appear to express genes that they do not, such as immunoglobulin genes in
+// T cells.  Separately for each dataset, we estimate the ambient profile from empty droplets in
+// the raw matrix, which are the barcodes that are not cells and have between 1 and
+// EMPTY_MAX_UMIS gene expression UMIs.  The ambient profile is the fraction a_f of gene
+// expression UMIs in these droplets that are from each gene f.
+//
+// Then we estimate the fraction rho of UMIs in cells that are ambient.  This may instead be
+// specified using AMBIENT_FRAC=rho.  For each of the TOP_AMBIENT genes having highest ambient
+// fraction, we find the cells whose counts are explained by ambient RNA alone, iteratively, by
+// excluding cells whose count x is greater than mu + 3 * sqrt(mu) + 1, where mu = rho * N * a_f
+// is the expected ambient count and N is the total gene expression UMI count of the cell.  The
+// estimate of rho for the gene is then the sum of x divided by the sum of N * a_f over the
+// remaining cells.  We take rho to be the median of the estimates for the genes.
+//
+// The corrected count for a gene in a cell is max(0, x - rho * N * a_f), and this is scaled in
+// the same way as the uncorrected count.  Corrected values are provided as per-cell lead
+// variables, which are stored in origin_info.alt_bc_fields:
+// - <gene>_g_corr, or equivalently <gene>_corr = corrected count for a gene;
+// - gex_corr = sum of corrected counts for all genes.
+// These are computed only if they are referenced by some argument (see referenced_vars).  Only
+// gene expression features can be corrected, so for example <feature>_ab_corr is not allowed.
+
+use crate::proc_args_check::referenced_vars;
+use enclone_core::defs::{EncloneControl, GexInfo};
+use enclone_core::median::median_f64;
+use ndarray::s;
+use std::cmp::min;
+use std::collections::HashMap;
+use string_utils::TextUtils;
+use vector_utils::{bin_position, reverse_sort};
+
+const EMPTY_MAX_UMIS: f64 = 100.0;
+const TOP_AMBIENT: usize = 20;
+const BATCH: usize = 10_000;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Get the counts for some barcodes in a dataset, given by their indices in gex_barcodes, as lists
+// of (feature, count).  Only gene expression features are included if gex_only is set.  This
+// uses the matrix if it has been loaded, and otherwise reads just the needed rows from the h5
+// file, reading consecutive rows together.
+
+pub fn feature_counts(
+    gex_info: &GexInfo,
+    li: usize,
+    barcodes: &[usize],
+    gex_only: bool,
+) -> Vec<Vec<(usize, f64)>> {
+    let is_gex = &gex_info.is_gex[li];
+    let mut rows = vec![Vec::<(usize, f64)>::new(); barcodes.len()];
+    if gex_info.gex_matrices[li].initialized() {
+        for (row, &p) in rows.iter_mut().zip(barcodes.iter()) {
+            for (f, c) in gex_info.gex_matrices[li].row(p) {
+                if (!gex_only || is_gex[f]) && c > 0 {
+                    row.push((f, c as f64));
+                }
+            }
+        }
+    } else if gex_info.h5_data[li].is_some() {
+        let d_reader = gex_info.h5_data[li].as_ref().unwrap().as_reader();
+        let ind_reader = gex_info.h5_indices[li].as_ref().unwrap().as_reader();
+        let indptr = &gex_info.h5_indptr[li];
+        let mut i = 0;
+        while i < barcodes.len() {
+            let mut j = i + 1;
+            while j < barcodes.len() && barcodes[j] == barcodes[j - 1] + 1 {
+                j += 1;
+            }
+            let z1 = indptr[barcodes[i]] as usize;
+            let z2 = indptr[barcodes[j - 1] + 1] as usize;
+            let d: Vec<u32> = d_reader.read_slice(s![z1..z2]).unwrap().to_vec();
+            let ind: Vec<u32> = ind_reader.read_slice(s![z1..z2]).unwrap().to_vec();
+            for k in i..j {
+                let p = barcodes[k];
+                for m in indptr[p] as usize - z1..indptr[p + 1] as usize - z1 {
+                    let f = ind[m] as usize;
+                    if (!gex_only || is_gex[f]) && d[m] > 0 {
+                        rows[k].push((f, d[m] as f64));
+                    }
+                }
+            }
+            i = j;
+        }
+    }
+    rows
+}
+
+// Get the counts of some features for all barcodes in a dataset, as one vector per feature,
+// indexed by position in gex_barcodes.  The rows are read in batches, so only the counts for the
+// given features are kept.
+
+pub fn feature_columns(gex_info: &GexInfo, li: usize, features: &[usize]) -> Vec<Vec<f64>> {
+    let nbc = gex_info.gex_barcodes[li].len();
+    let mut cols = vec![vec![0.0; nbc]; features.len()];
+    if features.is_empty() {
+        return cols;
+    }
+    let mut index = HashMap::<usize, Vec<usize>>::new();
+    for (k, &f) in features.iter().enumerate() {
+        index.entry(f).or_default().push(k);
+    }
+    for start in (0..nbc).step_by(BATCH) {
+        let barcodes = (start..min(start + BATCH, nbc)).collect::<Vec<usize>>();
+        for (i, row) in feature_counts(gex_info, li, &barcodes, false)
+            .iter()
+            .enumerate()
+        {
+            for &(f, c) in row.iter() {
+                if let Some(ks) = index.get(&f) {
+                    for &k in ks.iter() {
+                        cols[k][start + i] = c;
+                    }
+                }
+            }
+        }
+    }
+    cols
+}
+
+// Get the gene expression counts for some barcodes in a dataset.
+
+pub fn gene_counts(gex_info: &GexInfo, li: usize, barcodes: &[usize]) -> Vec<Vec<(usize, f64)>> {
+    feature_counts(gex_info, li, barcodes, true)
+}
+
+// Get the indices in gex_barcodes of the cell barcodes of a dataset, in increasing order.
+
+pub fn cell_barcode_indices(gex_info: &GexInfo, li: usize) -> Vec<usize> {
+    let mut cells = gex_info.gex_cell_barcodes[li]
+        .iter()
+        .map(|bc| bin_position(&gex_info.gex_barcodes[li], bc))
+        .filter(|&p| p >= 0)
+        .map(|p| p as usize)
+        .collect::<Vec<usize>>();
+    cells.sort_unstable();
+    cells
+}
+
+// Estimate the ambient fraction from the cells.
+
+fn estimate_ambient_frac(
+    cells: &Vec<&Vec<(usize, f64)>>,
+    totals: &Vec<f64>,
+    ambient: &Vec<f64>,
+) -> f64 {
+    let mut top = ambient
+        .iter()
+        .enumerate()
+        .filter(|x| *x.1 > 0.0)
+        .map(|(f, a)| (*a, f))
+        .collect::<Vec<_>>();
+    reverse_sort(&mut top);
+    top.truncate(TOP_AMBIENT);
+    let mut ests = Vec::<f64>::new();
+    for &(a, f) in top.iter() {
+        let x = cells
+            .iter()
+            .map(|row| row.iter().find(|y| y.0 == f).map(|y| y.1).unwrap_or(0.0))
+            .collect::<Vec<f64>>();
+        let mut rho = 0.1;
+        for _ in 0..10 {
+            let (mut num, mut den) = (0.0, 0.0);
+            for i in 0..x.len() {
+                let mu = rho * totals[i] * a;
+                if x[i] <= mu + 3.0 * mu.sqrt() + 1.0 {
+                    num += x[i];
+                    den += totals[i] * a;
+                }
+            }
+            if den == 0.0 {
+                break;
+            }
+            rho = (num / den).min(1.0);
+        }
+        ests.push(rho);
+    }
+    if ests.is_empty() {
+        return 0.0;
+    }
+    ests.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    median_f64(&ests)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Compute corrected gene expression values for the variables that are used, and add them to
+// alt_bc_fields.
+
+pub fn ambient_correct(ctl: &mut EncloneControl, gex_info: &GexInfo) -> Result<(), String> {
+    // Find the variables, and for each, its feature_id key (<gene>_g), or None for gex_corr.
+
+    let mut vars = Vec::<(String, Option<String>)>::new();
+    let is_feature = |x: &str| gex_info.feature_id.iter().any(|y| y.contains_key(x));
+    for x in referenced_vars(ctl) {
+        if x == "gex_corr" {
+            vars.push((x, None));
+        } else if x.ends_with("_g_corr") && x.len() > "_g_corr".len() {
+            let g = x.rev_before("_corr").to_string();
+            if !is_feature(&g) {
+                return Err(format!(
+                    "\nThe variable {} cannot be computed, because {} is not the name or id of \
+                     a gene.\n",
+                    x,
+                    g.rev_before("_g")
+                ));
+            }
+            vars.push((x, Some(g)));
+        } else if x.ends_with("_corr") && x.len() > "_corr".len() {
+            let f = x.rev_before("_corr").to_string();
+            if is_feature(&format!("{}_g", f)) {
+                vars.push((x, Some(format!("{}_g", f))));
+            } else if ["_ab", "_cr", "_cu"]
+                .iter()
+                .any(|e| f.ends_with(e) || is_feature(&format!("{}{}", f, e)))
+            {
+                return Err(format!(
+                    "\nThe variable {} cannot be computed, because only gene expression \
+                     features can be corrected for ambient RNA.\n",
+                    x
+                ));
+            }
+        }
+    }
+    if vars.is_empty() {
+        return Ok(());
+    }
+    for var in vars.iter() {
+        if ctl
+            .origin_info
+            .alt_bc_fields
+            .iter()
+            .flatten()
+            .any(|x| x.0 == var.0)
+        {
+            return Err(format!(
+                "\nThe variable {} computed by AMBIENT is also defined by a BC, BC_JOINT or OBS \
+                 file.\n",
+                var.0
+            ));
+        }
+    }
+    let n = ctl.origin_info.n();
+    ctl.origin_info.alt_bc_fields.resize(n, Vec::new());
+    for li in 0..n {
+        let mut fields = vec![HashMap::<String, String>::new(); vars.len()];
+        if !gex_info.gex_features[li].is_empty() {
+            let barcodes = &gex_info.gex_barcodes[li];
+            let cells = cell_barcode_indices(gex_info, li);
+
+            // Find the ambient profile, reading the barcodes that are not cells in batches.
+
+            let mut ambient = vec![0.0; gex_info.gex_features[li].len()];
+            let mut total = 0.0;
+            let others = (0..barcodes.len())
+                .filter(|p| cells.binary_search(p).is_err())
+                .collect::<Vec<usize>>();
+            for batch in others.chunks(BATCH) {
+                for row in gene_counts(gex_info, li, batch).iter() {
+                    let t = row.iter().map(|x| x.1).sum::<f64>();
+                    if t > 0.0 && t <= EMPTY_MAX_UMIS {
+                        for &(f, c) in row.iter() {
+                            ambient[f] += c;
+                        }
+                        total += t;
+                    }
+                }
+            }
+            if total == 0.0 {
+                return Err(format!(
+                    "\nAMBIENT was specified, but no empty droplets were found for dataset {}.  \
+                     The raw feature barcode matrix is needed to estimate the ambient profile.\n",
+                    ctl.origin_info.dataset_id[li]
+                ));
+            }
+            for a in ambient.iter_mut() {
+                *a /= total;
+            }
+
+            // Find the ambient fraction.
+
+            let rows = gene_counts(gex_info, li, &cells);
+            let totals = rows
+                .iter()
+                .map(|r| r.iter().map(|x| x.1).sum::<f64>())
+                .collect::<Vec<f64>>();
+            let rho = if ctl.gen_opt.ambient_frac >= 0.0 {
+                ctl.gen_opt.ambient_frac
+            } else {
+                estimate_ambient_frac(&rows.iter().collect(), &totals, &ambient)
+            };
+
+            // Compute the corrected values.
+
+            let mult = if ctl.gen_opt.full_counts {
+                1.0
+            } else {
+                gex_info.gex_mults[li]
+            };
+            let fids = vars
+                .iter()
+                .map(|v| match v.1 {
+                    None => None,
+                    Some(ref g) => gex_info.feature_id[li].get(g).cloned(),
+                })
+                .collect::<Vec<_>>();
+            for (i, &p) in cells.iter().enumerate() {
+                let corr = |f: usize, x: f64| (x - rho * totals[i] * ambient[f]).max(0.0);
+                for k in 0..vars.len() {
+                    let value = if vars[k].1.is_none() {
+                        rows[i].iter().map(|&(f, x)| corr(f, x)).sum::<f64>()
+                    } else if let Some(f) = fids[k] {
+                        match rows[i].iter().find(|x| x.0 == f) {
+                            Some(&(_, x)) => corr(f, x),
+                            None => 0.0,
+                        }
+                    } else {
+                        continue;
+                    };
+                    fields[k].insert(barcodes[p].clone(), format!("{:.2}", value * mult));
+                }
+            }
+        }
+        for (var, f) in vars.iter().zip(fields.into_iter()) {
+            ctl.origin_info.alt_bc_fields[li].push((var.0.clone(), f));
+        }
+    }
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Simulate 120 cells having 1000 UMIs each, and an ambient fraction of 0.05, with ambient
+    // profile 0.5, 0.3 and 0.2 for genes 0, 1 and 2, so that the ambient counts of these genes
+    // are 25, 15 and 10.  The counts of genes 0 and 1 vary by one up or down.  The last 20 cells
+    // also express gene 0, with 500 UMIs, and would bias the estimate to 0.21 if they were not
+    // excluded.  Gene 3 is expressed by every cell, but is not ambient, and so is ignored.
+
+    #[test]
+    fn test_estimate_ambient_frac() {
+        let ambient = vec![0.5, 0.3, 0.2, 0.0];
+        let mut rows = Vec::<Vec<(usize, f64)>>::new();
+        for i in 0..120 {
+            let noise = if i % 2 == 0 { -1.0 } else { 1.0 };
+            let x0 = if i >= 100 { 500.0 } else { 25.0 + noise };
+            rows.push(vec![(0, x0), (1, 15.0 - noise), (2, 10.0), (3, 200.0)]);
+        }
+        let totals = vec![1000.0; rows.len()];
+        let rho = estimate_ambient_frac(&rows.iter().collect(), &totals, &ambient);
+        assert!((rho - 0.05).abs() < 1.0e-9);
+
+        // Without ambient genes, the estimate is zero.
+
+        let rho = estimate_ambient_frac(&rows.iter().collect(), &totals, &vec![0.0; 4]);
+        assert_eq!(rho, 0.0);
+    }
+}
@@ -0,0 +1,302 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Antigen specificity scoring for antigen-barcoded BCR experiments (LIBRA-seq style), as
+// requested by ANTIGEN_SCORE.
+//
+// The antigens are the features named by ANTIGENS, or by default, all features of type
+// "Antigen Capture".  The negative control antigens are the features named by ANTIGEN_NEG, if
+// any.  For each cell and antigen, we compute a normalized count
+//     x = ln(count + 1) - ln(mean count of the negative control antigens + 1),
+// so that cells that stick to every antigen are not scored as specific.  Then, separately for
+// each dataset and antigen, we find the median m and median absolute deviation d of x across all
+// gene expression cells, which nearly all serve as background, and define the score of a cell to
+// be (x - m) / (1.4826 * d), a robust z score.  (If d is zero, the standard deviation is used
+// instead.)  A cell is called as specific for an antigen if its score is at least ANTIGEN_CALL
+// (default 3).
+//
+// Per-cell results are stored as lead variables in origin_info.alt_bc_fields:
+// - <antigen>_libra = score;
+// - libra_call = antigens called for the cell, separated by +, or none.
+// Per-clonotype results are computed as lead variables by antigen_clono_value:
+// - <antigen>_libra_clono = mean score across the cells in the clonotype;
+// - libra_call_clono = antigens whose mean score is at least ANTIGEN_CALL, or none.
+// Antigen names are converted to variable names by replacing characters other than letters,
+// digits and underscores by underscores.
+
+use crate::ambient::feature_columns;
+use crate::read_obs::var_name;
+use enclone_core::defs::{EncloneControl, ExactClonotype, GexInfo};
+use enclone_core::median::median_f64;
+use std::collections::HashMap;
+use string_utils::TextUtils;
+use vector_utils::unique_sort;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Find the features in a dataset that are named by a list, matching either feature id or name.
+
+fn find_features(
+    features: &Vec<String>,
+    names: &Vec<String>,
+    arg: &str,
+    dataset: &str,
+) -> Result<Vec<(String, usize)>, String> {
+    let mut x = Vec::<(String, usize)>::new();
+    for name in names.iter() {
+        let f = features
+            .iter()
+            .position(|f| f.before("\t") == name || f.between("\t", "\t") == name);
+        if f.is_none() {
+            return Err(format!(
+                "\nThe feature {} in your {} argument is not present in dataset {}.\n",
+                name, arg, dataset
+            ));
+        }
+        let f = f.unwrap();
+        x.push((var_name(features[f].between("\t", "\t")), f));
+    }
+    Ok(x)
+}
+
+// Compute the robust z scores of a vector of values.
+
+fn robust_z(x: &Vec<f64>) -> Vec<f64> {
+    if x.is_empty() {
+        return Vec::new();
+    }
+    let mut sorted = x.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let m = median_f64(&sorted);
+    let mut dev = x.iter().map(|v| (v - m).abs()).collect::<Vec<f64>>();
+    dev.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mut scale = 1.4826 * median_f64(&dev);
+    if scale == 0.0 {
+        let mean = x.iter().sum::<f64>() / x.len() as f64;
+        let var = x.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / x.len() as f64;
+        scale = var.sqrt();
+    }
+    if scale == 0.0 {
+        scale = 1.0;
+    }
+    x.iter().map(|v| (v - m) / scale).collect()
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Compute per-cell antigen scores and calls, and add them to alt_bc_fields.
+
+pub fn antigen_scores(ctl: &mut EncloneControl, gex_info: &GexInfo) -> Result<(), String> {
+    let n = ctl.origin_info.n();
+    let mut names = Vec::<String>::new();
+    let mut fields = Vec::<Vec<(String, HashMap<String, String>)>>::new();
+    for li in 0..n {
+        let features = &gex_info.gex_features[li];
+        if features.is_empty() {
+            fields.push(Vec::new());
+            continue;
+        }
+        let dataset = &ctl.origin_info.dataset_id[li];
+        let neg = find_features(features, &ctl.gen_opt.antigen_neg, "ANTIGEN_NEG", dataset)?;
+        let antigens = if !ctl.gen_opt.antigens.is_empty() {
+            find_features(features, &ctl.gen_opt.antigens, "ANTIGENS", dataset)?
+        } else {
+            let mut x = Vec::<(String, usize)>::new();
+            for (f, feature) in features.iter().enumerate() {
+                if feature.after("\t").after("\t") == "Antigen Capture"
+                    && !neg.iter().any(|y| y.1 == f)
+                {
+                    x.push((var_name(feature.between("\t", "\t")), f));
+                }
+            }
+            x
+        };
+
+        // Get counts.
+
+        let ncells = gex_info.gex_barcodes[li].len();
+        let ids = antigens
+            .iter()
+            .chain(neg.iter())
+            .map(|x| x.1)
+            .collect::<Vec<_>>();
+        let counts = feature_columns(gex_info, li, &ids);
+
+        // Compute scores and calls.
+
+        let mut calls = vec![Vec::<String>::new(); ncells];
+        let mut f = Vec::<(String, HashMap<String, String>)>::new();
+        for (k, antigen) in antigens.iter().enumerate() {
+            let mut x = Vec::<f64>::new();
+            for p in 0..ncells {
+                let mut negc = 0.0;
+                if !neg.is_empty() {
+                    for j in 0..neg.len() {
+                        negc += counts[antigens.len() + j][p];
+                    }
+                    negc /= neg.len() as f64;
+                }
+                x.push((counts[k][p] + 1.0).ln() - (negc + 1.0).ln());
+            }
+            let mut scores = HashMap::<String, String>::new();
+            for (p, z) in robust_z(&x).iter().enumerate() {
+                let bc = &gex_info.gex_barcodes[li][p];
+                scores.insert(bc.clone(), format!("{:.2}", z));
+                if *z >= ctl.gen_opt.antigen_call {
+                    calls[p].push(antigen.0.clone());
+                }
+            }
+            f.push((format!("{}_libra", antigen.0), scores));
+            names.push(antigen.0.clone());
+        }
+        let mut call = HashMap::<String, String>::new();
+        for p in 0..ncells {
+            let c = if calls[p].is_empty() {
+                "none".to_string()
+            } else {
+                calls[p].join("+")
+            };
+            call.insert(gex_info.gex_barcodes[li][p].clone(), c);
+        }
+        f.push(("libra_call".to_string(), call));
+        fields.push(f);
+    }
+    unique_sort(&mut names);
+    if names.is_empty() {
+        return Err(
+            "\nANTIGEN_SCORE was specified, but no antigen features were found.  You may need \
+             to specify them using ANTIGENS.\n"
+                .to_string(),
+        );
+    }
+
+    // Save the results, so that each dataset has every variable.
+
+    ctl.origin_info.alt_bc_fields.resize(n, Vec::new());
+    let mut vars = names
+        .iter()
+        .map(|x| format!("{}_libra", x))
+        .collect::<Vec<String>>();
+    vars.push("libra_call".to_string());
+    for var in vars.iter() {
+        if ctl
+            .origin_info
+            .alt_bc_fields
+            .iter()
+            .any(|f| f.iter().any(|x| x.0 == *var))
+        {
+            return Err(format!(
+                "\nThe variable {} computed by ANTIGEN_SCORE is also defined by a BC, BC_JOINT \
+                 or OBS file.\n",
+                var
+            ));
+        }
+    }
+    for (li, f) in fields.into_iter().enumerate() {
+        for var in vars.iter() {
+            let x = match f.iter().position(|x| x.0 == *var) {
+                Some(j) => f[j].1.clone(),
+                None => HashMap::new(),
+            };
+            ctl.origin_info.alt_bc_fields[li].push((var.clone(), x));
+        }
+    }
+    ctl.gen_opt.antigen_names = names;
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Determine if a variable is a per-clonotype antigen specificity variable.
+
+pub fn is_antigen_clono_var(ctl: &EncloneControl, var: &str) -> bool {
+    if ctl.gen_opt.antigen_names.is_empty() {
+        return false;
+    }
+    var == "libra_call_clono"
+        || (var.ends_with("_libra_clono")
+            && ctl
+                .gen_opt
+                .antigen_names
+                .contains(&var.rev_before("_libra_clono").to_string()))
+}
+
+// Compute the value of a per-clonotype antigen specificity variable, from the per-cell scores.
+
+pub fn antigen_clono_value(
+    ctl: &EncloneControl,
+    exacts: &Vec<usize>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    var: &str,
+) -> Option<String> {
+    if !is_antigen_clono_var(ctl, var) {
+        return None;
+    }
+    let mean_score = |antigen: &str| {
+        let name = format!("{}_libra", antigen);
+        let (mut sum, mut count) = (0.0, 0);
+        for &u in exacts.iter() {
+            for clone in exact_clonotypes[u].clones.iter() {
+                let li = clone[0].dataset_index;
+                for x in ctl.origin_info.alt_bc_fields[li].iter() {
+                    if x.0 == name {
+                        if let Some(v) = x.1.get(&clone[0].barcode) {
+                            sum += v.force_f64();
+                            count += 1;
+                        }
+                    }
+                }
+            }
+        }
+        if count == 0 {
+            None
+        } else {
+            Some(sum / count as f64)
+        }
+    };
+    if var == "libra_call_clono" {
+        let mut calls = Vec::<String>::new();
+        for antigen in ctl.gen_opt.antigen_names.iter() {
+            if let Some(m) = mean_score(antigen) {
+                if m >= ctl.gen_opt.antigen_call {
+                    calls.push(antigen.clone());
+                }
+            }
+        }
+        if calls.is_empty() {
+            Some("none".to_string())
+        } else {
+            Some(calls.join("+"))
+        }
+    } else {
+        match mean_score(var.rev_before("_libra_clono")) {
+            Some(m) => Some(format!("{:.2}", m)),
+            None => Some(String::new()),
+        }
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_robust_z() {
+        // The median is 2 and the median absolute deviation is 1.
+
+        let z = robust_z(&vec![1.0, 2.0, 3.0, 2.0, 10.0]);
+        let expected = [-1.0, 0.0, 1.0, 0.0, 8.0];
+        for i in 0..5 {
+            assert!((z[i] - expected[i] / 1.4826).abs() < 1.0e-12);
+        }
+
+        // If the median absolute deviation is zero, the standard deviation is used, and if that
+        // is also zero, the values are only centered.
+
+        let z = robust_z(&vec![0.0, 0.0, 0.0, 0.0, 5.0]);
+        assert!((z[4] - 2.5).abs() < 1.0e-12);
+        assert_eq!(robust_z(&vec![3.0, 3.0]), vec![0.0, 0.0]);
+        assert!(robust_z(&Vec::new()).is_empty());
+    }
+}
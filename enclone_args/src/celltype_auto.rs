@@ -0,0 +1,257 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Classify cells into B and T cell subsets using marker gene expression, as requested by
+// CELLTYPE_AUTO.  This does not require a Cell Ranger celltypes file.
+//
+// Each cell type is defined by a list of marker genes.  The default lists are in DEFAULT_MARKERS,
+// and may be replaced by CELLTYPE_MARKERS=file, where the file is a CSV file having fields type
+// and gene, and one line per marker.  Genes are matched to gene names, ignoring case, so that
+// the default lists work for both human and mouse.
+//
+// Separately for each dataset, the normalized expression of a gene in a cell is
+// ln(1 + 10000 * count / total), where total is the number of gene expression UMIs in the cell.
+// This is divided by its 99th percentile across the cells that express the gene, and capped at
+// one.  Using only expressing cells matters for markers that are detected in few cells, such as
+// FOXP3, which would otherwise have a percentile of zero, and contribute nothing.  The score of
+// a cell type is the mean of these values for its markers, and a cell is assigned to the type of
+// highest score, provided that the score is at least MIN_SCORE, and is otherwise called unassigned.
+//
+// The result is stored in origin_info.alt_bc_fields as the per-cell lead variable celltype_auto,
+// so that it may be used in filters (e.g. KEEP_CELL_IF) and color schemes, like other fields
+// from BC files.
+
+use crate::ambient::{cell_barcode_indices, gene_counts};
+use enclone_core::defs::{EncloneControl, GexInfo};
+use io_utils::open_userfile_for_read;
+use std::collections::HashMap;
+use std::io::BufRead;
+use string_utils::{parse_csv, TextUtils};
+
+const MIN_SCORE: f64 = 0.3;
+
+const DEFAULT_MARKERS: [(&str, &[&str]); 7] = [
+    ("B_naive", &["MS4A1", "CD19", "IGHD", "TCL1A", "FCER2"]),
+    ("B_memory", &["MS4A1", "CD19", "CD27", "TNFRSF13B", "AIM2"]),
+    ("plasma", &["JCHAIN", "MZB1", "XBP1", "PRDM1", "SDC1"]),
+    ("T_CD4", &["CD3E", "CD3D", "CD4", "IL7R"]),
+    ("T_CD8", &["CD3E", "CD3D", "CD8A", "CD8B"]),
+    ("Treg", &["CD3E", "CD4", "FOXP3", "IL2RA", "CTLA4"]),
+    ("MAIT", &["CD3E", "SLC4A10", "KLRB1", "ZBTB16", "TRAV1-2"]),
+];
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Read the marker genes from a CELLTYPE_MARKERS file, as (type, genes), in the order in which the
+// types first appear.
+
+fn read_markers(path: &str) -> Result<Vec<(String, Vec<String>)>, String> {
+    let mut markers = Vec::<(String, Vec<String>)>::new();
+    let f = open_userfile_for_read(path);
+    let (mut type_pos, mut gene_pos) = (0, 0);
+    for (i, line) in f.lines().enumerate() {
+        let s = line.map_err(|e| {
+            format!(
+                "\nFailed to read the CELLTYPE_MARKERS file\n{}\nwith error {}.\n",
+                path, e
+            )
+        })?;
+        let fields = parse_csv(&s);
+        if i == 0 {
+            let t = fields.iter().position(|x| x == "type");
+            let g = fields.iter().position(|x| x == "gene");
+            if t.is_none() || g.is_none() {
+                return Err(format!(
+                    "\nThe CELLTYPE_MARKERS file\n{}\nneeds to have fields type and gene.\n",
+                    path
+                ));
+            }
+            type_pos = t.unwrap();
+            gene_pos = g.unwrap();
+            continue;
+        }
+        if fields.len() <= type_pos.max(gene_pos) {
+            return Err(format!(
+                "\nThe line\n{}\nin the CELLTYPE_MARKERS file\n{}\nhas too few fields.\n",
+                s, path
+            ));
+        }
+        let (t, g) = (&fields[type_pos], &fields[gene_pos]);
+        match markers.iter().position(|x| x.0 == *t) {
+            Some(k) => markers[k].1.push(g.clone()),
+            None => markers.push((t.clone(), vec![g.clone()])),
+        }
+    }
+    if markers.is_empty() {
+        return Err(format!(
+            "\nThe CELLTYPE_MARKERS file\n{}\ndoes not define any markers.\n",
+            path
+        ));
+    }
+    Ok(markers)
+}
+
+// Find the value at a given quantile of a vector of values.
+
+fn quantile(x: &Vec<f64>, q: f64) -> f64 {
+    if x.is_empty() {
+        return 0.0;
+    }
+    let mut sorted = x.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    sorted[((sorted.len() - 1) as f64 * q).round() as usize]
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Given gene expression counts for some cells, as (feature index, count), and cell types, each
+// defined by the feature indices of its markers, assign each cell to a type, or to unassigned.
+
+fn assign_types<'a>(rows: &[Vec<(usize, f64)>], types: &'a [(String, Vec<usize>)]) -> Vec<&'a str> {
+    let mut genes = types.iter().flat_map(|t| t.1.clone()).collect::<Vec<_>>();
+    genes.sort_unstable();
+    genes.dedup();
+
+    // Compute normalized expression of the marker genes in the cells.
+
+    let mut expr = vec![vec![0.0; rows.len()]; genes.len()];
+    for (i, row) in rows.iter().enumerate() {
+        let total = row.iter().map(|x| x.1).sum::<f64>();
+        if total == 0.0 {
+            continue;
+        }
+        for &(f, c) in row.iter() {
+            if let Ok(g) = genes.binary_search(&f) {
+                expr[g][i] = (10000.0 * c / total).ln_1p();
+            }
+        }
+    }
+    for e in expr.iter_mut() {
+        let q = quantile(&e.iter().filter(|&&v| v > 0.0).cloned().collect(), 0.99);
+        for v in e.iter_mut() {
+            *v = if q > 0.0 { (*v / q).min(1.0) } else { 0.0 };
+        }
+    }
+
+    // Score and classify the cells.
+
+    let mut assigned = Vec::<&str>::with_capacity(rows.len());
+    for i in 0..rows.len() {
+        let mut best = ("unassigned", MIN_SCORE);
+        for t in types.iter() {
+            let score =
+                t.1.iter()
+                    .map(|f| expr[genes.binary_search(f).unwrap()][i])
+                    .sum::<f64>()
+                    / t.1.len() as f64;
+            if score >= best.1 && (best.0 == "unassigned" || score > best.1) {
+                best = (t.0.as_str(), score);
+            }
+        }
+        assigned.push(best.0);
+    }
+    assigned
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Classify the gene expression cells, and add the result to alt_bc_fields.
+
+pub fn celltype_auto(ctl: &mut EncloneControl, gex_info: &GexInfo) -> Result<(), String> {
+    let markers = if ctl.gen_opt.celltype_markers.is_empty() {
+        DEFAULT_MARKERS
+            .iter()
+            .map(|x| {
+                (
+                    x.0.to_string(),
+                    x.1.iter().map(|g| g.to_string()).collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>()
+    } else {
+        read_markers(&ctl.gen_opt.celltype_markers)?
+    };
+    if ctl
+        .origin_info
+        .alt_bc_fields
+        .iter()
+        .flatten()
+        .any(|x| x.0 == "celltype_auto")
+    {
+        return Err(
+            "\nThe variable celltype_auto computed by CELLTYPE_AUTO is also defined by a BC, \
+             BC_JOINT or OBS file.\n"
+                .to_string(),
+        );
+    }
+    let n = ctl.origin_info.n();
+    ctl.origin_info.alt_bc_fields.resize(n, Vec::new());
+    for li in 0..n {
+        let mut calls = HashMap::<String, String>::new();
+        let features = &gex_info.gex_features[li];
+        if !features.is_empty() {
+            // Find the marker genes in the features.
+
+            let mut to_feature = HashMap::<String, usize>::new();
+            for (f, feature) in features.iter().enumerate() {
+                if gex_info.is_gex[li][f] {
+                    to_feature.insert(feature.between("\t", "\t").to_uppercase(), f);
+                }
+            }
+            let types = markers
+                .iter()
+                .map(|m| {
+                    let mut fs =
+                        m.1.iter()
+                            .filter_map(|g| to_feature.get(&g.to_uppercase()).cloned())
+                            .collect::<Vec<usize>>();
+                    fs.sort_unstable();
+                    fs.dedup();
+                    (m.0.clone(), fs)
+                })
+                .filter(|m| !m.1.is_empty())
+                .collect::<Vec<_>>();
+            // Classify the cells.
+
+            let cells = cell_barcode_indices(gex_info, li);
+            let rows = gene_counts(gex_info, li, &cells);
+            let assigned = assign_types(&rows, &types);
+            for (i, &p) in cells.iter().enumerate() {
+                calls.insert(
+                    gex_info.gex_barcodes[li][p].clone(),
+                    assigned[i].to_string(),
+                );
+            }
+        }
+        ctl.origin_info.alt_bc_fields[li].push(("celltype_auto".to_string(), calls));
+    }
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Features 0 and 1 are B cell markers, features 2 and 3 are T cell markers, and feature 4 is
+    // not a marker.  Each cell has 100 UMIs, except for the fourth, which has 1000.
+
+    #[test]
+    fn test_assign_types() {
+        let types = vec![("B".to_string(), vec![0, 1]), ("T".to_string(), vec![2, 3])];
+        let rows = vec![
+            // Both B markers at full strength: B.
+            vec![(0, 10.0), (1, 10.0), (4, 80.0)],
+            // Both T markers, and a trace of one B marker: T.
+            vec![(0, 1.0), (2, 10.0), (3, 10.0), (4, 79.0)],
+            // One B marker at full strength, scoring 0.5: B.
+            vec![(1, 10.0), (4, 90.0)],
+            // Only a trace of a T marker, scoring below MIN_SCORE: unassigned.
+            vec![(2, 1.0), (4, 999.0)],
+            // No gene expression at all: unassigned.
+            vec![],
+        ];
+        let assigned = assign_types(&rows, &types);
+        assert_eq!(assigned, vec!["B", "T", "B", "unassigned", "unassigned"]);
+    }
+}
@@ -0,0 +1,223 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Demultiplex cells using hashtag or cell multiplexing (CellPlex) oligo counts, as requested by
+// HTO_DEMUX.
+//
+// The tags are the features named by HTO, or by default, all features of type
+// "Multiplexing Capture".  An entry in HTO may have the form feature:origin, in which case cells
+// assigned to the tag are assigned to that origin (sample), and otherwise the tag name is used as
+// the origin name.
+//
+// Separately for each dataset and tag, counts are normalized across cells using the centered log
+// ratio transformation, and then a two-component Gaussian mixture model is fit to the normalized
+// values.  A cell is positive for the tag if it is more likely to be in the upper component.  A
+// cell that is positive for exactly one tag is assigned to that tag, and to the corresponding
+// origin, unless an origin has already been assigned to it by META or BC.  A cell that is
+// positive for more than one tag is called a doublet, and removed by the doublet filter (unless
+// NDOUBLET is specified).  A cell that is positive for no tag is left unassigned.
+
+use crate::ambient::feature_columns;
+use enclone_core::defs::{EncloneControl, GexInfo};
+use std::collections::HashSet;
+use string_utils::TextUtils;
+use vector_utils::unique_sort;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Centered log ratio transformation of the counts for one tag across cells.
+
+fn clr(x: &Vec<f64>) -> Vec<f64> {
+    let mut s = 0.0;
+    for v in x.iter() {
+        if *v > 0.0 {
+            s += v.ln_1p();
+        }
+    }
+    let g = (s / x.len() as f64).exp();
+    x.iter().map(|v| (v / g).ln_1p()).collect()
+}
+
+// Fit a two-component Gaussian mixture model to a vector of values by expectation maximization,
+// and determine which values are more likely to come from the upper component.
+
+fn upper_component(x: &Vec<f64>) -> Vec<bool> {
+    let n = x.len();
+    if n < 2 {
+        return vec![false; n];
+    }
+    let mut sorted = x.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let quantile = |q: f64| sorted[((n - 1) as f64 * q).round() as usize];
+    let (mut mu0, mut mu1) = (quantile(0.5), quantile(0.99));
+    if mu1 <= mu0 {
+        return vec![false; n];
+    }
+    const MIN_SD: f64 = 1.0e-3;
+    let (mut sd0, mut sd1) = ((mu1 - mu0) / 2.0, (mu1 - mu0) / 2.0);
+    let mut w = 0.1;
+    let density = |v: f64, mu: f64, sd: f64| (-0.5 * ((v - mu) / sd).powi(2)).exp() / sd;
+    let mut r = vec![0.0; n];
+    for _ in 0..100 {
+        for i in 0..n {
+            let p1 = w * density(x[i], mu1, sd1);
+            let p0 = (1.0 - w) * density(x[i], mu0, sd0);
+            r[i] = if p0 + p1 > 0.0 {
+                p1 / (p0 + p1)
+            } else if x[i] > mu1 {
+                1.0
+            } else {
+                0.0
+            };
+        }
+        let s1 = r.iter().sum::<f64>();
+        let s0 = n as f64 - s1;
+        if s1 == 0.0 || s0 == 0.0 {
+            break;
+        }
+        w = s1 / n as f64;
+        mu1 = (0..n).map(|i| r[i] * x[i]).sum::<f64>() / s1;
+        mu0 = (0..n).map(|i| (1.0 - r[i]) * x[i]).sum::<f64>() / s0;
+        sd1 = ((0..n).map(|i| r[i] * (x[i] - mu1).powi(2)).sum::<f64>() / s1)
+            .sqrt()
+            .max(MIN_SD);
+        sd0 = ((0..n)
+            .map(|i| (1.0 - r[i]) * (x[i] - mu0).powi(2))
+            .sum::<f64>()
+            / s0)
+            .sqrt()
+            .max(MIN_SD);
+    }
+    if mu1 <= mu0 {
+        return vec![false; n];
+    }
+    (0..n).map(|i| r[i] > 0.5 && x[i] > mu0).collect()
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Assign cells to tags and origins, and find doublets.
+
+pub fn hto_demux(ctl: &mut EncloneControl, gex_info: &GexInfo) -> Result<(), String> {
+    let n = ctl.origin_info.n();
+    ctl.origin_info.tag_doublet = vec![HashSet::<String>::new(); n];
+    let mut found = false;
+    for li in 0..n {
+        let features = &gex_info.gex_features[li];
+        if features.is_empty() {
+            continue;
+        }
+
+        // Find the tags.
+
+        let mut tags = Vec::<(usize, String, String)>::new();
+        if ctl.gen_opt.hto.is_empty() {
+            for (f, feature) in features.iter().enumerate() {
+                if feature.after("\t").after("\t") == "Multiplexing Capture" {
+                    let name = feature.between("\t", "\t").to_string();
+                    tags.push((f, name.clone(), name));
+                }
+            }
+        } else {
+            for x in ctl.gen_opt.hto.iter() {
+                let (name, origin) = if x.contains(':') {
+                    (x.before(":"), x.after(":"))
+                } else {
+                    (x.as_str(), x.as_str())
+                };
+                let f = features
+                    .iter()
+                    .position(|f| f.before("\t") == name || f.between("\t", "\t") == name);
+                if f.is_none() {
+                    return Err(format!(
+                        "\nThe feature {} in your HTO argument is not present in dataset {}.\n",
+                        name, ctl.origin_info.dataset_id[li]
+                    ));
+                }
+                tags.push((f.unwrap(), name.to_string(), origin.to_string()));
+            }
+        }
+        if tags.is_empty() {
+            continue;
+        }
+        found = true;
+
+        // Get counts.
+
+        let ncells = gex_info.gex_barcodes[li].len();
+        let ids = tags.iter().map(|t| t.0).collect::<Vec<usize>>();
+        let counts = feature_columns(gex_info, li, &ids);
+
+        // Classify cells.
+
+        let positive = counts
+            .iter()
+            .map(|x| upper_component(&clr(x)))
+            .collect::<Vec<_>>();
+        for p in 0..ncells {
+            let calls = (0..tags.len())
+                .filter(|&k| positive[k][p])
+                .collect::<Vec<usize>>();
+            let bc = &gex_info.gex_barcodes[li][p];
+            if calls.len() == 1 {
+                let tag = &tags[calls[0]];
+                ctl.origin_info.tag[li].insert(bc.clone(), tag.1.clone());
+                if !ctl.origin_info.origin_for_bc[li].contains_key(bc) {
+                    ctl.origin_info.origin_for_bc[li].insert(bc.clone(), tag.2.clone());
+                }
+            } else if calls.len() > 1 {
+                ctl.origin_info.tag_doublet[li].insert(bc.clone());
+            }
+        }
+    }
+    if !found {
+        return Err(
+            "\nHTO_DEMUX was specified, but no tag features were found.  You may need to \
+             specify them using HTO.\n"
+                .to_string(),
+        );
+    }
+
+    // Update the lists of origins and tags.
+
+    for li in 0..n {
+        for x in ctl.origin_info.origin_for_bc[li].values() {
+            ctl.origin_info.origin_list.push(x.clone());
+        }
+        for x in ctl.origin_info.tag[li].values() {
+            ctl.origin_info.tag_list.push(x.clone());
+        }
+    }
+    unique_sort(&mut ctl.origin_info.origin_list);
+    unique_sort(&mut ctl.origin_info.tag_list);
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clr() {
+        // The mean of ln(1 + x) is ln(8) / 3, so the counts are divided by 2.
+
+        let y = clr(&vec![0.0, 1.0, 3.0]);
+        let expected = [0.0, 1.5_f64.ln(), 2.5_f64.ln()];
+        for i in 0..3 {
+            assert!((y[i] - expected[i]).abs() < 1.0e-12);
+        }
+        assert_eq!(clr(&vec![0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_upper_component() {
+        let mut x = (0..95).map(|i| (i % 5) as f64 * 0.1).collect::<Vec<f64>>();
+        x.append(&mut vec![10.0, 10.2, 10.4, 9.8, 9.6]);
+        let y = upper_component(&x);
+        assert_eq!(y.iter().filter(|&&b| b).count(), 5);
+        assert!(y[95..].iter().all(|&b| b));
+        assert_eq!(upper_component(&vec![1.0; 10]), vec![false; 10]);
+        assert_eq!(upper_component(&vec![5.0]), vec![false]);
+    }
+}
@@ -2,7 +2,12 @@
 
 use io_utils::*;
 
+pub mod ambient;
+pub mod antigen_score;
+pub mod celltype_auto;
+pub mod hto_demux;
 pub mod load_gex;
+pub mod load_gex_alt;
 pub mod load_gex_core;
 pub mod load_gex_util;
 pub mod proc_args;
@@ -12,7 +17,10 @@ pub mod proc_args_check;
 pub mod proc_args_post;
 pub mod process_special_arg1;
 pub mod process_special_arg2;
+pub mod read_airr;
 pub mod read_json;
+pub mod read_obs;
+pub mod read_proto_in;
 
 // parse_csv_pure: same as parse_csv, but don't strip out quotes
 
@@ -3,6 +3,10 @@
 // Load gene expression and feature barcoding (antibody, antigen) data from
 // Cell Ranger outputs.
 
+use crate::ambient::ambient_correct;
+use crate::antigen_score::antigen_scores;
+use crate::celltype_auto::celltype_auto;
+use crate::hto_demux::hto_demux;
 use crate::load_gex_core::load_gex;
 use enclone_core::defs::{EncloneControl, GexInfo};
 
@@ -105,7 +109,9 @@ pub fn get_gex_info(ctl: &mut EncloneControl) -> Result<GexInfo, String> {
         let gex_outs = &ctl.origin_info.gex_path;
         for i in 0..ctl.origin_info.dataset_path.len() {
             // let bin_file = format!("{}/feature_barcode_matrix.bin", gex_outs[i]);
-            if !gex_outs[i].is_empty()
+            // The h5 path is empty for MEX and h5ad inputs, whose matrices are always loaded.
+
+            if !gex_outs[i].is_empty() && !h5_paths[i].is_empty()
             /* && !(path_exists(&bin_file) && !ctl.gen_opt.force_h5) */
             {
                 let f = &h5_paths[i];
@@ -166,7 +172,7 @@ pub fn get_gex_info(ctl: &mut EncloneControl) -> Result<GexInfo, String> {
 
     // Answer.
 
-    Ok(GexInfo {
+    let gex_info = GexInfo {
         gex_features,
         gex_barcodes,
         gex_matrices,
@@ -197,5 +203,18 @@ pub fn get_gex_info(ctl: &mut EncloneControl) -> Result<GexInfo, String> {
         feature_metrics,
         json_metrics,
         metrics,
-    })
+    };
+    if ctl.gen_opt.ambient {
+        ambient_correct(ctl, &gex_info)?;
+    }
+    if ctl.gen_opt.antigen_score {
+        antigen_scores(ctl, &gex_info)?;
+    }
+    if ctl.gen_opt.celltype_auto {
+        celltype_auto(ctl, &gex_info)?;
+    }
+    if ctl.gen_opt.hto_demux {
+        hto_demux(ctl, &gex_info)?;
+    }
+    Ok(gex_info)
 }
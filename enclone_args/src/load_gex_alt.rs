@@ -0,0 +1,591 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Load gene expression data from inputs other than Cell Ranger outs directories.  Two formats are
+// supported, both of which are typically produced by reprocessing data with another pipeline:
+//
+// 1. A filtered Matrix Market (MEX) directory, containing matrix.mtx, features.tsv (or the older
+//    genes.tsv) and barcodes.tsv, each of which may be gzipped.  All the barcodes in it are
+//    taken to be cells.  A Cell Ranger outs directory containing only filtered_feature_bc_matrix
+//    is also accepted.
+//
+// 2. An AnnData h5ad file.  Every barcode in obs is taken to be a cell.  Counts are taken from
+//    layers/counts if present, else raw/X, else X, and are rounded to integers, so X should
+//    only be used if it has not been normalized.  Clusters are taken from the first of the obs
+//    columns leiden, louvain, cluster, clusters, seurat_clusters that is present, cell types
+//    from cell_type, celltype or cell_types, and PCA coordinates from obsm/X_pca.  Only the
+//    group-based layout of AnnData 0.7 and later is supported.
+//
+// The output is in the form returned by slurp_h5, plus what would otherwise be read from the
+// Cell Ranger analysis directory.  Barcodes are returned in sorted order.
+
+#[cfg(target_os = "windows")]
+use hdf5::{
+    types::{FixedAscii, FixedUnicode, FloatSize, TypeDescriptor, VarLenAscii, VarLenUnicode},
+    Dataset, File, Group,
+};
+#[cfg(not(target_os = "windows"))]
+use hdf5x::{
+    types::{FixedAscii, FixedUnicode, FloatSize, TypeDescriptor, VarLenAscii, VarLenUnicode},
+    Dataset, File, Group,
+};
+
+use io_utils::{open_maybe_compressed, path_exists};
+use ndarray::s;
+use std::cmp::min;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use vector_utils::sort_sync2;
+
+const DENSE_BATCH: usize = 10_000; // number of rows of a dense matrix to read at once
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Test if a GEX path is an h5ad file.
+
+pub fn is_h5ad(path: &str) -> bool {
+    path.ends_with(".h5ad") && path_exists(path)
+}
+
+// Find a file in a MEX directory, allowing for compression.
+
+fn mex_file(dir: &str, names: &[&str]) -> Option<String> {
+    for name in names.iter() {
+        for suffix in ["", ".gz"].iter() {
+            let f = format!("{}/{}{}", dir, name, suffix);
+            if path_exists(&f) {
+                return Some(f);
+            }
+        }
+    }
+    None
+}
+
+// If a GEX path is a MEX directory, or an outs directory containing only a MEX directory,
+// return the MEX directory.  A Cell Ranger outs directory having an h5 file is not treated as
+// a MEX directory.
+
+pub fn mex_dir(path: &str) -> Option<String> {
+    if path.ends_with(".h5ad") {
+        return None;
+    }
+    let h5 = [
+        "raw_feature_bc_matrix.h5",
+        "raw_gene_bc_matrices_h5.h5",
+        "multi/count/raw_feature_bc_matrix.h5",
+    ];
+    for x in h5.iter() {
+        if path_exists(&format!("{}/{}", path, x)) {
+            return None;
+        }
+    }
+    for dir in [
+        path.to_string(),
+        format!("{}/filtered_feature_bc_matrix", path),
+    ]
+    .iter()
+    {
+        if mex_file(dir, &["matrix.mtx"]).is_some()
+            && mex_file(dir, &["features.tsv", "genes.tsv"]).is_some()
+            && mex_file(dir, &["barcodes.tsv"]).is_some()
+        {
+            return Some(dir.clone());
+        }
+    }
+    None
+}
+
+// Read the lines of a possibly compressed file.
+
+fn read_lines(f: &str) -> Result<Vec<String>, String> {
+    let mut lines = Vec::<String>::new();
+    for line in BufReader::new(open_maybe_compressed(f)).lines() {
+        if line.is_err() {
+            return Err(format!("\nProblem reading {}.\n", f));
+        }
+        lines.push(line.unwrap());
+    }
+    Ok(lines)
+}
+
+// Sort matrix rows by barcode, and check that barcodes are unique.
+
+fn sort_by_barcode(
+    barcodes: &mut Vec<String>,
+    matrix: &mut Vec<Vec<(i32, i32)>>,
+    path: &str,
+) -> Result<(), String> {
+    sort_sync2(barcodes, matrix);
+    for i in 1..barcodes.len() {
+        if barcodes[i] == barcodes[i - 1] {
+            return Err(format!(
+                "\nThe barcode {} appears more than once in\n{}\n",
+                barcodes[i], path
+            ));
+        }
+    }
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Load a MEX directory.  Features are given as "id\tname\ttype", as for an h5 file, and those
+// lacking a type are assumed to be gene expression.  The matrix is returned by barcode.
+
+pub fn load_mex(
+    dir: &str,
+    barcodes: &mut Vec<String>,
+    features: &mut Vec<String>,
+    matrix: &mut Vec<Vec<(i32, i32)>>,
+) -> Result<(), String> {
+    let matrix_file = mex_file(dir, &["matrix.mtx"]).unwrap();
+    let features_file = mex_file(dir, &["features.tsv", "genes.tsv"]).unwrap();
+    let barcodes_file = mex_file(dir, &["barcodes.tsv"]).unwrap();
+
+    // Read features and barcodes.
+
+    for s in read_lines(&features_file)?.iter() {
+        if s.is_empty() {
+            continue;
+        }
+        let fields = s.split('\t').collect::<Vec<&str>>();
+        let name = if fields.len() >= 2 {
+            fields[1]
+        } else {
+            fields[0]
+        };
+        let ftype = if fields.len() >= 3 {
+            fields[2]
+        } else {
+            "Gene Expression"
+        };
+        features.push(format!("{}\t{}\t{}", fields[0], name, ftype));
+    }
+    for s in read_lines(&barcodes_file)?.iter() {
+        if !s.is_empty() {
+            barcodes.push(s.clone());
+        }
+    }
+
+    // Read the matrix, whose rows are features and columns are barcodes, both numbered
+    // starting at one.  The file is read a line at a time, as it may be large.
+
+    matrix.resize(barcodes.len(), Vec::new());
+    let mut have_dims = false;
+    for s in BufReader::new(open_maybe_compressed(&matrix_file)).lines() {
+        let s = s.map_err(|_| format!("\nProblem reading {}.\n", matrix_file))?;
+        if s.starts_with('%') || s.is_empty() {
+            continue;
+        }
+        let fields = s.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() < 3 {
+            return Err(format!("\nIllegal line\n{}\nin {}.\n", s, matrix_file));
+        }
+        if !have_dims {
+            have_dims = true;
+            let (nf, nb) = (fields[0].parse::<usize>(), fields[1].parse::<usize>());
+            if nf != Ok(features.len()) || nb != Ok(barcodes.len()) {
+                return Err(format!(
+                    "\nThe dimensions {} x {} of the matrix in\n{}\ndo not match the {} \
+                    features and {} barcodes in that directory.\n",
+                    fields[0],
+                    fields[1],
+                    dir,
+                    features.len(),
+                    barcodes.len()
+                ));
+            }
+            continue;
+        }
+        let (f, b, n) = (
+            fields[0].parse::<usize>(),
+            fields[1].parse::<usize>(),
+            fields[2].parse::<f64>(),
+        );
+        if f.is_err() || b.is_err() || n.is_err() {
+            return Err(format!("\nIllegal line\n{}\nin {}.\n", s, matrix_file));
+        }
+        let (f, b, n) = (f.unwrap(), b.unwrap(), n.unwrap().round() as i32);
+        if f == 0 || f > features.len() || b == 0 || b > barcodes.len() {
+            return Err(format!(
+                "\nOut of range entry\n{}\nin {}.\n",
+                s, matrix_file
+            ));
+        }
+        if n != 0 {
+            matrix[b - 1].push((f as i32 - 1, n));
+        }
+    }
+    for i in 0..matrix.len() {
+        matrix[i].sort_unstable();
+    }
+    sort_by_barcode(barcodes, matrix, dir)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Test if a group has a member.
+
+pub fn has(x: &Group, name: &str) -> bool {
+    x.dataset(name).is_ok() || x.group(name).is_ok()
+}
+
+// Read a string attribute, if present.
+
+pub fn string_attr(x: &Group, name: &str) -> Option<String> {
+    let a = x.attr(name).ok()?;
+    if let Ok(s) = a.as_reader().read_scalar::<VarLenUnicode>() {
+        return Some(s.to_string());
+    }
+    if let Ok(s) = a.as_reader().read_scalar::<VarLenAscii>() {
+        return Some(s.to_string());
+    }
+    None
+}
+
+// Read a one-dimensional dataset of strings, numbers or booleans, as strings.  Missing
+// floating point values are returned as empty strings.
+
+pub fn read_strings(x: &Dataset) -> Result<Vec<String>, String> {
+    fn strings<T: ToString, E>(v: Result<Vec<T>, E>) -> Option<Vec<String>> {
+        v.ok().map(|v| v.iter().map(|s| s.to_string()).collect())
+    }
+    fn floats<T: Into<f64> + Copy + ToString, E>(v: Result<Vec<T>, E>) -> Option<Vec<String>> {
+        v.ok().map(|v| {
+            v.iter()
+                .map(|&s| {
+                    if s.into().is_nan() {
+                        String::new()
+                    } else {
+                        s.to_string()
+                    }
+                })
+                .collect()
+        })
+    }
+    let r = x.as_reader();
+    let v = match x.dtype().and_then(|t| t.to_descriptor()) {
+        Ok(TypeDescriptor::VarLenUnicode) => strings(r.read_raw::<VarLenUnicode>()),
+        Ok(TypeDescriptor::VarLenAscii) => strings(r.read_raw::<VarLenAscii>()),
+        Ok(TypeDescriptor::FixedAscii(_)) => strings(r.read_raw::<FixedAscii<256>>()),
+        Ok(TypeDescriptor::FixedUnicode(_)) => strings(r.read_raw::<FixedUnicode<256>>()),
+        Ok(TypeDescriptor::Float(FloatSize::U4)) => floats(r.read_raw::<f32>()),
+        Ok(TypeDescriptor::Float(_)) => floats(r.read_raw::<f64>()),
+        Ok(TypeDescriptor::Integer(_)) => strings(r.read_raw::<i64>()),
+        Ok(TypeDescriptor::Unsigned(_)) => strings(r.read_raw::<u64>()),
+        Ok(TypeDescriptor::Boolean) => strings(r.read_raw::<bool>()),
+        _ => None,
+    };
+    v.ok_or_else(|| format!("\nUnable to read {} as strings.\n", x.name()))
+}
+
+// Read a dense two-dimensional numeric dataset, returning its rows.
+
+pub fn read_rows(x: &Dataset) -> Result<Vec<Vec<f64>>, String> {
+    let shape = x.shape();
+    let v = x.as_reader().read_raw::<f64>();
+    if shape.len() != 2 || v.is_err() {
+        return Err(format!("\nUnable to read {} as a matrix.\n", x.name()));
+    }
+    let v = v.unwrap();
+    Ok(v.chunks(shape[1].max(1)).map(|r| r.to_vec()).collect())
+}
+
+// Read a column of a dataframe (obs or var).  This may be a categorical, stored as a group
+// having codes and categories, a nullable integer, boolean or string array, stored by AnnData
+// 0.10 and later as a group having values and mask, where mask is true for missing values, or a
+// plain array.  Missing values are returned as None.
+
+pub fn read_column(df: &Group, col: &str) -> Result<Vec<Option<String>>, String> {
+    if let Ok(g) = df.group(col) {
+        if let (Ok(values), Ok(mask)) = (g.dataset("values"), g.dataset("mask")) {
+            let values = read_strings(&values)?;
+            let mask = mask.as_reader().read_raw::<bool>();
+            if mask.is_err() || mask.as_ref().unwrap().len() != values.len() {
+                return Err(format!("\nUnable to read mask for {}.\n", col));
+            }
+            return Ok(values
+                .into_iter()
+                .zip(mask.unwrap())
+                .map(|(v, m)| if m || v.is_empty() { None } else { Some(v) })
+                .collect());
+        }
+        let codes = g.dataset("codes").map_err(|e| format!("\n{}\n", e))?;
+        let codes = codes.as_reader().read_raw::<i64>();
+        let cats = g.dataset("categories").map_err(|e| format!("\n{}\n", e))?;
+        let cats = read_strings(&cats)?;
+        if codes.is_err() {
+            return Err(format!("\nUnable to read codes for {}.\n", col));
+        }
+        return Ok(codes
+            .unwrap()
+            .iter()
+            .map(|&c| {
+                if c >= 0 && (c as usize) < cats.len() {
+                    Some(cats[c as usize].clone())
+                } else {
+                    None
+                }
+            })
+            .collect());
+    }
+    let x = df.dataset(col).map_err(|e| format!("\n{}\n", e))?;
+    Ok(read_strings(&x)?
+        .into_iter()
+        .map(|s| if s.is_empty() { None } else { Some(s) })
+        .collect())
+}
+
+// Read the index of a dataframe.
+
+pub fn read_index(df: &Group) -> Result<Vec<String>, String> {
+    let name = string_attr(df, "_index").unwrap_or_else(|| "_index".to_string());
+    let x = df.dataset(&name).map_err(|_| {
+        format!(
+            "\nUnable to find the index of {} in h5ad file.  Perhaps it was written by \
+            a version of AnnData older than 0.7.\n",
+            df.name()
+        )
+    })?;
+    read_strings(&x)
+}
+
+// Load an h5ad file.
+
+pub fn load_h5ad(
+    path: &str,
+    barcodes: &mut Vec<String>,
+    features: &mut Vec<String>,
+    matrix: &mut Vec<Vec<(i32, i32)>>,
+    cluster: &mut HashMap<String, usize>,
+    cell_type: &mut HashMap<String, String>,
+    pca: &mut HashMap<String, Vec<f64>>,
+) -> Result<(), String> {
+    let h = File::open(&path);
+    if h.is_err() {
+        return Err(format!(
+            "\nencountered error reading h5ad file\n{}\nas follows\n{}\n",
+            path,
+            h.err().unwrap()
+        ));
+    }
+    let h = h.unwrap();
+    let err = |e: String| format!("\nProblem reading h5ad file\n{}:{}", path, e);
+    let obs = h.group("obs").map_err(|e| err(format!("\n{}\n", e)))?;
+    *barcodes = read_index(&obs).map_err(err)?;
+    let nobs = barcodes.len();
+
+    // Find the counts and the corresponding features.
+
+    let mut found = None;
+    for (x, var) in [("layers/counts", "var"), ("raw/X", "raw/var"), ("X", "var")].iter() {
+        if has(&h, x) {
+            found = Some((x.to_string(), var.to_string()));
+            break;
+        }
+    }
+    if found.is_none() {
+        return Err(err("\nno count matrix found.\n".to_string()));
+    }
+    let (xname, varname) = found.unwrap();
+    let var = h.group(&varname).map_err(|e| err(format!("\n{}\n", e)))?;
+    let names = read_index(&var).map_err(err)?;
+    let nvar = names.len();
+    let ids = if has(&var, "gene_ids") {
+        read_column(&var, "gene_ids").map_err(err)?
+    } else {
+        names.iter().map(|x| Some(x.clone())).collect()
+    };
+    let types = if has(&var, "feature_types") {
+        read_column(&var, "feature_types").map_err(err)?
+    } else {
+        vec![None; nvar]
+    };
+    for j in 0..nvar {
+        features.push(format!(
+            "{}\t{}\t{}",
+            ids[j].as_ref().unwrap_or(&names[j]),
+            names[j],
+            types[j].as_deref().unwrap_or("Gene Expression")
+        ));
+    }
+
+    // Read the counts, which may be a dense matrix or a sparse matrix in CSR or CSC form.
+
+    matrix.resize(nobs, Vec::new());
+    if let Ok(g) = h.group(&xname) {
+        let read = |name: &str| g.dataset(name).map_err(|e| err(format!("\n{}\n", e)));
+        let data = read("data")?.as_reader().read_raw::<f64>();
+        let indices = read("indices")?.as_reader().read_raw::<i64>();
+        let indptr = read("indptr")?.as_reader().read_raw::<i64>();
+        if data.is_err() || indices.is_err() || indptr.is_err() {
+            return Err(err(format!("\nunable to read sparse matrix {}.\n", xname)));
+        }
+        let (data, indices, indptr) = (data.unwrap(), indices.unwrap(), indptr.unwrap());
+        let format = string_attr(&g, "encoding-type")
+            .or_else(|| string_attr(&g, "h5sparse_format"))
+            .unwrap_or_default();
+        let csc = format.starts_with("csc") || (format.is_empty() && indptr.len() != nobs + 1);
+        let (nouter, ninner) = if csc { (nvar, nobs) } else { (nobs, nvar) };
+        if indptr.len() != nouter + 1 {
+            return Err(err(format!(
+                "\nthe sparse matrix {} does not have dimensions {} x {}.\n",
+                xname, nobs, nvar
+            )));
+        }
+        let nnz = min(data.len(), indices.len()) as i64;
+        if indptr[0] < 0 || indptr[nouter] > nnz || indptr.windows(2).any(|w| w[0] > w[1]) {
+            return Err(err(format!(
+                "\nthe indptr array of the sparse matrix {} is not increasing, or is \
+                 inconsistent with its data and indices.\n",
+                xname
+            )));
+        }
+        for i in 0..nouter {
+            for k in indptr[i] as usize..indptr[i + 1] as usize {
+                let j = indices[k] as usize;
+                let n = data[k].round() as i32;
+                if j >= ninner {
+                    return Err(err(format!("\nout of range index in {}.\n", xname)));
+                }
+                if n != 0 {
+                    if csc {
+                        matrix[j].push((i as i32, n));
+                    } else {
+                        matrix[i].push((j as i32, n));
+                    }
+                }
+            }
+        }
+    } else {
+        // A dense matrix is read in batches of rows, as it may be large.
+
+        let x = h.dataset(&xname).map_err(|e| err(format!("\n{}\n", e)))?;
+        if x.shape() != [nobs, nvar] {
+            return Err(err(format!(
+                "\nthe matrix {} does not have dimensions {} x {}.\n",
+                xname, nobs, nvar
+            )));
+        }
+        for start in (0..nobs).step_by(DENSE_BATCH) {
+            let stop = min(start + DENSE_BATCH, nobs);
+            let rows = x
+                .as_reader()
+                .read_slice_2d::<f64, _>(s![start..stop, ..])
+                .map_err(|_| err(format!("\nunable to read the matrix {}.\n", xname)))?;
+            for (i, row) in rows.outer_iter().enumerate() {
+                for (j, v) in row.iter().enumerate() {
+                    let n = v.round() as i32;
+                    if n != 0 {
+                        matrix[start + i].push((j as i32, n));
+                    }
+                }
+            }
+        }
+    }
+    for i in 0..matrix.len() {
+        matrix[i].sort_unstable();
+    }
+
+    // Read clusters.  If all cluster names are integers, they are used as cluster ids, and
+    // otherwise clusters are numbered starting at one, in the order of their names.
+
+    for col in [
+        "leiden",
+        "louvain",
+        "cluster",
+        "clusters",
+        "seurat_clusters",
+    ]
+    .iter()
+    {
+        if has(&obs, col) {
+            let c = read_column(&obs, col).map_err(err)?;
+            let mut cats = c.iter().flatten().cloned().collect::<Vec<_>>();
+            cats.sort();
+            cats.dedup();
+            let numeric = cats.iter().all(|x| x.parse::<usize>().is_ok());
+            for i in 0..nobs.min(c.len()) {
+                if let Some(x) = &c[i] {
+                    let id = if numeric {
+                        x.parse::<usize>().unwrap()
+                    } else {
+                        cats.binary_search(x).unwrap() + 1
+                    };
+                    cluster.insert(barcodes[i].clone(), id);
+                }
+            }
+            break;
+        }
+    }
+
+    // Read cell types.
+
+    for col in ["cell_type", "celltype", "cell_types"].iter() {
+        if has(&obs, col) {
+            let c = read_column(&obs, col).map_err(err)?;
+            for i in 0..nobs.min(c.len()) {
+                if let Some(x) = &c[i] {
+                    cell_type.insert(barcodes[i].clone(), x.clone());
+                }
+            }
+            break;
+        }
+    }
+
+    // Read PCA coordinates.
+
+    if let Ok(x) = h.dataset("obsm/X_pca") {
+        let rows = read_rows(&x).map_err(err)?;
+        for i in 0..nobs.min(rows.len()) {
+            pca.insert(barcodes[i].clone(), rows[i].clone());
+        }
+    }
+    sort_by_barcode(barcodes, matrix, path)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+
+    #[test]
+    fn test_load_mex() {
+        let dir = std::env::temp_dir().join(format!("enclone_test_mex_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap().to_string();
+        write(
+            format!("{}/features.tsv", dir),
+            "ENSG1\tCD3E\tGene Expression\nENSG2\tCD19\n",
+        )
+        .unwrap();
+        write(format!("{}/barcodes.tsv", dir), "CCC-1\nAAA-1\n").unwrap();
+        let header = "%%MatrixMarket matrix coordinate integer general\n%\n";
+        write(
+            format!("{}/matrix.mtx", dir),
+            format!("{}2 2 3\n2 1 4\n1 1 2\n1 2 7\n", header),
+        )
+        .unwrap();
+        assert_eq!(mex_dir(&dir), Some(dir.clone()));
+        let (mut barcodes, mut features, mut matrix) = (Vec::new(), Vec::new(), Vec::new());
+        let res = load_mex(&dir, &mut barcodes, &mut features, &mut matrix);
+
+        // A matrix whose dimensions do not match the barcodes is rejected.
+
+        write(format!("{}/matrix.mtx", dir), format!("{}2 3 0\n", header)).unwrap();
+        let bad = load_mex(&dir, &mut Vec::new(), &mut Vec::new(), &mut Vec::new());
+        remove_dir_all(&dir).unwrap();
+        assert!(res.is_ok());
+        assert_eq!(barcodes, ["AAA-1", "CCC-1"]);
+        assert_eq!(
+            features,
+            [
+                "ENSG1\tCD3E\tGene Expression",
+                "ENSG2\tCD19\tGene Expression"
+            ]
+        );
+        assert_eq!(matrix, vec![vec![(0, 7)], vec![(0, 2), (1, 4)]]);
+        assert!(bad.is_err());
+    }
+}
@@ -2,6 +2,7 @@
 //
 // Load gene expression and feature barcoding (antibody, antigen) data from Cell Ranger outputs.
 
+use crate::load_gex_alt::{is_h5ad, load_h5ad, load_mex, mex_dir};
 use crate::load_gex_util::*;
 use crate::*;
 use enclone_core::defs::EncloneControl;
@@ -132,6 +133,44 @@ pub fn load_gex(
             // exists.
 
             let root = gex_outs[i].clone();
+
+            // Handle a MEX directory or h5ad file.  These provide the matrix, and in the h5ad
+            // case, possibly clusters, cell types and PCA, but nothing else.
+
+            let mex = mex_dir(&root);
+            if mex.is_some() || is_h5ad(&root) {
+                let mut matrix = Vec::<Vec<(i32, i32)>>::new();
+                let s = if let Some(dir) = mex {
+                    pathlist.push(dir.clone());
+                    load_mex(&dir, &mut r.2, &mut r.1, &mut matrix)
+                } else {
+                    pathlist.push(root.clone());
+                    load_h5ad(
+                        &root,
+                        &mut r.2,
+                        &mut r.1,
+                        &mut matrix,
+                        &mut r.7,
+                        &mut r.8,
+                        &mut r.9,
+                    )
+                };
+                if s.is_err() {
+                    r.11 = s.err().unwrap();
+                    return;
+                }
+                r.3 = MirrorSparseMatrix::build_from_vec(&matrix, &r.2, &r.1);
+                r.6 = r.2.clone();
+                r.10 = !r.8.is_empty();
+                r.4 = Some(1.0);
+                if r.1
+                    .iter()
+                    .any(|f| f.after("\t").after("\t") == "Antibody Capture")
+                {
+                    r.5 = Some(1.0);
+                }
+                return;
+            }
             let mut outs = root.clone();
             if root.ends_with("/outs") && path_exists(&root) {
                 outs = root;
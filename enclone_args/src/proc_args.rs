@@ -6,9 +6,10 @@ use crate::proc_args2::{
 use crate::proc_args_post::proc_args_post;
 use crate::process_special_arg1::process_special_arg1;
 use crate::process_special_arg2::process_special_arg2;
+use crate::read_proto_in::proto_in_chain_type;
 use enclone_core::defs::{ClonotypeHeuristics, EncloneControl};
 use enclone_core::test_def::replace_at_test;
-use enclone_core::{require_readable_file, tilde_expand_me};
+use enclone_core::{airr_clone_filename, require_readable_file, tilde_expand_me};
 use itertools::Itertools;
 use std::{process::Command, time::Instant};
 use string_utils::{strme, TextUtils};
@@ -48,6 +49,11 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
         } else if args[i].starts_with("TCR_GEX=") {
             args2.push(format!("TCR={}", args[i].after("TCR_GEX=")));
             args2.push(format!("GEX={}", args[i].after("TCR_GEX=")));
+        } else if args[i].starts_with("PROTO_IN=") {
+            let mut path = args[i].after("PROTO_IN=").to_string();
+            tilde_expand_me(&mut path);
+            args2.push(format!("{}={}", proto_in_chain_type(&path)?, path));
+            args2.push(format!("PROTO_IN={}", path));
         } else if args[i].starts_with("GD_BC=") {
             args2.push(format!(
                 "BC={}/outs/genetic_demux_results/clusters.tsv",
@@ -115,6 +121,8 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
     ctl.gen_opt.jscore_bits_multiplier = 2.2;
     ctl.gen_opt.max_heavies = 1000000;
     ctl.gen_opt.subsample = -1.0;
+    ctl.gen_opt.antigen_call = 3.0;
+    ctl.gen_opt.ambient_frac = -1.0;
 
     // Set up clonotyping control parameters.
 
@@ -161,6 +169,9 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
     ctl.join_alg_opt.auto_share = 15;
     ctl.join_alg_opt.comp_filt = 8;
     ctl.join_alg_opt.comp_filt_bound = 80;
+    ctl.join_alg_opt.scorer = "default".to_string();
+    ctl.join_alg_opt.hamming = 0.15;
+    ctl.join_alg_opt.shm_p = 0.001;
 
     ctl.join_print_opt.pfreq = 1_000_000_000;
     ctl.join_print_opt.quiet = true;
@@ -379,19 +390,23 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
         ("ACCEPT_BROKEN", &mut ctl.gen_opt.accept_broken),
         ("ACCEPT_INCONSISTENT", &mut ctl.gen_opt.accept_inconsistent),
         ("ACCEPT_REUSE", &mut ctl.gen_opt.accept_reuse),
+        ("AIRR_IN", &mut ctl.gen_opt.airr_in),
         (
             "ALIGN_JALIGN_CONSISTENCY",
             &mut ctl.gen_opt.align_jun_align_consistency,
         ),
         ("ALLOW_INCONSISTENT", &mut ctl.gen_opt.allow_inconsistent),
+        ("AMBIENT", &mut ctl.gen_opt.ambient),
         ("ANN", &mut ctl.join_print_opt.ann),
         ("ANN0", &mut ctl.join_print_opt.ann0),
+        ("ANTIGEN_SCORE", &mut ctl.gen_opt.antigen_score),
         ("BARCODES", &mut ctl.clono_print_opt.barcodes),
         ("BASELINE", &mut ctl.gen_opt.baseline),
         ("BASICX", &mut ctl.join_alg_opt.basicx),
         ("BCJOIN", &mut ctl.join_alg_opt.bcjoin),
         ("BUILT_IN", &mut ctl.gen_opt.built_in),
         ("CDIFF", &mut ctl.clono_filt_opt.cdiff),
+        ("CELLTYPE_AUTO", &mut ctl.gen_opt.celltype_auto),
         ("CHAIN_BRIEF", &mut ctl.clono_print_opt.chain_brief),
         ("COMPLETE", &mut ctl.gen_opt.complete),
         ("CON", &mut ctl.allele_print_opt.con),
@@ -423,6 +438,7 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
         ("GROUP_NO_NAIVE", &mut ctl.clono_group_opt.no_naive),
         ("HAVE_ONESIE", &mut ctl.clono_filt_opt.have_onesie),
         ("HEAVY_CHAIN_REUSE", &mut ctl.gen_opt.heavy_chain_reuse),
+        ("HTO_DEMUX", &mut ctl.gen_opt.hto_demux),
         ("IMGT", &mut ctl.gen_opt.imgt),
         ("IMGT_FIX", &mut ctl.gen_opt.imgt_fix),
         ("INDELS", &mut ctl.gen_opt.indels),
@@ -466,6 +482,8 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
         ("PRE_EVAL", &mut ctl.gen_opt.pre_eval),
         ("PRE_EVAL_SHOW", &mut ctl.gen_opt.pre_eval_show),
         ("PROTECT_BADS", &mut ctl.clono_filt_opt.protect_bads),
+        ("PROTO_INDEX", &mut ctl.gen_opt.proto_index),
+        ("PSEUDOBULK_GROUP", &mut ctl.gen_opt.pseudobulk_group),
         ("QUAD_HIVE", &mut ctl.plot_opt.plot_quad),
         ("RE", &mut ctl.gen_opt.reannotate),
         ("REPROD", &mut ctl.gen_opt.reprod),
@@ -473,6 +491,7 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
         ("REUSE", &mut ctl.gen_opt.reuse),
         ("ROW_FILL_VERBOSE", &mut ctl.gen_opt.row_fill_verbose),
         ("SCAN_EXACT", &mut ctl.gen_opt.gene_scan_exact),
+        ("SENS_SPEC", &mut ctl.gen_opt.sens_spec),
         ("SEQC", &mut ctl.clono_print_opt.seqc),
         ("SHOW_BC", &mut ctl.join_print_opt.show_bc),
         ("STABLE_DOC", &mut ctl.gen_opt.stable_doc),
@@ -495,6 +514,9 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
         ("TOP_GENES", &mut ctl.gen_opt.top_genes),
         ("TOY", &mut ctl.gen_opt.toy),
         ("TOY_COM", &mut ctl.gen_opt.toy_com),
+        ("TREE_ML", &mut ctl.gen_opt.tree_ml),
+        ("TREE_PARS", &mut ctl.gen_opt.tree_pars),
+        ("TREE_SVG_MUTS", &mut ctl.plot_opt.tree_svg_muts),
         ("UMI_FILT_MARK", &mut ctl.clono_filt_opt_def.umi_filt_mark),
         (
             "UMI_RATIO_FILT_MARK",
@@ -533,6 +555,7 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
         ("AUTO_SHARE", &mut ctl.join_alg_opt.auto_share),
         ("CDR3_NORMAL_LEN", &mut ctl.join_alg_opt.cdr3_normal_len),
         ("CHAINS_EXACT", &mut ctl.gen_opt.chains_exact),
+        ("DONOR_DEMUX", &mut ctl.gen_opt.donor_demux),
         ("JUN_SHARE", &mut ctl.join_alg_opt.comp_filt),
         ("JUN_SHARE_BOUND", &mut ctl.join_alg_opt.comp_filt_bound),
         ("MAX_CDR3_DIFFS", &mut ctl.join_alg_opt.max_cdr3_diffs),
@@ -573,6 +596,8 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
     // Define arguments that set something to an f64.
 
     let set_f64 = [
+        ("AMBIENT_FRAC", &mut ctl.gen_opt.ambient_frac),
+        ("ANTIGEN_CALL", &mut ctl.gen_opt.antigen_call),
         ("CDR3_MULT", &mut ctl.join_alg_opt.cdr3_mult),
         ("JSCORE_BITS_MULT", &mut ctl.gen_opt.jscore_bits_multiplier),
         ("MULT_POW", &mut ctl.join_alg_opt.mult_pow),
@@ -596,7 +621,11 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
         ("CONFIG", &mut ctl.gen_opt.config_file),
         ("EXT", &mut ctl.gen_opt.ext),
         ("GROUP_CDR3", &mut ctl.clono_group_opt.cdr3),
+        // not tested for writeability, as it may be the same file as JOIN_STATE_IN
+        ("JOIN_STATE_OUT", &mut ctl.gen_opt.join_state_out),
         ("PCHAINS", &mut ctl.parseable_opt.pchains),
+        // not tested for writeability, as it may be a directory
+        ("PSEUDOBULK", &mut ctl.gen_opt.pseudobulk),
         ("SESSION_NAME", &mut ctl.gen_opt.session_name),
         ("TRACE_BARCODE", &mut ctl.gen_opt.trace_barcode),
     ];
@@ -604,9 +633,11 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
     // Define arguments that set something to a string that is an output file name.
 
     let set_string_writeable = [
+        ("AIRR", &mut ctl.gen_opt.airr),
         ("BINARY", &mut ctl.gen_opt.binary),
         ("DONOR_REF_FILE", &mut ctl.gen_opt.dref_file),
         ("HONEY_OUT", &mut ctl.plot_opt.honey_out),
+        ("JOIN_ID_MAP", &mut ctl.gen_opt.join_id_map),
         ("PROTO", &mut ctl.gen_opt.proto),
         ("SUBSET_JSON", &mut ctl.gen_opt.subset_json),
     ];
@@ -614,9 +645,13 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
     // Define arguments that set something to a string that is an output file name or stdout.
 
     let set_string_writeable_or_stdout = [
+        ("DE", &mut ctl.gen_opt.de_filename),
         ("PEER_GROUP", &mut ctl.gen_opt.peer_group_filename),
         ("PHYLIP_AA", &mut ctl.gen_opt.phylip_aa),
         ("PHYLIP_DNA", &mut ctl.gen_opt.phylip_dna),
+        ("TREE_ML_POST", &mut ctl.gen_opt.tree_ml_post),
+        ("TREE_PARS_FASTA", &mut ctl.gen_opt.tree_pars_fasta),
+        ("UCA_FASTA", &mut ctl.gen_opt.uca_fasta),
     ];
 
     // Define arguments that set something to a string that is an input file name, represented
@@ -636,8 +671,12 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
 
     let set_string_readable_plain = [
         ("BC_JOINT", &mut ctl.gen_opt.bc_joint),
+        ("CELLTYPE_MARKERS", &mut ctl.gen_opt.celltype_markers),
         ("EXTERNAL_REF", &mut ctl.gen_opt.external_ref),
+        ("JOIN_STATE_IN", &mut ctl.gen_opt.join_state_in),
+        ("OBS", &mut ctl.gen_opt.obs),
         ("POST_FILTER", &mut ctl.gen_opt.post_filter),
+        ("PROTO_IN", &mut ctl.gen_opt.proto_in),
         ("REF", &mut ctl.gen_opt.refname),
     ];
 
@@ -801,6 +840,9 @@ pub fn proc_args(mut ctl: &mut EncloneControl, args: &Vec<String>) -> Result<(),
                     println!("creating file {} to test writability", val);
                 }
                 test_writeable(&val, evil_eye)?;
+                if *var == "AIRR" {
+                    test_writeable(&airr_clone_filename(val), evil_eye)?;
+                }
                 continue 'args_loop;
             }
         }
@@ -2,11 +2,15 @@
 
 // Check lvars, cvars, and pcols.
 
+use crate::antigen_score::is_antigen_clono_var;
 use enclone_core::allowed_vars::{
     CVARS_ALLOWED, CVARS_ALLOWED_PCELL, GVARS_ALLOWED, LVARS_ALLOWED, PCVARS_ALLOWED,
     PLVARS_ALLOWED,
 };
-use enclone_core::defs::{EncloneControl, GexInfo};
+use enclone_core::cell_color::CellColor;
+use enclone_core::defs::{abbr_var, EncloneControl, GexInfo};
+use enclone_vars::decode_arith;
+use expr_tools::vars_of_node;
 use itertools::Itertools;
 use rayon::prelude::*;
 use regex::Regex;
@@ -86,10 +90,7 @@ pub fn involves_gex_fb(x: &String) -> bool {
             ends.push(format!("{}{}", z, y));
         }
     }
-    let mut x = x.clone();
-    if x.contains(':') {
-        x = x.rev_after(":").to_string();
-    }
+    let mut x = abbr_var(x).1;
     if x.ends_with("_cell") {
         x = x.rev_before("_cell").to_string();
     }
@@ -121,10 +122,7 @@ pub fn is_pattern(x: &String, parseable: bool) -> bool {
             ends.push(format!("{}{}", z, y));
         }
     }
-    let mut x = x.clone();
-    if x.contains(':') {
-        x = x.rev_after(":").to_string();
-    }
+    let mut x = abbr_var(x).1;
     if parseable && x.ends_with("_cell") {
         x = x.rev_before("_cell").to_string();
     }
@@ -183,10 +181,7 @@ fn check_gene_fb(
         }
     }
     for x in to_check.iter() {
-        let mut x = x.to_string();
-        if x.contains(':') {
-            x = x.after(":").to_string();
-        }
+        let x = abbr_var(x).1;
         if !gex_info.have_gex
             && !gex_info.have_fb
             && (*x == "n_gex".to_string() || *x == "n_gex_cell".to_string())
@@ -264,10 +259,7 @@ fn check_gene_fb(
     // Do the check.
 
     for i in 0..to_check.len() {
-        let mut x = to_check[i].clone();
-        if x.contains(':') {
-            x = x.after(":").to_string();
-        }
+        let x = abbr_var(&to_check[i]).1;
         let mut y = x.clone();
         if category == "parseable" && y.ends_with("_cell") {
             y = y.before("_cell").to_string();
@@ -415,6 +407,43 @@ fn check_gene_fb(
 
 // ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
 
+// Check the variables in PLOT_EMBED.  These need to be defined for cells by a BC, BC_JOINT or OBS
+// file (or by an option such as AMBIENT that adds per-cell fields), or be pca1, pca2, ..., where
+// the gene expression data have that many PCA coordinates.
+
+pub fn check_plot_embed_vars(ctl: &EncloneControl, gex_info: &GexInfo) -> Result<(), String> {
+    for var in [&ctl.plot_opt.plot_embed_xvar, &ctl.plot_opt.plot_embed_yvar] {
+        let k = if var.starts_with("pca") {
+            var.after("pca").parse::<usize>().unwrap_or(0)
+        } else {
+            0
+        };
+        let known = ctl
+            .origin_info
+            .alt_bc_fields
+            .iter()
+            .flatten()
+            .any(|x| x.0 == *var)
+            || (k >= 1
+                && gex_info
+                    .pca
+                    .iter()
+                    .flat_map(|x| x.values())
+                    .any(|v| v.len() >= k));
+        if !known {
+            return Err(format!(
+                "\nThe variable {} in your PLOT_EMBED argument is not defined by a BC, \
+                 BC_JOINT or OBS file, and is not pca1, pca2, ... for PCA coordinates that are \
+                 available.\n",
+                var
+            ));
+        }
+    }
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
 // Check pcols args.
 
 pub fn check_pcols(
@@ -435,10 +464,7 @@ pub fn check_pcols(
     let ends = build_ends();
     let mut nd_used = false;
     for x in cols.iter() {
-        let mut x = x.to_string();
-        if x.contains(':') {
-            x = x.after(":").to_string();
-        }
+        let x = abbr_var(x).1;
         let mut ok = false;
         // Note that the following test is probably redundant with some of the testing below.
         if check_one_lvar(&*x, ctl, gex_info, &mut nd_used, &ends, false)? {
@@ -453,11 +479,9 @@ pub fn check_pcols(
             ok = true;
         }
         for y in ctl.clono_print_opt.lvars.iter() {
-            if y.contains(':') {
-                let y = y.before(":");
-                if x == y {
-                    ok = true;
-                }
+            let (abbr, var) = abbr_var(y);
+            if abbr != var && x == abbr {
+                ok = true;
             }
         }
         for y in PLVARS_ALLOWED.iter() {
@@ -605,10 +629,7 @@ pub fn check_one_lvar(
             return Ok(true);
         }
     }
-    let mut x = x.to_string();
-    if x.contains(':') {
-        x = x.after(":").to_string();
-    }
+    let x = abbr_var(x).1;
 
     // See if type is ok.
 
@@ -640,6 +661,12 @@ pub fn check_one_lvar(
         }
     }
 
+    // Check per-clonotype antigen specificity variables.
+
+    if is_antigen_clono_var(ctl, &x) {
+        return Ok(true);
+    }
+
     // Check alt_bc_fields.
 
     for li in 0..ctl.origin_info.alt_bc_fields.len() {
@@ -811,6 +838,54 @@ pub fn build_ends() -> Vec<String> {
 
 // ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
 
+// Find all the variables that are referenced by arguments, namely those that are checked after
+// gene expression data are loaded (LVARS, PCOLS, TREE, PLOTXY_EXACT, PLOT_EMBED, color schemes,
+// bounds, SIM_MAT_PLOT and VAR_DEF), together with those in FCELL.  Prefixes of the form name: are
+// removed.  This is used to decide which variables that are expensive to compute are needed.
+
+pub fn referenced_vars(ctl: &EncloneControl) -> Vec<String> {
+    let mut vars = Vec::<String>::new();
+    vars.append(&mut ctl.clono_print_opt.lvars.clone());
+    vars.append(&mut ctl.parseable_opt.pcols.clone());
+    vars.append(&mut ctl.gen_opt.tree.clone());
+    if !ctl.plot_opt.plot_xy_filename.is_empty() {
+        vars.push(ctl.plot_opt.plot_xy_xvar.clone());
+        vars.push(ctl.plot_opt.plot_xy_yvar.clone());
+    }
+    if !ctl.plot_opt.plot_embed_filename.is_empty() {
+        vars.push(ctl.plot_opt.plot_embed_xvar.clone());
+        vars.push(ctl.plot_opt.plot_embed_yvar.clone());
+    }
+    for color in [&ctl.plot_opt.cell_color, &ctl.plot_opt.tree_svg_color] {
+        match color {
+            CellColor::ByVariableValue(x) => vars.push(x.var.clone()),
+            CellColor::ByCategoricalVariableValue(x) => vars.append(&mut x.vars.clone()),
+            _ => {}
+        }
+    }
+    for x in ctl.clono_filt_opt.bounds.iter() {
+        vars.append(&mut x.var.clone());
+    }
+    vars.append(&mut ctl.plot_opt.sim_mat_plot_vars.clone());
+    for x in ctl.gen_opt.var_def.iter() {
+        for v in vars_of_node(&x.2).iter() {
+            vars.push(decode_arith(v));
+        }
+    }
+    for con in ctl.clono_filt_opt_def.fcell.iter() {
+        for v in con.iter_variable_identifiers() {
+            vars.push(v.to_string());
+        }
+    }
+    for v in vars.iter_mut() {
+        *v = abbr_var(v).1;
+    }
+    unique_sort(&mut vars);
+    vars
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
 // Check lvars args.
 
 pub fn check_lvars(ctl: &EncloneControl, gex_info: &GexInfo) -> Result<(), String> {
@@ -849,3 +924,28 @@ pub fn check_gvars(ctl: &EncloneControl) -> Result<(), String> {
     }
     Ok(())
 }
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_lvars() {
+        let mut ctl = EncloneControl::default();
+        let gex_info = GexInfo::default();
+        for lvars in [
+            &["stable_id"][..],
+            &["n", "sid:stable_id"],
+            &["clonotype_ncells"],
+        ] {
+            ctl.clono_print_opt.lvars = lvars.iter().map(|x| x.to_string()).collect();
+            assert!(check_lvars(&ctl, &gex_info).is_ok());
+        }
+        for lvars in [&["stable_idx"][..], &["n_cell"]] {
+            ctl.clono_print_opt.lvars = lvars.iter().map(|x| x.to_string()).collect();
+            assert!(check_lvars(&ctl, &gex_info).is_err());
+        }
+    }
+}
@@ -3,7 +3,9 @@
 use crate::proc_args2::proc_args_tail;
 use crate::proc_args3::{get_path_fail, proc_meta, proc_meta_core, proc_xcr};
 use crate::proc_args_check::check_cvars;
-use enclone_core::defs::EncloneControl;
+use crate::read_obs::parse_obs;
+use enclone_core::cell_color::CellColor;
+use enclone_core::defs::{ClonotypeSet, EncloneControl};
 use enclone_core::tilde_expand_me;
 use enclone_vars::encode_arith;
 use evalexpr::build_operator_tree;
@@ -462,6 +464,74 @@ pub fn proc_args_post(
                 .to_string(),
         );
     }
+    if (ctl.gen_opt.de_test.is_some()
+        || ctl.gen_opt.de_control.is_some()
+        || ctl.gen_opt.de_control_vdj)
+        && ctl.gen_opt.de_filename.is_empty()
+    {
+        return Err(
+            "\nIt doesn't make sense to specify DE_TEST or DE_CONTROL unless DE is also \
+             specified.\n"
+                .to_string(),
+        );
+    }
+    if (!ctl.gen_opt.antigens.is_empty() || !ctl.gen_opt.antigen_neg.is_empty())
+        && !ctl.gen_opt.antigen_score
+    {
+        return Err(
+            "\nIt doesn't make sense to specify ANTIGENS or ANTIGEN_NEG unless ANTIGEN_SCORE is \
+             also specified.\n"
+                .to_string(),
+        );
+    }
+    if !ctl.gen_opt.hto.is_empty() && !ctl.gen_opt.hto_demux {
+        return Err(
+            "\nIt doesn't make sense to specify HTO unless HTO_DEMUX is also specified.\n"
+                .to_string(),
+        );
+    }
+    if ctl.gen_opt.ambient_frac != -1.0 {
+        if !ctl.gen_opt.ambient {
+            return Err(
+                "\nIt doesn't make sense to specify AMBIENT_FRAC unless AMBIENT is also \
+                 specified.\n"
+                    .to_string(),
+            );
+        }
+        if !(0.0..1.0).contains(&ctl.gen_opt.ambient_frac) {
+            return Err(
+                "\nThe value of AMBIENT_FRAC must be at least 0 and less than 1.\n".to_string(),
+            );
+        }
+    }
+    if !ctl.gen_opt.celltype_markers.is_empty() && !ctl.gen_opt.celltype_auto {
+        return Err(
+            "\nIt doesn't make sense to specify CELLTYPE_MARKERS unless CELLTYPE_AUTO is also \
+             specified.\n"
+                .to_string(),
+        );
+    }
+    if ctl.gen_opt.pseudobulk_group && ctl.gen_opt.pseudobulk.is_empty() {
+        return Err(
+            "\nIt doesn't make sense to specify PSEUDOBULK_GROUP unless PSEUDOBULK is also \
+             specified.\n"
+                .to_string(),
+        );
+    }
+    if ctl.plot_opt.tree_svg_file.is_empty()
+        && (!ctl.plot_opt.tree_svg_layout.is_empty()
+            || ctl.plot_opt.tree_svg_muts
+            || !matches!(ctl.plot_opt.tree_svg_color, CellColor::Unspecified))
+    {
+        return Err(
+            "\nIt doesn't make sense to specify TREE_SVG_LAYOUT or TREE_SVG_COLOR or \
+             TREE_SVG_MUTS unless TREE_SVG is also specified.\n"
+                .to_string(),
+        );
+    }
+    if ctl.gen_opt.donor_demux == 1 {
+        return Err("\nThe value of DONOR_DEMUX must be at least 2.\n".to_string());
+    }
     if ctl.clono_print_opt.conx && ctl.clono_print_opt.conp {
         return Err("\nPlease specify at most one of CONX and CONP.\n".to_string());
     }
@@ -558,6 +628,12 @@ pub fn proc_args_post(
         parse_bc_joint(&mut ctl)?;
     }
 
+    // Process OBS.
+
+    if !ctl.gen_opt.obs.is_empty() {
+        parse_obs(&mut ctl)?;
+    }
+
     // More argument sanity checking.
 
     let t = Instant::now();
@@ -665,6 +741,25 @@ pub fn proc_args_post(
                 .to_string(),
         );
     }
+    if ctl.gen_opt.donor_demux > 0 && ctl.origin_info.donor_for_bc.iter().any(|x| !x.is_empty()) {
+        return Err(
+            "\nIt doesn't make sense to specify DONOR_DEMUX if donors have already been \
+             assigned to barcodes.\n"
+                .to_string(),
+        );
+    }
+    if ctl.gen_opt.donor_demux > 0 {
+        let mut donor_ids = ctl.origin_info.donor_id.clone();
+        unique_sort(&mut donor_ids);
+        if donor_ids.len() > 1 {
+            return Err(
+                "\nIt doesn't make sense to specify DONOR_DEMUX if datasets have been assigned \
+                 to more than one donor, since DONOR_DEMUX assigns the cells in all datasets \
+                 to a single set of donors.\n"
+                    .to_string(),
+            );
+        }
+    }
     let mut donors = Vec::<String>::new();
     let mut origins = Vec::<String>::new();
     let mut tags = Vec::<String>::new();
@@ -685,6 +780,9 @@ pub fn proc_args_post(
         donors.push(ctl.origin_info.donor_id[i].clone());
         origins.push(ctl.origin_info.origin_id[i].clone());
     }
+    for k in 1..=ctl.gen_opt.donor_demux {
+        donors.push(format!("donor{}", k));
+    }
     unique_sort(&mut donors);
     unique_sort(&mut origins);
     unique_sort(&mut tags);
@@ -701,6 +799,14 @@ pub fn proc_args_post(
             ctl.clono_filt_opt_def.donor = true;
         }
     }
+    if ctl.gen_opt.donor_demux > 0 {
+        let n = ctl.origin_info.n();
+        ctl.origin_info.alt_bc_fields.resize(n, Vec::new());
+        for li in 0..n {
+            ctl.origin_info.alt_bc_fields[li]
+                .push(("donor_demux_conf".to_string(), HashMap::new()));
+        }
+    }
     ctl.perf_stats(&t, "after main args loop 2");
     proc_args_tail(ctl, args)?;
 
@@ -728,5 +834,10 @@ pub fn proc_args_post(
             .unwrap()
             .require_valid_variables(ctl)?;
     }
+    for x in [&ctl.gen_opt.de_test, &ctl.gen_opt.de_control].iter() {
+        if let Some(ClonotypeSet::Condition(x)) = x {
+            x.require_valid_variables(ctl)?;
+        }
+    }
     Ok(())
 }
@@ -5,13 +5,15 @@
 use crate::proc_args2::test_writeable;
 use crate::proc_args2::{is_simple_arg, is_usize_arg};
 use enclone_core::cell_color::*;
-use enclone_core::defs::EncloneControl;
+use enclone_core::defs::{abbr_var, EncloneControl};
+use enclone_core::join_score::join_scorer;
 use enclone_core::tilde_expand_me;
 use enclone_vars::encode_arith;
 use evalexpr::build_operator_tree;
 use expr_tools::test_functions_in_node;
 use io_utils::path_exists;
 use itertools::Itertools;
+use std::collections::HashMap;
 use std::fs::{read_to_string, remove_file, File};
 use string_utils::TextUtils;
 use vector_utils::{unique_sort, VecUtils};
@@ -36,6 +38,14 @@ pub fn process_special_arg1(
             return Err("\nCurrently the only allowed value for PG_DIST is MFL.\n".to_string());
         }
         ctl.gen_opt.peer_group_dist = dist.to_string();
+    } else if arg.starts_with("CLONOTYPE_IDS=") {
+        let ids = arg.after("CLONOTYPE_IDS=");
+        if ids != "index" && ids != "stable" {
+            return Err(
+                "\nThe only allowed values for CLONOTYPE_IDS are index and stable.\n".to_string(),
+            );
+        }
+        ctl.gen_opt.stable_clonotype_ids = ids == "stable";
     } else if is_simple_arg(arg, "H5")? {
         ctl.gen_opt.force_h5 = true;
     } else if is_simple_arg(arg, "NH5")? {
@@ -130,6 +140,29 @@ pub fn process_special_arg1(
             );
         }
         ctl.join_alg_opt.basic_h = Some(val.force_f64());
+    } else if arg.starts_with("JOIN_SCORER=") {
+        let mut name = arg.after("JOIN_SCORER=");
+        join_scorer(name)?;
+        if name.is_empty() {
+            name = "default";
+        }
+        ctl.join_alg_opt.scorer = name.to_string();
+    } else if arg.starts_with("JOIN_HAMMING=") {
+        let val = arg.after("JOIN_HAMMING=");
+        if !val.parse::<f64>().is_ok() || val.force_f64() < 0.0 || val.force_f64() > 1.0 {
+            return Err(
+                "\nArgument to JOIN_HAMMING needs to be a number between 0 and 1.\n".to_string(),
+            );
+        }
+        ctl.join_alg_opt.hamming = val.force_f64();
+    } else if arg.starts_with("JOIN_SHM_P=") {
+        let val = arg.after("JOIN_SHM_P=");
+        if !val.parse::<f64>().is_ok() || val.force_f64() < 0.0 || val.force_f64() > 1.0 {
+            return Err(
+                "\nArgument to JOIN_SHM_P needs to be a number between 0 and 1.\n".to_string(),
+            );
+        }
+        ctl.join_alg_opt.shm_p = val.force_f64();
     } else if arg.starts_with("JOIN_CDR3_IDENT=") {
         let val = arg.after("JOIN_CDR3_IDENT=");
         if !val.parse::<f64>().is_ok() || val.force_f64() < 0.0 || val.force_f64() > 100.0 {
@@ -253,12 +286,9 @@ pub fn process_special_arg1(
                         return Err(err);
                     }
                     schema = "variable".to_string();
-                    var = p[1].to_string();
-                    display_var = var.clone();
-                    if var.contains(':') {
-                        display_var = var.before(":").to_string();
-                        var = var.after(":").to_string();
-                    }
+                    let x = abbr_var(&p[1]);
+                    display_var = x.0;
+                    var = x.1;
                     if p.len() >= 3 && !p[2].is_empty() && p[2] != "turbo" {
                         return Err(err);
                     }
@@ -434,6 +464,75 @@ pub fn process_special_arg1(
         for j in 1..fields.len() {
             ctl.plot_opt.sim_mat_plot_vars.push(fields[j].to_string());
         }
+    } else if arg.starts_with("TREE_SVG=") {
+        let mut val = arg.after("TREE_SVG=").to_string();
+        tilde_expand_me(&mut val);
+        if val != "stdout" && val != "gui" && val != "gui_stdout" {
+            if !val.ends_with(".svg") {
+                return Err("\nTREE_SVG filename needs to end with .svg.\n".to_string());
+            }
+            test_writeable(&val, ctl.gen_opt.evil_eye)?;
+        }
+        ctl.plot_opt.tree_svg_file = val;
+    } else if arg.starts_with("TREE_SVG_LAYOUT=") {
+        let layout = arg.after("TREE_SVG_LAYOUT=");
+        if layout != "rect" && layout != "circular" {
+            return Err(
+                "\nThe value of TREE_SVG_LAYOUT needs to be rect or circular.\n".to_string(),
+            );
+        }
+        ctl.plot_opt.tree_svg_layout = layout.to_string();
+    } else if arg.starts_with("TREE_SVG_COLOR=") {
+        let p = arg
+            .after("TREE_SVG_COLOR=")
+            .split(',')
+            .collect::<Vec<&str>>();
+        let err = format!(
+            "\nUnrecognized TREE_SVG_COLOR specification {}.\n",
+            p.join(",")
+        );
+        if p.len() == 1 && p[0] == "dataset" {
+            ctl.plot_opt.tree_svg_color = CellColor::ByDataset(ColorByDataset {});
+        } else if p[0] == "origin" {
+            if p.len() % 2 == 0 {
+                return Err(err);
+            }
+            let mut specification = HashMap::<String, String>::new();
+            for i in (1..p.len()).step_by(2) {
+                specification.insert(p[i].to_string(), p[i + 1].to_string());
+            }
+            ctl.plot_opt.tree_svg_color = CellColor::BySample(ColorBySample {
+                by_meta: false,
+                specification,
+            });
+        } else if p[0] == "iso" {
+            ctl.plot_opt.tree_svg_color = CellColor::ByIsotype(ColorByIsotype {
+                color: p[1..].iter().map(|c| c.to_string()).collect(),
+                show_legend: true,
+            });
+        } else if p[0] == "var" && (p.len() == 2 || p.len() == 5) {
+            let (display_var, var) = abbr_var(p[1]);
+            let (mut min, mut max) = (None, None);
+            if p.len() == 5 {
+                if p[2] != "minmax" || p[3].parse::<f64>().is_err() || p[4].parse::<f64>().is_err()
+                {
+                    return Err(err);
+                }
+                min = Some(p[3].force_f64());
+                max = Some(p[4].force_f64());
+                if min >= max {
+                    return Err(err);
+                }
+            }
+            ctl.plot_opt.tree_svg_color = CellColor::ByVariableValue(ColorByVariableValue {
+                var,
+                display_var,
+                min,
+                max,
+            });
+        } else {
+            return Err(err);
+        }
     } else if arg.starts_with("G=") {
         let mut x = Vec::<usize>::new();
         if arg != "G=all" {
@@ -523,6 +622,47 @@ pub fn process_special_arg1(
             }
             remove_file(&val).unwrap_or_else(|_| panic!("could not remove file {}", val));
         }
+    } else if arg.starts_with("PLOT_EMBED=") {
+        let fields = arg.after("PLOT_EMBED=").split(',').collect::<Vec<&str>>();
+        let err = "\nPLOT_EMBED requires arguments xvar,yvar,filename, optionally followed \
+            by any of top:n, group and edges.\n"
+            .to_string();
+        if fields.len() < 3 || fields[0..3].iter().any(|x| x.is_empty()) {
+            return Err(err);
+        }
+        ctl.plot_opt.plot_embed_xvar = fields[0].to_string();
+        ctl.plot_opt.plot_embed_yvar = fields[1].to_string();
+        ctl.plot_opt.plot_embed_top = 10;
+        for x in fields[3..].iter() {
+            if *x == "group" {
+                ctl.plot_opt.plot_embed_group = true;
+            } else if *x == "edges" {
+                ctl.plot_opt.plot_embed_edges = true;
+            } else if x.starts_with("top:") && x.after("top:").parse::<usize>().is_ok() {
+                ctl.plot_opt.plot_embed_top = x.after("top:").force_usize();
+            } else {
+                return Err(err);
+            }
+        }
+        let mut val = fields[2].to_string();
+        tilde_expand_me(&mut val);
+        if val != "stdout" && !val.ends_with(".svg") && !val.ends_with(".png") {
+            return Err(
+                "\nThe PLOT_EMBED filename needs to be stdout or end with .svg or .png.\n"
+                    .to_string(),
+            );
+        }
+        ctl.plot_opt.plot_embed_filename = val.clone();
+        if val != "stdout" {
+            let f = File::create(&val);
+            if f.is_err() {
+                return Err(format!(
+                    "\nYou've specified an output file\n{}\nthat cannot be written.\n",
+                    val
+                ));
+            }
+            remove_file(&val).unwrap_or_else(|_| panic!("could not remove file {}", val));
+        }
     } else if is_usize_arg(arg, "REQUIRED_FPS")? {
         ctl.gen_opt.required_fps = Some(arg.after("REQUIRED_FPS=").force_usize());
     } else if is_usize_arg(arg, "REQUIRED_CELLS")? {
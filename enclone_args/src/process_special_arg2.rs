@@ -3,7 +3,7 @@
 // Process a special argument, i.e. one that does not fit into a neat bucket.
 
 use crate::proc_args2::{is_f64_arg, is_usize_arg};
-use enclone_core::defs::EncloneControl;
+use enclone_core::defs::{abbr_var, ClonotypeSet, EncloneControl};
 use enclone_core::linear_condition::LinearCondition;
 use enclone_core::{require_readable_file, tilde_expand_me};
 use evalexpr::build_operator_tree;
@@ -13,6 +13,20 @@ use std::io::BufRead;
 use string_utils::{parse_csv, TextUtils};
 use vector_utils::unique_sort;
 
+// Parse the argument to DE_TEST or DE_CONTROL.  This is a comma-separated list of ids, each of
+// which is a group id, a clonotype id group.clonotype, or a stable clonotype id, or failing that,
+// a linear condition on lead variables.
+
+fn clonotype_set(x: &str) -> Result<ClonotypeSet, String> {
+    let id = Regex::new(r"^([0-9]+(\.[0-9]+)?|[0-9a-f]{16}(-[0-9]+)?)$").unwrap();
+    let ids = x.split(',').map(str::to_owned).collect::<Vec<String>>();
+    if ids.iter().all(|y| id.is_match(y)) {
+        Ok(ClonotypeSet::Ids(ids))
+    } else {
+        Ok(ClonotypeSet::Condition(LinearCondition::new(x)?))
+    }
+}
+
 pub fn process_special_arg2(
     arg: &str,
     ctl: &mut EncloneControl,
@@ -375,6 +389,15 @@ pub fn process_special_arg2(
             }
         }
         ctl.gen_opt.gene_scan_threshold = Some(threshold);
+    } else if arg.starts_with("DE_TEST=") {
+        ctl.gen_opt.de_test = Some(clonotype_set(arg.after("DE_TEST="))?);
+    } else if arg.starts_with("DE_CONTROL=") {
+        let x = arg.after("DE_CONTROL=");
+        if x == "vdj" {
+            ctl.gen_opt.de_control_vdj = true;
+        } else {
+            ctl.gen_opt.de_control = Some(clonotype_set(x)?);
+        }
     } else if arg.starts_with("PLOT=") {
         *using_plot = true;
         let x = arg.after("PLOT=").split(',').collect::<Vec<&str>>();
@@ -454,10 +477,7 @@ pub fn process_special_arg2(
             ctl.parseable_opt.pcols_sort = ctl.parseable_opt.pcols.clone();
             ctl.parseable_opt.pcols_sortx = ctl.parseable_opt.pcols.clone();
             for j in 0..ctl.parseable_opt.pcols_sortx.len() {
-                if ctl.parseable_opt.pcols_sortx[j].contains(':') {
-                    ctl.parseable_opt.pcols_sortx[j] =
-                        ctl.parseable_opt.pcols_sortx[j].before(":").to_string();
-                }
+                ctl.parseable_opt.pcols_sortx[j] = abbr_var(&ctl.parseable_opt.pcols_sortx[j]).0;
             }
             unique_sort(&mut ctl.parseable_opt.pcols_sort);
             unique_sort(&mut ctl.parseable_opt.pcols_sortx);
@@ -558,6 +578,21 @@ pub fn process_special_arg2(
         for x in arg.after("DVARS=").split(',').collect::<Vec<&str>>() {
             ctl.gen_opt.dvars.push(x.to_string());
         }
+    } else if arg.starts_with("ANTIGENS=") {
+        ctl.gen_opt.antigens.clear();
+        for x in arg.after("ANTIGENS=").split(',').collect::<Vec<&str>>() {
+            ctl.gen_opt.antigens.push(x.to_string());
+        }
+    } else if arg.starts_with("ANTIGEN_NEG=") {
+        ctl.gen_opt.antigen_neg.clear();
+        for x in arg.after("ANTIGEN_NEG=").split(',').collect::<Vec<&str>>() {
+            ctl.gen_opt.antigen_neg.push(x.to_string());
+        }
+    } else if arg.starts_with("HTO=") {
+        ctl.gen_opt.hto.clear();
+        for x in arg.after("HTO=").split(',').collect::<Vec<&str>>() {
+            ctl.gen_opt.hto.push(x.to_string());
+        }
     } else if arg.starts_with("GVARS=") {
         ctl.gen_opt.gvars.clear();
         for x in arg.after("GVARS=").split(',').collect::<Vec<&str>>() {
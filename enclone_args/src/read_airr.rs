@@ -0,0 +1,541 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+
+// Read an AIRR Community rearrangement TSV file, as an alternative to
+// all_contig_annotations.json.  See
+// https://docs.airr-community.org/en/stable/datarep/rearrangements.html.
+//
+// A dataset is treated as AIRR if its path ends in .tsv (possibly followed by .gz or .lz4),
+// or if the argument AIRR_IN is given.
+//
+// Fields that are used:
+// • cell_id -- rows having no cell_id are ignored, and all other barcodes are treated as cells
+// • sequence -- or if absent, sequence_alignment, with gap characters removed
+// • sequence_id -- optional, used as the contig name
+// • productive -- optional, but if present, should be true for rows to be used, except in
+//   REPROD mode, where, as for json input, non-productive rows are kept
+// • umi_count -- optional, if absent then duplicate_count is used, and if that is absent too,
+//   one UMI is assumed
+// • consensus_count -- optional, used as the read count
+// • v_call, d_call, j_call, c_call -- optional, see below
+// • v_sequence_start, v_sequence_end, v_germline_start, v_germline_end -- optional, see below
+// • d_sequence_start, j_sequence_start, j_sequence_end, j_germline_start, j_germline_end,
+//   c_sequence_start -- optional, see below
+// • junction -- optional, see below.
+//
+// If the V and J calls and coordinates are present, and the genes named by the calls are in the
+// reference that enclone is using, the annotation is taken from the file.  Otherwise, or if the
+// junction given in the file does not agree with the CDR3 that enclone finds using the
+// annotation, the contig is annotated by enclone, as in RE mode.  Only the first gene in each
+// call is used, and allele suffixes are ignored.
+//
+// AIRR files have no quality scores, so every base is assigned quality AIRR_QUAL.
+
+use crate::read_json::{
+    group_contigs_by_barcode, origin_donor_tag, reannotate_contig, ContigAnnotation,
+};
+use debruijn::dna_string::DnaString;
+use enclone_core::defs::{EncloneControl, OriginInfo, TigData};
+use io_utils::{open_maybe_compressed, path_exists};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use string_utils::{stringme, TextUtils};
+use vdj_ann::annotate::get_cdr3_using_ann;
+use vdj_ann::refx::RefData;
+use vector_utils::unique_sort;
+
+pub const AIRR_QUAL: u8 = 40;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Determine if a dataset path is to be read as an AIRR rearrangement file.
+
+pub fn is_airr_path(path: &str, ctl: &EncloneControl) -> bool {
+    ctl.gen_opt.airr_in
+        || path.ends_with(".tsv")
+        || path.ends_with(".tsv.gz")
+        || path.ends_with(".tsv.lz4")
+}
+
+// Parse an AIRR boolean.  The standard says T or F, but other spellings are seen in practice.
+
+fn airr_bool(x: &str) -> bool {
+    x == "T" || x == "TRUE" || x == "True" || x == "true" || x == "1"
+}
+
+// Find the reference index for the first gene in an AIRR call, e.g. IGHV3-23*01,IGHV3-23D*01.
+
+fn ref_id_from_call(call: &str, refdata: &RefData, seg: &str) -> Option<usize> {
+    let mut gene = call.to_string();
+    if gene.contains(',') {
+        gene = gene.before(",").to_string();
+    }
+    if gene.contains('*') {
+        gene = gene.before("*").to_string();
+    }
+    if gene.is_empty() {
+        return None;
+    }
+    for i in 0..refdata.refs.len() {
+        if refdata.name[i] == gene && refdata.segtype[i] == seg {
+            return Some(i);
+        }
+    }
+    None
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Use the annotation given in an AIRR row.  Return None if it is absent or can't be used.
+
+fn annotation_from_airr(
+    get: &dyn Fn(&str) -> Option<String>,
+    full_seq: &str,
+    refdata: &RefData,
+    ctl: &EncloneControl,
+) -> Option<ContigAnnotation> {
+    let pos = |f: &str| -> Option<usize> {
+        let x = get(f)?;
+        let n = x.parse::<usize>().ok()?;
+        // AIRR coordinates are one-based.
+        if n == 0 {
+            return None;
+        }
+        Some(n - 1)
+    };
+    let v_ref_id = ref_id_from_call(&get("v_call")?, refdata, "V")?;
+    let j_ref_id = ref_id_from_call(&get("j_call")?, refdata, "J")?;
+    let (v_seq_start, v_seq_stop) = (pos("v_sequence_start")?, pos("v_sequence_end")? + 1);
+    let (v_germ_start, v_germ_stop) = (pos("v_germline_start")?, pos("v_germline_end")? + 1);
+    let (j_seq_start, j_seq_stop) = (pos("j_sequence_start")?, pos("j_sequence_end")? + 1);
+    let (j_germ_start, j_germ_stop) = (pos("j_germline_start")?, pos("j_germline_end")? + 1);
+
+    // Require that the V alignment starts at the start of the reference V, that the J alignment
+    // ends at the end of the reference J, and that there are no indels in V.
+
+    if v_seq_stop <= v_seq_start
+        || v_germ_stop <= v_germ_start
+        || j_seq_stop <= j_seq_start
+        || v_germ_start != 0
+        || j_germ_stop != refdata.refs[j_ref_id].len()
+        || v_seq_stop - v_seq_start != v_germ_stop - v_germ_start
+        || v_seq_stop > j_seq_start
+        || j_seq_stop > full_seq.len()
+    {
+        return None;
+    }
+    let mut d_ref_id = None;
+    let mut d_start = None;
+    if let Some(call) = get("d_call") {
+        d_ref_id = ref_id_from_call(&call, refdata, "D");
+        if d_ref_id.is_some() {
+            d_start = pos("d_sequence_start");
+        }
+    }
+    let mut c_ref_id = None;
+    let mut c_start = None;
+    if let Some(call) = get("c_call") {
+        c_ref_id = ref_id_from_call(&call, refdata, "C");
+        if c_ref_id.is_some() {
+            c_start = pos("c_sequence_start");
+        }
+    }
+    let len = (v_seq_stop - v_seq_start) as i32;
+    let t = v_ref_id as i32;
+    let annv = vec![(0_i32, len, t, 0, 0)];
+
+    // Find the CDR3, using the V annotation placed on the full contig.
+
+    let x = DnaString::from_dna_string(full_seq);
+    let mut cdr3 = Vec::<(usize, Vec<u8>, usize, usize)>::new();
+    let full_annv = vec![(v_seq_start as i32, len, t, 0, 0)];
+    get_cdr3_using_ann(&x, refdata, &full_annv, &mut cdr3);
+    if cdr3.is_empty() || cdr3[0].0 < v_seq_start {
+        return None;
+    }
+    let cdr3_aa = stringme(&cdr3[0].1);
+    let cdr3_dna = x
+        .slice(cdr3[0].0, cdr3[0].0 + 3 * cdr3_aa.len())
+        .to_string();
+    if let Some(junction) = get("junction") {
+        if junction.to_uppercase() != cdr3_dna {
+            return None;
+        }
+    }
+    let cdr3_start = cdr3[0].0 - v_seq_start;
+    let chain_type = refdata.name[v_ref_id][0..3].to_string();
+    let left = chain_type == *"IGH"
+        || chain_type == *"TRB"
+        || (chain_type == *"TRD" && ctl.gen_opt.gamma_delta);
+    Some(ContigAnnotation {
+        v_ref_id,
+        d_ref_id,
+        j_ref_id,
+        c_ref_id,
+        u_ref_id: None,
+        tig_start: v_seq_start as isize,
+        tig_stop: j_seq_stop as isize,
+        v_stop: v_seq_stop,
+        v_stop_ref: v_germ_stop,
+        d_start,
+        j_start: j_seq_start,
+        j_start_ref: j_germ_start,
+        c_start,
+        annv,
+        cdr3_aa,
+        cdr3_dna,
+        cdr3_start,
+        chain_type,
+        left,
+    })
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Parse one row of an AIRR file.  Push a TigData entry onto tigs if the row is usable.
+
+fn parse_airr_row(
+    fields: &[&str],
+    to_field: &HashMap<String, usize>,
+    row: usize,
+    origin_info: &OriginInfo,
+    li: usize,
+    refdata: &RefData,
+    ctl: &EncloneControl,
+    tigs: &mut Vec<TigData>,
+) {
+    let get = |f: &str| -> Option<String> {
+        let j = to_field.get(f)?;
+        if *j >= fields.len() || fields[*j].is_empty() {
+            return None;
+        }
+        Some(fields[*j].to_string())
+    };
+    let barcode = get("cell_id");
+    if barcode.is_none() {
+        return;
+    }
+    let barcode = barcode.unwrap();
+
+    // REPROD skips the productive filter, as it does for json input, so that contigs are
+    // judged by enclone's own annotation.
+
+    if !ctl.gen_opt.reprod {
+        if let Some(p) = get("productive") {
+            if !airr_bool(&p) {
+                return;
+            }
+        }
+    }
+    let full_seq;
+    if let Some(s) = get("sequence") {
+        full_seq = s.to_uppercase();
+    } else if let Some(s) = get("sequence_alignment") {
+        full_seq = s.replace(['.', '-'], "").to_uppercase();
+    } else {
+        return;
+    }
+    if full_seq.is_empty()
+        || !full_seq
+            .chars()
+            .all(|c| c == 'A' || c == 'C' || c == 'G' || c == 'T')
+    {
+        return;
+    }
+    let tigname = get("sequence_id").unwrap_or_else(|| format!("{}_contig_{}", barcode, row));
+
+    // Get annotation, either from the file or by computing it.
+
+    let mut a = None;
+    if !ctl.gen_opt.reannotate && !ctl.gen_opt.reprod {
+        a = annotation_from_airr(&get, &full_seq, refdata, ctl);
+    }
+    if a.is_none() {
+        a = reannotate_contig(&full_seq, &barcode, refdata, ctl);
+    }
+    if a.is_none() {
+        return;
+    }
+    let a = a.unwrap();
+    if a.tig_start < 0 || a.tig_stop < 0 {
+        return;
+    }
+    let (tig_start, tig_stop) = (a.tig_start as usize, a.tig_stop as usize);
+
+    // Test for a busted CDR3, as for json input.
+
+    if a.cdr3_aa.contains('*') || a.cdr3_start + 3 * a.cdr3_aa.len() > tig_stop - tig_start {
+        return;
+    }
+
+    // Get counts.
+
+    let mut umi_count = 1;
+    if let Some(n) = get("umi_count").or_else(|| get("duplicate_count")) {
+        umi_count = n.parse::<usize>().unwrap_or(1);
+    }
+    let mut read_count = 0;
+    if let Some(n) = get("consensus_count") {
+        read_count = n.parse::<usize>().unwrap_or(0);
+    }
+    let (origin_index, donor_index, tag_index) = origin_donor_tag(origin_info, li, &barcode);
+    let full_quals = vec![AIRR_QUAL; full_seq.len()];
+    tigs.push(TigData {
+        cdr3_dna: a.cdr3_dna,
+        len: tig_stop - tig_start,
+        v_start: tig_start,
+        v_stop: a.v_stop,
+        v_stop_ref: a.v_stop_ref,
+        d_start: a.d_start,
+        j_start: a.j_start,
+        j_start_ref: a.j_start_ref,
+        j_stop: tig_stop,
+        c_start: a.c_start,
+        full_seq: full_seq.as_bytes().to_vec(),
+        v_ref_id: a.v_ref_id,
+        d_ref_id: a.d_ref_id,
+        j_ref_id: a.j_ref_id,
+        c_ref_id: a.c_ref_id,
+        u_ref_id: a.u_ref_id,
+        fr1_start: 0,
+        cdr1_start: None,
+        fr2_start: None,
+        cdr2_start: None,
+        fr3_start: None,
+        cdr3_aa: a.cdr3_aa,
+        cdr3_start: a.cdr3_start,
+        quals: full_quals[tig_start..tig_stop].to_vec(),
+        full_quals,
+        barcode,
+        tigname,
+        left: a.left,
+        dataset_index: li,
+        origin_index,
+        donor_index,
+        tag_index,
+        umi_count,
+        read_count,
+        chain_type: a.chain_type,
+        annv: a.annv,
+        validated_umis: None,
+        non_validated_umis: None,
+        invalidated_umis: None,
+        frac_reads_used: None,
+    });
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Read an AIRR rearrangement file.  This returns the same structure as read_json.  There is no
+// GEX cell information in AIRR files, so gex_cells is left empty.
+
+pub fn read_airr(
+    origin_info: &OriginInfo,
+    li: usize,
+    tsv: &str,
+    refdata: &RefData,
+    ctl: &EncloneControl,
+    vdj_cells: &mut Vec<String>,
+    gex_cells: &mut Vec<String>,
+    gex_cells_specified: &mut bool,
+) -> Result<Vec<Vec<TigData>>, String> {
+    *gex_cells_specified = false;
+    if !path_exists(tsv) {
+        return Err(format!(
+            "\nThe path\n\
+             \"{}\"\n\
+             does not exist.  Please check how you have specified the\n\
+             input files to enclone, including the PRE argument.\n",
+            tsv
+        ));
+    }
+    let f = BufReader::new(open_maybe_compressed(tsv));
+    let mut lines = Vec::<String>::new();
+    for line in f.lines() {
+        if line.is_err() {
+            return Err(format!("\nProblem reading {}.\n", tsv));
+        }
+        lines.push(line.unwrap());
+    }
+    if lines.is_empty() {
+        return Err(format!("\nThe AIRR file {} is empty.\n", tsv));
+    }
+    let mut to_field = HashMap::<String, usize>::new();
+    for (j, x) in lines[0].split('\t').enumerate() {
+        to_field.insert(x.to_string(), j);
+    }
+    if !to_field.contains_key("cell_id") {
+        return Err(format!(
+            "\nThe AIRR file {} does not have a cell_id field, so enclone can't use it.\n",
+            tsv
+        ));
+    }
+    if !to_field.contains_key("sequence") && !to_field.contains_key("sequence_alignment") {
+        return Err(format!(
+            "\nThe AIRR file {} has neither a sequence nor a sequence_alignment field, so \
+             enclone can't use it.\n",
+            tsv
+        ));
+    }
+    let mut results = Vec::<(usize, Vec<TigData>)>::new();
+    for i in 1..lines.len() {
+        results.push((i, Vec::<TigData>::new()));
+    }
+    results.par_iter_mut().for_each(|res| {
+        let i = res.0;
+        let fields = lines[i].split('\t').collect::<Vec<&str>>();
+        parse_airr_row(
+            &fields,
+            &to_field,
+            i,
+            origin_info,
+            li,
+            refdata,
+            ctl,
+            &mut res.1,
+        );
+    });
+
+    // Every barcode in the file is taken to be a cell, so long as it has a cell_id.
+
+    let cell_id = to_field["cell_id"];
+    for i in 1..lines.len() {
+        let fields = lines[i].split('\t').collect::<Vec<&str>>();
+        if cell_id < fields.len() && !fields[cell_id].is_empty() {
+            vdj_cells.push(fields[cell_id].to_string());
+        }
+    }
+    unique_sort(vdj_cells);
+    let mut tigs = Vec::<TigData>::new();
+    for i in 0..results.len() {
+        tigs.append(&mut results[i].1);
+    }
+    tigs.sort_by(|a, b| a.barcode.cmp(&b.barcode));
+    Ok(group_contigs_by_barcode(tigs, ctl, vdj_cells, gex_cells))
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use vdj_ann::refx::make_vdj_ref_data_core;
+
+    // An IGH contig, from its start codon through the end of J, and the V and J reference
+    // sequences that it matches exactly.
+
+    const V: &str =
+        "ATGGAGTTTGGGCTGAGCTGGCTTTTTCTTGTGGCTATTTTAAAAGGTGTCCAGTGTGAGGTGCAGCTGTTGGAGTCTGG\
+        GGGAGGCTTGGTACAGCCTGGGGGGTCCCTGAGACTCTCCTGTGCAGCCTCTGGATTCACCTTTAGTTTCTATGCCATGA\
+        GCTGGGTCCGCCAGGCTCCAGGGAAGGGGCTGGAGTGGGTCTCGGTTATTAGTGGTAGCGGTGACAGTACATACTACGCT\
+        GACTCCGTGAAGGGCCGGTTCACCATCTCCAGAGACAATTCCAAGAACACGCTGTATTTGCAAATGAATAGGCTGAGAGC\
+        CGAGGACACGGCCGTATATTACTGTGCGTTTGA";
+    const J: &str = "CTTCTGGGGCCAGGGAACCCTGGTCACCGTCTCCTCAG";
+
+    #[test]
+    fn test_read_airr() {
+        let mut refdata = RefData::new();
+        let refx = format!(
+            ">1|IGHV3-23 ENST00000390609|IGHV3-23|L-REGION+V-REGION|IG|IGH|None|00\n{}\n\
+             >2|IGHJ4 ENST00000461719|IGHJ4|J-REGION|IG|IGH|None|00\n{}\n",
+            V, J
+        );
+        make_vdj_ref_data_core(&mut refdata, &refx, "", false, true, None);
+        let mut ctl = EncloneControl::default();
+        ctl.gen_opt.subsample = -1.0;
+        let origin_info = OriginInfo {
+            origin_id: vec![String::new()],
+            donor_id: vec![String::new()],
+            origin_for_bc: vec![HashMap::new()],
+            donor_for_bc: vec![HashMap::new()],
+            tag: vec![HashMap::new()],
+            ..Default::default()
+        };
+        let dir = std::env::temp_dir().join(format!("enclone_test_airr_{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        let path = |f: &str| dir.join(f).to_str().unwrap().to_string();
+
+        // The first row is used, the second is not productive, and the third has no cell_id.
+        // Coordinates are one-based and inclusive.
+
+        let seq = format!("{}{}", V, J);
+        let (nv, nj) = (V.len(), J.len());
+        let header = "cell_id\tsequence_id\tsequence\tproductive\tv_call\tj_call\t\
+            v_sequence_start\tv_sequence_end\tv_germline_start\tv_germline_end\t\
+            j_sequence_start\tj_sequence_end\tj_germline_start\tj_germline_end\t\
+            junction\tumi_count\tconsensus_count";
+        let row = |bc: &str, productive: &str| {
+            format!(
+                "{}\t{}_contig_1\t{}\t{}\tIGHV3-23*01\tIGHJ4*02\t1\t{}\t1\t{}\t{}\t{}\t1\t{}\t\
+                 TGTGCGTTTGACTTCTGG\t16\t1190",
+                bc,
+                bc,
+                seq,
+                productive,
+                nv,
+                nv,
+                nv + 1,
+                nv + nj,
+                nj
+            )
+        };
+        let lines = vec![
+            header.to_string(),
+            row("AAAC-1", "T"),
+            row("CCCG-1", "F"),
+            row("", "T"),
+        ];
+        write(path("good.tsv"), format!("{}\n", lines.join("\n"))).unwrap();
+        let (mut vdj_cells, mut gex_cells, mut gex_cells_specified) =
+            (Vec::new(), Vec::new(), true);
+        let tigs = read_airr(
+            &origin_info,
+            0,
+            &path("good.tsv"),
+            &refdata,
+            &ctl,
+            &mut vdj_cells,
+            &mut gex_cells,
+            &mut gex_cells_specified,
+        );
+
+        // Files lacking a cell_id or any sequence field are rejected.
+
+        write(path("no_cell_id.tsv"), "sequence\nACGT\n").unwrap();
+        write(path("no_sequence.tsv"), "cell_id\tjunction\nAAAC-1\tACGT\n").unwrap();
+        let read = |f: &str| {
+            read_airr(
+                &origin_info,
+                0,
+                &path(f),
+                &refdata,
+                &ctl,
+                &mut Vec::new(),
+                &mut Vec::new(),
+                &mut false,
+            )
+        };
+        let (no_cell_id, no_sequence) = (read("no_cell_id.tsv"), read("no_sequence.tsv"));
+        remove_dir_all(&dir).unwrap();
+        let tigs = tigs.unwrap();
+        assert!(!gex_cells_specified);
+        assert_eq!(vdj_cells, ["AAAC-1", "CCCG-1"]);
+        assert_eq!(tigs.len(), 1);
+        assert_eq!(tigs[0].len(), 1);
+        let t = &tigs[0][0];
+        assert_eq!(t.barcode, "AAAC-1");
+        assert_eq!(t.tigname, "AAAC-1_contig_1");
+        assert_eq!(t.cdr3_aa, "CAFDFW");
+        assert_eq!(refdata.name[t.v_ref_id], "IGHV3-23");
+        assert_eq!(refdata.name[t.j_ref_id], "IGHJ4");
+        assert_eq!(
+            (t.v_start, t.v_stop, t.j_start, t.j_stop),
+            (0, nv, nv, nv + nj)
+        );
+        assert_eq!((t.umi_count, t.read_count), (16, 1190));
+        assert!(t.quals.iter().all(|&q| q == AIRR_QUAL));
+        assert!(no_cell_id.is_err());
+        assert!(no_sequence.is_err());
+    }
+}
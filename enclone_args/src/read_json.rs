@@ -25,6 +25,7 @@
 use self::annotate::{annotate_seq, get_cdr3_using_ann, print_some_annotations};
 use self::refx::RefData;
 use self::transcript::is_valid;
+use crate::read_airr::{is_airr_path, read_airr};
 use debruijn::dna_string::DnaString;
 use enclone_core::defs::{EncloneControl, OriginInfo, TigData};
 use io_utils::{open_maybe_compressed, path_exists, read_vector_entry_from_json};
@@ -94,6 +95,251 @@ fn json_error(
 
 // ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
 
+// Find the origin, donor and tag indices for a barcode in a given dataset.
+
+pub fn origin_donor_tag(
+    origin_info: &OriginInfo,
+    li: usize,
+    barcode: &str,
+) -> (Option<usize>, Option<usize>, Option<usize>) {
+    let mut origin = None;
+    let mut donor = None;
+    let mut tag = None;
+    if origin_info.origin_for_bc[li].contains_key(&barcode.to_string()) {
+        origin = Some(origin_info.origin_for_bc[li][&barcode.to_string()].clone());
+    } else {
+        // the way we use s1 here is flaky
+        if !origin_info.origin_id[li].is_empty()
+            && (origin_info.origin_id[li] != *"s1" || origin_info.origin_for_bc[li].is_empty())
+        {
+            origin = Some(origin_info.origin_id[li].clone());
+        }
+    }
+    if origin_info.donor_for_bc[li].contains_key(&barcode.to_string()) {
+        donor = Some(origin_info.donor_for_bc[li][&barcode.to_string()].clone());
+    } else {
+        // the way we use d1 here is flaky
+        if !origin_info.origin_id[li].is_empty()
+            && (origin_info.donor_id[li] != *"d1" || origin_info.donor_for_bc[li].is_empty())
+        {
+            donor = Some(origin_info.donor_id[li].clone());
+        }
+    }
+    if origin_info.tag[li].contains_key(&barcode.to_string()) {
+        tag = Some(origin_info.tag[li][&barcode.to_string()].clone());
+    }
+    let mut origin_index = None;
+    let mut donor_index = None;
+    let mut tag_index = None;
+    if origin.is_some() {
+        if origin.is_some() {
+            origin_index = Some(bin_position(&origin_info.origin_list, &origin.unwrap()) as usize);
+        }
+        if donor.is_some() {
+            donor_index = Some(bin_position(&origin_info.donor_list, &donor.unwrap()) as usize);
+        }
+    }
+    if tag.is_some() {
+        tag_index = Some(bin_position(&origin_info.tag_list, &tag.unwrap()) as usize);
+    }
+    (origin_index, donor_index, tag_index)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Annotation of a contig that has been recomputed from its sequence, rather than read from
+// the annotations field of a json entry.
+
+pub struct ContigAnnotation {
+    pub v_ref_id: usize,
+    pub d_ref_id: Option<usize>,
+    pub j_ref_id: usize,
+    pub c_ref_id: Option<usize>,
+    pub u_ref_id: Option<usize>,
+    pub tig_start: isize,
+    pub tig_stop: isize,
+    pub v_stop: usize,
+    pub v_stop_ref: usize,
+    pub d_start: Option<usize>,
+    pub j_start: usize,
+    pub j_start_ref: usize,
+    pub c_start: Option<usize>,
+    pub annv: Vec<(i32, i32, i32, i32, i32)>,
+    pub cdr3_aa: String,
+    pub cdr3_dna: String,
+    pub cdr3_start: usize,
+    pub chain_type: String,
+    pub left: bool,
+}
+
+// Annotate a contig sequence using the reference.  Return None if the contig is not a valid
+// V..J sequence.
+
+pub fn reannotate_contig(
+    full_seq: &str,
+    barcode: &str,
+    refdata: &RefData,
+    ctl: &EncloneControl,
+) -> Option<ContigAnnotation> {
+    let x = DnaString::from_dna_string(full_seq);
+    let mut ann = Vec::<(i32, i32, i32, i32, i32)>::new();
+    annotate_seq(&x, refdata, &mut ann, true, false, true);
+
+    // If there are multiple V segment alignments, possibly reduce to just one.
+
+    let mut ann2 = Vec::<(i32, i32, i32, i32, i32)>::new();
+    let mut j = 0;
+    while j < ann.len() {
+        let t = ann[j].2 as usize;
+        let mut k = j + 1;
+        while k < ann.len() {
+            if refdata.segtype[ann[k].2 as usize] != refdata.segtype[t] {
+                break;
+            }
+            k += 1;
+        }
+        if refdata.segtype[t] == "V" && k - j > 1 {
+            let mut entries = 1;
+            if j < ann.len() - 1
+                && ann[j + 1].2 as usize == t
+                && ((ann[j].0 + ann[j].1 == ann[j + 1].0 && ann[j].3 + ann[j].1 < ann[j + 1].3)
+                    || (ann[j].0 + ann[j].1 < ann[j + 1].0 && ann[j].3 + ann[j].1 == ann[j + 1].3))
+            {
+                entries = 2;
+            }
+            for l in j..j + entries {
+                ann2.push(ann[l]);
+            }
+        } else {
+            for l in j..k {
+                ann2.push(ann[l]);
+            }
+        }
+        j = k;
+    }
+    ann = ann2;
+
+    // Proceed.
+
+    if ctl.gen_opt.trace_barcode == *barcode {
+        let mut log = Vec::<u8>::new();
+        print_some_annotations(refdata, &ann, &mut log, false);
+        print!("\n{}", strme(&log));
+    }
+    let mut log = Vec::<u8>::new();
+    if ctl.gen_opt.trace_barcode == *barcode {
+        if !is_valid(
+            &x,
+            refdata,
+            &ann,
+            true,
+            &mut log,
+            Some(ctl.gen_opt.gamma_delta),
+        ) {
+            print!("{}", strme(&log));
+            println!("invalid");
+            return None;
+        }
+    } else if !is_valid(
+        &x,
+        refdata,
+        &ann,
+        false,
+        &mut log,
+        Some(ctl.gen_opt.gamma_delta),
+    ) {
+        return None;
+    }
+    let mut cdr3 = Vec::<(usize, Vec<u8>, usize, usize)>::new();
+    get_cdr3_using_ann(&x, refdata, &ann, &mut cdr3);
+    let cdr3_aa = stringme(&cdr3[0].1);
+    let mut cdr3_start = cdr3[0].0;
+    let cdr3_dna = x
+        .slice(cdr3_start, cdr3_start + 3 * cdr3_aa.len())
+        .to_string();
+    let (mut v_ref_id, mut j_ref_id) = (1000000, 0);
+    let mut d_ref_id: Option<usize> = None;
+    let mut c_ref_id = None;
+    let mut u_ref_id = None;
+    let mut chain_type = String::new();
+    let mut left = false;
+    let (mut tig_start, mut tig_stop) = (-1_isize, -1_isize);
+    let mut v_stop = 0;
+    let mut v_stop_ref = 0;
+    let mut d_start = None;
+    let mut j_start = 0;
+    let mut j_start_ref = 0;
+    let mut c_start = None;
+    let mut annv = Vec::<(i32, i32, i32, i32, i32)>::new();
+    let mut seen_j = false;
+    for i in 0..ann.len() {
+        let t = ann[i].2 as usize;
+        if refdata.is_u(t) {
+            u_ref_id = Some(t);
+        } else if refdata.is_v(t) && !seen_j {
+            v_ref_id = t;
+            annv.push(ann[i]);
+            chain_type = refdata.name[t][0..3].to_string();
+            if chain_type == *"IGH"
+                || chain_type == *"TRB"
+                || (chain_type == *"TRD" && ctl.gen_opt.gamma_delta)
+            {
+                left = true;
+            }
+            if ann[i].3 == 0 {
+                tig_start = ann[i].0 as isize;
+                if tig_start > cdr3_start as isize {
+                    panic!(
+                        "Something is wrong with the CDR3 start for this contig:\n\n{}.",
+                        &full_seq
+                    );
+                }
+                cdr3_start -= tig_start as usize;
+            }
+            v_stop = (ann[i].0 + ann[i].1) as usize;
+            v_stop_ref = (ann[i].3 + ann[i].1) as usize;
+        } else if refdata.is_d(t) {
+            d_start = Some(ann[i].0 as usize);
+            d_ref_id = Some(t);
+        } else if refdata.is_j(t) {
+            j_ref_id = t;
+            tig_stop = (ann[i].0 + ann[i].1) as isize;
+            j_start = ann[i].0 as usize;
+            j_start_ref = ann[i].3 as usize;
+            seen_j = true;
+        } else if refdata.is_c(t) {
+            c_ref_id = Some(t);
+            c_start = Some(ann[i].0 as usize);
+        }
+    }
+    for i in (0..annv.len()).rev() {
+        annv[i].0 -= annv[0].0;
+    }
+    Some(ContigAnnotation {
+        v_ref_id,
+        d_ref_id,
+        j_ref_id,
+        c_ref_id,
+        u_ref_id,
+        tig_start,
+        tig_stop,
+        v_stop,
+        v_stop_ref,
+        d_start,
+        j_start,
+        j_start_ref,
+        c_start,
+        annv,
+        cdr3_aa,
+        cdr3_dna,
+        cdr3_start,
+        chain_type,
+        left,
+    })
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
 fn parse_vector_entry_from_json(
     x: &Vec<u8>,
     json: &String,
@@ -223,127 +469,30 @@ fn parse_vector_entry_from_json(
     // Reannotate.
 
     if reannotate || ctl.gen_opt.reprod {
-        let x = DnaString::from_dna_string(full_seq);
-        let mut ann = Vec::<(i32, i32, i32, i32, i32)>::new();
-        annotate_seq(&x, refdata, &mut ann, true, false, true);
-
-        // If there are multiple V segment alignments, possibly reduce to just one.
-
-        let mut ann2 = Vec::<(i32, i32, i32, i32, i32)>::new();
-        let mut j = 0;
-        while j < ann.len() {
-            let t = ann[j].2 as usize;
-            let mut k = j + 1;
-            while k < ann.len() {
-                if refdata.segtype[ann[k].2 as usize] != refdata.segtype[t] {
-                    break;
-                }
-                k += 1;
-            }
-            if refdata.segtype[t] == "V" && k - j > 1 {
-                let mut entries = 1;
-                if j < ann.len() - 1
-                    && ann[j + 1].2 as usize == t
-                    && ((ann[j].0 + ann[j].1 == ann[j + 1].0 && ann[j].3 + ann[j].1 < ann[j + 1].3)
-                        || (ann[j].0 + ann[j].1 < ann[j + 1].0
-                            && ann[j].3 + ann[j].1 == ann[j + 1].3))
-                {
-                    entries = 2;
-                }
-                for l in j..j + entries {
-                    ann2.push(ann[l]);
-                }
-            } else {
-                for l in j..k {
-                    ann2.push(ann[l]);
-                }
-            }
-            j = k;
-        }
-        ann = ann2;
-
-        // Proceed.
-
-        if ctl.gen_opt.trace_barcode == *barcode {
-            let mut log = Vec::<u8>::new();
-            print_some_annotations(refdata, &ann, &mut log, false);
-            print!("\n{}", strme(&log));
-        }
-        let mut log = Vec::<u8>::new();
-        if ctl.gen_opt.trace_barcode == *barcode {
-            if !is_valid(
-                &x,
-                refdata,
-                &ann,
-                true,
-                &mut log,
-                Some(ctl.gen_opt.gamma_delta),
-            ) {
-                print!("{}", strme(&log));
-                println!("invalid");
-                return Ok(());
-            }
-        } else if !is_valid(
-            &x,
-            refdata,
-            &ann,
-            false,
-            &mut log,
-            Some(ctl.gen_opt.gamma_delta),
-        ) {
+        let a = reannotate_contig(full_seq, barcode, refdata, ctl);
+        if a.is_none() {
             return Ok(());
         }
-        let mut cdr3 = Vec::<(usize, Vec<u8>, usize, usize)>::new();
-        get_cdr3_using_ann(&x, refdata, &ann, &mut cdr3);
-        cdr3_aa = stringme(&cdr3[0].1);
-        cdr3_start = cdr3[0].0;
-        cdr3_dna = x
-            .slice(cdr3_start, cdr3_start + 3 * cdr3_aa.len())
-            .to_string();
-        let mut seen_j = false;
-        for i in 0..ann.len() {
-            let t = ann[i].2 as usize;
-            if refdata.is_u(t) {
-                u_ref_id = Some(t);
-            } else if refdata.is_v(t) && !seen_j {
-                v_ref_id = t;
-                annv.push(ann[i]);
-                chain_type = refdata.name[t][0..3].to_string();
-                if chain_type == *"IGH"
-                    || chain_type == *"TRB"
-                    || (chain_type == *"TRD" && ctl.gen_opt.gamma_delta)
-                {
-                    left = true;
-                }
-                if ann[i].3 == 0 {
-                    tig_start = ann[i].0 as isize;
-                    if tig_start > cdr3_start as isize {
-                        panic!(
-                            "Something is wrong with the CDR3 start for this contig:\n\n{}.",
-                            &full_seq
-                        );
-                    }
-                    cdr3_start -= tig_start as usize;
-                }
-                v_stop = (ann[i].0 + ann[i].1) as usize;
-                v_stop_ref = (ann[i].3 + ann[i].1) as usize;
-            } else if refdata.is_d(t) {
-                d_start = Some(ann[i].0 as usize);
-                d_ref_id = Some(t);
-            } else if refdata.is_j(t) {
-                j_ref_id = t;
-                tig_stop = (ann[i].0 + ann[i].1) as isize;
-                j_start = ann[i].0 as usize;
-                j_start_ref = ann[i].3 as usize;
-                seen_j = true;
-            } else if refdata.is_c(t) {
-                c_ref_id = Some(t);
-                c_start = Some(ann[i].0 as usize);
-            }
-        }
-        for i in (0..annv.len()).rev() {
-            annv[i].0 -= annv[0].0;
-        }
+        let a = a.unwrap();
+        v_ref_id = a.v_ref_id;
+        d_ref_id = a.d_ref_id;
+        j_ref_id = a.j_ref_id;
+        c_ref_id = a.c_ref_id;
+        u_ref_id = a.u_ref_id;
+        tig_start = a.tig_start;
+        tig_stop = a.tig_stop;
+        v_stop = a.v_stop;
+        v_stop_ref = a.v_stop_ref;
+        d_start = a.d_start;
+        j_start = a.j_start;
+        j_start_ref = a.j_start_ref;
+        c_start = a.c_start;
+        annv = a.annv;
+        cdr3_aa = a.cdr3_aa;
+        cdr3_dna = a.cdr3_dna;
+        cdr3_start = a.cdr3_start;
+        chain_type = a.chain_type;
+        left = a.left;
     } else {
         // Use annotations from json file.
 
@@ -569,46 +718,7 @@ fn parse_vector_entry_from_json(
     let quals = quals[tig_start..tig_stop].to_vec();
     let umi_count = v["umi_count"].as_i64().unwrap() as usize;
     let read_count = v["read_count"].as_i64().unwrap() as usize;
-    let mut origin = None;
-    let mut donor = None;
-    let mut tag = None;
-    if origin_info.origin_for_bc[li].contains_key(&barcode.clone()) {
-        origin = Some(origin_info.origin_for_bc[li][&barcode.clone()].clone());
-    } else {
-        // the way we use s1 here is flaky
-        if !origin_info.origin_id[li].is_empty()
-            && (origin_info.origin_id[li] != *"s1" || origin_info.origin_for_bc[li].is_empty())
-        {
-            origin = Some(origin_info.origin_id[li].clone());
-        }
-    }
-    if origin_info.donor_for_bc[li].contains_key(&barcode.clone()) {
-        donor = Some(origin_info.donor_for_bc[li][&barcode.clone()].clone());
-    } else {
-        // the way we use d1 here is flaky
-        if !origin_info.origin_id[li].is_empty()
-            && (origin_info.donor_id[li] != *"d1" || origin_info.donor_for_bc[li].is_empty())
-        {
-            donor = Some(origin_info.donor_id[li].clone());
-        }
-    }
-    if origin_info.tag[li].contains_key(&barcode.clone()) {
-        tag = Some(origin_info.tag[li][&barcode.clone()].clone());
-    }
-    let mut origin_index = None;
-    let mut donor_index = None;
-    let mut tag_index = None;
-    if origin.is_some() {
-        if origin.is_some() {
-            origin_index = Some(bin_position(&origin_info.origin_list, &origin.unwrap()) as usize);
-        }
-        if donor.is_some() {
-            donor_index = Some(bin_position(&origin_info.donor_list, &donor.unwrap()) as usize);
-        }
-    }
-    if tag.is_some() {
-        tag_index = Some(bin_position(&origin_info.tag_list, &tag.unwrap()) as usize);
-    }
+    let (origin_index, donor_index, tag_index) = origin_donor_tag(origin_info, li, barcode);
     let mut valu = None;
     if validated_umis_present {
         valu = Some(validated_umis);
@@ -668,6 +778,74 @@ fn parse_vector_entry_from_json(
 
 // ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
 
+// Group contigs by barcode, which are assumed to be adjacent in the input, and then subsample if
+// requested.
+
+pub fn group_contigs_by_barcode(
+    tigs: Vec<TigData>,
+    ctl: &EncloneControl,
+    vdj_cells: &mut Vec<String>,
+    gex_cells: &mut Vec<String>,
+) -> Vec<Vec<TigData>> {
+    let mut tig_bc = Vec::<Vec<TigData>>::new();
+    let mut r = 0;
+    while r < tigs.len() {
+        let mut s = r + 1;
+        while s < tigs.len() {
+            if tigs[s].barcode != tigs[r].barcode {
+                break;
+            }
+            s += 1;
+        }
+
+        // For now we require at most four contigs (but we don't yet merge foursies).
+
+        if s - r <= 4 || ctl.clono_filt_opt_def.nmax {
+            let mut bc_tigs = Vec::<TigData>::new();
+            for u in r..s {
+                bc_tigs.push(tigs[u].clone());
+            }
+            bc_tigs.sort();
+            tig_bc.push(bc_tigs);
+        }
+        r = s;
+    }
+    unique_sort(vdj_cells);
+
+    // Subsample.
+
+    if ctl.gen_opt.subsample >= 0.0 {
+        let mut rng = rand::thread_rng();
+        let mut to_delete1 = vec![false; tig_bc.len()];
+        let mut to_delete2 = vec![false; vdj_cells.len()];
+        let mut to_delete3 = vec![false; gex_cells.len()];
+        for i in 0..tig_bc.len() {
+            let y: f64 = rng.gen();
+            if y < 1.0 - ctl.gen_opt.subsample {
+                to_delete1[i] = true;
+                let bc = &tig_bc[i][0].barcode;
+                let p = bin_position(&vdj_cells, &bc);
+                if p >= 0 {
+                    to_delete2[p as usize] = true;
+                }
+                let p = bin_position(&gex_cells, &bc);
+                if p >= 0 {
+                    to_delete3[p as usize] = true;
+                }
+            }
+        }
+        erase_if(&mut tig_bc, &to_delete1);
+        erase_if(vdj_cells, &to_delete2);
+        erase_if(gex_cells, &to_delete3);
+    }
+
+    // Done.
+
+    tig_bc
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
 // Parse the JSON annotations file.
 //
 // In the future could be converted to LazyWrite:
@@ -809,61 +987,7 @@ pub fn read_json(
         tigs.append(&mut results[i].5);
     }
     unique_sort(gex_cells);
-    let mut tig_bc = Vec::<Vec<TigData>>::new();
-    let mut r = 0;
-    while r < tigs.len() {
-        let mut s = r + 1;
-        while s < tigs.len() {
-            if tigs[s].barcode != tigs[r].barcode {
-                break;
-            }
-            s += 1;
-        }
-
-        // For now we require at most four contigs (but we don't yet merge foursies).
-
-        if s - r <= 4 || ctl.clono_filt_opt_def.nmax {
-            let mut bc_tigs = Vec::<TigData>::new();
-            for u in r..s {
-                bc_tigs.push(tigs[u].clone());
-            }
-            bc_tigs.sort();
-            tig_bc.push(bc_tigs);
-        }
-        r = s;
-    }
-    unique_sort(vdj_cells);
-
-    // Subsample.
-
-    if ctl.gen_opt.subsample >= 0.0 {
-        let mut rng = rand::thread_rng();
-        let mut to_delete1 = vec![false; tig_bc.len()];
-        let mut to_delete2 = vec![false; vdj_cells.len()];
-        let mut to_delete3 = vec![false; gex_cells.len()];
-        for i in 0..tig_bc.len() {
-            let y: f64 = rng.gen();
-            if y < 1.0 - ctl.gen_opt.subsample {
-                to_delete1[i] = true;
-                let bc = &tig_bc[i][0].barcode;
-                let p = bin_position(&vdj_cells, &bc);
-                if p >= 0 {
-                    to_delete2[p as usize] = true;
-                }
-                let p = bin_position(&gex_cells, &bc);
-                if p >= 0 {
-                    to_delete3[p as usize] = true;
-                }
-            }
-        }
-        erase_if(&mut tig_bc, &to_delete1);
-        erase_if(vdj_cells, &to_delete2);
-        erase_if(gex_cells, &to_delete3);
-    }
-
-    // Done.
-
-    Ok(tig_bc)
+    Ok(group_contigs_by_barcode(tigs, ctl, vdj_cells, gex_cells))
 }
 
 // ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
@@ -914,6 +1038,24 @@ pub fn parse_json_annotations_files(
     }
     results.par_iter_mut().for_each(|res| {
         let li = res.0;
+        if is_airr_path(&ctl.origin_info.dataset_path[li], ctl) {
+            let resx = read_airr(
+                &ctl.origin_info,
+                li,
+                &ctl.origin_info.dataset_path[li],
+                refdata,
+                ctl,
+                &mut res.5,
+                &mut res.6,
+                &mut res.7,
+            );
+            if resx.is_ok() {
+                res.2 = resx.unwrap();
+            } else {
+                res.8 = resx.err().unwrap();
+            }
+            return;
+        }
         let json = format!("{}/{}", ctl.origin_info.dataset_path[li], ann);
         let json_lz4 = format!("{}/{}.lz4", ctl.origin_info.dataset_path[li], ann);
         if !path_exists(&json) && !path_exists(&json_lz4) {
@@ -0,0 +1,482 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Read per-cell embeddings and annotations from CSV, TSV or h5ad files, as specified by
+// OBS=path or OBS=path1,...,pathn, and attach them to cells as lead variables.  The values are
+// stored in origin_info.alt_bc_fields, exactly as for fields in BC and BC_JOINT files, so they
+// may be used anywhere that those can, including PLOTXY_EXACT, FCELL and HONEY color schemes.
+//
+// For a CSV or TSV file, there must be a barcode field, and every other field becomes a variable
+// of the same name.
+//
+// For an h5ad file, each two-dimensional array obsm/X_name yields variables name1, name2, ...,
+// with name converted to lower case, so for example X_umap yields umap1 and umap2, and X_scVI
+// yields scvi1, scvi2, ....  Each column in obs yields a variable obs:column.  Although abbr:var
+// elsewhere means variable var, displayed as abbr, a colon after obs is part of the variable
+// name (see abbr_var).  Characters in column names other than letters, digits and underscores
+// are replaced by underscores.  Columns that cannot be read are skipped, with a warning.
+//
+// In both cases, if there is a dataset field (or obs column), its values must be dataset names,
+// and each line (or cell) is applied only to that dataset.  Otherwise, if one file is given per
+// dataset, each line is applied to the dataset for its file, and if there is only one dataset,
+// to that dataset.  A single file without a dataset field cannot be used with more than one
+// dataset, because barcodes are only unique within a dataset.
+
+#[cfg(target_os = "windows")]
+use hdf5::File;
+#[cfg(not(target_os = "windows"))]
+use hdf5x::File;
+
+use crate::load_gex_alt::{read_column, read_index, read_rows, string_attr};
+use enclone_core::defs::EncloneControl;
+use io_utils::open_maybe_compressed;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader};
+use string_utils::parse_csv;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Variable names, barcodes, datasets (if specified) and values, indexed by variable, then by
+// barcode.
+
+type ObsData = (
+    Vec<String>,
+    Vec<String>,
+    Option<Vec<String>>,
+    Vec<Vec<String>>,
+);
+
+// Convert a name to one that can be used as a variable.
+
+pub fn var_name(x: &str) -> String {
+    x.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+// Read a CSV or TSV file.
+
+fn read_obs_csv(path: &str) -> Result<ObsData, String> {
+    let tsv = path.ends_with(".tsv") || path.ends_with(".tsv.gz");
+    let split = |s: &str| {
+        if tsv {
+            s.split('\t').map(str::to_owned).collect::<Vec<String>>()
+        } else {
+            parse_csv(s)
+        }
+    };
+    let mut lines = Vec::<String>::new();
+    for line in BufReader::new(open_maybe_compressed(path)).lines() {
+        if line.is_err() {
+            return Err(format!("\nProblem reading {}.\n", path));
+        }
+        lines.push(line.unwrap());
+    }
+    if lines.is_empty() {
+        return Err(format!("\nThe OBS file\n{}\nis empty.\n", path));
+    }
+    let header = split(&lines[0]);
+    let barcode_pos = header.iter().position(|x| x == "barcode");
+    if barcode_pos.is_none() {
+        return Err(format!(
+            "\nThe OBS file\n{}\nis missing the barcode field.\n",
+            path
+        ));
+    }
+    let barcode_pos = barcode_pos.unwrap();
+    let dataset_pos = header.iter().position(|x| x == "dataset");
+    let cols = (0..header.len())
+        .filter(|&j| j != barcode_pos && Some(j) != dataset_pos)
+        .collect::<Vec<_>>();
+    let names = cols.iter().map(|&j| header[j].clone()).collect::<Vec<_>>();
+    let mut barcodes = Vec::<String>::new();
+    let mut datasets = Vec::<String>::new();
+    let mut values = vec![Vec::<String>::new(); cols.len()];
+    for s in lines[1..].iter() {
+        let fields = split(s);
+        if fields.len() != header.len() {
+            return Err(format!(
+                "\nThere is a line\n{}\nin {}\n\
+                 that has {} fields, which isn't right, because the header line \
+                 has {} fields.\n",
+                s,
+                path,
+                fields.len(),
+                header.len(),
+            ));
+        }
+        barcodes.push(fields[barcode_pos].clone());
+        if let Some(p) = dataset_pos {
+            datasets.push(fields[p].clone());
+        }
+        for (k, &j) in cols.iter().enumerate() {
+            values[k].push(fields[j].clone());
+        }
+    }
+    let datasets = dataset_pos.map(|_| datasets);
+    Ok((names, barcodes, datasets, values))
+}
+
+// Read an h5ad file.
+
+fn read_obs_h5ad(path: &str) -> Result<ObsData, String> {
+    let h = File::open(&path);
+    if h.is_err() {
+        return Err(format!(
+            "\nencountered error reading h5ad file\n{}\nas follows\n{}\n",
+            path,
+            h.err().unwrap()
+        ));
+    }
+    let h = h.unwrap();
+    let err = |e: String| format!("\nProblem reading h5ad file\n{}:{}", path, e);
+    let obs = h.group("obs").map_err(|e| err(format!("\n{}\n", e)))?;
+    let barcodes = read_index(&obs).map_err(err)?;
+    let n = barcodes.len();
+    let (mut names, mut values) = (Vec::<String>::new(), Vec::<Vec<String>>::new());
+    let mut datasets = None;
+
+    // Read embeddings.
+
+    if let Ok(obsm) = h.group("obsm") {
+        let mut members = obsm.member_names().unwrap_or_default();
+        members.sort();
+        for m in members.iter() {
+            if !m.starts_with("X_") {
+                continue;
+            }
+            if let Ok(x) = obsm.dataset(m) {
+                if x.shape().len() != 2 || x.shape()[0] != n {
+                    continue;
+                }
+                let rows = read_rows(&x).map_err(err)?;
+                let base = var_name(&m["X_".len()..]).to_lowercase();
+                for k in 0..x.shape()[1] {
+                    names.push(format!("{}{}", base, k + 1));
+                    values.push(rows.iter().map(|r| (r[k] as f32).to_string()).collect());
+                }
+            }
+        }
+    }
+
+    // Read annotations.
+
+    let index = string_attr(&obs, "_index").unwrap_or_else(|| "_index".to_string());
+    let mut members = obs.member_names().unwrap_or_default();
+    members.sort();
+    for m in members.iter() {
+        if *m == index || m.starts_with("__") {
+            continue;
+        }
+        let c = read_column(&obs, m);
+        if let Err(e) = c {
+            eprintln!(
+                "\nWarning: the obs column {} in the h5ad file\n{}\ncould not be read, and so \
+                 is ignored:{}",
+                m, path, e
+            );
+            continue;
+        }
+        let c = c.unwrap();
+        if c.len() != n {
+            continue;
+        }
+        let c = c
+            .into_iter()
+            .map(|x| x.unwrap_or_default())
+            .collect::<Vec<_>>();
+        if m == "dataset" {
+            datasets = Some(c);
+        } else {
+            names.push(format!("obs:{}", var_name(m)));
+            values.push(c);
+        }
+    }
+    Ok((names, barcodes, datasets, values))
+}
+
+// Read a file of either type.
+
+fn read_obs(path: &str) -> Result<ObsData, String> {
+    if path.ends_with(".h5ad") {
+        read_obs_h5ad(path)
+    } else {
+        read_obs_csv(path)
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Read the OBS files and add their variables to alt_bc_fields.
+
+pub fn parse_obs(ctl: &mut EncloneControl) -> Result<(), String> {
+    let paths = ctl
+        .gen_opt
+        .obs
+        .split(',')
+        .map(str::to_owned)
+        .collect::<Vec<String>>();
+    let n = ctl.origin_info.n();
+    if paths.len() > 1 && paths.len() != n {
+        return Err(format!(
+            "\nOBS lists {} files, but there are {} datasets.  Please give either one file, or \
+             one file per dataset.\n",
+            paths.len(),
+            n
+        ));
+    }
+    ctl.origin_info.alt_bc_fields.resize(n, Vec::new());
+    let mut to_li = HashMap::<String, usize>::new();
+    for li in 0..n {
+        to_li.insert(ctl.origin_info.dataset_id[li].clone(), li);
+    }
+
+    // Gather the values for each variable, in the order the variables are first seen.
+
+    let mut names = Vec::<String>::new();
+    let mut fields = Vec::<Vec<HashMap<String, String>>>::new();
+    for (pi, path) in paths.iter().enumerate() {
+        let (vars, barcodes, datasets, values) = read_obs(path)?;
+        let file_li = if paths.len() > 1 { Some(pi) } else { None };
+        if datasets.is_none() && file_li.is_none() && n > 1 {
+            return Err(format!(
+                "\nThe OBS file\n{}\nhas no dataset field, so it can't be used with {} \
+                 datasets, because barcodes are only unique within a dataset.  Please add a \
+                 dataset field, or give one OBS file per dataset.\n",
+                path, n
+            ));
+        }
+        for (k, name) in vars.iter().enumerate() {
+            for li in 0..n {
+                if ctl.origin_info.alt_bc_fields[li]
+                    .iter()
+                    .any(|x| x.0 == *name)
+                {
+                    return Err(format!(
+                        "\nThe variable {} from the OBS file\n{}\nis also defined by a BC or \
+                         BC_JOINT file.\n",
+                        name, path
+                    ));
+                }
+            }
+            let v = match names.iter().position(|x| x == name) {
+                Some(v) => v,
+                None => {
+                    names.push(name.clone());
+                    fields.push(vec![HashMap::<String, String>::new(); n]);
+                    names.len() - 1
+                }
+            };
+            for i in 0..barcodes.len() {
+                if values[k][i].is_empty() {
+                    continue;
+                }
+                let li = match &datasets {
+                    Some(d) => {
+                        if !to_li.contains_key(&d[i]) {
+                            return Err(format!(
+                                "\nIn the OBS file\n{},\nthe value\n{}\nis found for dataset, \
+                                 however that is not an abbreviated dataset name.\n",
+                                path, d[i],
+                            ));
+                        }
+                        to_li[&d[i]]
+                    }
+                    None => file_li.unwrap_or(0),
+                };
+                fields[v][li].insert(barcodes[i].clone(), values[k][i].clone());
+            }
+        }
+    }
+    for (name, f) in names.into_iter().zip(fields.into_iter()) {
+        for (li, g) in f.into_iter().enumerate() {
+            ctl.origin_info.alt_bc_fields[li].push((name.clone(), g));
+        }
+    }
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(target_os = "windows")]
+    use hdf5::types::VarLenUnicode;
+    #[cfg(not(target_os = "windows"))]
+    use hdf5x::types::VarLenUnicode;
+    use ndarray::arr2;
+    use std::fs::{create_dir_all, remove_dir_all, write};
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("enclone_test_{}_{}", name, std::process::id()));
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn path(dir: &PathBuf, f: &str) -> String {
+        dir.join(f).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_read_obs_csv() {
+        let dir = temp_dir("obs_csv");
+        write(
+            path(&dir, "obs.csv"),
+            "barcode,dataset,umap1,cell_type\n\
+             AAA-1,d1,1.5,CD4 T\n\
+             CCC-1,d2,-2,\n",
+        )
+        .unwrap();
+        write(path(&dir, "obs.tsv"), "cell_type\tbarcode\nB cell\tGGG-1\n").unwrap();
+        write(path(&dir, "short.csv"), "barcode,umap1\nAAA-1\n").unwrap();
+        write(path(&dir, "no_barcode.csv"), "cell,umap1\nAAA-1,1\n").unwrap();
+        let csv = read_obs(&path(&dir, "obs.csv"));
+        let tsv = read_obs(&path(&dir, "obs.tsv"));
+        let short = read_obs(&path(&dir, "short.csv"));
+        let no_barcode = read_obs(&path(&dir, "no_barcode.csv"));
+        remove_dir_all(&dir).unwrap();
+        let (names, barcodes, datasets, values) = csv.unwrap();
+        assert_eq!(names, ["umap1", "cell_type"]);
+        assert_eq!(barcodes, ["AAA-1", "CCC-1"]);
+        assert_eq!(datasets, Some(vec!["d1".to_string(), "d2".to_string()]));
+        assert_eq!(values, vec![vec!["1.5", "-2"], vec!["CD4 T", ""]]);
+        let (names, barcodes, datasets, values) = tsv.unwrap();
+        assert_eq!(names, ["cell_type"]);
+        assert_eq!(barcodes, ["GGG-1"]);
+        assert_eq!(datasets, None);
+        assert_eq!(values, vec![vec!["B cell"]]);
+        assert!(short.is_err());
+        assert!(no_barcode.is_err());
+    }
+
+    // Write an h5ad file having one column of each kind that AnnData writes, and check that
+    // each is read according to its type.  A column that cannot be read is skipped.
+
+    #[test]
+    fn test_read_obs_h5ad() {
+        let dir = temp_dir("obs_h5ad");
+        let h5ad = path(&dir, "obs.h5ad");
+        {
+            let strs = |v: &[&str]| {
+                v.iter()
+                    .map(|s| s.parse::<VarLenUnicode>().unwrap())
+                    .collect::<Vec<_>>()
+            };
+            let f = File::create(&h5ad).unwrap();
+            let obs = f.create_group("obs").unwrap();
+            let data = strs(&["AAA-1", "CCC-1"]);
+            obs.new_dataset_builder()
+                .with_data(&data)
+                .create("_index")
+                .unwrap();
+            let leiden = obs.create_group("leiden").unwrap();
+            let codes = vec![1_i8, -1];
+            leiden
+                .new_dataset_builder()
+                .with_data(&codes)
+                .create("codes")
+                .unwrap();
+            let cats = strs(&["0", "1"]);
+            leiden
+                .new_dataset_builder()
+                .with_data(&cats)
+                .create("categories")
+                .unwrap();
+            let score = vec![0.5_f32, f32::NAN];
+            obs.new_dataset_builder()
+                .with_data(&score)
+                .create("score")
+                .unwrap();
+            let genes = vec![3_i64, 4];
+            obs.new_dataset_builder()
+                .with_data(&genes)
+                .create("n genes")
+                .unwrap();
+            let doublet = vec![true, false];
+            obs.new_dataset_builder()
+                .with_data(&doublet)
+                .create("doublet")
+                .unwrap();
+            let counts = obs.create_group("counts").unwrap();
+            let count_values = vec![7_i64, 0];
+            counts
+                .new_dataset_builder()
+                .with_data(&count_values)
+                .create("values")
+                .unwrap();
+            let mask = vec![false, true];
+            counts
+                .new_dataset_builder()
+                .with_data(&mask)
+                .create("mask")
+                .unwrap();
+            let broken = obs.create_group("broken").unwrap();
+            broken
+                .new_dataset_builder()
+                .with_data(&mask)
+                .create("mask")
+                .unwrap();
+            let obsm = f.create_group("obsm").unwrap();
+            let umap = arr2(&[[1.0_f64, 2.0], [3.0, 4.0]]);
+            obsm.new_dataset_builder()
+                .with_data(&umap)
+                .create("X_umap")
+                .unwrap();
+        }
+        let res = read_obs(&h5ad);
+        remove_dir_all(&dir).unwrap();
+        let (names, barcodes, datasets, values) = res.unwrap();
+        assert_eq!(
+            names,
+            [
+                "umap1",
+                "umap2",
+                "obs:counts",
+                "obs:doublet",
+                "obs:leiden",
+                "obs:n_genes",
+                "obs:score"
+            ]
+        );
+        assert_eq!(barcodes, ["AAA-1", "CCC-1"]);
+        assert_eq!(datasets, None);
+        assert_eq!(
+            values,
+            vec![
+                vec!["1", "3"],
+                vec!["2", "4"],
+                vec!["7", ""],
+                vec!["true", "false"],
+                vec!["1", ""],
+                vec!["3", "4"],
+                vec!["0.5", ""],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_obs() {
+        let dir = temp_dir("parse_obs");
+        write(path(&dir, "d1.csv"), "barcode,clust\nAAA-1,1\n").unwrap();
+        write(path(&dir, "d2.csv"), "barcode,clust\nAAA-1,2\n").unwrap();
+        let mut ctl = EncloneControl::default();
+        ctl.origin_info.dataset_path = vec![String::new(); 2];
+        ctl.origin_info.dataset_id = vec!["d1".to_string(), "d2".to_string()];
+        ctl.gen_opt.obs = path(&dir, "d1.csv");
+        let single = parse_obs(&mut ctl);
+        ctl.gen_opt.obs = format!("{},{}", path(&dir, "d1.csv"), path(&dir, "d2.csv"));
+        let per_dataset = parse_obs(&mut ctl);
+        remove_dir_all(&dir).unwrap();
+        assert!(single.is_err());
+        assert!(per_dataset.is_ok());
+        let alt = &ctl.origin_info.alt_bc_fields;
+        assert_eq!(alt.len(), 2);
+        for (li, val) in ["1", "2"].iter().enumerate() {
+            assert_eq!(alt[li].len(), 1);
+            assert_eq!(alt[li][0].0, "clust");
+            assert_eq!(alt[li][0].1["AAA-1"], *val);
+        }
+    }
+}
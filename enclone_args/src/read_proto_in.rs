@@ -0,0 +1,309 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+
+// Read a PROTO or BINARY file written by a previous enclone run, so that new filters, variables,
+// grouping and plots can be applied without parsing the original json files, finding alleles or
+// joining.  This is requested by PROTO_IN=file, which is rewritten as PROTO_IN=file plus either
+// BCR=file or TCR=file, according to the reference in the file.
+//
+// The file supplies the reference (unless REF is given, in which case it must be the same), the
+// donor reference, and the clonotypes.  Contigs are reconstructed from the exact subclonotypes:
+// every cell in an exact subclonotype is given the contig sequence that is stored for the exact
+// subclonotype, together with the UMI and read counts and contig name stored for the cell.  Then
+// clonotypes are formed by joining exact subclonotypes whose cells were in the same clonotype
+// in the file, rather than by running the join algorithm.  Filters are applied as usual, and so
+// can only remove cells.
+//
+// Limitations:
+// • Quality scores are not stored, so every base is assigned quality PROTO_IN_QUAL.
+// • The file is read as a single dataset, for which metadata may be given as usual, e.g. via BC.
+//   If the file records that its cells came from more than one dataset, each barcode is given as
+//   dataset/barcode, so that cells having the same barcode in different datasets are kept apart.
+//   Files made by older versions of enclone do not record datasets, and for these, if a barcode
+//   appears more than once, only its first occurrence is used.
+// • Donor reference sequences are used only if their donor indices are valid for this run.
+// • Contigs are reannotated using the reference, rather than using the annotations stored in the
+//   file.  Since the reference must be the same, the annotations are usually the same, but they
+//   can differ if the file was made by a different version of enclone, or if annotations were
+//   changed by options used in that run.
+//
+// A BINARY file cannot be read in part, so it is read in full each time it is needed.
+
+use crate::read_json::{group_contigs_by_barcode, origin_donor_tag, reannotate_contig};
+use debruijn::dna_string::DnaString;
+use enclone_core::defs::{EncloneControl, TigData};
+use enclone_proto::proto_io::{read_proto, read_proto_until_clonotypes};
+use enclone_proto::types::{EncloneOutputs, Region};
+use io_utils::path_exists;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::BufReader;
+use vdj_ann::refx::RefData;
+use vector_utils::unique_sort;
+
+pub const PROTO_IN_QUAL: u8 = 40;
+
+// Data from a PROTO_IN file, other than the contigs.  The map clonotype_of sends each barcode, as
+// used in this run, to the index of its clonotype in the file, and alt_refs is in the format
+// returned by find_alleles.
+
+#[derive(Default)]
+pub struct ProtoInData {
+    pub clonotype_of: HashMap<String, usize>,
+    pub alt_refs: Vec<(usize, usize, DnaString, usize, bool)>,
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Determine if a dataset path is a PROTO_IN file.
+
+pub fn is_proto_in_path(path: &str, ctl: &EncloneControl) -> bool {
+    !ctl.gen_opt.proto_in.is_empty() && path.ends_with(&ctl.gen_opt.proto_in)
+}
+
+// Copy everything but the clonotypes.
+
+fn header(outs: &EncloneOutputs) -> EncloneOutputs {
+    EncloneOutputs {
+        version: outs.version.clone(),
+        metadata: outs.metadata.clone(),
+        universal_reference: outs.universal_reference.clone(),
+        donor_reference: outs.donor_reference.clone(),
+        num_clonotypes: outs.num_clonotypes,
+        clonotypes: Vec::new(),
+    }
+}
+
+// Read a PROTO or BINARY file.  If header_only, the clonotypes are not returned.  For a PROTO
+// file, they are then not read, but a BINARY file is always read in full.
+
+pub fn read_enclone_outputs(path: &str, header_only: bool) -> Result<EncloneOutputs, String> {
+    if !path_exists(path) {
+        return Err(format!(
+            "\nThe PROTO_IN path\n\"{}\"\ndoes not exist.\n",
+            path
+        ));
+    }
+    match read_proto_until_clonotypes(path) {
+        Ok((outs, _)) if header_only => return Ok(outs),
+        Ok(_) => {
+            return read_proto(path)
+                .map_err(|e| format!("\nProblem reading the PROTO_IN file {}: {}.\n", path, e))
+        }
+        Err(_) => {}
+    }
+    let f = File::open(path)
+        .map_err(|e| format!("\nProblem reading the PROTO_IN file {}: {}.\n", path, e))?;
+    let outs = bincode::deserialize_from(BufReader::new(f)).map_err(|e| {
+        format!(
+            "\nThe PROTO_IN file {} could not be read as either a PROTO or a BINARY file: {}.\n",
+            path, e
+        )
+    })?;
+    if header_only {
+        Ok(header(&outs))
+    } else {
+        Ok(outs)
+    }
+}
+
+// Determine if a PROTO_IN file has BCR or TCR data, from its reference.  Return "BCR" or "TCR".
+
+pub fn proto_in_chain_type(path: &str) -> Result<&'static str, String> {
+    let outs = read_enclone_outputs(path, true)?;
+    let items = &outs.universal_reference.items;
+    if !items.is_empty() && items.iter().all(|x| x.display_name.starts_with("IG")) {
+        Ok("BCR")
+    } else if !items.is_empty() && items.iter().all(|x| x.display_name.starts_with("TR")) {
+        Ok("TCR")
+    } else {
+        Err(format!(
+            "\nUnable to determine if the PROTO_IN file {} contains BCR or TCR data.\n",
+            path
+        ))
+    }
+}
+
+// Make a reference fasta from the universal reference in a PROTO_IN file, with headers in the
+// same format as the Cell Ranger reference.
+
+pub fn proto_in_reference(path: &str) -> Result<String, String> {
+    let outs = read_enclone_outputs(path, true)?;
+    let mut refx = String::new();
+    for x in outs.universal_reference.items.iter() {
+        let region = match Region::from_i32(x.region) {
+            Some(Region::U) => "5'UTR",
+            Some(Region::V) => "L-REGION+V-REGION",
+            Some(Region::D) => "D-REGION",
+            Some(Region::J) => "J-REGION",
+            Some(Region::C) => "C-REGION",
+            None => return Err(format!("\nUnknown region in PROTO_IN file {}.\n", path)),
+        };
+        let name = &x.display_name;
+        refx += &format!(
+            ">{}|{}|{}|{}|{}|{}|None|00\n{}\n",
+            x.ref_idx,
+            name,
+            name,
+            region,
+            &name[0..2.min(name.len())],
+            &name[0..3.min(name.len())],
+            String::from_utf8_lossy(&x.nt_sequence),
+        );
+    }
+    Ok(refx)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Read a PROTO_IN file.  This fills in the same structures as parse_json_annotations_files.
+// There is no GEX cell information, so gex_cells are left empty.
+
+pub fn read_proto_in(
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    tig_bc: &mut Vec<Vec<TigData>>,
+    vdj_cells: &mut Vec<Vec<String>>,
+    gex_cells: &mut Vec<Vec<String>>,
+    gex_cells_specified: &mut Vec<bool>,
+) -> Result<ProtoInData, String> {
+    if ctl.origin_info.n() != 1 {
+        return Err("\nPROTO_IN cannot be combined with other VDJ inputs.\n".to_string());
+    }
+    let path = &ctl.origin_info.dataset_path[0];
+    let outs = read_enclone_outputs(path, false)?;
+
+    // Check that the reference is the one used to make the file.
+
+    let uref = &outs.universal_reference.items;
+    let mut same = uref.len() == refdata.refs.len();
+    for i in 0..uref.len() {
+        if !same {
+            break;
+        }
+        same = uref[i].nt_sequence == refdata.refs[i].to_ascii_vec();
+    }
+    if !same {
+        return Err(format!(
+            "\nThe reference sequence that you specified is not the one that was used to make \
+             the PROTO_IN file\n{}.\nPlease omit REF, so that the reference in the file is used.\n",
+            path
+        ));
+    }
+
+    // Find the datasets that the cells came from, if they are recorded in the file.
+
+    let mut datasets = Vec::<&String>::new();
+    for clono in outs.clonotypes.iter() {
+        for ex in clono.exact_clonotypes.iter() {
+            datasets.extend(ex.cell_datasets.iter());
+        }
+    }
+    unique_sort(&mut datasets);
+    let multi = datasets.len() > 1;
+
+    // Reconstruct contigs.  Cells are identified by dataset and barcode.
+
+    let mut data = ProtoInData::default();
+    let mut tigs = Vec::<TigData>::new();
+    let mut seen = HashSet::<(&str, &String)>::new();
+    let mut cells = Vec::<String>::new();
+    for (i, clono) in outs.clonotypes.iter().enumerate() {
+        for ex in clono.exact_clonotypes.iter() {
+            let mut chains = Vec::new();
+            for c in ex.chains.iter() {
+                let x = &c.chain;
+                let full_seq = String::from_utf8_lossy(&x.nt_sequence).to_string();
+                let a = reannotate_contig(&full_seq, &ex.cell_barcodes[0], refdata, ctl);
+                if let Some(a) = a {
+                    if a.tig_start >= 0 && a.tig_stop >= 0 {
+                        chains.push((x, full_seq, a));
+                    }
+                }
+            }
+            for (l, bc) in ex.cell_barcodes.iter().enumerate() {
+                let dataset = ex.cell_datasets.get(l).map(|x| x.as_str()).unwrap_or("");
+                if !seen.insert((dataset, bc)) {
+                    continue;
+                }
+                let barcode = if multi {
+                    format!("{}/{}", dataset, bc)
+                } else {
+                    bc.clone()
+                };
+                let barcode = &barcode;
+                cells.push(barcode.clone());
+                data.clonotype_of.insert(barcode.clone(), i);
+                let (origin_index, donor_index, tag_index) =
+                    origin_donor_tag(&ctl.origin_info, 0, barcode);
+                for (x, full_seq, a) in chains.iter() {
+                    let (tig_start, tig_stop) = (a.tig_start as usize, a.tig_stop as usize);
+                    let full_quals = vec![PROTO_IN_QUAL; full_seq.len()];
+                    tigs.push(TigData {
+                        cdr3_dna: a.cdr3_dna.clone(),
+                        len: tig_stop - tig_start,
+                        v_start: tig_start,
+                        v_stop: a.v_stop,
+                        v_stop_ref: a.v_stop_ref,
+                        d_start: a.d_start,
+                        j_start: a.j_start,
+                        j_start_ref: a.j_start_ref,
+                        j_stop: tig_stop,
+                        c_start: a.c_start,
+                        full_seq: full_seq.as_bytes().to_vec(),
+                        v_ref_id: a.v_ref_id,
+                        d_ref_id: a.d_ref_id,
+                        j_ref_id: a.j_ref_id,
+                        c_ref_id: a.c_ref_id,
+                        u_ref_id: a.u_ref_id,
+                        fr1_start: 0,
+                        cdr1_start: None,
+                        fr2_start: None,
+                        cdr2_start: None,
+                        fr3_start: None,
+                        cdr3_aa: a.cdr3_aa.clone(),
+                        cdr3_start: a.cdr3_start,
+                        quals: full_quals[tig_start..tig_stop].to_vec(),
+                        full_quals,
+                        barcode: barcode.clone(),
+                        tigname: x.contig_ids.get(l).cloned().unwrap_or_default(),
+                        left: a.left,
+                        dataset_index: 0,
+                        origin_index,
+                        donor_index,
+                        tag_index,
+                        umi_count: x.umi_counts.get(l).copied().unwrap_or(1) as usize,
+                        read_count: x.read_counts.get(l).copied().unwrap_or(0) as usize,
+                        chain_type: a.chain_type.clone(),
+                        annv: a.annv.clone(),
+                        validated_umis: None,
+                        non_validated_umis: None,
+                        invalidated_umis: None,
+                        frac_reads_used: None,
+                    });
+                }
+            }
+        }
+    }
+    unique_sort(&mut cells);
+    let mut gex = Vec::<String>::new();
+    tigs.sort_by(|a, b| a.barcode.cmp(&b.barcode));
+    tig_bc.append(&mut group_contigs_by_barcode(
+        tigs, ctl, &mut cells, &mut gex,
+    ));
+    vdj_cells.push(cells);
+    gex_cells.push(gex);
+    gex_cells_specified.push(false);
+
+    // Get the donor reference.
+
+    for x in outs.donor_reference.items.iter() {
+        let donor = x.donor_idx as usize;
+        if donor < ctl.origin_info.donor_list.len() {
+            let seq = DnaString::from_dna_string(&String::from_utf8_lossy(&x.nt_sequence));
+            data.alt_refs
+                .push((donor, x.universal_idx as usize, seq, 0, false));
+        }
+    }
+    data.alt_refs.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    Ok(data)
+}
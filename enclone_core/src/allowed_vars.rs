@@ -4,7 +4,7 @@
 
 // Lead variables for exact subclonotypes and cells.
 
-pub const LVARS_ALLOWED: [&str; 42] = [
+pub const LVARS_ALLOWED: [&str; 43] = [
     "datasets",
     "origins",
     "donors",
@@ -42,6 +42,7 @@ pub const LVARS_ALLOWED: [&str; 42] = [
     "nchains",
     "nchains_present",
     "clonotype_ncells",
+    "stable_id",
     "nbc",
     "hcomp",
     "jun_ins",
@@ -51,7 +52,7 @@ pub const LVARS_ALLOWED: [&str; 42] = [
 
 // Chain variables that can be used for contigs and chains
 
-pub const CVARS_ALLOWED: [&str; 95] = [
+pub const CVARS_ALLOWED: [&str; 97] = [
     "var",
     "u",
     "u_min",
@@ -147,15 +148,18 @@ pub const CVARS_ALLOWED: [&str; 95] = [
     "d_Δ",
     "allele",
     "allele_d",
+    "uca_dna",
+    "uca_aa",
 ];
 
 // We should not have to specify this.
 pub const CVARS_ALLOWED_PCELL: [&str; 3] = ["u_cell", "r_cell", "v_name_orig_cell"];
 
-pub const PLVARS_ALLOWED: [&str; 5] = [
+pub const PLVARS_ALLOWED: [&str; 6] = [
     "group_id",
     "group_ncells",
     "clonotype_id",
+    "join_id",
     "exact_subclonotype_id",
     "barcodes",
 ];
@@ -19,7 +19,7 @@ use perf_stats::peak_mem_usage_gb;
 
 use regex::Regex;
 use std::cmp::max;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use std::io::BufRead;
 use std::sync::atomic::AtomicBool;
@@ -89,6 +89,8 @@ pub struct OriginInfo {
     pub donor_for_bc: Vec<HashMap<String, String>>,
     // map dataset index to map of barcode to tag:
     pub tag: Vec<HashMap<String, String>>,
+    // map dataset index to set of barcodes called as doublets by HTO_DEMUX:
+    pub tag_doublet: Vec<HashSet<String>>,
     // map dataset index to map of barcode to color:
     pub barcode_color: Vec<HashMap<String, String>>,
     pub alt_bc_fields: Vec<Vec<(String, HashMap<String, String>)>>,
@@ -105,6 +107,16 @@ impl OriginInfo {
     }
 }
 
+// A set of clonotypes, as specified by DE_TEST or DE_CONTROL: either a list of group ids,
+// clonotype ids (group.clonotype) and stable ids, or a condition on the mean values of lead
+// variables across the cells in a clonotype.
+
+#[derive(Clone, PartialEq)]
+pub enum ClonotypeSet {
+    Ids(Vec<String>),
+    Condition(LinearCondition),
+}
+
 // Miscellaneous general options.
 
 #[derive(Default, PartialEq)]
@@ -130,6 +142,7 @@ pub struct GeneralOpt {
     pub clustal_dna: String,
     pub phylip_aa: String,
     pub phylip_dna: String,
+    pub airr: String,
     pub min_cells_exact: usize,
     pub min_chains_exact: usize,
     pub chains_exact: usize,
@@ -137,8 +150,15 @@ pub struct GeneralOpt {
     pub exact: Option<usize>,
     pub binary: String,
     pub proto: String,
+    pub proto_index: bool,
     // Optional path to a json file containing metadata
     pub proto_metadata: Option<String>,
+    // PROTO or BINARY file from a previous run, to be used as input
+    pub proto_in: String,
+    // cached join state, for incremental clonotyping: input, output, and stable id map output
+    pub join_state_in: String,
+    pub join_state_out: String,
+    pub join_id_map: String,
     pub h5: bool,
     pub h5_pre: bool,
     pub accept_reuse: bool,
@@ -162,6 +182,7 @@ pub struct GeneralOpt {
     pub required_datasets: Option<usize>,
     pub cellranger: bool,
     pub summary: bool,
+    pub sens_spec: bool, // compute the expensive sensitivity/specificity stats in SUMMARY
     pub summary_clean: bool,
     pub summary_csv: bool,
     pub cr_version: String,
@@ -205,9 +226,12 @@ pub struct GeneralOpt {
     pub accept_broken: bool,
     pub require_unbroken_ok: bool,
     pub built_in: bool,
+    pub airr_in: bool,
+    pub api: bool,
     pub reprod: bool,
     pub peer_group_filename: String,
     pub peer_group_dist: String,
+    pub stable_clonotype_ids: bool,
     pub peer_group_readable: bool,
     pub subset_json: String,
     pub fold_headers: bool,
@@ -258,6 +282,30 @@ pub struct GeneralOpt {
     pub external_ref: String,
     pub fails_only: bool,
     pub bc_joint: String,
+    pub obs: String,
+    pub de_filename: String,
+    pub de_test: Option<ClonotypeSet>,
+    pub de_control: Option<ClonotypeSet>,
+    pub de_control_vdj: bool,
+    pub antigen_score: bool,
+    pub antigens: Vec<String>,
+    pub antigen_neg: Vec<String>,
+    pub antigen_call: f64,
+    pub antigen_names: Vec<String>,
+    pub hto_demux: bool,
+    pub hto: Vec<String>,
+    pub donor_demux: usize,
+    pub ambient: bool,
+    pub ambient_frac: f64,
+    pub celltype_auto: bool,
+    pub celltype_markers: String,
+    pub pseudobulk: String,
+    pub pseudobulk_group: bool,
+    pub tree_pars: bool,
+    pub tree_pars_fasta: String,
+    pub tree_ml: bool,
+    pub tree_ml_post: String,
+    pub uca_fasta: String,
     pub post_filter: String,
     pub mix_only: bool,
     pub no_alt_alleles: bool,
@@ -278,6 +326,12 @@ pub struct PlotOpt {
     pub plot_xy_x_log10: bool,
     pub plot_xy_y_log10: bool,
     pub plot_xy_sym: bool,
+    pub plot_embed_filename: String,
+    pub plot_embed_xvar: String,
+    pub plot_embed_yvar: String,
+    pub plot_embed_top: usize,
+    pub plot_embed_group: bool,
+    pub plot_embed_edges: bool,
     pub plot_conditions: Vec<String>,
     pub plot_colors: Vec<String>,
     pub plot_file: String,
@@ -290,6 +344,10 @@ pub struct PlotOpt {
     pub legend: Vec<(String, String)>,
     pub sim_mat_plot_file: String,
     pub sim_mat_plot_vars: Vec<String>,
+    pub tree_svg_file: String,
+    pub tree_svg_layout: String,
+    pub tree_svg_muts: bool,
+    pub tree_svg_color: CellColor,
     pub honey_in: Option<String>,
     pub honey_out: String,
     pub split_plot_by_dataset: bool,
@@ -360,6 +418,9 @@ pub struct JoinAlgOpt {
     pub comp_filt: usize,
     pub comp_filt_bound: usize,
     pub super_comp_filt: usize,
+    pub scorer: String, // name of join scorer, see join_score.rs
+    pub hamming: f64,   // max normalized CDR3 distance for hamming scorer
+    pub shm_p: f64,     // min probability for shm scorer
 }
 
 // Clonotype filtering options.
@@ -887,6 +948,21 @@ pub struct ColInfo {
     pub chain_descrip: Vec<String>,
     pub mat: Vec<Vec<Option<usize>>>,
     pub cvars: Vec<Vec<String>>,
+    pub ucas: Vec<(Vec<u8>, Vec<usize>)>, // UCA and D segments, empty unless needed (see uca.rs)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Split a variable specification abbr:var into the name to display and the variable.  Variables
+// read from an OBS file have names obs:column, and the colon in them does not follow an
+// abbreviation, so obs:leiden is displayed as itself, and x:obs:leiden displays obs:leiden as x.
+
+pub fn abbr_var(x: &str) -> (String, String) {
+    if x.contains(':') && !x.starts_with("obs:") {
+        (x.before(":").to_string(), x.after(":").to_string())
+    } else {
+        (x.to_string(), x.to_string())
+    }
 }
 
 // ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
@@ -927,6 +1003,7 @@ pub fn justification(x: &str) -> u8 {
         || x == "nbc"
         || x == "allele"
         || x == "allele_d"
+        || x.starts_with("uca_")
     {
         b'l'
     } else {
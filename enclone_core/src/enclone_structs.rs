@@ -2,6 +2,7 @@
 
 use self::refx::RefData;
 use crate::defs::{AlleleData, CloneInfo, EncloneControl, ExactClonotype, GexInfo};
+use crate::results::EncloneResults;
 use enclone_proto::types::DonorReferenceItem;
 use qd::Double;
 use std::{collections::HashMap, time::Instant};
@@ -27,6 +28,7 @@ pub struct MainEncloneOutput {
 pub struct EncloneState {
     pub inter: EncloneIntermediates,
     pub outs: MainEncloneOutput,
+    pub results: Option<EncloneResults>, // typed results, if API was specified
 }
 
 #[derive(Default)]
@@ -60,4 +62,7 @@ pub struct EncloneExacts {
     pub fate: Vec<HashMap<String, String>>, // GETS MODIFIED SUBSEQUENTLY
     pub is_bcr: bool,
     pub allele_data: AlleleData,
+    // join ids of cells, keyed by dataset index and barcode (see enclone/src/join_state.rs),
+    // empty unless needed
+    pub join_ids: HashMap<(usize, String), usize>,
 }
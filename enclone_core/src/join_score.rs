@@ -0,0 +1,443 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+
+// Pluggable join scoring.  The decision as to whether two exact subclonotypes (more precisely,
+// two entries in info) should be placed in the same clonotype is made by a JoinScorer, which is
+// selected by name using JOIN_SCORER=name.  The available scorers are:
+//
+// default: the enclone join heuristic, as implemented by join_one;
+//
+// hamming: a simple model like that of Change-O DefineClones and SCOPer, in which two entries
+// are joined if for each chain, they have the same V and J genes (ignoring alleles) and the same
+// CDR3 length, and the Hamming distance between their concatenated CDR3 nucleotide sequences,
+// divided by its length, is at most JOIN_HAMMING (default 0.15);
+//
+// shm: an SHM-aware model.  The genes and CDR3 lengths are required to agree as for hamming.
+// Then the somatic hypermutation rate is estimated from the differences of the two entries from
+// their reference V segments, and the number of CDR3 differences is compared to a Poisson
+// distribution having mean equal to the number expected from independent mutation of the two
+// entries at that rate.  The entries are joined if the probability of seeing at least as many
+// differences is at least JOIN_SHM_P (default 0.001).
+//
+// The alternative scorers do not join across donors unless MIX_DONORS is specified, and like the
+// default scorer, only consider entries having two chains.  To compare scorers, run enclone on
+// the same inputs with each, and SUMMARY and SENS_SPEC, which adds sensitivity and
+// specificity statistics to the summary.  Note that SENS_SPEC compares all pairs of cells that
+// have the same V and J genes and CDR3 lengths, so it can be slow on large datasets, and is off
+// by default.
+//
+// The scorer is constructed once per run, in join_exacts, and shared by all threads.
+//
+// To add a scorer, implement JoinScorer and add it to JOIN_SCORERS and join_scorer.
+
+use crate::defs::{CloneInfo, EncloneControl, ExactClonotype, PotentialJoin};
+use crate::join_one::join_one;
+use enclone_proto::types::DonorReferenceItem;
+use qd::Double;
+use std::collections::HashMap;
+use vdj_ann::refx::RefData;
+use vector_utils::unique_sort;
+
+pub const JOIN_SCORERS: [&str; 3] = ["default", "hamming", "shm"];
+
+pub trait JoinScorer: Sync {
+    // Decide if info[k1] and info[k2] should be joined.  If so, push an entry onto pot and
+    // return true.
+
+    fn join(
+        &self,
+        is_bcr: bool,
+        k1: usize,
+        k2: usize,
+        ctl: &EncloneControl,
+        exact_clonotypes: &Vec<ExactClonotype>,
+        info: &Vec<CloneInfo>,
+        to_bc: &HashMap<(usize, usize), Vec<String>>,
+        sr: &Vec<Vec<Double>>,
+        pot: &mut Vec<PotentialJoin>,
+        refdata: &RefData,
+        dref: &Vec<DonorReferenceItem>,
+    ) -> bool;
+}
+
+// Get the join scorer having a given name.
+
+pub fn join_scorer(name: &str) -> Result<Box<dyn JoinScorer>, String> {
+    match name {
+        "default" | "" => Ok(Box::new(DefaultScorer {})),
+        "hamming" => Ok(Box::new(HammingScorer {})),
+        "shm" => Ok(Box::new(ShmScorer {})),
+        _ => Err(format!(
+            "\nUnknown join scorer {}.  The allowed values for JOIN_SCORER are {}.\n",
+            name,
+            JOIN_SCORERS.join(", ")
+        )),
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+pub struct DefaultScorer {}
+
+impl JoinScorer for DefaultScorer {
+    fn join(
+        &self,
+        is_bcr: bool,
+        k1: usize,
+        k2: usize,
+        ctl: &EncloneControl,
+        exact_clonotypes: &Vec<ExactClonotype>,
+        info: &Vec<CloneInfo>,
+        to_bc: &HashMap<(usize, usize), Vec<String>>,
+        sr: &Vec<Vec<Double>>,
+        pot: &mut Vec<PotentialJoin>,
+        refdata: &RefData,
+        dref: &Vec<DonorReferenceItem>,
+    ) -> bool {
+        join_one(
+            is_bcr,
+            k1,
+            k2,
+            ctl,
+            exact_clonotypes,
+            info,
+            to_bc,
+            sr,
+            pot,
+            refdata,
+            dref,
+        )
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Test the conditions shared by the alternative scorers: both entries have two chains, the V and
+// J gene names agree, ignoring alleles, the CDR3 lengths agree, and unless MIX_DONORS was
+// specified, the donors agree.  If these conditions are satisfied, return the number of CDR3
+// differences and the total CDR3 length.
+
+fn comparable(
+    k1: usize,
+    k2: usize,
+    ctl: &EncloneControl,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    info: &Vec<CloneInfo>,
+    refdata: &RefData,
+) -> Option<(usize, usize)> {
+    let gene = |id: usize| refdata.name[id].split('*').next().unwrap().to_string();
+    if info[k1].vs.len() != 2 || info[k2].vs.len() != 2 {
+        return None;
+    }
+    let (mut diffs, mut total) = (0, 0);
+    for z in 0..2 {
+        if gene(info[k1].vsids[z]) != gene(info[k2].vsids[z])
+            || gene(info[k1].jsids[z]) != gene(info[k2].jsids[z])
+        {
+            return None;
+        }
+        let (x1, x2) = (info[k1].cdr3s[z].as_bytes(), info[k2].cdr3s[z].as_bytes());
+        if x1.len() != x2.len() {
+            return None;
+        }
+        for m in 0..x1.len() {
+            if x1[m] != x2[m] {
+                diffs += 1;
+            }
+        }
+        total += x1.len();
+    }
+    if !ctl.clono_filt_opt_def.donor {
+        let donors = |k: usize| {
+            let ex = &exact_clonotypes[info[k].clonotype_index];
+            let mut d = ex
+                .clones
+                .iter()
+                .filter_map(|x| x[0].donor_index)
+                .collect::<Vec<_>>();
+            unique_sort(&mut d);
+            d
+        };
+        let (d1, d2) = (donors(k1), donors(k2));
+        if !d1.is_empty() && !d2.is_empty() && d1 != d2 {
+            return None;
+        }
+    }
+    if total == 0 {
+        return None;
+    }
+    Some((diffs, total))
+}
+
+pub struct HammingScorer {}
+
+impl JoinScorer for HammingScorer {
+    fn join(
+        &self,
+        _is_bcr: bool,
+        k1: usize,
+        k2: usize,
+        ctl: &EncloneControl,
+        exact_clonotypes: &Vec<ExactClonotype>,
+        info: &Vec<CloneInfo>,
+        _to_bc: &HashMap<(usize, usize), Vec<String>>,
+        _sr: &Vec<Vec<Double>>,
+        pot: &mut Vec<PotentialJoin>,
+        refdata: &RefData,
+        _dref: &Vec<DonorReferenceItem>,
+    ) -> bool {
+        match comparable(k1, k2, ctl, exact_clonotypes, info, refdata) {
+            Some((diffs, total)) if diffs as f64 / total as f64 <= ctl.join_alg_opt.hamming => {
+                pot.push(PotentialJoin {
+                    k1,
+                    k2,
+                    cd: diffs as isize,
+                    ..Default::default()
+                });
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Return the probability that a Poisson random variable with mean lambda is at least n.
+
+fn poisson_tail(lambda: f64, n: usize) -> f64 {
+    let mut term = (-lambda).exp();
+    let mut below = 0.0;
+    for i in 0..n {
+        below += term;
+        term *= lambda / (i + 1) as f64;
+    }
+    (1.0 - below).max(0.0)
+}
+
+// Estimate the somatic hypermutation rate of info[k], as the fraction of positions in its V
+// segments that differ from the reference.  A pseudocount keeps the estimate positive.
+
+fn shm_rate(info: &CloneInfo) -> f64 {
+    let (mut diffs, mut total) = (1, 100);
+    for z in 0..info.vs.len() {
+        let v = &info.vs[z];
+        let n = v.len().min(info.tigs[z].len());
+        for p in 0..n {
+            let r = b"ACGT"[v.get(p) as usize];
+            if info.tigs[z][p] != r {
+                diffs += 1;
+            }
+        }
+        total += n;
+    }
+    diffs as f64 / total as f64
+}
+
+pub struct ShmScorer {}
+
+impl JoinScorer for ShmScorer {
+    fn join(
+        &self,
+        _is_bcr: bool,
+        k1: usize,
+        k2: usize,
+        ctl: &EncloneControl,
+        exact_clonotypes: &Vec<ExactClonotype>,
+        info: &Vec<CloneInfo>,
+        _to_bc: &HashMap<(usize, usize), Vec<String>>,
+        _sr: &Vec<Vec<Double>>,
+        pot: &mut Vec<PotentialJoin>,
+        refdata: &RefData,
+        _dref: &Vec<DonorReferenceItem>,
+    ) -> bool {
+        let (diffs, total) = match comparable(k1, k2, ctl, exact_clonotypes, info, refdata) {
+            Some(x) => x,
+            None => return false,
+        };
+        let lambda = (shm_rate(&info[k1]) + shm_rate(&info[k2])) * total as f64;
+        let p = poisson_tail(lambda, diffs);
+        if p < ctl.join_alg_opt.shm_p {
+            return false;
+        }
+        pot.push(PotentialJoin {
+            k1,
+            k2,
+            cd: diffs as isize,
+            score: p,
+            ..Default::default()
+        });
+        true
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use debruijn::dna_string::DnaString;
+
+    const V: &str = "ACGTACGTAC";
+
+    // Make an entry having two chains, whose V segments are both V, and match the reference
+    // exactly.
+
+    fn info(cdr3s: [&str; 2], vsid: usize) -> CloneInfo {
+        CloneInfo {
+            vs: vec![DnaString::from_dna_string(V); 2],
+            tigs: vec![V.as_bytes().to_vec(); 2],
+            vsids: vec![vsid, 4],
+            jsids: vec![3, 5],
+            cdr3s: cdr3s.iter().map(|x| x.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn setup() -> (EncloneControl, RefData) {
+        let mut ctl = EncloneControl::default();
+        ctl.join_alg_opt.hamming = 0.15;
+        ctl.join_alg_opt.shm_p = 0.001;
+        ctl.clono_filt_opt_def.donor = true;
+        let mut refdata = RefData::new();
+        for x in [
+            "IGHV1-2*01",
+            "IGHV1-2*02",
+            "IGHV3-23",
+            "IGHJ4",
+            "IGKV1-5",
+            "IGKJ1",
+        ]
+        .iter()
+        {
+            refdata.name.push(x.to_string());
+        }
+        (ctl, refdata)
+    }
+
+    // Apply a scorer to info[0] and info[1].
+
+    fn score(
+        scorer: &dyn JoinScorer,
+        ctl: &EncloneControl,
+        info: &Vec<CloneInfo>,
+        refdata: &RefData,
+    ) -> Option<PotentialJoin> {
+        let mut pot = Vec::<PotentialJoin>::new();
+        let joined = scorer.join(
+            true,
+            0,
+            1,
+            ctl,
+            &Vec::new(),
+            info,
+            &HashMap::new(),
+            &Vec::new(),
+            &mut pot,
+            refdata,
+            &Vec::new(),
+        );
+        assert_eq!(joined, pot.len() == 1);
+        pot.pop()
+    }
+
+    #[test]
+    fn test_poisson_tail() {
+        // The tail at 0 is everything, even if the mean is 0.
+
+        assert_eq!(poisson_tail(0.0, 0), 1.0);
+        assert_eq!(poisson_tail(2.5, 0), 1.0);
+        assert_eq!(poisson_tail(0.0, 1), 0.0);
+
+        // P(X >= 1) = 1 - exp(-1) for mean 1, and P(X >= 2) = 1 - 3 exp(-2) for mean 2.
+
+        assert!((poisson_tail(1.0, 1) - 0.632120559).abs() < 1.0e-9);
+        assert!((poisson_tail(2.0, 2) - 0.593994150).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn test_shm_rate() {
+        // No chains: just the pseudocount, 1/100.
+
+        assert_eq!(shm_rate(&CloneInfo::default()), 0.01);
+
+        // No mutations in 20 bases: 1/120.
+
+        let mut x = info(["TGTGCGAGAT", "TGTCAGCAGT"], 0);
+        assert_eq!(shm_rate(&x), 1.0 / 120.0);
+
+        // Two mutations: 3/120.
+
+        x.tigs[1][0] = b'T';
+        x.tigs[1][9] = b'G';
+        assert_eq!(shm_rate(&x), 3.0 / 120.0);
+    }
+
+    #[test]
+    fn test_hamming_scorer() {
+        let (ctl, refdata) = setup();
+        let s = HammingScorer {};
+        let a = info(["TGTGCGAGAT", "TGTCAGCAGT"], 0);
+
+        // Equal sequences are joined, even if the V alleles differ.
+
+        let p = score(&s, &ctl, &vec![a.clone(), a.clone()], &refdata).unwrap();
+        assert_eq!((p.k1, p.k2, p.cd), (0, 1, 0));
+        let p = score(
+            &s,
+            &ctl,
+            &vec![a.clone(), info(["TGTGCGAGAT", "TGTCAGCAGT"], 1)],
+            &refdata,
+        );
+        assert_eq!(p.unwrap().cd, 0);
+
+        // 3 differences in 20 bases are within 0.15, but 4 are not.
+
+        let b = info(["AAAGCGAGAT", "TGTCAGCAGT"], 0);
+        assert_eq!(
+            score(&s, &ctl, &vec![a.clone(), b], &refdata).unwrap().cd,
+            3
+        );
+        let b = info(["AAAGCGAGAT", "TGTCAGCAGA"], 0);
+        assert!(score(&s, &ctl, &vec![a.clone(), b], &refdata).is_none());
+
+        // Different V genes or CDR3 lengths are not joined.
+
+        let b = info(["TGTGCGAGAT", "TGTCAGCAGT"], 2);
+        assert!(score(&s, &ctl, &vec![a.clone(), b], &refdata).is_none());
+        let b = info(["TGTGCGAGATT", "TGTCAGCAGT"], 0);
+        assert!(score(&s, &ctl, &vec![a, b], &refdata).is_none());
+    }
+
+    #[test]
+    fn test_shm_scorer() {
+        // Both entries have no mutations, so their rates are 1/120, and the expected number of
+        // CDR3 differences is 2/120 * 20 = 1/3.
+
+        let (ctl, refdata) = setup();
+        let s = ShmScorer {};
+        let a = info(["TGTGCGAGAT", "TGTCAGCAGT"], 0);
+
+        // Equal sequences: P(X >= 0) = 1.
+
+        let p = score(&s, &ctl, &vec![a.clone(), a.clone()], &refdata).unwrap();
+        assert_eq!((p.cd, p.score), (0, 1.0));
+
+        // One difference: P(X >= 1) = 1 - exp(-1/3).
+
+        let b = info(["AGTGCGAGAT", "TGTCAGCAGT"], 0);
+        let p = score(&s, &ctl, &vec![a.clone(), b], &refdata).unwrap();
+        assert_eq!(p.cd, 1);
+        assert!((p.score - 0.283468689).abs() < 1.0e-9);
+
+        // Three differences: P(X >= 3) = 1 - exp(-1/3) * 25/18, which is above 0.001.
+
+        let b = info(["AAAGCGAGAT", "TGTCAGCAGT"], 0);
+        let p = score(&s, &ctl, &vec![a.clone(), b], &refdata).unwrap();
+        assert!((p.score - 0.004817624).abs() < 1.0e-9);
+
+        // Four differences: P(X >= 4) = 0.000395, which is below 0.001.
+
+        let b = info(["AAAGCGAGAT", "TGTCAGCAGA"], 0);
+        assert!(score(&s, &ctl, &vec![a, b], &refdata).is_none());
+    }
+}
@@ -8,6 +8,7 @@ pub mod defs;
 pub mod enclone_structs;
 pub mod hcomp;
 pub mod join_one;
+pub mod join_score;
 pub mod linear_condition;
 pub mod logging;
 pub mod main_testlist;
@@ -17,11 +18,14 @@ pub mod opt_d;
 pub mod packing;
 pub mod prepare_for_apocalypse;
 pub mod print_tools;
+pub mod results;
 pub mod set_speakers;
 pub mod slurp;
+pub mod stable_id;
 pub mod stringulate;
 pub mod test_def;
 pub mod testlist;
+pub mod uca;
 pub mod var_reg;
 
 use lazy_static::lazy_static;
@@ -48,6 +52,15 @@ pub fn tilde_expand_me(_s: &mut String) {
     }
 }
 
+// Name of the AIRR clone file that accompanies the AIRR rearrangement file.
+
+pub fn airr_clone_filename(f: &str) -> String {
+    match f.strip_suffix(".tsv") {
+        Some(stem) => format!("{}_clone.tsv", stem),
+        None => format!("{}_clone.tsv", f),
+    }
+}
+
 pub fn hcat(col1: &[String], col2: &[String], sep: usize) -> Vec<String> {
     let mut cat = Vec::<String>::new();
     let height = max(col1.len(), col2.len());
@@ -0,0 +1,184 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+
+// Typed results of an enclone run, for code that runs enclone in-process rather than parsing
+// its printed output.  These are only computed if ctl.gen_opt.api is set, which is done by
+// main_enclone_api, as used by the builder in enclone_main::api.  There is no corresponding
+// command-line argument.
+//
+// Clonotypes are listed in the order that enclone prints them, and a clonotype's id is the
+// printed group.clonotype label, e.g. "3.1", or its stable id, if CLONOTYPE_IDS=stable.  The
+// chains of an exact subclonotype are indexed by column, so that chain cx of every exact
+// subclonotype in a clonotype is the same chain, or None if the exact subclonotype lacks that
+// chain.
+
+use crate::defs::{ColInfo, EncloneControl, ExactClonotype};
+use std::collections::HashMap;
+use vdj_ann::refx::RefData;
+
+#[derive(Clone, Debug, Default)]
+pub struct EncloneResults {
+    pub dataset_names: Vec<String>, // dataset short names, indexed by CellResult.dataset_index
+    pub groups: Vec<GroupResult>,
+    pub clonotypes: Vec<ClonotypeResult>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GroupResult {
+    pub id: usize,              // one-based group id, as printed
+    pub clonotypes: Vec<usize>, // indices into EncloneResults.clonotypes
+    pub ncells: usize,          // number of cells in the group
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ClonotypeResult {
+    pub id: String,                 // printed clonotype id, e.g. "3.1", or stable id
+    pub stable_id: String,          // stable clonotype id, see stable_id.rs
+    pub group: usize,               // index into EncloneResults.groups
+    pub ncells: usize,              // number of cells in the clonotype
+    pub columns: Vec<ColumnResult>, // the chains, as columns
+    pub exact_subclonotypes: Vec<ExactSubclonotypeResult>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ColumnResult {
+    pub chain_type: String, // e.g. IGH
+    pub v_gene: String,
+    pub d_gene: Option<String>,
+    pub j_gene: String,
+    pub c_gene: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ExactSubclonotypeResult {
+    pub id: usize,                        // one-based id within the clonotype, as printed
+    pub chains: Vec<Option<ChainResult>>, // indexed by column
+    pub cells: Vec<CellResult>,
+    pub values: HashMap<String, String>, // values of the requested variables
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChainResult {
+    pub chain_type: String,
+    pub v_gene: String,
+    pub d_gene: Option<String>,
+    pub j_gene: String,
+    pub c_gene: Option<String>,
+    pub cdr3_dna: String,
+    pub cdr3_aa: String,
+    pub seq: String,      // V..J sequence
+    pub full_seq: String, // full contig sequence, for the first cell
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CellResult {
+    pub barcode: String,
+    pub dataset_index: usize,
+    pub origin: Option<String>,
+    pub donor: Option<String>,
+    pub tag: Option<String>,
+    pub umi_counts: Vec<Option<usize>>, // indexed by column
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Build the results from the final clonotypes and groups.
+
+pub fn make_results(
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    exacts: &Vec<Vec<usize>>,
+    rsi: &Vec<ColInfo>,
+    groups: &Vec<Vec<(i32, String)>>,
+    out_datas: &Vec<Vec<HashMap<String, String>>>,
+    stable_ids: &Vec<String>,
+) -> EncloneResults {
+    let name = |id: Option<usize>| id.map(|id| refdata.name[id].clone());
+    let mut res = EncloneResults {
+        dataset_names: ctl.origin_info.dataset_id.clone(),
+        ..Default::default()
+    };
+    for i in 0..groups.len() {
+        let mut g = GroupResult {
+            id: i + 1,
+            ..Default::default()
+        };
+        for j in 0..groups[i].len() {
+            let oo = groups[i][j].0 as usize;
+            let mat = &rsi[oo].mat;
+            let mut clono = ClonotypeResult {
+                id: format!("{}.{}", i + 1, j + 1),
+                stable_id: stable_ids[oo].clone(),
+                group: i,
+                ..Default::default()
+            };
+            if ctl.gen_opt.stable_clonotype_ids {
+                clono.id = stable_ids[oo].clone();
+            }
+            for cx in 0..mat.len() {
+                let mut chain_type = String::new();
+                for k in 0..exacts[oo].len() {
+                    if let Some(m) = mat[cx][k] {
+                        chain_type = exact_clonotypes[exacts[oo][k]].share[m].chain_type.clone();
+                        break;
+                    }
+                }
+                clono.columns.push(ColumnResult {
+                    chain_type,
+                    v_gene: refdata.name[rsi[oo].vids[cx]].clone(),
+                    d_gene: name(rsi[oo].dids[cx]),
+                    j_gene: refdata.name[rsi[oo].jids[cx]].clone(),
+                    c_gene: name(rsi[oo].cids[cx]),
+                });
+            }
+            for k in 0..exacts[oo].len() {
+                let ex = &exact_clonotypes[exacts[oo][k]];
+                clono.ncells += ex.ncells();
+                let mut e = ExactSubclonotypeResult {
+                    id: k + 1,
+                    ..Default::default()
+                };
+                for cx in 0..mat.len() {
+                    e.chains.push(mat[cx][k].map(|m| {
+                        let x = &ex.share[m];
+                        ChainResult {
+                            chain_type: x.chain_type.clone(),
+                            v_gene: refdata.name[x.v_ref_id].clone(),
+                            d_gene: name(x.d_ref_id),
+                            j_gene: refdata.name[x.j_ref_id].clone(),
+                            c_gene: name(x.c_ref_id),
+                            cdr3_dna: x.cdr3_dna.clone(),
+                            cdr3_aa: x.cdr3_aa.clone(),
+                            seq: String::from_utf8(x.seq.clone()).unwrap(),
+                            full_seq: String::from_utf8(ex.clones[0][m].full_seq.clone()).unwrap(),
+                        }
+                    }));
+                }
+                for l in 0..ex.clones.len() {
+                    let c = &ex.clones[l][0];
+                    e.cells.push(CellResult {
+                        barcode: c.barcode.clone(),
+                        dataset_index: c.dataset_index,
+                        origin: c
+                            .origin_index
+                            .map(|o| ctl.origin_info.origin_list[o].clone()),
+                        donor: c.donor_index.map(|d| ctl.origin_info.donor_list[d].clone()),
+                        tag: c.tag_index.map(|t| ctl.origin_info.tag_list[t].clone()),
+                        umi_counts: (0..mat.len())
+                            .map(|cx| mat[cx][k].map(|m| ex.clones[l][m].umi_count))
+                            .collect(),
+                    });
+                }
+                if k < out_datas[oo].len() {
+                    e.values = out_datas[oo][k].clone();
+                }
+                clono.exact_subclonotypes.push(e);
+            }
+            g.ncells += clono.ncells;
+            g.clonotypes.push(res.clonotypes.len());
+            res.clonotypes.push(clono);
+        }
+        res.groups.push(g);
+    }
+    res
+}
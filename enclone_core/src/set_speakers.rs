@@ -115,6 +115,8 @@ pub fn set_speakers(ctl: &EncloneControl, parseable_fields: &mut Vec<String>, ma
     speaker!("group_id");
     speaker!("group_ncells");
     speaker!("clonotype_id");
+    speaker!("stable_id");
+    speaker!("join_id");
     speaker!("exact_subclonotype_id");
     speaker!("barcodes");
     for x in ctl.origin_info.dataset_list.iter() {
@@ -0,0 +1,223 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+
+// Stable clonotype identifiers.  The printed clonotype id (group.clonotype) depends on the
+// order in which clonotypes are printed, and so changes from run to run as data or arguments
+// change.  A stable id is instead derived from the content of the clonotype, so that the same
+// clonotype in two runs is likely to have the same id.
+//
+// The stable id of a clonotype is a 16 hex digit hash of the V gene name, J gene name and CDR3
+// DNA sequence of the lead chain of its largest exact subclonotype.  The lead chain is the
+// heavy or TRB chain (or the first such chain, in sorted order, if there is more than one),
+// or if there is no such chain, the first chain in sorted order.  Ties for the largest exact
+// subclonotype are broken by content.  The hash is FNV-1a, which is used instead of the
+// standard library hasher because the output of the latter may change across Rust versions.
+//
+// Collision policy.  If several clonotypes in a run have the same stable id, they are sorted
+// by decreasing number of cells, then by the content of their largest exact subclonotype, then
+// by the content of all their exact subclonotypes.  The first keeps the id, and the others are
+// given the id followed by -2, -3, ....  Thus collided ids are stable to the extent that the
+// colliding clonotypes are.
+//
+// Because of the collision policy, stable ids can only be computed once all clonotypes are
+// known.  For the lead variable stable_id, clonotypes whose ids have collision suffixes are
+// therefore printed a second time (see enclone_print/src/print_clonotypes.rs).
+
+use crate::defs::ExactClonotype;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use vdj_ann::refx::RefData;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// 64-bit FNV-1a hash.
+
+fn fnv1a(x: &[u8]) -> u64 {
+    let mut h = 0xcbf29ce484222325_u64;
+    for c in x.iter() {
+        h ^= *c as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h
+}
+
+// The content of an exact subclonotype, as used in stable ids: its number of cells, and for each
+// chain, whether it is a heavy or TRB chain, and its V gene name, J gene name and CDR3 DNA
+// sequence, separated by |.
+
+#[derive(Clone)]
+struct ExactContent {
+    ncells: usize,
+    chains: Vec<(bool, String)>,
+}
+
+fn exact_content(ex: &ExactClonotype, refdata: &RefData) -> ExactContent {
+    ExactContent {
+        ncells: ex.ncells(),
+        chains: ex
+            .share
+            .iter()
+            .map(|x| {
+                let key = format!(
+                    "{}|{}|{}",
+                    refdata.name[x.v_ref_id], refdata.name[x.j_ref_id], x.cdr3_dna
+                );
+                (x.left, key)
+            })
+            .collect(),
+    }
+}
+
+// The chain keys of an exact subclonotype, in sorted order.
+
+fn exact_key(ex: &ExactContent) -> Vec<String> {
+    let mut keys = ex.chains.iter().map(|x| x.1.clone()).collect::<Vec<_>>();
+    keys.sort();
+    keys
+}
+
+// Find the largest exact subclonotype in a clonotype.
+
+fn largest_exact(exacts: &[ExactContent]) -> &ExactContent {
+    exacts
+        .iter()
+        .max_by_key(|ex| (ex.ncells, Reverse(exact_key(ex))))
+        .unwrap()
+}
+
+// Compute the stable id of a clonotype, before collision handling.
+
+fn stable_id_base(exacts: &[ExactContent]) -> String {
+    let ex = largest_exact(exacts);
+    let mut lead = ex.chains.iter().filter(|x| x.0).map(|x| &x.1).min();
+    if lead.is_none() {
+        lead = ex.chains.iter().map(|x| &x.1).min();
+    }
+    format!(
+        "{:016x}",
+        fnv1a(lead.map(|x| x.as_str()).unwrap_or("").as_bytes())
+    )
+}
+
+// Compute stable ids from the content of each clonotype, applying the collision policy.
+
+fn stable_ids_from_content(clonotypes: &[Vec<ExactContent>]) -> Vec<String> {
+    let mut ids = clonotypes
+        .iter()
+        .map(|x| stable_id_base(x))
+        .collect::<Vec<_>>();
+    let mut by_id = HashMap::<String, Vec<usize>>::new();
+    for (i, id) in ids.iter().enumerate() {
+        by_id.entry(id.clone()).or_default().push(i);
+    }
+    for mut x in by_id.into_values() {
+        if x.len() == 1 {
+            continue;
+        }
+        x.sort_by_cached_key(|&i| {
+            let ncells = clonotypes[i].iter().map(|ex| ex.ncells).sum::<usize>();
+            let mut all = clonotypes[i].iter().map(exact_key).collect::<Vec<_>>();
+            all.sort();
+            (
+                Reverse(ncells),
+                exact_key(largest_exact(&clonotypes[i])),
+                all,
+                i,
+            )
+        });
+        for (k, &i) in x.iter().enumerate().skip(1) {
+            ids[i] = format!("{}-{}", ids[i], k + 1);
+        }
+    }
+    ids
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Compute the stable ids of all clonotypes, applying the collision policy.
+
+pub fn stable_clonotype_ids(
+    exacts: &Vec<Vec<usize>>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    refdata: &RefData,
+) -> Vec<String> {
+    let clonotypes = exacts
+        .iter()
+        .map(|x| {
+            x.iter()
+                .map(|&u| exact_content(&exact_clonotypes[u], refdata))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    stable_ids_from_content(&clonotypes)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exact(ncells: usize, chains: &[(bool, &str)]) -> ExactContent {
+        ExactContent {
+            ncells,
+            chains: chains.iter().map(|x| (x.0, x.1.to_string())).collect(),
+        }
+    }
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(fnv1a(b""), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+    }
+
+    #[test]
+    fn test_stable_ids_are_stable() {
+        // The id depends only on the heavy chain of the largest exact subclonotype, so it does
+        // not change if the order of clonotypes or of their exact subclonotypes changes, or if
+        // a small exact subclonotype is added.
+
+        let h1 = (true, "IGHV1-2*01|IGHJ4*01|TGTGCGAGAGATTGG");
+        let l1 = (false, "IGKV1-5*01|IGKJ1*01|TGTCAACAGTATTGG");
+        let h2 = (true, "IGHV3-23*01|IGHJ6*01|TGTGCGAAAGGCTGG");
+        let l2 = (false, "IGLV2-14*01|IGLJ2*01|TGCAGCTCATATTTC");
+        let c1 = vec![exact(3, &[l1, h1]), exact(1, &[h1])];
+        let c2 = vec![exact(2, &[h2, l2])];
+        let ids = stable_ids_from_content(&[c1.clone(), c2.clone()]);
+        assert_eq!(ids[0], format!("{:016x}", fnv1a(h1.1.as_bytes())));
+        assert_eq!(ids[1], format!("{:016x}", fnv1a(h2.1.as_bytes())));
+        let c1x = vec![exact(1, &[h1]), exact(3, &[h1, l1]), exact(1, &[l2])];
+        assert_eq!(
+            stable_ids_from_content(&[c2, c1x]),
+            vec![ids[1].clone(), ids[0].clone()]
+        );
+
+        // If there is no heavy chain, the first light chain is used.
+
+        let ids = stable_ids_from_content(&[vec![exact(1, &[l2, l1])]]);
+        assert_eq!(ids[0], format!("{:016x}", fnv1a(l1.1.as_bytes())));
+    }
+
+    #[test]
+    fn test_stable_id_collisions() {
+        // Three clonotypes have the same heavy chain.  The largest keeps the id, and the others
+        // are ordered by size and then by content, regardless of input order.
+
+        let h = (true, "IGHV1-2*01|IGHJ4*01|TGTGCGAGAGATTGG");
+        let la = (false, "IGKV1-5*01|IGKJ1*01|TGTCAACAGTATTGG");
+        let lb = (false, "IGKV1-5*01|IGKJ1*01|TGTCAACAGTACTGG");
+        let a = vec![exact(2, &[h, la])];
+        let b = vec![exact(2, &[h, lb])];
+        let c = vec![exact(5, &[h, lb])];
+        let base = format!("{:016x}", fnv1a(h.1.as_bytes()));
+        let ids = stable_ids_from_content(&[a.clone(), b.clone(), c.clone()]);
+        assert_eq!(
+            ids,
+            vec![format!("{}-3", base), format!("{}-2", base), base.clone()]
+        );
+        let ids = stable_ids_from_content(&[c, b, a]);
+        assert_eq!(
+            ids,
+            vec![base.clone(), format!("{}-2", base), format!("{}-3", base)]
+        );
+    }
+}
@@ -0,0 +1,440 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Infer the unmutated common ancestor (UCA) of a clonotype, for one chain, as its nucleotide
+// V..J sequence, in the coordinates of seq_del_amino.
+//
+// The germline is the donor reference V (or the universal reference V, if there is no donor
+// allele), the optimal D, as found by opt_d for the exact subclonotype having the fewest V
+// mutations (for chains having a D segment), and the reference J.  The junction is segmented
+// into V, N/P, D, N/P and J regions, by choosing the V 3' deletion, the J 5' deletion (each at
+// most MAX_DEL) and the placement of a trimmed D that maximize the likelihood below.
+//
+// Model.  The exact subclonotypes are treated as independent descendants of the UCA.  An exact
+// subclonotype u has the UCA base at each site with probability 1 - mu_u, and each other base
+// with probability mu_u / 3, where mu_u is its mutation rate, as estimated from its V segment.
+// The prior on the UCA base is:
+// - at a templated (V, D or J) site, the germline base with probability 1 - GERMLINE_ERR;
+// - at a P site (one of the first MAX_P sites adjacent to an untrimmed segment end), the
+//   palindromic base with probability P_PAL, and otherwise uniform;
+// - at an N site, uniform.
+// Given the segmentation, the base at each site is the one of maximum posterior probability.
+// This fills in N/P nucleotides from the members of the clonotype, and also corrects germline
+// bases for which the data are overwhelming, e.g. for J alleles that are not in the reference.
+// Sites having no data and no germline base are N.
+//
+// The UCA is used by the variables uca_aa and uca_dna, which are computed for each exact
+// subclonotype, and by UCA_FASTA.  So if any of these are used, the UCA is computed once for
+// each column of a clonotype, when its ColInfo is defined, and stored there as ucas.
+
+use crate::defs::{abbr_var, ColInfo, EncloneControl, ExactClonotype};
+use crate::opt_d::opt_d;
+use enclone_proto::types::DonorReferenceItem;
+use vdj_ann::refx::RefData;
+
+const MAX_DEL: usize = 20;
+const MAX_P: usize = 2;
+const GERMLINE_ERR: f64 = 0.01;
+const P_PAL: f64 = 0.5;
+const MIN_MU: f64 = 0.001;
+const MAX_MU: f64 = 0.25;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+const BASES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+fn base_index(b: u8) -> Option<usize> {
+    BASES.iter().position(|x| *x == b)
+}
+
+fn complement(b: u8) -> u8 {
+    match b {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        _ => b'N',
+    }
+}
+
+// Define the log prior for a templated site, given the germline base, and for a P site, given the
+// palindromic base.
+
+fn templated_prior(g: u8) -> [f64; 4] {
+    let mut prior = [(GERMLINE_ERR / 3.0).ln(); 4];
+    if let Some(i) = base_index(g) {
+        prior[i] = (1.0 - GERMLINE_ERR).ln();
+    } else {
+        prior = [0.25_f64.ln(); 4];
+    }
+    prior
+}
+
+fn p_prior(pal: u8) -> [f64; 4] {
+    let mut prior = [((1.0 - P_PAL) / 4.0).ln(); 4];
+    if let Some(i) = base_index(pal) {
+        prior[i] = (P_PAL + (1.0 - P_PAL) / 4.0).ln();
+    }
+    prior
+}
+
+// Compute the log of the marginal likelihood of the data at a site, given the prior.
+
+fn site_score(ll: &[f64; 4], prior: &[f64; 4]) -> f64 {
+    let x = (0..4).map(|b| ll[b] + prior[b]).collect::<Vec<f64>>();
+    let m = x.iter().cloned().fold(f64::MIN, f64::max);
+    m + x.iter().map(|y| (y - m).exp()).sum::<f64>().ln()
+}
+
+// Find the contiguous run having the greatest sum within a vector of gains, as (sum, start, stop).
+
+fn max_run(gains: &[f64]) -> (f64, usize, usize) {
+    let (mut best, mut sum, mut start) = ((0.0, 0, 0), 0.0, 0);
+    for (p, g) in gains.iter().enumerate() {
+        if sum <= 0.0 {
+            sum = 0.0;
+            start = p;
+        }
+        sum += g;
+        if sum > best.0 {
+            best = (sum, start, p + 1);
+        }
+    }
+    best
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Given the log likelihood of each base at each site, and the germline segments, segment the
+// junction, and find the bases of maximum posterior probability.
+
+fn segment(ll: &[[f64; 4]], vref: &[u8], dseq: &[u8], jref: &[u8]) -> Vec<u8> {
+    let len = ll.len();
+
+    // Define the templated bases, and the site scores.
+
+    let vtop = vref.len().min(len);
+    let jbot = len - jref.len().min(len);
+    let vbase = |p: usize| vref[p];
+    let jbase = |p: usize| jref[jref.len() - (len - p)];
+    let uniform = [0.25_f64.ln(); 4];
+    let sn = (0..len)
+        .map(|p| site_score(&ll[p], &uniform))
+        .collect::<Vec<f64>>();
+    let mut sv = vec![0.0; vtop + 1];
+    for p in 0..vtop {
+        sv[p + 1] = sv[p] + site_score(&ll[p], &templated_prior(vbase(p)));
+    }
+    let mut sj = vec![0.0; len + 1];
+    for p in (jbot..len).rev() {
+        sj[p] = sj[p + 1] + site_score(&ll[p], &templated_prior(jbase(p)));
+    }
+    let mut snx = vec![0.0; len + 1];
+    for p in 0..len {
+        snx[p + 1] = snx[p] + sn[p];
+    }
+
+    // Find the P sites for given V and J boundaries, as (site, palindromic base).
+
+    let p_sites = |a: usize, b: usize| {
+        let mut x = Vec::<(usize, u8)>::new();
+        if a == vref.len() {
+            for i in 0..MAX_P.min(b - a).min(a) {
+                x.push((a + i, complement(vref[a - 1 - i])));
+            }
+        }
+        if len - b == jref.len() {
+            for i in 0..MAX_P.min(b - a).min(jref.len()) {
+                if !x.iter().any(|y| y.0 == b - 1 - i) {
+                    x.push((b - 1 - i, complement(jref[i])));
+                }
+            }
+        }
+        x
+    };
+
+    // Find the segmentation of maximum likelihood.  A D placement is given by its offset delta,
+    // the difference between a site and the corresponding position on the D, and the sites
+    // start..stop that it covers.
+
+    let (amin, bmax) = (vtop.saturating_sub(MAX_DEL), (jbot + MAX_DEL).min(len));
+    let mut opt = (
+        f64::MIN,
+        vtop,
+        jbot.max(vtop),
+        None::<(isize, usize, usize)>,
+    );
+    for a in amin..=vtop {
+        for b in a.max(jbot)..=bmax.max(a) {
+            let mut score = sv[a] + sj[b] + snx[b] - snx[a];
+            for (p, pal) in p_sites(a, b) {
+                score += site_score(&ll[p], &p_prior(pal)) - sn[p];
+            }
+            let mut dbest = (0.0, None);
+            if !dseq.is_empty() {
+                let dl = dseq.len() as isize;
+                for delta in a as isize - dl + 1..b as isize {
+                    let start = a.max(delta.max(0) as usize);
+                    let stop = b.min((delta + dl) as usize);
+                    if start >= stop {
+                        continue;
+                    }
+                    let gains = (start..stop)
+                        .map(|p| {
+                            let g = dseq[(p as isize - delta) as usize];
+                            site_score(&ll[p], &templated_prior(g)) - sn[p]
+                        })
+                        .collect::<Vec<f64>>();
+                    let (gain, s1, s2) = max_run(&gains);
+                    if gain > dbest.0 {
+                        dbest = (gain, Some((delta, start + s1, start + s2)));
+                    }
+                }
+            }
+            score += dbest.0;
+            if score > opt.0 {
+                opt = (score, a, b, dbest.1);
+            }
+        }
+    }
+
+    // Define the prior at each site, and find the bases of maximum posterior probability.
+
+    let (_, a, b, dplace) = opt;
+    let mut priors = vec![uniform; len];
+    for p in 0..len {
+        if p < a {
+            priors[p] = templated_prior(vbase(p));
+        } else if p >= b {
+            priors[p] = templated_prior(jbase(p));
+        }
+    }
+    for (p, pal) in p_sites(a, b) {
+        priors[p] = p_prior(pal);
+    }
+    if let Some((delta, start, stop)) = dplace {
+        let dl = dseq.len();
+        let dpos = |p: usize| (p as isize - delta) as usize;
+        if dpos(start) == 0 {
+            for i in 0..MAX_P.min(start - a).min(dl) {
+                priors[start - 1 - i] = p_prior(complement(dseq[i]));
+            }
+        }
+        if dpos(stop - 1) == dl - 1 {
+            for i in 0..MAX_P.min(b - stop).min(dl) {
+                priors[stop + i] = p_prior(complement(dseq[dl - 1 - i]));
+            }
+        }
+        for p in start..stop {
+            priors[p] = templated_prior(dseq[dpos(p)]);
+        }
+    }
+    let mut dna = Vec::<u8>::with_capacity(len);
+    for p in 0..len {
+        let post = (0..4)
+            .map(|x| ll[p][x] + priors[p][x])
+            .collect::<Vec<f64>>();
+        let m = post.iter().cloned().fold(f64::MIN, f64::max);
+        let top = (0..4).filter(|x| post[*x] == m).collect::<Vec<usize>>();
+        dna.push(if top.len() == 1 { BASES[top[0]] } else { b'N' });
+    }
+    dna
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Determine if the UCA is used.
+
+pub fn uca_needed(ctl: &EncloneControl) -> bool {
+    !ctl.gen_opt.uca_fasta.is_empty()
+        || ctl
+            .clono_print_opt
+            .cvars
+            .iter()
+            .chain(ctl.parseable_opt.pcols.iter())
+            .any(|x| abbr_var(x).1.starts_with("uca_"))
+}
+
+// Find the UCAs for all columns of a clonotype, to be stored in its ColInfo.
+
+pub fn ucas(
+    exacts: &Vec<usize>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    rsi: &ColInfo,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+    ctl: &EncloneControl,
+) -> Vec<(Vec<u8>, Vec<usize>)> {
+    (0..rsi.mat.len())
+        .map(|col| compute_uca(col, exacts, exact_clonotypes, rsi, refdata, dref, ctl))
+        .collect()
+}
+
+// Find the UCA for the chain in a given column of a clonotype, returning its sequence, and the
+// D segments (none, one or two) that were used.  This is the value stored in the ColInfo, if
+// there is one.
+
+pub fn uca(
+    col: usize,
+    exacts: &Vec<usize>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    rsi: &ColInfo,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+    ctl: &EncloneControl,
+) -> (Vec<u8>, Vec<usize>) {
+    match rsi.ucas.get(col) {
+        Some(x) => x.clone(),
+        None => compute_uca(col, exacts, exact_clonotypes, rsi, refdata, dref, ctl),
+    }
+}
+
+fn compute_uca(
+    col: usize,
+    exacts: &Vec<usize>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    rsi: &ColInfo,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+    ctl: &EncloneControl,
+) -> (Vec<u8>, Vec<usize>) {
+    let len = rsi.seq_del_lens[col];
+    let mut vref = refdata.refs[rsi.vids[col]].to_ascii_vec();
+    if let Some(vp) = rsi.vpids[col] {
+        vref = dref[vp].nt_sequence.clone();
+    }
+    let jref = refdata.refs[rsi.jids[col]].to_ascii_vec();
+
+    // Estimate the mutation rate of each exact subclonotype, and compute the log likelihood of
+    // each base at each site.
+
+    let vlen = vref.len().saturating_sub(ctl.heur.ref_v_trim).min(len);
+    let mut ll = vec![[0.0; 4]; len];
+    let mut best = (f64::MAX, 0, 0);
+    for (u, e) in exacts.iter().enumerate() {
+        if let Some(r) = rsi.mat[col][u] {
+            let seq = &exact_clonotypes[*e].share[r].seq_del_amino;
+            let (mut n, mut diffs) = (0, 0);
+            for p in 0..vlen {
+                if base_index(seq[p]).is_some() {
+                    n += 1;
+                    if seq[p] != vref[p] {
+                        diffs += 1;
+                    }
+                }
+            }
+            let mu = ((diffs as f64 + 1.0) / (n as f64 + 2.0)).clamp(MIN_MU, MAX_MU);
+            if mu < best.0 {
+                best = (mu, *e, r);
+            }
+            let (same, other) = ((1.0 - mu).ln(), (mu / 3.0).ln());
+            for p in 0..len {
+                if let Some(b) = base_index(seq[p]) {
+                    for x in 0..4 {
+                        ll[p][x] += if x == b { same } else { other };
+                    }
+                }
+            }
+        }
+    }
+
+    // Find the optimal D.
+
+    let mut ds = Vec::<usize>::new();
+    let ex = &exact_clonotypes[best.1];
+    if ex.share[best.2].left && !refdata.ds.is_empty() {
+        let mid = best.2;
+        let mut scores = Vec::<f64>::new();
+        let mut dsx = Vec::<Vec<usize>>::new();
+        opt_d(
+            ex.share[mid].v_ref_id,
+            ex.share[mid].j_ref_id,
+            &ex.share[mid].seq_del,
+            &ex.share[mid].annv,
+            &ex.share[mid].cdr3_aa,
+            refdata,
+            dref,
+            &mut scores,
+            &mut dsx,
+            ctl.gen_opt.jscore_match,
+            ctl.gen_opt.jscore_mismatch,
+            ctl.gen_opt.jscore_gap_open,
+            ctl.gen_opt.jscore_gap_extend,
+            ctl.gen_opt.jscore_bits_multiplier,
+            rsi.vpids[col],
+        );
+        if !dsx.is_empty() {
+            ds = dsx[0].clone();
+        }
+    }
+    let mut dseq = Vec::<u8>::new();
+    for d in ds.iter() {
+        dseq.append(&mut refdata.refs[*d].to_ascii_vec());
+    }
+
+    (segment(&ll, &vref, &dseq, &jref), ds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_max_run() {
+        assert_eq!(max_run(&[]), (0.0, 0, 0));
+        assert_eq!(max_run(&[-1.0, -2.0]), (0.0, 0, 0));
+        assert_eq!(max_run(&[2.0]), (2.0, 0, 1));
+        assert_eq!(max_run(&[1.0, -3.0, 2.0, -1.0, 2.0, -5.0]), (3.0, 2, 5));
+        assert_eq!(max_run(&[1.0, -1.0, 1.0]), (1.0, 0, 1));
+        assert_eq!(max_run(&[-1.0, 1.0, 1.0, -0.5]), (2.0, 1, 3));
+    }
+
+    // Compute the log likelihoods for a sequence observed in several exact subclonotypes, where
+    // sites that are lower case are unobserved.
+
+    fn observe(seq: &[u8]) -> Vec<[f64; 4]> {
+        let (mu, count) = (0.01, 3.0);
+        seq.iter()
+            .map(|b| match base_index(*b) {
+                Some(i) => {
+                    let mut x = [count * (mu / 3.0_f64).ln(); 4];
+                    x[i] = count * (1.0 - mu).ln();
+                    x
+                }
+                None => [0.0; 4],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_segment_no_d() {
+        // The V is untrimmed, and is followed by a P base (T, the complement of the last V base),
+        // then three N bases, then the J, with two bases deleted.  A V base, the P base, the
+        // middle N base and a J base are unobserved.  The P base is inferred from the V, and the
+        // N base is N.
+
+        let vref = b"GCTAGCCTGA";
+        let jref = b"TGGACCAAGGTC";
+        let obs = b"GCTAgCCTGAtCcCGAcCAAGGTC";
+        let dna = segment(&observe(obs), vref, b"", jref);
+        assert_eq!(dna, b"GCTAGCCTGATCNCGACCAAGGTC".to_vec());
+    }
+
+    #[test]
+    fn test_segment_d() {
+        // Three bases are deleted from the V and one from the J, and the D is trimmed by two
+        // bases at each end, with three N bases before it and two after it.  An unobserved base in
+        // the D is inferred from the D, and an unobserved N base is N.
+
+        let vref = b"GCTAGCCTGACAG";
+        let dseq = b"TTGTATTACGAC";
+        let jref = b"CTGGACCAAGGTC";
+        let obs = b"GCTAGCCTGAAcAGTAtTACGCATGGACCAAGGTC";
+        let dna = segment(&observe(obs), vref, dseq, jref);
+        assert_eq!(dna, b"GCTAGCCTGAANAGTATTACGCATGGACCAAGGTC".to_vec());
+
+        // Without the D, the D region is N, except for bases that are observed.
+
+        let dna = segment(&observe(obs), vref, b"", jref);
+        assert_eq!(dna, b"GCTAGCCTGAANAGTANTACGCATGGACCAAGGTC".to_vec());
+    }
+}
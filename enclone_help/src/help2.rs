@@ -533,6 +533,13 @@ pub fn help2(args: &Vec<String>, _ctl: &EncloneControl, h: &mut HelpDesk) -> Res
         h.doc2("Note that in many cases D gene assignments are essentially random, as");
         h.doc2("it is often not possible to know the true D gene assignment.");
         h.doc2("If the value is \"null\" it means that having no D gene at all scores better");
+        h.ldoc(
+            "uca_dna",
+            "inferred unmutated common ancestor of the clonotype, from the start of the",
+        );
+        h.doc2("V segment to the end of the J segment, using the donor V allele, the optimal");
+        h.doc2("D gene, and the most likely N/P bases given the exact subclonotypes");
+        h.doc("uca_aa", "translation of uca_dna");
         h.ldoc(
             "vjlen",
             "number of bases from the start of the V region to the end of the J region",
@@ -149,6 +149,18 @@ pub fn help3(args: &Vec<String>, h: &mut HelpDesk) -> Result<(), String> {
             "clonotype_id",
             "identifier of clonotype within the clonotype group = 0, 1, ...",
         );
+        h.doc2("or the stable id, if CLONOTYPE_IDS=stable is specified");
+        h.ldoc(
+            "stable_id",
+            "identifier of clonotype derived from its content, and so likely to be",
+        );
+        h.doc2("the same across runs: a hash of the V, J and CDR3 of the lead chain of");
+        h.doc2("the largest exact subclonotype, with suffix -2, -3, ... on collision");
+        h.ldoc(
+            "join_id",
+            "integer identifier of clonotype, assigned just after joining, that is",
+        );
+        h.doc2("kept across runs that use JOIN_STATE_IN; see also JOIN_ID_MAP");
         h.print_tab2()?;
         h.print("\n")?;
 
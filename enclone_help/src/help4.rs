@@ -358,6 +358,10 @@ pub fn help4(args: &Vec<String>, mut h: &mut HelpDesk) -> Result<(), String> {
         );
         h.doc2("or origin or donor or tag short name; may name only one such category");
         h.doc("clonotype_ncells", "total number of cells in the clonotype");
+        h.doc(
+            "stable_id",
+            "identifier of clonotype derived from its content; see enclone help parseable",
+        );
         h.ldoc(
             "nd<k>",
             "For k a positive integer, this creates k+1 fields, that are specific to each",
@@ -0,0 +1,279 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Library API for running enclone in-process.  Inputs, filters and grouping options are
+// specified as typed structs, and the result is an EncloneResults object, with no terminal
+// formatting involved.  For example:
+//
+//     let results = Enclone::new()
+//         .inputs(Inputs { bcr: vec![path], ..Default::default() })
+//         .filters(Filters { min_cells: Some(2), ..Default::default() })
+//         .grouping(Grouping { criteria: vec![GroupCriterion::VjRefname], min_group: Some(2) })
+//         .variables(&["u_cell1", "const1"])
+//         .run()?;
+//
+// Internally the options are translated into enclone arguments, so that they are validated in
+// exactly the same way as on the command line.  Because enclone arguments are lists separated
+// by characters such as comma, a value that contains the separator of its argument cannot be
+// represented, and is rejected, rather than being silently split.  Options that do not have a
+// typed equivalent may be passed via Enclone::arg.
+
+use crate::main_enclone::main_enclone_api;
+use enclone_core::results::EncloneResults;
+use itertools::Itertools;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Input data.  The VDJ paths may be Cell Ranger outs directories (or their parents), or AIRR
+// rearrangement TSV files.  If gex is nonempty, it must be parallel to the VDJ paths.
+
+#[derive(Clone, Debug, Default)]
+pub struct Inputs {
+    pub bcr: Vec<String>,
+    pub tcr: Vec<String>,
+    pub tcrgd: Vec<String>,
+    pub gex: Vec<String>,
+    pub meta: Option<String>,      // META file, in place of the above
+    pub reference: Option<String>, // reference fasta file
+    pub mouse: bool,
+    pub pre: Vec<String>, // directories to prepend to paths
+}
+
+// Filters on clonotypes.  These correspond to the enclone arguments of the same name.
+
+#[derive(Clone, Debug, Default)]
+pub struct Filters {
+    pub min_cells: Option<usize>,
+    pub max_cells: Option<usize>,
+    pub min_chains: Option<usize>,
+    pub max_chains: Option<usize>,
+    pub min_umis: Option<usize>,
+    pub min_exacts: Option<usize>,
+    pub cdr3: Option<String>, // regular expression that some CDR3 must match
+    pub seg: Vec<String>,     // require use of one of these reference segments
+    pub barcode: Vec<String>, // require one of these barcodes
+}
+
+// Grouping of clonotypes.  The criteria are combined, as for GROUP=.
+
+#[derive(Clone, Debug, Default)]
+pub struct Grouping {
+    pub criteria: Vec<GroupCriterion>,
+    pub min_group: Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GroupCriterion {
+    VjRefname,
+    VHeavyRefname,
+    VjHeavyRefname,
+    VdjRefname,
+    VdjHeavyRefname,
+    Len,
+    Cdr3Len,
+    Cdr3HeavyLen,
+    Cdr3LightLen,
+    Heavy(f64), // percent identity
+    AaHeavy(f64),
+    Light(f64),
+    AaLight(f64),
+    Cdr3Heavy(f64),
+    Cdr3AaHeavy(f64),
+    Cdr3Light(f64),
+    Cdr3AaLight(f64),
+}
+
+impl GroupCriterion {
+    pub fn arg(&self) -> String {
+        match self {
+            GroupCriterion::VjRefname => "vj_refname".to_string(),
+            GroupCriterion::VHeavyRefname => "v_heavy_refname".to_string(),
+            GroupCriterion::VjHeavyRefname => "vj_heavy_refname".to_string(),
+            GroupCriterion::VdjRefname => "vdj_refname".to_string(),
+            GroupCriterion::VdjHeavyRefname => "vdj_heavy_refname".to_string(),
+            GroupCriterion::Len => "len".to_string(),
+            GroupCriterion::Cdr3Len => "cdr3_len".to_string(),
+            GroupCriterion::Cdr3HeavyLen => "cdr3_heavy_len".to_string(),
+            GroupCriterion::Cdr3LightLen => "cdr3_light_len".to_string(),
+            GroupCriterion::Heavy(x) => format!("heavy>={}%", x),
+            GroupCriterion::AaHeavy(x) => format!("aa_heavy>={}%", x),
+            GroupCriterion::Light(x) => format!("light>={}%", x),
+            GroupCriterion::AaLight(x) => format!("aa_light>={}%", x),
+            GroupCriterion::Cdr3Heavy(x) => format!("cdr3_heavy>={}%", x),
+            GroupCriterion::Cdr3AaHeavy(x) => format!("cdr3_aa_heavy>={}%", x),
+            GroupCriterion::Cdr3Light(x) => format!("cdr3_light>={}%", x),
+            GroupCriterion::Cdr3AaLight(x) => format!("cdr3_aa_light>={}%", x),
+        }
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Form an argument name=v1<sep>v2..., checking that no value contains a separator character.
+
+fn list_arg(name: &str, vals: &[String], sep: char, bad: &[char]) -> Result<String, String> {
+    for v in vals.iter() {
+        if let Some(c) = v.chars().find(|c| bad.contains(c)) {
+            return Err(format!(
+                "\nThe value \"{}\" for {} cannot contain the character {}.\n",
+                v, name, c
+            ));
+        }
+    }
+    Ok(format!("{}={}", name, vals.iter().format(&sep.to_string())))
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Enclone {
+    inputs: Inputs,
+    filters: Filters,
+    grouping: Grouping,
+    variables: Vec<String>,
+    args: Vec<String>,
+}
+
+impl Enclone {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn inputs(mut self, inputs: Inputs) -> Self {
+        self.inputs = inputs;
+        self
+    }
+
+    pub fn filters(mut self, filters: Filters) -> Self {
+        self.filters = filters;
+        self
+    }
+
+    pub fn grouping(mut self, grouping: Grouping) -> Self {
+        self.grouping = grouping;
+        self
+    }
+
+    // Variables whose values are to be reported for each exact subclonotype, in
+    // ExactSubclonotypeResult.values.  These are the same as the variables allowed by PCOLS.
+
+    pub fn variables(mut self, vars: &[&str]) -> Self {
+        self.variables = vars.iter().map(|v| v.to_string()).collect();
+        self
+    }
+
+    // Pass an arbitrary enclone argument, e.g. "MIX_DONORS".
+
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    // Translate into enclone arguments.  Paths are separated by commas, and may not contain
+    // commas, or the semicolons and colons that separate donors and origins.
+
+    pub fn to_args(&self) -> Result<Vec<String>, String> {
+        let path_bad = [',', ';', ':'];
+        let mut args = vec!["enclone".to_string()];
+        let i = &self.inputs;
+        if !i.pre.is_empty() {
+            args.push(list_arg("PRE", &i.pre, ',', &path_bad)?);
+        }
+        for (name, paths) in [("BCR", &i.bcr), ("TCR", &i.tcr), ("TCRGD", &i.tcrgd)] {
+            if !paths.is_empty() {
+                args.push(list_arg(name, paths, ',', &path_bad)?);
+            }
+        }
+        if !i.gex.is_empty() {
+            args.push(list_arg("GEX", &i.gex, ',', &path_bad)?);
+        }
+        if let Some(meta) = &i.meta {
+            args.push(format!("META={}", meta));
+        }
+        if let Some(r) = &i.reference {
+            args.push(format!("REF={}", r));
+        }
+        if i.mouse {
+            args.push("MOUSE".to_string());
+        }
+        let f = &self.filters;
+        for (name, x) in [
+            ("MIN_CELLS", f.min_cells),
+            ("MAX_CELLS", f.max_cells),
+            ("MIN_CHAINS", f.min_chains),
+            ("MAX_CHAINS", f.max_chains),
+            ("MIN_UMIS", f.min_umis),
+            ("MIN_EXACTS", f.min_exacts),
+            ("MIN_GROUP", self.grouping.min_group),
+        ] {
+            if let Some(x) = x {
+                args.push(format!("{}={}", name, x));
+            }
+        }
+        if let Some(cdr3) = &f.cdr3 {
+            args.push(format!("CDR3={}", cdr3));
+        }
+        if !f.seg.is_empty() {
+            args.push(list_arg("SEG", &f.seg, '|', &['|'])?);
+        }
+        if !f.barcode.is_empty() {
+            args.push(list_arg("BARCODE", &f.barcode, ',', &[','])?);
+        }
+        if !self.grouping.criteria.is_empty() {
+            let g = self.grouping.criteria.iter().map(|c| c.arg());
+            args.push(format!("GROUP={}", g.format(",")));
+        }
+        if !self.variables.is_empty() {
+            args.push(list_arg("PCOLS", &self.variables, ',', &[',', '='])?);
+        }
+        args.append(&mut self.args.clone());
+        args.push("NOPRINT".to_string());
+        args.push("NOPAGER".to_string());
+        args.push("NO_NEWLINE".to_string());
+        Ok(args)
+    }
+
+    // Run enclone.
+
+    pub fn run(&self) -> Result<EncloneResults, String> {
+        let state = main_enclone_api(&self.to_args()?)?;
+        Ok(state.results.unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_args() {
+        let e = Enclone::new()
+            .inputs(Inputs {
+                bcr: vec!["a".to_string(), "b".to_string()],
+                ..Default::default()
+            })
+            .filters(Filters {
+                min_cells: Some(2),
+                seg: vec!["IGHV1-2".to_string(), "IGHV3-23".to_string()],
+                ..Default::default()
+            })
+            .variables(&["u_cell1", "const1"]);
+        let args = e.to_args().unwrap();
+        assert!(args.contains(&"BCR=a,b".to_string()));
+        assert!(args.contains(&"MIN_CELLS=2".to_string()));
+        assert!(args.contains(&"SEG=IGHV1-2|IGHV3-23".to_string()));
+        assert!(args.contains(&"PCOLS=u_cell1,const1".to_string()));
+        assert!(!args.contains(&"API".to_string()));
+
+        // Values containing separators are rejected.
+
+        let bad = Enclone::new().inputs(Inputs {
+            bcr: vec!["a,b".to_string()],
+            ..Default::default()
+        });
+        assert!(bad.to_args().is_err());
+        let bad = Enclone::new().filters(Filters {
+            seg: vec!["IGHV1-2|IGHV3-23".to_string()],
+            ..Default::default()
+        });
+        assert!(bad.to_args().is_err());
+        assert!(Enclone::new().variables(&["x=1"]).to_args().is_err());
+    }
+}
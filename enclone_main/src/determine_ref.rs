@@ -2,6 +2,8 @@
 
 // Start of code to determine the reference sequence that is to be used.
 
+use enclone_args::read_airr::is_airr_path;
+use enclone_args::read_proto_in::{is_proto_in_path, proto_in_reference};
 use enclone_core::defs::EncloneControl;
 use io_utils::{open_for_read, open_maybe_compressed, path_exists, read_vector_entry_from_json};
 use serde_json::Value;
@@ -25,7 +27,10 @@ pub fn determine_ref(ctl: &mut EncloneControl, refx: &mut String) -> Result<(),
         ann = "contig_annotations.json";
     }
     let mut jsonx = String::new();
-    if ctl.origin_info.n() > 0 {
+    let airr = ctl.origin_info.n() > 0 && is_airr_path(&ctl.origin_info.dataset_path[0], ctl);
+    let proto_in =
+        ctl.origin_info.n() > 0 && is_proto_in_path(&ctl.origin_info.dataset_path[0], ctl);
+    if ctl.origin_info.n() > 0 && !airr && !proto_in {
         let json = format!("{}/{}", ctl.origin_info.dataset_path[0], ann);
         let json_lz4 = format!("{}/{}.lz4", ctl.origin_info.dataset_path[0], ann);
         if !path_exists(&json) && !path_exists(&json_lz4) {
@@ -183,7 +188,7 @@ pub fn determine_ref(ctl: &mut EncloneControl, refx: &mut String) -> Result<(),
 
     // Step 4.  Test for presence of a reference file in the VDJ directories.
 
-    if refx.is_empty() && ctl.gen_opt.refname.is_empty() {
+    if refx.is_empty() && ctl.gen_opt.refname.is_empty() && !airr && !proto_in {
         let rpaths = [
             "outs/vdj_reference/fasta/regions.fa",
             "vdj_reference/fasta/regions.fa",
@@ -339,5 +344,19 @@ pub fn determine_ref(ctl: &mut EncloneControl, refx: &mut String) -> Result<(),
                 .to_string(),
         );
     }
+
+    // Step 6.  PROTO_IN input includes the reference that was used.  AIRR input does not say what
+    // reference was used, so if we still don't have one, use the built-in reference.
+
+    if refx.is_empty() && proto_in {
+        *refx = proto_in_reference(&ctl.gen_opt.proto_in)?;
+    }
+    if refx.is_empty() && airr {
+        if !ctl.gen_opt.mouse {
+            *refx = (*human_ref()).to_string();
+        } else {
+            *refx = (*mouse_ref()).to_string();
+        }
+    }
     Ok(())
 }
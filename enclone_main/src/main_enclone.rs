@@ -14,8 +14,11 @@ use enclone::secret::fetch_secmem;
 use enclone_args::load_gex::get_gex_info;
 use enclone_args::proc_args2::is_simple_arg;
 use enclone_args::proc_args_check::{
-    check_gvars, check_lvars, check_one_lvar, check_pcols, get_known_features,
+    check_gvars, check_lvars, check_one_lvar, check_pcols, check_plot_embed_vars,
+    get_known_features,
 };
+use enclone_args::read_airr::is_airr_path;
+use enclone_args::read_proto_in::is_proto_in_path;
 use enclone_core::cell_color::CellColor;
 use enclone_core::defs::EncloneControl;
 use enclone_core::enclone_structs::*;
@@ -36,7 +39,18 @@ use vector_utils::{bin_member, next_diff, unique_sort};
 // ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
 
 pub fn main_enclone(args: &Vec<String>) -> Result<EncloneState, String> {
-    let setup = main_enclone_setup(args)?;
+    main_enclone_core(args, false)
+}
+
+// Run enclone in-process, computing typed results (EncloneState.results), and the values of
+// PCOLS variables even if POUT is not specified.  This is used by the library API in api.rs.
+
+pub fn main_enclone_api(args: &Vec<String>) -> Result<EncloneState, String> {
+    main_enclone_core(args, true)
+}
+
+fn main_enclone_core(args: &Vec<String>, api: bool) -> Result<EncloneState, String> {
+    let setup = main_enclone_setup_core(args, api)?;
     if setup.tall.is_none() {
         return Ok(EncloneState::default());
     }
@@ -48,6 +62,14 @@ pub fn main_enclone(args: &Vec<String>) -> Result<EncloneState, String> {
 }
 
 pub fn main_enclone_setup(args: &Vec<String>) -> Result<EncloneSetup, String> {
+    main_enclone_setup_core(args, false)
+}
+
+pub fn main_enclone_setup_api(args: &Vec<String>) -> Result<EncloneSetup, String> {
+    main_enclone_setup_core(args, true)
+}
+
+fn main_enclone_setup_core(args: &Vec<String>, api: bool) -> Result<EncloneSetup, String> {
     let tall = Instant::now();
 
     // Test for enclone --check.
@@ -89,6 +111,7 @@ pub fn main_enclone_setup(args: &Vec<String>) -> Result<EncloneSetup, String> {
     let args_orig = args.clone();
     let mut ctl = EncloneControl::default();
     let args = critical_args(args, &mut ctl)?;
+    ctl.gen_opt.api = api;
     ctl.start_time = Some(tall);
     for i in 0..args.len() {
         let arg = &args[i];
@@ -242,6 +265,9 @@ pub fn main_enclone_setup(args: &Vec<String>) -> Result<EncloneSetup, String> {
             ctl.parseable_opt.pbarcode,
         )?;
     }
+    if !ctl.plot_opt.plot_embed_filename.is_empty() {
+        check_plot_embed_vars(&ctl, &gex_info)?;
+    }
     match ctl.plot_opt.cell_color {
         CellColor::ByVariableValue(ref x) => {
             check_pcols(&ctl, &gex_info, &vec![x.var.clone()], true)?;
@@ -251,6 +277,9 @@ pub fn main_enclone_setup(args: &Vec<String>) -> Result<EncloneSetup, String> {
         }
         _ => {}
     };
+    if let CellColor::ByVariableValue(ref x) = ctl.plot_opt.tree_svg_color {
+        check_pcols(&ctl, &gex_info, &vec![x.var.clone()], true)?;
+    }
     let mut bound_vars = Vec::<String>::new();
     for bi in 0..ctl.clono_filt_opt.bounds.len() {
         let x = &ctl.clono_filt_opt.bounds[bi];
@@ -450,6 +479,12 @@ pub fn main_enclone_setup(args: &Vec<String>) -> Result<EncloneSetup, String> {
     // Get VDJ data paths.
 
     for li in 0..ctl.origin_info.dataset_path.len() {
+        if is_airr_path(&ctl.origin_info.dataset_path[li], &ctl)
+            || is_proto_in_path(&ctl.origin_info.dataset_path[li], &ctl)
+        {
+            ctl.pathlist.push(ctl.origin_info.dataset_path[li].clone());
+            continue;
+        }
         let json = format!("{}/{}", ctl.origin_info.dataset_path[li], ann);
         let json_lz4 = format!("{}/{}.lz4", ctl.origin_info.dataset_path[li], ann);
         if !path_exists(&json) && !path_exists(&json_lz4) {
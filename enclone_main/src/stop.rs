@@ -4,6 +4,7 @@ use crate::opt_d_val::make_opt_d_val;
 use crate::subset::subset_json;
 use enclone_core::defs::{ColInfo, WALLCLOCK};
 use enclone_core::enclone_structs::*;
+use enclone_core::results::make_results;
 use enclone_print::print_clonotypes::print_clonotypes;
 use enclone_tail::grouper::grouper;
 use enclone_tail::tail::tail_code;
@@ -45,6 +46,7 @@ pub fn main_enclone_stop(mut inter: EncloneIntermediates) -> Result<EncloneState
     let vdj_cells = &inter.ex.vdj_cells;
     let refdata = &inter.setup.refdata;
     let join_info = &inter.ex.join_info;
+    let join_ids = &inter.ex.join_ids;
     let drefs = &inter.ex.drefs;
     let gex_info = &inter.setup.gex_info;
     let sr = &inter.ex.sr;
@@ -251,6 +253,7 @@ pub fn main_enclone_stop(mut inter: EncloneIntermediates) -> Result<EncloneState
     let mut in_center = Vec::<bool>::new();
     let mut rsi = Vec::<ColInfo>::new(); // ditto
     let mut out_datas = Vec::<Vec<HashMap<String, String>>>::new();
+    let mut stable_ids = Vec::<String>::new();
     let mut tests = Vec::<usize>::new();
     let mut controls = Vec::<usize>::new();
     if ctl.gen_opt.trace_barcode.len() > 0 {
@@ -288,6 +291,7 @@ pub fn main_enclone_stop(mut inter: EncloneIntermediates) -> Result<EncloneState
         &mut in_center,
         &mut rsi,
         &mut out_datas,
+        &mut stable_ids,
         &mut tests,
         &mut controls,
         &mut fate,
@@ -358,6 +362,7 @@ pub fn main_enclone_stop(mut inter: EncloneIntermediates) -> Result<EncloneState
     }
     erase_if(&mut exacts, &to_delete);
     erase_if(&mut out_datas, &to_delete);
+    erase_if(&mut stable_ids, &to_delete);
     erase_if(&mut rsi, &to_delete);
     erase_if(&mut pics, &to_delete);
     ctl.perf_stats(&t, "degrouping");
@@ -395,7 +400,9 @@ pub fn main_enclone_stop(mut inter: EncloneIntermediates) -> Result<EncloneState
         exact_clonotypes,
         ctl,
         &mut out_datas,
+        &stable_ids,
         join_info,
+        join_ids,
         gex_info,
         vdj_cells,
         fate,
@@ -411,6 +418,22 @@ pub fn main_enclone_stop(mut inter: EncloneIntermediates) -> Result<EncloneState
         &mut summary,
     )?;
 
+    // Make typed results for the library API.
+
+    let mut results = None;
+    if ctl.gen_opt.api {
+        results = Some(make_results(
+            ctl,
+            refdata,
+            exact_clonotypes,
+            &exacts,
+            &rsi,
+            &groups,
+            &out_datas,
+            &stable_ids,
+        ));
+    }
+
     // Report profiling.
 
     #[cfg(not(target_os = "windows"))]
@@ -495,5 +518,9 @@ pub fn main_enclone_stop(mut inter: EncloneIntermediates) -> Result<EncloneState
         ngroup: ctl.clono_group_opt.ngroup,
         pretty: ctl.pretty,
     };
-    Ok(EncloneState { inter, outs })
+    Ok(EncloneState {
+        inter,
+        outs,
+        results,
+    })
 }
@@ -2,7 +2,7 @@
 
 use crate::print_utils1::insert_position_rows;
 use ansi_escape::bold;
-use enclone_core::defs::{justification, ColInfo, EncloneControl, ExactClonotype};
+use enclone_core::defs::{abbr_var, justification, ColInfo, EncloneControl, ExactClonotype};
 use itertools::Itertools;
 use string_utils::{strme, TextUtils};
 use vector_utils::unique_sort;
@@ -34,10 +34,7 @@ pub fn build_table_stuff(
     }
     justify.push(b'l');
     for i in 0..lvars.len() {
-        let mut x = lvars[i].to_string();
-        if x.contains(':') {
-            x = x.before(":").to_string();
-        }
+        let x = abbr_var(&lvars[i]).0;
         row1.push(x.clone());
         justify.push(justification(&x));
     }
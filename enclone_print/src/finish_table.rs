@@ -4,7 +4,7 @@ use crate::build_table_stuff::build_table_stuff;
 use crate::print_utils1::make_table;
 use crate::print_utils3::{add_header_text, insert_reference_rows};
 use crate::print_utils5::{build_diff_row, insert_consensus_row};
-use enclone_core::defs::{justification, ColInfo, EncloneControl, ExactClonotype};
+use enclone_core::defs::{abbr_var, justification, ColInfo, EncloneControl, ExactClonotype};
 use enclone_proto::types::DonorReferenceItem;
 use std::collections::HashMap;
 use string_utils::TextUtils;
@@ -155,10 +155,7 @@ pub fn finish_table(
         let mut row = Vec::<String>::new();
         row.push("Σ".to_string());
         for i in 0..lvars.len() {
-            let mut x = lvars[i].clone();
-            if x.contains(':') {
-                x = x.before(":").to_string();
-            }
+            let x = abbr_var(&lvars[i]).0;
             let mut found = false;
             let mut total = 0.0;
             for j in 0..stats.len() {
@@ -191,10 +188,7 @@ pub fn finish_table(
         let mut row = Vec::<String>::new();
         row.push("μ".to_string());
         for i in 0..lvars.len() {
-            let mut x = lvars[i].clone();
-            if x.contains(':') {
-                x = x.before(":").to_string();
-            }
+            let x = abbr_var(&lvars[i]).0;
             let mut found = false;
             let mut total = 0.0;
             for j in 0..stats.len() {
@@ -3,7 +3,7 @@
 // This set of functions writes a protobuf data structure that
 // Loupe uses to access clonotype data.
 
-use enclone_proto::proto_io::write_proto;
+use enclone_proto::proto_io::{write_proto, write_proto_indexed};
 use enclone_proto::PROTO_VERSION;
 use vdj_ann::refx;
 
@@ -382,8 +382,11 @@ pub fn make_loupe_clonotype(
             }));
         }
         let mut cell_barcodes = Vec::<String>::new();
+        let mut cell_datasets = Vec::<String>::new();
         for l in 0..ex.clones.len() {
             cell_barcodes.push(ex.clones[l][0].barcode.clone());
+            let li = ex.clones[l][0].dataset_index;
+            cell_datasets.push(ctl.origin_info.dataset_id[li].clone());
         }
         let inkt_evidence = InvariantTCellAnnotation {
             alpha_chain_gene_match: ex.share[0].inkt_alpha_chain_gene_match,
@@ -409,6 +412,7 @@ pub fn make_loupe_clonotype(
                 })
                 .collect(),
             cell_barcodes,
+            cell_datasets,
             inkt_evidence,
             mait_evidence,
         });
@@ -424,6 +428,7 @@ pub fn make_loupe_clonotype(
         chains: xchains,
         exact_clonotypes: ecl,
         frequency: n as u32,
+        stable_id: None,
     }
 }
 
@@ -472,7 +477,11 @@ pub fn loupe_out(
             write_obj(&enclone_outputs, &ctl.gen_opt.binary);
         }
         if !ctl.gen_opt.proto.is_empty() {
-            write_proto(enclone_outputs, &ctl.gen_opt.proto).unwrap();
+            if ctl.gen_opt.proto_index {
+                write_proto_indexed(enclone_outputs, &ctl.gen_opt.proto).unwrap();
+            } else {
+                write_proto(enclone_outputs, &ctl.gen_opt.proto).unwrap();
+            }
         }
     }
 }
@@ -17,9 +17,13 @@ use crate::print_utils4::{build_show_aa, compute_bu, compute_some_stats};
 use crate::print_utils5::{delete_weaks, vars_and_shares};
 use enclone_args::proc_args_check::involves_gex_fb;
 use enclone_core::allowed_vars::{CVARS_ALLOWED, CVARS_ALLOWED_PCELL, LVARS_ALLOWED};
-use enclone_core::defs::{AlleleData, CloneInfo, ColInfo, EncloneControl, ExactClonotype, GexInfo};
+use enclone_core::defs::{
+    abbr_var, AlleleData, CloneInfo, ColInfo, EncloneControl, ExactClonotype, GexInfo,
+};
 use enclone_core::mammalian_fixed_len::mammalian_fixed_len_peer_groups;
 use enclone_core::set_speakers::set_speakers;
+use enclone_core::stable_id::stable_clonotype_ids;
+use enclone_core::uca::{uca_needed, ucas};
 use enclone_proto::types::{Clonotype, DonorReferenceItem};
 use equiv::EquivRel;
 use qd::Double;
@@ -69,6 +73,7 @@ pub fn print_clonotypes(
     in_center: &mut Vec<bool>,
     rsi: &mut Vec<ColInfo>,
     out_datas: &mut Vec<Vec<HashMap<String, String>>>,
+    stable_ids: &mut Vec<String>,
     tests: &mut Vec<usize>,
     controls: &mut Vec<usize>,
     fate: &mut Vec<HashMap<String, String>>,
@@ -188,14 +193,14 @@ pub fn print_clonotypes(
     //    [parallel to 1]
     // next to last three entries = whitelist contam, denominator for that, low gex count
     // added out_datas (used to be next to last three, now one more)
-    let mut results = Vec::<(
+    type OrbitResult = (
         usize,
         Vec<String>,
         Vec<(Vec<usize>, ColInfo)>,
         usize,
         usize,
         usize,
-        Vec<Clonotype>,
+        Vec<(Vec<usize>, Clonotype)>,
         Vec<Vec<HashMap<String, String>>>,
         isize,
         Vec<bool>,
@@ -203,16 +208,17 @@ pub fn print_clonotypes(
         Vec<(usize, String, String)>,
         Vec<bool>,
         String,
-    )>::new();
-    for i in 0..orbits.len() {
-        results.push((
+    );
+    let mut results = Vec::<OrbitResult>::new();
+    let new_result = |i: usize| {
+        (
             i,
             Vec::<String>::new(),
             Vec::<(Vec<usize>, ColInfo)>::new(),
             0,
             0,
             0,
-            Vec::<Clonotype>::new(),
+            Vec::<(Vec<usize>, Clonotype)>::new(),
             Vec::<Vec<HashMap<String, String>>>::new(),
             0,
             Vec::<bool>::new(),
@@ -220,9 +226,19 @@ pub fn print_clonotypes(
             Vec::new(),
             Vec::new(),
             String::new(),
-        ));
+        )
+    };
+    for i in 0..orbits.len() {
+        results.push(new_result(i));
     }
-    results.par_iter_mut().for_each(|res| {
+
+    // Define the processing of an orbit.  The lead variable stable_id needs the stable id of
+    // the clonotype, which is computed from its exact subclonotypes, except that collision
+    // suffixes are only known once all clonotypes have been found.  These are supplied by
+    // collided_ids, which is empty on the first traversal of the orbits.
+
+    let show_stable_id = lvars.iter().any(|x| abbr_var(x).1 == "stable_id");
+    let process_orbit = |res: &mut OrbitResult, collided_ids: &HashMap<Vec<usize>, String>| {
         let i = res.0;
         let o = &orbits[i];
         let mut od = Vec::<(Vec<usize>, usize, i32)>::new();
@@ -333,6 +349,9 @@ pub fn print_clonotypes(
             let cols = mat.len();
             let mut rsi = define_column_info(ctl, &exacts, exact_clonotypes, &mat, refdata);
             rsi.mat = mat;
+            if uca_needed(ctl) {
+                rsi.ucas = ucas(&exacts, exact_clonotypes, &rsi, refdata, dref, ctl);
+            }
             let mat = &rsi.mat;
 
             // Let n be the total number of cells in this pass.
@@ -363,13 +382,9 @@ pub fn print_clonotypes(
             // Generate Loupe data.
 
             if (!ctl.gen_opt.binary.is_empty() || !ctl.gen_opt.proto.is_empty()) && pass == 2 {
-                loupe_clonotypes.push(make_loupe_clonotype(
-                    exact_clonotypes,
-                    &exacts,
-                    &rsi,
-                    refdata,
-                    dref,
-                    ctl,
+                loupe_clonotypes.push((
+                    exacts.clone(),
+                    make_loupe_clonotype(exact_clonotypes, &exacts, &rsi, refdata, dref, ctl),
                 ));
             }
 
@@ -626,6 +641,17 @@ pub fn print_clonotypes(
                     cdr3_con = consensus_codon_cdr3(&rsi, &exacts, exact_clonotypes);
                 }
 
+                // Find the stable id of the clonotype, if it is shown.
+
+                let stable_id = if !show_stable_id {
+                    String::new()
+                } else if let Some(id) = collided_ids.get(&exacts) {
+                    id.clone()
+                } else {
+                    stable_clonotype_ids(&vec![exacts.clone()], exact_clonotypes, refdata)[0]
+                        .clone()
+                };
+
                 // Build rows.
 
                 let mut cell_count = 0;
@@ -684,6 +710,7 @@ pub fn print_clonotypes(
                         fate,
                         &cdr3_con,
                         allele_data,
+                        &stable_id,
                     );
                     stats.append(&mut these_stats.clone());
                     if pass == 1 {
@@ -924,7 +951,10 @@ pub fn print_clonotypes(
                 res.7.push(out_data);
             }
         }
-    });
+    };
+    results
+        .par_iter_mut()
+        .for_each(|res| process_orbit(res, &HashMap::new()));
     for i in 0..results.len() {
         if !results[i].13.is_empty() {
             return Err(results[i].13.clone());
@@ -941,11 +971,52 @@ pub fn print_clonotypes(
 
     results.sort_by_key(|x| -x.8);
 
+    // Compute stable clonotype ids.  This is done jointly for the clonotypes that are printed
+    // and those that are written to loupe output, so that collisions are handled in the same
+    // way for both.
+
+    let mut all_exacts = Vec::<Vec<usize>>::new();
+    for i in 0..results.len() {
+        for j in 0..results[i].2.len() {
+            all_exacts.push(results[i].2[j].0.clone());
+        }
+        for j in 0..results[i].6.len() {
+            all_exacts.push(results[i].6[j].0.clone());
+        }
+    }
+    unique_sort(&mut all_exacts);
+    let all_ids = stable_clonotype_ids(&all_exacts, exact_clonotypes, refdata);
+
+    // If stable_id is shown, process again the orbits containing clonotypes whose stable ids have
+    // collision suffixes.
+
+    if show_stable_id {
+        let mut collided_ids = HashMap::<Vec<usize>, String>::new();
+        for (x, id) in all_exacts.iter().zip(all_ids.iter()) {
+            if id.contains('-') {
+                collided_ids.insert(x.clone(), id.clone());
+            }
+        }
+        if !collided_ids.is_empty() {
+            results
+                .par_iter_mut()
+                .filter(|res| res.2.iter().any(|x| collided_ids.contains_key(&x.0)))
+                .for_each(|res| {
+                    *res = new_result(res.0);
+                    process_orbit(res, &collided_ids);
+                });
+        }
+    }
+    let stable_id = |x: &Vec<usize>| all_ids[bin_position(&all_exacts, x) as usize].clone();
+
     // Write loupe output.
 
     let mut all_loupe_clonotypes = Vec::<Clonotype>::new();
     for i in 0..results.len() {
-        all_loupe_clonotypes.append(&mut results[i].6);
+        for (x, mut c) in results[i].6.drain(..) {
+            c.stable_id = Some(stable_id(&x));
+            all_loupe_clonotypes.push(c);
+        }
     }
     loupe_out(ctl, all_loupe_clonotypes, refdata, dref);
 
@@ -956,6 +1027,7 @@ pub fn print_clonotypes(
             pics.push(results[i].1[j].clone());
             exacts.push(results[i].2[j].0.clone());
             rsi.push(results[i].2[j].1.clone());
+            stable_ids.push(stable_id(&results[i].2[j].0));
             in_center.push(results[i].12[j]);
         }
         out_datas.append(&mut results[i].7);
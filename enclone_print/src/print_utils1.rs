@@ -5,7 +5,9 @@ use ansi_escape::{
     emit_bold_escape, emit_eight_bit_color_escape, emit_end_escape, emit_red_escape,
 };
 use enclone_core::cell_color::CellColor;
-use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype, GexInfo, TigData1, POUT_SEP};
+use enclone_core::defs::{
+    ClonotypeSet, ColInfo, EncloneControl, ExactClonotype, GexInfo, TigData1, POUT_SEP,
+};
 use enclone_core::print_tools::{color_by_property, emit_codon_color_escape};
 use enclone_vars::decode_arith;
 use expr_tools::vars_of_node;
@@ -910,6 +912,9 @@ pub fn extra_args(ctl: &EncloneControl) -> Vec<String> {
         }
         _ => {}
     };
+    if let CellColor::ByVariableValue(ref x) = ctl.plot_opt.tree_svg_color {
+        extra_args.push(x.var.clone());
+    }
     for i in 0..ctl.clono_filt_opt.bounds.len() {
         extra_args.append(&mut ctl.clono_filt_opt.bounds[i].var.clone());
     }
@@ -917,6 +922,11 @@ pub fn extra_args(ctl: &EncloneControl) -> Vec<String> {
         extra_args.append(&mut ctl.gen_opt.gene_scan_test.as_ref().unwrap().var.clone());
         extra_args.append(&mut ctl.gen_opt.gene_scan_control.as_ref().unwrap().var.clone());
     }
+    for x in [&ctl.gen_opt.de_test, &ctl.gen_opt.de_control].iter() {
+        if let Some(ClonotypeSet::Condition(x)) = x {
+            extra_args.append(&mut x.var.clone());
+        }
+    }
     extra_args.append(&mut ctl.plot_opt.sim_mat_plot_vars.clone());
     for i in 0..ctl.gen_opt.var_def.len() {
         let x = &ctl.gen_opt.var_def[i].2;
@@ -924,6 +934,9 @@ pub fn extra_args(ctl: &EncloneControl) -> Vec<String> {
             extra_args.push(decode_arith(v));
         }
     }
+    if ctl.gen_opt.api {
+        extra_args.append(&mut ctl.parseable_opt.pcols_sortx.clone());
+    }
     unique_sort(&mut extra_args);
     extra_args
 }
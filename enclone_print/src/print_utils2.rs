@@ -76,6 +76,7 @@ pub fn row_fill(
     fate: &Vec<HashMap<String, String>>,
     cdr3_con: &Vec<Vec<u8>>,
     allele_data: &AlleleData,
+    stable_id: &str,
 ) -> Result<(), String> {
     // Redefine some things to reduce dependencies.
 
@@ -453,6 +454,7 @@ pub fn row_fill(
             ind_readers,
             h5_data,
             &alt_bcs,
+            stable_id,
         )? {
             let _ = proc_lvar2(
                 i,
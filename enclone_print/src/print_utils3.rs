@@ -497,6 +497,7 @@ pub fn define_column_info(
         chain_descrip,
         mat: Vec::<Vec<Option<usize>>>::new(),
         cvars,
+        ucas: Vec::new(),
     }
 }
 
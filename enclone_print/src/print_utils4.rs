@@ -1,7 +1,7 @@
 // Copyright (c) 2021 10X Genomics, Inc. All rights reserved.
 
 use amino::codon_to_aa;
-use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype, GexInfo};
+use enclone_core::defs::{abbr_var, ColInfo, EncloneControl, ExactClonotype, GexInfo};
 use enclone_proto::types::DonorReferenceItem;
 use equiv::EquivRel;
 use itertools::Itertools;
@@ -749,10 +749,7 @@ pub fn compute_bu(
                     if var == *"gex" {
                         row.push(format!("{}", gex_count.round()));
                     } else {
-                        let mut y = var.clone();
-                        if y.contains(':') {
-                            y = y.after(":").to_string();
-                        }
+                        let mut y = abbr_var(&var).1;
                         let y0 = y.clone();
                         let suffixes = ["_min", "_max", "_μ", "_Σ", "_cell", "_%"];
                         for s in suffixes.iter() {
@@ -8,6 +8,7 @@ use enclone_core::align_to_vdj_ref::*;
 use enclone_core::defs::*;
 use enclone_core::median::*;
 use enclone_core::opt_d::*;
+use enclone_core::uca::*;
 use enclone_proto::types::*;
 use itertools::Itertools;
 use stats_utils::*;
@@ -1363,6 +1364,14 @@ pub fn proc_cvar_auto(
         let u_mean = (utot as f64 / numis.len() as f64).round() as usize;
 
         (format!("{}", u_mean), Vec::new(), "exact".to_string())
+    } else if vname == "uca_aa" {
+        let (dna, _) = uca(col, exacts, exact_clonotypes, rsi, refdata, dref, ctl);
+
+        (stringme(&aa_seq(&dna, 0)), Vec::new(), "clono".to_string())
+    } else if vname == "uca_dna" {
+        let (dna, _) = uca(col, exacts, exact_clonotypes, rsi, refdata, dref, ctl);
+
+        (stringme(&dna), Vec::new(), "clono".to_string())
     } else if vname == "udiff" {
         let ulen = ex.share[mid].v_start;
         let uid = ex.share[mid].u_ref_id;
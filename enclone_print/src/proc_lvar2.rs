@@ -4,7 +4,8 @@
 // plus a small helper function get_gex_matrix_entry.
 
 use crate::print_utils4::get_gex_matrix_entry;
-use enclone_core::defs::{EncloneControl, ExactClonotype, GexInfo, POUT_SEP};
+use enclone_args::antigen_score::antigen_clono_value;
+use enclone_core::defs::{abbr_var, EncloneControl, ExactClonotype, GexInfo, POUT_SEP};
 use enclone_core::median::rounded_median;
 use itertools::Itertools;
 use std::collections::HashMap;
@@ -91,14 +92,12 @@ pub fn proc_lvar2(
 
     let (mut counts_sub, mut fcounts_sub) = (Vec::<usize>::new(), Vec::<f64>::new());
     let xorig = x.clone();
-    let (mut x, mut y) = (x.to_string(), x.to_string());
-    if x.contains(':') {
-        x = x.before(":").to_string();
-    }
-    if y.contains(':') {
-        y = y.after(":").to_string();
-    }
+    let (x, mut y) = abbr_var(x);
     let y0 = y.clone();
+    if let Some(val) = antigen_clono_value(ctl, exacts, exact_clonotypes, &y0) {
+        lvar_stats1![i, x, val];
+        return true;
+    }
     for _ in 1..=2 {
         let suffixes = ["_min", "_max", "_μ", "_Σ", "_cell", "_%"];
         for s in suffixes.iter() {
@@ -50,18 +50,14 @@ pub fn proc_lvar_auto(
     ind_readers: &Vec<Option<Reader>>,
     h5_data: &Vec<(usize, Vec<u32>, Vec<u32>)>,
     alt_bcs: &Vec<String>,
+    stable_id: &str,
 ) -> Result<bool, String> {
     let clonotype_id = exacts[u];
     let ex = &exact_clonotypes[clonotype_id];
     let mat = &rsi.mat;
     let cols = varmat[0].len();
     let verbose = ctl.gen_opt.row_fill_verbose;
-    let mut vname = var.clone();
-    let mut abbr = var.clone();
-    if var.contains(':') {
-        abbr = var.before(":").to_string();
-        vname = var.after(":").to_string();
-    }
+    let (abbr, vname) = abbr_var(var);
 
     macro_rules! speak {
         ($u:expr, $var:expr, $val:expr) => {
@@ -123,6 +119,8 @@ pub fn proc_lvar_auto(
         }
 
         (val, Vec::new(), "exact".to_string())
+    } else if vname == "stable_id" {
+        (stable_id.to_string(), Vec::new(), "clono".to_string())
     } else if vname == "clonotype_ncells" {
         let mut n = 0;
         for u in exacts.iter() {
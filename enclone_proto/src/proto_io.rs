@@ -18,13 +18,25 @@
 //! In the above diagram,
 //! - `Length` is an unsigned 32 bit integer stored in **Big endian** order.
 //! - If there are multiple messages, they are stored consecutively following the same format.
+//!
+//! ## Random access
+//! Optionally, `write_proto_indexed` also writes a sidecar file (see `index_path`) containing a
+//! `ProtoIndex` message, which records the byte offset of each clonotype and the clonotype
+//! containing each cell, given by dataset and barcode.  `IndexedProtoReader` uses this to fetch a
+//! single clonotype, or the clonotype for a cell, without reading the whole file.  The index
+//! records the size and modification time of the proto file, and is ignored (and rebuilt in
+//! memory) if the proto file no longer has that size or modification time, or if the index was
+//! made by an older version that did not record datasets.  `write_proto` deletes any existing
+//! index file, since it would no longer be valid.
 
-use crate::types::{Clonotype, EncloneOutputs};
+use crate::types::{Clonotype, EncloneOutputs, ProtoIndex};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use prost::Message;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::Path;
+use std::cmp::Ordering;
+use std::fs::{metadata, remove_file, File};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 const BUFFER_CAPACITY: usize = 1_000_000;
 
@@ -33,6 +45,9 @@ pub enum Error {
     #[error("Expected to get {expected} bytes from the reader. Got {got} bytes!")]
     Truncated { expected: usize, got: usize },
 
+    #[error("Clonotype index {index} is out of range, as there are {len} clonotypes!")]
+    OutOfRange { index: usize, len: usize },
+
     #[error(transparent)]
     Io(#[from] std::io::Error),
 
@@ -84,6 +99,8 @@ impl<W: Write> ProtoWriter<W> {
 pub struct ProtoReader<R: Read> {
     decode_buffer: Vec<u8>,
     reader: R,
+    // Number of bytes consumed from the reader
+    position: u64,
 }
 
 impl<R: Read> ProtoReader<R> {
@@ -91,8 +108,13 @@ impl<R: Read> ProtoReader<R> {
         ProtoReader {
             decode_buffer: Vec::with_capacity(BUFFER_CAPACITY),
             reader,
+            position: 0,
         }
     }
+    /// Number of bytes consumed from the reader so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
     // Clear the decode_buffer and fill it with `num_bytes` bytes from the reader
     fn read_exact(&mut self, num_bytes: usize) -> Result<(), Error> {
         self.decode_buffer.clear();
@@ -100,6 +122,7 @@ impl<R: Read> ProtoReader<R> {
             .by_ref()
             .take(num_bytes as u64)
             .read_to_end(&mut self.decode_buffer)?;
+        self.position += self.decode_buffer.len() as u64;
         // If we did not get num_bytes bytes, return an error
         if self.decode_buffer.len() != num_bytes {
             return Err(Error::Truncated {
@@ -160,25 +183,207 @@ impl<R: Read> ProtoReader<R> {
 /// ```
 /// The newlines are only showed for illustration
 pub fn write_proto(enclone_outputs: EncloneOutputs, path: impl AsRef<Path>) -> Result<(), Error> {
+    write_proto_core(enclone_outputs, path.as_ref(), false)
+}
+
+/// Same as `write_proto`, but also write the random-access index to `index_path(path)`.
+pub fn write_proto_indexed(
+    enclone_outputs: EncloneOutputs,
+    path: impl AsRef<Path>,
+) -> Result<(), Error> {
+    write_proto_core(enclone_outputs, path.as_ref(), true)
+}
+
+fn write_proto_core(
+    enclone_outputs: EncloneOutputs,
+    path: &Path,
+    write_index: bool,
+) -> Result<(), Error> {
     let writer = BufWriter::new(File::create(path)?);
     let mut proto_writer = ProtoWriter::with_writer(writer);
+    let mut pos = 0;
 
     // Write the version
-    proto_writer.encode_and_write(enclone_outputs.version)?;
+    pos += proto_writer.encode_and_write(enclone_outputs.version)?;
     // Write the metadata
-    proto_writer.encode_and_write(enclone_outputs.metadata)?;
+    pos += proto_writer.encode_and_write(enclone_outputs.metadata)?;
     // Write the universal reference
-    proto_writer.encode_and_write(enclone_outputs.universal_reference)?;
+    pos += proto_writer.encode_and_write(enclone_outputs.universal_reference)?;
     // Write the donor reference
-    proto_writer.encode_and_write(enclone_outputs.donor_reference)?;
+    pos += proto_writer.encode_and_write(enclone_outputs.donor_reference)?;
     // Write the number of clonotypes. Not bothering to write this raw
-    proto_writer.encode_and_write(enclone_outputs.clonotypes.len() as u32)?;
-    for cl in enclone_outputs.clonotypes {
-        proto_writer.encode_and_write(cl)?;
+    pos += proto_writer.encode_and_write(enclone_outputs.clonotypes.len() as u32)?;
+    let mut offsets = Vec::<u64>::new();
+    let mut barcodes = Vec::<(String, String, u32)>::new();
+    for (i, cl) in enclone_outputs.clonotypes.into_iter().enumerate() {
+        if write_index {
+            offsets.push(pos as u64);
+            barcodes.append(&mut cell_keys(&cl, i as u32));
+        }
+        pos += proto_writer.encode_and_write(cl)?;
+    }
+    proto_writer.finish().flush()?;
+    let idx = index_path(path);
+    if write_index {
+        let index = make_index(offsets, barcodes, pos as u64, proto_mtime(path));
+        write_index_file(index, idx)?;
+    } else if idx.exists() {
+        remove_file(idx)?;
     }
     Ok(())
 }
 
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+/// Path of the index file for a proto file: the same path, with ".idx" appended.
+pub fn index_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut p = path.as_ref().as_os_str().to_owned();
+    p.push(".idx");
+    PathBuf::from(p)
+}
+
+/// Modification time of a file, in nanoseconds since the Unix epoch, if available.
+fn proto_mtime(path: &Path) -> Option<u64> {
+    let t = metadata(path).ok()?.modified().ok()?;
+    Some(t.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+}
+
+/// The cells in clonotype `i`, as (dataset, barcode, i).  The dataset is empty if not recorded.
+fn cell_keys(cl: &Clonotype, i: u32) -> Vec<(String, String, u32)> {
+    let mut keys = Vec::new();
+    for ex in cl.exact_clonotypes.iter() {
+        for (k, bc) in ex.cell_barcodes.iter().enumerate() {
+            let dataset = ex.cell_datasets.get(k).cloned().unwrap_or_default();
+            keys.push((dataset, bc.clone(), i));
+        }
+    }
+    keys
+}
+
+fn make_index(
+    clonotype_offsets: Vec<u64>,
+    mut barcodes: Vec<(String, String, u32)>,
+    proto_size: u64,
+    proto_mtime: Option<u64>,
+) -> ProtoIndex {
+    barcodes.sort();
+    let mut index = ProtoIndex {
+        clonotype_offsets,
+        proto_size: Some(proto_size),
+        proto_mtime,
+        ..Default::default()
+    };
+    for (dataset, barcode, i) in barcodes.into_iter() {
+        index.barcode_datasets.push(dataset);
+        index.barcodes.push(barcode);
+        index.barcode_clonotypes.push(i);
+    }
+    index
+}
+
+/// Write a `ProtoIndex` as a single length delimited message.
+pub fn write_index_file(index: ProtoIndex, path: impl AsRef<Path>) -> Result<(), Error> {
+    let mut proto_writer = ProtoWriter::with_writer(BufWriter::new(File::create(path)?));
+    proto_writer.encode_and_write(index)?;
+    proto_writer.finish().flush()?;
+    Ok(())
+}
+
+/// Build the index for a proto file by streaming through it.  This is used if the proto file was
+/// written without an index.
+pub fn build_index(path: impl AsRef<Path>) -> Result<ProtoIndex, Error> {
+    let path = path.as_ref();
+    let (proto_size, mtime) = (metadata(path)?.len(), proto_mtime(path));
+    let (outputs, mut proto_reader) = read_proto_until_clonotypes(path)?;
+    let mut offsets = Vec::<u64>::new();
+    let mut barcodes = Vec::<(String, String, u32)>::new();
+    for i in 0..outputs.num_clonotypes {
+        offsets.push(proto_reader.position());
+        let cl: Clonotype = proto_reader.read_and_decode()?;
+        barcodes.append(&mut cell_keys(&cl, i));
+    }
+    Ok(make_index(offsets, barcodes, proto_size, mtime))
+}
+
+/// Random access to the clonotypes in a proto file.
+pub struct IndexedProtoReader {
+    file: BufReader<File>,
+    index: ProtoIndex,
+}
+
+impl IndexedProtoReader {
+    /// Open a proto file, using its index file if present and made for the current version of
+    /// the proto file, and otherwise building the index.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let idx = index_path(path);
+        let mut index = None;
+        if idx.exists() {
+            let x: ProtoIndex =
+                ProtoReader::from_reader(BufReader::new(File::open(idx)?)).read_and_decode()?;
+            if x.proto_size == Some(metadata(path)?.len())
+                && x.proto_mtime.is_some()
+                && x.proto_mtime == proto_mtime(path)
+                && x.barcode_datasets.len() == x.barcodes.len()
+            {
+                index = Some(x);
+            }
+        }
+        let index = match index {
+            Some(index) => index,
+            None => build_index(path)?,
+        };
+        Ok(IndexedProtoReader {
+            file: BufReader::new(File::open(path)?),
+            index,
+        })
+    }
+    pub fn num_clonotypes(&self) -> usize {
+        self.index.clonotype_offsets.len()
+    }
+    pub fn index(&self) -> &ProtoIndex {
+        &self.index
+    }
+    /// Read clonotype `i`, counting from zero.
+    pub fn get_clonotype(&mut self, i: usize) -> Result<Clonotype, Error> {
+        if i >= self.num_clonotypes() {
+            return Err(Error::OutOfRange {
+                index: i,
+                len: self.num_clonotypes(),
+            });
+        }
+        self.file
+            .seek(SeekFrom::Start(self.index.clonotype_offsets[i]))?;
+        ProtoReader::from_reader(&mut self.file).read_and_decode()
+    }
+    /// Find the index of the clonotype containing a cell, given by dataset and barcode.  The
+    /// dataset should be empty if the proto file does not record datasets.
+    pub fn find_barcode(&self, dataset: &str, barcode: &str) -> Option<usize> {
+        let (d, b) = (&self.index.barcode_datasets, &self.index.barcodes);
+        let (mut lo, mut hi) = (0, b.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match (d[mid].as_str(), b[mid].as_str()).cmp(&(dataset, barcode)) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => return Some(self.index.barcode_clonotypes[mid] as usize),
+            }
+        }
+        None
+    }
+    /// Read the clonotype containing a cell, if any.
+    pub fn get_clonotype_for_barcode(
+        &mut self,
+        dataset: &str,
+        barcode: &str,
+    ) -> Result<Option<Clonotype>, Error> {
+        match self.find_barcode(dataset, barcode) {
+            Some(i) => Ok(Some(self.get_clonotype(i)?)),
+            None => Ok(None),
+        }
+    }
+}
+
 /// A read that mirrors the write above. The fields until the list of clonotypes are read here.
 /// The clonotypes are assigned an empty vector.
 pub fn read_proto_until_clonotypes(
@@ -268,3 +473,110 @@ impl<R: Read> Iterator for ClonotypeIter<R> {
         }
     }
 }
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ExactSubClonotype;
+
+    // Make outputs in which clonotype i has exact subclonotypes j = 0..=i, each having one cell,
+    // with barcode ACGTj-1 and dataset {prefix}i.  Thus the same barcode appears in several
+    // datasets.
+
+    fn outputs(nclonotypes: usize, prefix: &str) -> EncloneOutputs {
+        let clonotypes = (0..nclonotypes)
+            .map(|i| Clonotype {
+                exact_clonotypes: (0..=i)
+                    .map(|j| ExactSubClonotype {
+                        cell_barcodes: vec![format!("ACGT{}-1", j)],
+                        cell_datasets: vec![format!("{}{}", prefix, i)],
+                        ..Default::default()
+                    })
+                    .collect(),
+                frequency: i as u32 + 1,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        EncloneOutputs {
+            version: "test".to_string(),
+            num_clonotypes: nclonotypes as u32,
+            clonotypes,
+            ..Default::default()
+        }
+    }
+
+    fn check(path: &Path, expected: &EncloneOutputs) {
+        let mut reader = IndexedProtoReader::open(path).unwrap();
+        assert_eq!(reader.num_clonotypes(), expected.clonotypes.len());
+        for i in (0..expected.clonotypes.len()).rev() {
+            assert_eq!(reader.get_clonotype(i).unwrap(), expected.clonotypes[i]);
+            for ex in expected.clonotypes[i].exact_clonotypes.iter() {
+                let (d, b) = (&ex.cell_datasets[0], &ex.cell_barcodes[0]);
+                assert_eq!(reader.find_barcode(d, b), Some(i));
+            }
+        }
+        assert!(reader.get_clonotype(expected.clonotypes.len()).is_err());
+        assert_eq!(reader.find_barcode("", "ACGT0-1"), None);
+        assert_eq!(reader.find_barcode("d0", "TTTT-1"), None);
+    }
+
+    fn read_index(path: &Path) -> ProtoIndex {
+        ProtoReader::from_reader(BufReader::new(File::open(index_path(path)).unwrap()))
+            .read_and_decode()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_indexed_round_trip() {
+        let dir = std::env::temp_dir().join(format!("enclone_proto_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("test.proto");
+        let idx = index_path(&path);
+
+        // Write with an index, and read back in random order.
+
+        let (x, y) = (outputs(5, "d"), outputs(3, "d"));
+        write_proto_indexed(x.clone(), &path).unwrap();
+        assert!(idx.exists());
+        check(&path, &x);
+
+        // Rewriting without an index deletes the old index.
+
+        write_proto(y.clone(), &path).unwrap();
+        assert!(!idx.exists());
+        check(&path, &y);
+
+        // An index for a file of a different size is detected and ignored.
+
+        write_proto_indexed(x.clone(), &path).unwrap();
+        let stale = std::fs::read(&idx).unwrap();
+        write_proto_indexed(y.clone(), &path).unwrap();
+        std::fs::write(&idx, stale).unwrap();
+        check(&path, &y);
+
+        // An index for a file of the same size but a different modification time is detected
+        // and ignored.  The two writes may have the same modification time at coarse resolution,
+        // so the recorded time is made earlier.
+
+        let z = outputs(5, "e");
+        write_proto_indexed(x.clone(), &path).unwrap();
+        let mut stale = read_index(&path);
+        write_proto_indexed(z.clone(), &path).unwrap();
+        assert_eq!(stale.proto_size, read_index(&path).proto_size);
+        stale.proto_mtime = Some(stale.proto_mtime.unwrap() - 1);
+        stale.proto_size = Some(metadata(&path).unwrap().len());
+        write_index_file(stale, &idx).unwrap();
+        check(&path, &z);
+
+        // An index that does not record datasets is ignored.
+
+        let mut old = read_index(&path);
+        old.barcode_datasets.clear();
+        write_index_file(old, &idx).unwrap();
+        check(&path, &z);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
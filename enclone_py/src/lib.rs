@@ -0,0 +1,208 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+
+// Python bindings for enclone.  Build with maturin, e.g. "maturin develop --release" from this
+// directory.  Then from python:
+//
+//     import enclone_py, pandas as pd
+//     r = enclone_py.run(["BCR=/path/to/outs", "MIN_CELLS=2", "PCOLS=u_cell1,const1"],
+//         bc = {"outs": {"AAACCTGAGCGATAGC-1": {"origin": "s1", "celltype": "B"}}})
+//     cells = pd.DataFrame(r["cells"])
+//
+// The arguments are ordinary enclone arguments.  The result is a dict of three tables, each a
+// dict of equal-length columns, which may be passed directly to pandas.DataFrame or
+// pyarrow.table:
+// * exact_subclonotypes: one row per exact subclonotype, with the values of the PCOLS variables;
+// * chains: one row per chain of each exact subclonotype;
+// * cells: one row per cell.
+//
+// The optional bc argument is used in place of META/BC files.  It maps dataset short names to
+// dicts that map barcodes to metadata.  The metadata fields origin, donor, tag and color have
+// the same meaning as in a BC file, and other fields may be used as variables.
+
+use enclone_core::results::EncloneResults;
+use enclone_main::main_enclone::main_enclone_api;
+use io_utils::fwriteln;
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+
+type BarcodeMetadata = HashMap<String, HashMap<String, HashMap<String, String>>>;
+
+// enclone keeps some state in process-wide globals, so only one run may be in progress at a
+// time.  Runs from other python threads wait on this lock, with the GIL released.
+
+lazy_static! {
+    static ref RUN_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Write barcode metadata in BC_JOINT format, so that it is validated and processed exactly as a
+// file provided by the user would be.
+
+fn write_bc_joint(bc: &BarcodeMetadata, f: &mut impl Write) {
+    let mut fields = Vec::<String>::new();
+    for x in bc.values() {
+        for y in x.values() {
+            fields.append(&mut y.keys().cloned().collect());
+        }
+    }
+    fields.sort();
+    fields.dedup();
+    fwriteln!(f, "dataset\tbarcode\t{}", fields.iter().format("\t"));
+    for (dataset, x) in bc.iter().sorted() {
+        for (barcode, y) in x.iter().sorted() {
+            let vals = fields.iter().map(|v| y.get(v).cloned().unwrap_or_default());
+            fwriteln!(f, "{}\t{}\t{}", dataset, barcode, vals.format("\t"));
+        }
+    }
+}
+
+// Run enclone, returning typed results.
+
+fn run_enclone(args: &[String], bc: Option<&BarcodeMetadata>) -> Result<EncloneResults, String> {
+    let mut argsx = vec!["enclone".to_string()];
+    argsx.append(&mut args.to_vec());
+
+    // Each call gets its own file, which is deleted when bc_file goes out of scope.
+
+    let mut bc_file = None;
+    if let Some(bc) = bc {
+        let mut f = tempfile::Builder::new()
+            .prefix("enclone_py_bc_")
+            .suffix(".tsv")
+            .tempfile()
+            .map_err(|e| format!("\nFailed to create temporary file: {}\n", e))?;
+        write_bc_joint(bc, &mut f);
+        f.flush()
+            .map_err(|e| format!("\nFailed to write temporary file: {}\n", e))?;
+        argsx.push(format!("BC_JOINT={}", f.path().display()));
+        bc_file = Some(f);
+    }
+    for x in ["NOPRINT", "NOPAGER", "NO_NEWLINE"] {
+        argsx.push(x.to_string());
+    }
+    let res = {
+        // A run that panicked poisons the lock, but does not prevent later runs.
+        let _guard = RUN_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        main_enclone_api(&argsx).map(|state| state.results.unwrap_or_default())
+    };
+    drop(bc_file);
+    res
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Convert results to column-oriented tables.
+
+fn make_tables(py: Python, r: &EncloneResults) -> PyResult<PyObject> {
+    let (mut e_clono, mut e_group, mut e_id, mut e_ncells) = (vec![], vec![], vec![], vec![]);
+    let mut vars = Vec::<String>::new();
+    for c in r.clonotypes.iter() {
+        for e in c.exact_subclonotypes.iter() {
+            vars.append(&mut e.values.keys().cloned().collect());
+        }
+    }
+    vars.sort();
+    vars.dedup();
+    let mut e_vals = vec![Vec::<Option<String>>::new(); vars.len()];
+    let (mut h_clono, mut h_exact, mut h_chain, mut h_type) = (vec![], vec![], vec![], vec![]);
+    let (mut h_v, mut h_d, mut h_j, mut h_c) = (vec![], vec![], vec![], vec![]);
+    let (mut h_cdr3_aa, mut h_cdr3_dna, mut h_seq) = (vec![], vec![], vec![]);
+    let (mut b_clono, mut b_exact, mut b_barcode, mut b_dataset) = (vec![], vec![], vec![], vec![]);
+    let (mut b_origin, mut b_donor, mut b_tag) = (vec![], vec![], vec![]);
+    for c in r.clonotypes.iter() {
+        for e in c.exact_subclonotypes.iter() {
+            e_clono.push(c.id.clone());
+            e_group.push(c.group + 1);
+            e_id.push(e.id);
+            e_ncells.push(e.cells.len());
+            for (i, v) in vars.iter().enumerate() {
+                e_vals[i].push(e.values.get(v).cloned());
+            }
+            for (cx, x) in e.chains.iter().enumerate() {
+                if let Some(x) = x {
+                    h_clono.push(c.id.clone());
+                    h_exact.push(e.id);
+                    h_chain.push(cx + 1);
+                    h_type.push(x.chain_type.clone());
+                    h_v.push(x.v_gene.clone());
+                    h_d.push(x.d_gene.clone());
+                    h_j.push(x.j_gene.clone());
+                    h_c.push(x.c_gene.clone());
+                    h_cdr3_aa.push(x.cdr3_aa.clone());
+                    h_cdr3_dna.push(x.cdr3_dna.clone());
+                    h_seq.push(x.seq.clone());
+                }
+            }
+            for b in e.cells.iter() {
+                b_clono.push(c.id.clone());
+                b_exact.push(e.id);
+                b_barcode.push(b.barcode.clone());
+                b_dataset.push(r.dataset_names[b.dataset_index].clone());
+                b_origin.push(b.origin.clone());
+                b_donor.push(b.donor.clone());
+                b_tag.push(b.tag.clone());
+            }
+        }
+    }
+    let exacts = PyDict::new(py);
+    exacts.set_item("clonotype_id", e_clono)?;
+    exacts.set_item("group_id", e_group)?;
+    exacts.set_item("exact_subclonotype_id", e_id)?;
+    exacts.set_item("ncells", e_ncells)?;
+    for (i, v) in vars.iter().enumerate() {
+        exacts.set_item(v, e_vals[i].clone())?;
+    }
+    let chains = PyDict::new(py);
+    chains.set_item("clonotype_id", h_clono)?;
+    chains.set_item("exact_subclonotype_id", h_exact)?;
+    chains.set_item("chain", h_chain)?;
+    chains.set_item("chain_type", h_type)?;
+    chains.set_item("v_gene", h_v)?;
+    chains.set_item("d_gene", h_d)?;
+    chains.set_item("j_gene", h_j)?;
+    chains.set_item("c_gene", h_c)?;
+    chains.set_item("cdr3_aa", h_cdr3_aa)?;
+    chains.set_item("cdr3_dna", h_cdr3_dna)?;
+    chains.set_item("seq", h_seq)?;
+    let cells = PyDict::new(py);
+    cells.set_item("clonotype_id", b_clono)?;
+    cells.set_item("exact_subclonotype_id", b_exact)?;
+    cells.set_item("barcode", b_barcode)?;
+    cells.set_item("dataset", b_dataset)?;
+    cells.set_item("origin", b_origin)?;
+    cells.set_item("donor", b_donor)?;
+    cells.set_item("tag", b_tag)?;
+    let tables = PyDict::new(py);
+    tables.set_item("exact_subclonotypes", exacts)?;
+    tables.set_item("chains", chains)?;
+    tables.set_item("cells", cells)?;
+    Ok(tables.into())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+/// Run enclone with the given arguments, and optionally barcode metadata, given as a dict that
+/// maps dataset short names to dicts that map barcodes to dicts of field values.  Returns a dict
+/// of tables named exact_subclonotypes, chains and cells, each a dict of columns.
+#[pyfunction]
+#[pyo3(text_signature = "(args, bc=None)")]
+fn run(py: Python, args: Vec<String>, bc: Option<BarcodeMetadata>) -> PyResult<PyObject> {
+    let res = py.allow_threads(|| run_enclone(&args, bc.as_ref()));
+    match res {
+        Ok(r) => make_tables(py, &r),
+        Err(e) => Err(PyRuntimeError::new_err(e.trim().to_string())),
+    }
+}
+
+#[pymodule]
+fn enclone_py(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    Ok(())
+}
@@ -66,6 +66,7 @@ pub fn main_enclone_stop_ranger(mut inter: EncloneIntermediates) -> Result<(), S
     let mut in_center = Vec::<bool>::new();
     let mut rsi = Vec::<ColInfo>::new(); // ditto
     let mut out_datas = Vec::<Vec<HashMap<String, String>>>::new();
+    let mut stable_ids = Vec::<String>::new();
     let mut tests = Vec::<usize>::new();
     let mut controls = Vec::<usize>::new();
     print_clonotypes(
@@ -89,6 +90,7 @@ pub fn main_enclone_stop_ranger(mut inter: EncloneIntermediates) -> Result<(), S
         &mut in_center,
         &mut rsi,
         &mut out_datas,
+        &mut stable_ids,
         &mut tests,
         &mut controls,
         fate,
@@ -12,13 +12,19 @@ use crate::populate_features::populate_features;
 use crate::some_filters::some_filters;
 use debruijn::dna_string::DnaString;
 use enclone::allele::{find_alleles, sub_alts};
+use enclone::donor_demux::donor_demux;
 use enclone::graph_filter::graph_filter;
 use enclone::info::build_info;
-use enclone::join::join_exacts;
+use enclone::join::{join_by_assignment, join_exacts};
+use enclone::join_state::{
+    covers_all_donors, load_join_state, make_join_state, merge_alt_refs, restore_joins,
+    save_join_state, write_join_id_map,
+};
 use enclone::misc1::{cross_filter, lookup_heavy_chain_reuse};
 use enclone::misc2::{check_for_barcode_reuse, find_exact_subclonotypes, search_for_shm_indels};
 use enclone::misc3::sort_tig_bc;
 use enclone_args::read_json::parse_json_annotations_files;
+use enclone_args::read_proto_in::{read_proto_in, ProtoInData};
 use enclone_core::defs::{AlleleData, CloneInfo, TigData};
 use enclone_core::enclone_structs::*;
 use enclone_core::hcomp::heavy_complexity;
@@ -100,7 +106,7 @@ pub fn stirling2_ratio_table_double(n_max: usize) -> Vec<Vec<Double>> {
 
 // ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
 
-pub fn main_enclone_start(setup: EncloneSetup) -> Result<EncloneIntermediates, String> {
+pub fn main_enclone_start(mut setup: EncloneSetup) -> Result<EncloneIntermediates, String> {
     let tr = Instant::now();
     let ctl = &setup.ctl;
     let gex_info = &setup.gex_info;
@@ -117,7 +123,7 @@ pub fn main_enclone_start(setup: EncloneSetup) -> Result<EncloneIntermediates, S
     flag_defective(ctl, refdata, &mut log, &mut broken);
     ctl.perf_stats(&tr, "flagging defective references");
 
-    // Parse the json annotations file.
+    // Parse the json annotations file, or read PROTO_IN.
 
     let tparse = Instant::now();
     let mut tig_bc = Vec::<Vec<TigData>>::new();
@@ -125,18 +131,40 @@ pub fn main_enclone_start(setup: EncloneSetup) -> Result<EncloneIntermediates, S
     let mut gex_cells = Vec::<Vec<String>>::new();
     let mut gex_cells_specified = Vec::<bool>::new();
     let mut fate = vec![HashMap::<String, String>::new(); ctl.origin_info.n()];
-    parse_json_annotations_files(
-        ctl,
-        &mut tig_bc,
-        refdata,
-        to_ref_index,
-        &mut vdj_cells,
-        &mut gex_cells,
-        &mut gex_cells_specified,
-        &mut fate,
-    )?;
+    let proto_in = !ctl.gen_opt.proto_in.is_empty();
+    let mut proto_in_data = ProtoInData::default();
+    if proto_in {
+        proto_in_data = read_proto_in(
+            ctl,
+            refdata,
+            &mut tig_bc,
+            &mut vdj_cells,
+            &mut gex_cells,
+            &mut gex_cells_specified,
+        )?;
+    } else {
+        parse_json_annotations_files(
+            ctl,
+            &mut tig_bc,
+            refdata,
+            to_ref_index,
+            &mut vdj_cells,
+            &mut gex_cells,
+            &mut gex_cells_specified,
+            &mut fate,
+        )?;
+    }
     ctl.perf_stats(&tparse, "loading from json");
 
+    // Assign cells to donors using their contigs.
+
+    if ctl.gen_opt.donor_demux > 0 {
+        let tdemux = Instant::now();
+        donor_demux(&mut setup.ctl, &setup.refdata, &mut tig_bc)?;
+        setup.ctl.perf_stats(&tdemux, "demultiplexing donors");
+    }
+    let ctl = &setup.ctl;
+
     // Populate features.
 
     let tpop = Instant::now();
@@ -197,6 +225,21 @@ pub fn main_enclone_start(setup: EncloneSetup) -> Result<EncloneIntermediates, S
         }
     }
 
+    // Delete cells called as doublets by HTO_DEMUX.
+
+    if ctl.clono_filt_opt_def.doublet && !ctl.origin_info.tag_doublet.is_empty() {
+        tig_bc.retain(|x| {
+            let li = x[0].dataset_index;
+            let bc = &x[0].barcode;
+            if ctl.origin_info.tag_doublet[li].contains(bc) {
+                fate[li].insert(bc.clone(), "failed DOUBLET filter".to_string());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
     // Filter using light --> heavy graph.
 
     graph_filter(ctl, &mut tig_bc, ctl.gen_opt.graph, &mut fate);
@@ -303,14 +346,27 @@ pub fn main_enclone_start(setup: EncloneSetup) -> Result<EncloneIntermediates, S
     let mut info: Vec<CloneInfo> = build_info(refdata, ctl, &mut exact_clonotypes, &mut fate);
     ctl.perf_stats(&tinfo, "building info");
 
-    // Derive consensus sequences for alternate alleles of V segments.  Then create donor
-    // reference sequences for Loupe.
+    // Derive consensus sequences for alternate alleles of V segments, or for PROTO_IN, take them
+    // from the file.  Then create donor reference sequences for Loupe.
 
     let talt = Instant::now();
+    let mut join_state = None;
+    if !ctl.gen_opt.join_state_in.is_empty() {
+        join_state = Some(load_join_state(&ctl.gen_opt.join_state_in)?);
+    }
     // {(donor, ref id, alt seq, support, is_ref)}:
     let mut alt_refs = Vec::<(usize, usize, DnaString, usize, bool)>::new();
-    if !ctl.gen_opt.no_alt_alleles {
-        alt_refs = find_alleles(refdata, ctl, &exact_clonotypes);
+    if proto_in {
+        if !ctl.gen_opt.no_alt_alleles {
+            alt_refs = proto_in_data.alt_refs.clone();
+        }
+    } else if !ctl.gen_opt.no_alt_alleles {
+        if join_state.is_none() || !covers_all_donors(join_state.as_ref().unwrap(), ctl) {
+            alt_refs = find_alleles(refdata, ctl, &exact_clonotypes);
+        }
+        if let Some(state) = &join_state {
+            merge_alt_refs(state, refdata, ctl, &mut alt_refs);
+        }
     }
     ctl.perf_stats(&talt, "finding alt alleles");
     if !ctl.gen_opt.dref_file.is_empty() {
@@ -398,22 +454,84 @@ pub fn main_enclone_start(setup: EncloneSetup) -> Result<EncloneIntermediates, S
     ctl.perf_stats(&tcomp, "computing complexity");
 
     // Form equivalence relation on exact subclonotypes.  We also keep the raw joins, consisting
-    // of pairs of info indices, that were originally joined.
+    // of pairs of info indices, that were originally joined.  For PROTO_IN, the clonotypes in
+    // the file are used instead.  If there is cached join state, restore its joins, and only
+    // make comparisons that involve new exact subclonotypes.
 
     let mut join_info = Vec::<(usize, usize, bool, Vec<u8>)>::new();
     let mut raw_joins = Vec::<(i32, i32)>::new();
-    let mut eq: EquivRel = join_exacts(
-        is_bcr,
-        &to_bc,
-        refdata,
-        ctl,
-        &exact_clonotypes,
-        &info,
-        &mut join_info,
-        &mut raw_joins,
-        &sr,
-        &drefs,
-    );
+    let mut prior = Vec::<Option<usize>>::new();
+    if let Some(state) = &join_state {
+        prior = restore_joins(
+            state,
+            &exact_clonotypes,
+            &info,
+            &mut raw_joins,
+            &mut join_info,
+        );
+    }
+    let mut eq: EquivRel = if proto_in {
+        join_by_assignment(
+            &exact_clonotypes,
+            &info,
+            &proto_in_data.clonotype_of,
+            &mut raw_joins,
+        )
+    } else {
+        join_exacts(
+            is_bcr,
+            &to_bc,
+            refdata,
+            ctl,
+            &exact_clonotypes,
+            &info,
+            &mut join_info,
+            &mut raw_joins,
+            &sr,
+            &drefs,
+            &prior,
+        )
+    };
+
+    // Save join state and stable clonotype ids.
+
+    let mut join_ids = HashMap::<(usize, String), usize>::new();
+    if !ctl.gen_opt.join_state_out.is_empty()
+        || !ctl.gen_opt.join_id_map.is_empty()
+        || ctl.parseable_opt.pcols.iter().any(|x| x == "join_id")
+    {
+        let (state, ids, merged) = make_join_state(
+            join_state.as_ref(),
+            &prior,
+            &exact_clonotypes,
+            &info,
+            &eq,
+            &raw_joins,
+            &join_info,
+            &alt_refs,
+            refdata,
+            ctl,
+        );
+        if !ctl.gen_opt.join_state_out.is_empty() {
+            save_join_state(&state, &ctl.gen_opt.join_state_out)?;
+        }
+        for (u, ex) in exact_clonotypes.iter().enumerate() {
+            if let Some(id) = ids[u] {
+                for clone in ex.clones.iter() {
+                    join_ids.insert((clone[0].dataset_index, clone[0].barcode.clone()), id);
+                }
+            }
+        }
+        if !ctl.gen_opt.join_id_map.is_empty() {
+            write_join_id_map(
+                &ctl.gen_opt.join_id_map,
+                &exact_clonotypes,
+                &ids,
+                &merged,
+                ctl,
+            );
+        }
+    }
 
     // If NWEAK_ONESIES is not specified, disintegrate certain onesie clonotypes into single cell
     // clonotypes.  This requires editing of exact_clonotypes, info, eq, join_info and raw_joins.
@@ -874,6 +992,7 @@ pub fn main_enclone_start(setup: EncloneSetup) -> Result<EncloneIntermediates, S
                 var_pos: Vec::new(),
                 var_bases: Vec::new(),
             },
+            join_ids,
         },
     })
 }
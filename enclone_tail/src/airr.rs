@@ -0,0 +1,316 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+
+// Generate AIRR Community format output, as specified at
+// https://docs.airr-community.org/en/stable/datarep/rearrangements.html and
+// https://docs.airr-community.org/en/stable/datarep/clone.html.
+//
+// AIRR=filename writes a rearrangement TSV file having one row for each chain of each cell, and
+// a clone TSV file, named by airr_clone_filename, having one row for each chain of each
+// clonotype.  In the clone file, clone_count, exact_subclonotype_count and umi_count are for the
+// cells in the clonotype that have the chain.  The clone_id is the clonotype id, as printed by
+// enclone (group.clonotype), or the stable clonotype id if CLONOTYPE_IDS=stable, and the
+// germline alignments use the donor reference V segment, if one was found.
+//
+// A chain is called productive if its V..J sequence has no stop codon in the reading frame of V,
+// and its junction is in that frame.  Nonproductive chains only appear if REPROD is specified.
+//
+// Alignments are not IMGT gapped.  In the germline alignments, the junction region between the
+// end of V and the start of J is filled with N.  Cigar strings are not provided.
+
+use amino::aa_seq;
+use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype, TigData1};
+use enclone_proto::types::DonorReferenceItem;
+use io_utils::fwriteln;
+use itertools::Itertools;
+use std::io::Write;
+use string_utils::strme;
+use vdj_ann::refx::RefData;
+
+pub const AIRR_REARRANGEMENT_FIELDS: [&str; 31] = [
+    "sequence_id",
+    "sequence",
+    "rev_comp",
+    "productive",
+    "v_call",
+    "d_call",
+    "j_call",
+    "c_call",
+    "locus",
+    "sequence_alignment",
+    "germline_alignment",
+    "junction",
+    "junction_aa",
+    "cdr3",
+    "cdr3_aa",
+    "v_cigar",
+    "d_cigar",
+    "j_cigar",
+    "v_sequence_start",
+    "v_sequence_end",
+    "j_sequence_start",
+    "j_sequence_end",
+    "c_sequence_start",
+    "cell_id",
+    "clone_id",
+    "exact_subclonotype_id",
+    "repertoire_id",
+    "sample_processing_id",
+    "umi_count",
+    "consensus_count",
+    "duplicate_count",
+];
+
+pub const AIRR_CLONE_FIELDS: [&str; 14] = [
+    "clone_id",
+    "locus",
+    "v_call",
+    "d_call",
+    "j_call",
+    "c_call",
+    "junction",
+    "junction_aa",
+    "junction_length",
+    "junction_aa_length",
+    "germline_alignment",
+    "clone_count",
+    "exact_subclonotype_count",
+    "umi_count",
+];
+
+// Write the header lines for the two AIRR files.
+
+pub fn airr_headers(
+    airr_out: &mut Box<dyn std::io::Write>,
+    airr_clone_out: &mut Box<dyn std::io::Write>,
+) {
+    fwriteln!(
+        airr_out,
+        "{}",
+        AIRR_REARRANGEMENT_FIELDS.iter().format("\t")
+    );
+    fwriteln!(airr_clone_out, "{}", AIRR_CLONE_FIELDS.iter().format("\t"));
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Get the germline V segment for a chain, using the donor reference if available.
+
+fn germline_v(x: &TigData1, refdata: &RefData, dref: &Vec<DonorReferenceItem>) -> Vec<u8> {
+    match x.v_ref_id_donor {
+        Some(d) => dref[d].nt_sequence.clone(),
+        None => refdata.refs[x.v_ref_id].to_ascii_vec(),
+    }
+}
+
+// Align the V..J sequence of a chain to its germline.  The V segment alignment is defined by
+// annv, which may include one indel, and the J segment is assumed to be aligned without indels
+// from j_start_ref to its end.  Return (sequence_alignment, germline_alignment).
+
+pub fn airr_alignment(
+    x: &TigData1,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+) -> (Vec<u8>, Vec<u8>) {
+    let seq = &x.seq;
+    let vref = germline_v(x, refdata, dref);
+    let jref = refdata.refs[x.j_ref_id].to_ascii_vec();
+    let (mut sa, mut ga) = (Vec::<u8>::new(), Vec::<u8>::new());
+
+    // Do the V segment.
+
+    let (mut tpos, mut rpos) = (0, 0);
+    for a in x.annv.iter() {
+        let (tstart, len, rstart) = (a.0 as usize, a.1 as usize, a.3 as usize);
+        while tpos < tstart && tpos < seq.len() {
+            sa.push(seq[tpos]);
+            ga.push(b'-');
+            tpos += 1;
+        }
+        while rpos < rstart && rpos < vref.len() {
+            sa.push(b'-');
+            ga.push(vref[rpos]);
+            rpos += 1;
+        }
+        for _ in 0..len {
+            if tpos >= seq.len() || rpos >= vref.len() {
+                break;
+            }
+            sa.push(seq[tpos]);
+            ga.push(vref[rpos]);
+            tpos += 1;
+            rpos += 1;
+        }
+    }
+
+    // Do the junction region.
+
+    let jstart = x.j_start - x.v_start;
+    while tpos < jstart && tpos < seq.len() {
+        sa.push(seq[tpos]);
+        ga.push(b'N');
+        tpos += 1;
+    }
+
+    // Do the J segment.
+
+    let mut rpos = x.j_start_ref;
+    while tpos < seq.len() {
+        sa.push(seq[tpos]);
+        if rpos < jref.len() {
+            ga.push(jref[rpos]);
+        } else {
+            ga.push(b'N');
+        }
+        tpos += 1;
+        rpos += 1;
+    }
+    (sa, ga)
+}
+
+// Determine if a chain is productive.
+
+pub fn airr_productive(x: &TigData1) -> bool {
+    x.cdr3_start % 3 == 0 && x.cdr3_dna.len() % 3 == 0 && !aa_seq(&x.seq, 0).contains(&b'*')
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Generate AIRR output for one clonotype, which is clonotype j in group i.
+
+pub fn generate_airr_output(
+    i: usize,
+    j: usize,
+    oo: usize,
+    exacts: &Vec<Vec<usize>>,
+    rsi: &Vec<ColInfo>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+    stable_ids: &Vec<String>,
+    airr_out: &mut Box<dyn std::io::Write>,
+    airr_clone_out: &mut Box<dyn std::io::Write>,
+) {
+    if ctl.gen_opt.airr.is_empty() {
+        return;
+    }
+    let clone_id = if ctl.gen_opt.stable_clonotype_ids {
+        stable_ids[oo].clone()
+    } else {
+        format!("{}.{}", i + 1, j + 1)
+    };
+    let name = |id: Option<usize>| -> String {
+        match id {
+            Some(id) => refdata.name[id].clone(),
+            None => String::new(),
+        }
+    };
+    let mat = &rsi[oo].mat;
+
+    // Write the rearrangement rows.
+
+    for (k, u) in exacts[oo].iter().enumerate() {
+        let ex = &exact_clonotypes[*u];
+        for l in 0..ex.clones.len() {
+            for cx in 0..mat.len() {
+                if mat[cx][k].is_none() {
+                    continue;
+                }
+                let m = mat[cx][k].unwrap();
+                let x = &ex.share[m];
+                let c = &ex.clones[l][m];
+                let (sa, ga) = airr_alignment(x, refdata, dref);
+                let junction = &x.cdr3_dna;
+                let mut cdr3 = String::new();
+                let mut cdr3_aa = String::new();
+                if junction.len() >= 6 {
+                    cdr3 = junction[3..junction.len() - 3].to_string();
+                    cdr3_aa = x.cdr3_aa[1..x.cdr3_aa.len() - 1].to_string();
+                }
+                let j_start = c.v_start + (x.j_start - x.v_start);
+                let mut c_start = String::new();
+                if c.c_start.is_some() {
+                    c_start = format!("{}", c.c_start.unwrap() + 1);
+                }
+                let row = vec![
+                    c.tigname.clone(),
+                    strme(&c.full_seq).to_string(),
+                    "F".to_string(),
+                    if airr_productive(x) { "T" } else { "F" }.to_string(),
+                    refdata.name[x.v_ref_id].clone(),
+                    name(x.d_ref_id),
+                    refdata.name[x.j_ref_id].clone(),
+                    name(x.c_ref_id),
+                    x.chain_type.clone(),
+                    strme(&sa).to_string(),
+                    strme(&ga).to_string(),
+                    junction.clone(),
+                    x.cdr3_aa.clone(),
+                    cdr3,
+                    cdr3_aa,
+                    String::new(),
+                    String::new(),
+                    String::new(),
+                    format!("{}", c.v_start + 1),
+                    format!("{}", c.v_start + (x.v_stop - x.v_start)),
+                    format!("{}", j_start + 1),
+                    format!("{}", c.j_stop),
+                    c_start,
+                    c.barcode.clone(),
+                    clone_id.clone(),
+                    format!("{}", k + 1),
+                    ctl.origin_info.dataset_id[c.dataset_index].clone(),
+                    match c.origin_index {
+                        Some(o) => ctl.origin_info.origin_list[o].clone(),
+                        None => String::new(),
+                    },
+                    format!("{}", c.umi_count),
+                    format!("{}", c.read_count),
+                    format!("{}", c.umi_count),
+                ];
+                fwriteln!(airr_out, "{}", row.iter().format("\t"));
+            }
+        }
+    }
+
+    // Write the clone rows, one for each chain.  The chain is described by the first exact
+    // subclonotype that has it.
+
+    for cx in 0..mat.len() {
+        let k = (0..exacts[oo].len()).find(|&k| mat[cx][k].is_some());
+        if k.is_none() {
+            continue;
+        }
+        let k = k.unwrap();
+        let x = &exact_clonotypes[exacts[oo][k]].share[mat[cx][k].unwrap()];
+        let (_, ga) = airr_alignment(x, refdata, dref);
+        let (mut ncells, mut nexacts, mut numis) = (0, 0, 0);
+        for (k, u) in exacts[oo].iter().enumerate() {
+            if let Some(m) = mat[cx][k] {
+                let ex = &exact_clonotypes[*u];
+                ncells += ex.ncells();
+                nexacts += 1;
+                for l in 0..ex.clones.len() {
+                    numis += ex.clones[l][m].umi_count;
+                }
+            }
+        }
+        let row = vec![
+            clone_id.clone(),
+            x.chain_type.clone(),
+            refdata.name[rsi[oo].vids[cx]].clone(),
+            name(rsi[oo].dids[cx]),
+            refdata.name[rsi[oo].jids[cx]].clone(),
+            name(rsi[oo].cids[cx]),
+            x.cdr3_dna.clone(),
+            x.cdr3_aa.clone(),
+            format!("{}", x.cdr3_dna.len()),
+            format!("{}", x.cdr3_aa.len()),
+            strme(&ga).to_string(),
+            format!("{}", ncells),
+            format!("{}", nexacts),
+            format!("{}", numis),
+        ];
+        fwriteln!(airr_clone_out, "{}", row.iter().format("\t"));
+    }
+}
@@ -0,0 +1,575 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Execute DE=filename, which finds genes that are differentially expressed between a test set of
+// cells and a control set of cells, using the Wilcoxon rank-sum test.
+//
+// The test set consists of the cells in the clonotypes specified by DE_TEST, or if DE_TEST is not
+// specified, the cells in all the clonotypes that are printed, so that for example
+// KEEP_CLONO_IF_CELL_MEAN may be used to define the test set.  The control set consists of the
+// cells in the clonotypes specified by DE_CONTROL, or if DE_CONTROL=vdj, the cells in all other
+// printed clonotypes, or if DE_CONTROL is not specified, all other gene expression cells.  In all
+// cases, cells in the test set are excluded from the control set.
+//
+// For each gene, we compute the mean normalized count in the test and control sets, the log2 fold
+// change, using a pseudocount of one, and a two-sided p value, using the normal approximation to
+// the rank-sum statistic, with correction for ties and continuity.  The p values are adjusted for
+// multiple testing using the Benjamini-Hochberg procedure, and genes are listed in order of
+// increasing p value.  Genes that are not expressed in either set are not tested.
+//
+// Normalized counts are raw counts scaled by the gene expression multiplier of the dataset
+// (gex_mults), as for the variable gex, unless FULL_COUNTS is specified.  There is no per-cell
+// normalization, for example by the total count of the cell.
+
+use enclone_core::defs::{ClonotypeSet, EncloneControl, ExactClonotype, GexInfo, POUT_SEP};
+use enclone_core::linear_condition::LinearCondition;
+use ndarray::s;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use string_utils::TextUtils;
+use vector_utils::{bin_position, unique_sort, VecUtils};
+
+#[cfg(target_os = "windows")]
+use hdf5::Reader;
+#[cfg(not(target_os = "windows"))]
+use hdf5x::Reader;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Complementary error function, with fractional error less than 1.2e-7 everywhere.  This is
+// erfcc from Numerical Recipes.
+
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let mut p = 0.17087277;
+    for c in [
+        -0.82215223,
+        1.48851587,
+        -1.13520398,
+        0.27886807,
+        -0.18628806,
+        0.09678418,
+        0.37409196,
+        1.00002368,
+    ]
+    .iter()
+    {
+        p = c + t * p;
+    }
+    let r = t * (-z * z - 1.26551223 + t * p).exp();
+    if x >= 0.0 {
+        r
+    } else {
+        2.0 - r
+    }
+}
+
+// Compute the two-sided p value for the Wilcoxon rank-sum test, given the nonzero values in the
+// test and control sets, and the total sizes of the sets.  All other values are zero.
+
+fn wilcoxon(test: &[f64], control: &[f64], nt: usize, nc: usize) -> f64 {
+    let n = (nt + nc) as f64;
+    let n0 = nt + nc - test.len() - control.len();
+    let mut vals = Vec::<(f64, bool)>::new();
+    for x in test.iter() {
+        vals.push((*x, true));
+    }
+    for x in control.iter() {
+        vals.push((*x, false));
+    }
+    vals.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    // Compute the rank sum for the test set, and the tie correction.
+
+    let mut rank_sum = (nt - test.len()) as f64 * (n0 as f64 + 1.0) / 2.0;
+    let mut ties = (n0 as f64).powi(3) - n0 as f64;
+    let mut i = 0;
+    while i < vals.len() {
+        let mut j = i + 1;
+        while j < vals.len() && vals[j].0 == vals[i].0 {
+            j += 1;
+        }
+        let rank = (n0 + i + 1 + n0 + j) as f64 / 2.0;
+        for k in i..j {
+            if vals[k].1 {
+                rank_sum += rank;
+            }
+        }
+        let t = (j - i) as f64;
+        ties += t.powi(3) - t;
+        i = j;
+    }
+
+    // Use the normal approximation.
+
+    let (nt, nc) = (nt as f64, nc as f64);
+    let u = rank_sum - nt * (nt + 1.0) / 2.0;
+    let mu = nt * nc / 2.0;
+    let var = nt * nc / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if var <= 0.0 {
+        return 1.0;
+    }
+    let z = ((u - mu).abs() - 0.5).max(0.0) / var.sqrt();
+    erfc(z / 2.0_f64.sqrt()).min(1.0)
+}
+
+// Adjust p values using the Benjamini-Hochberg procedure.
+
+fn benjamini_hochberg(p: &[f64]) -> Vec<f64> {
+    let m = p.len();
+    let mut ids = (0..m).collect::<Vec<usize>>();
+    ids.sort_by(|&a, &b| p[a].partial_cmp(&p[b]).unwrap());
+    let mut q = vec![1.0; m];
+    let mut min = 1.0_f64;
+    for k in (0..m).rev() {
+        min = min.min(p[ids[k]] * m as f64 / (k + 1) as f64);
+        q[ids[k]] = min;
+    }
+    q
+}
+
+// Find the mean value of each variable in a linear condition across the cells in a clonotype,
+// and determine if the condition is satisfied.  This uses the same definition of mean as
+// KEEP_CLONO_IF_CELL_MEAN, namely the mean over cells for which the variable has a numerical
+// value.
+
+fn satisfies_condition(
+    x: &LinearCondition,
+    exacts: &[usize],
+    exact_clonotypes: &Vec<ExactClonotype>,
+    out_data: &Vec<HashMap<String, String>>,
+) -> bool {
+    let mut means = Vec::<f64>::new();
+    for var in x.var.iter() {
+        let (mut sum, mut count) = (0.0, 0);
+        for (u, d) in out_data.iter().enumerate() {
+            if let Some(val_list) = d.get(var) {
+                let vals = val_list.split(POUT_SEP).collect::<Vec<&str>>();
+                let mult = if vals.solo() {
+                    exact_clonotypes[exacts[u]].ncells()
+                } else {
+                    1
+                };
+                for v in vals.iter() {
+                    if let Ok(v) = v.parse::<f64>() {
+                        sum += v * mult as f64;
+                        count += mult;
+                    }
+                }
+            }
+        }
+        if count == 0 {
+            return false;
+        }
+        means.push(sum / count as f64);
+    }
+    x.satisfied(&means)
+}
+
+// Determine which printed clonotypes are in a clonotype set.
+
+fn in_set(
+    set: &ClonotypeSet,
+    arg: &str,
+    exacts: &Vec<Vec<usize>>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    out_datas: &Vec<Vec<HashMap<String, String>>>,
+    groups: &Vec<Vec<(i32, String)>>,
+    stable_ids: &Vec<String>,
+) -> Result<Vec<usize>, String> {
+    let mut x = Vec::<usize>::new();
+    match set {
+        ClonotypeSet::Ids(ids) => {
+            let mut found = vec![false; ids.len()];
+            for i in 0..groups.len() {
+                for j in 0..groups[i].len() {
+                    let o = groups[i][j].0 as usize;
+                    let names = [
+                        format!("{}", i + 1),
+                        format!("{}.{}", i + 1, j + 1),
+                        stable_ids[o].clone(),
+                    ];
+                    for k in 0..ids.len() {
+                        if names.contains(&ids[k]) {
+                            found[k] = true;
+                            x.push(o);
+                        }
+                    }
+                }
+            }
+            for k in 0..ids.len() {
+                if !found[k] {
+                    return Err(format!(
+                        "\nThe id {} in your {} argument does not match any printed group or \
+                         clonotype.\n",
+                        ids[k], arg
+                    ));
+                }
+            }
+        }
+        ClonotypeSet::Condition(cond) => {
+            for i in 0..groups.len() {
+                for j in 0..groups[i].len() {
+                    let o = groups[i][j].0 as usize;
+                    if satisfies_condition(cond, &exacts[o], exact_clonotypes, &out_datas[o]) {
+                        x.push(o);
+                    }
+                }
+            }
+        }
+    }
+    unique_sort(&mut x);
+    Ok(x)
+}
+
+// Get the normalized counts (or if raw is specified, the raw counts) for the gene expression
+// features in a cell, indexed by global feature index.  Normalization is only by the dataset
+// multiplier.
+
+pub fn cell_counts(
+    ctl: &EncloneControl,
+    gex_info: &GexInfo,
+    li: usize,
+    p: usize,
+    to_global: &Vec<Vec<Option<usize>>>,
+    h5_data: &Vec<(usize, Vec<u32>, Vec<u32>)>,
+    d_readers: &Vec<Option<Reader>>,
+    ind_readers: &Vec<Option<Reader>>,
+    raw: bool,
+) -> Vec<(usize, f64)> {
+    let mut row = Vec::<(usize, f64)>::new();
+    if gex_info.gex_matrices[li].initialized() {
+        for (f, n) in gex_info.gex_matrices[li].row(p).iter() {
+            row.push((*f, *n as f64));
+        }
+    } else {
+        let z1 = gex_info.h5_indptr[li][p] as usize;
+        let z2 = gex_info.h5_indptr[li][p + 1] as usize;
+        let d: Vec<u32>;
+        let ind: Vec<u32>;
+        if ctl.gen_opt.h5_pre {
+            d = h5_data[li].1[z1..z2].to_vec();
+            ind = h5_data[li].2[z1..z2].to_vec();
+        } else {
+            d = d_readers[li]
+                .as_ref()
+                .unwrap()
+                .read_slice(s![z1..z2])
+                .unwrap()
+                .to_vec();
+            ind = ind_readers[li]
+                .as_ref()
+                .unwrap()
+                .read_slice(s![z1..z2])
+                .unwrap()
+                .to_vec();
+        }
+        for j in 0..d.len() {
+            row.push((ind[j] as usize, d[j] as f64));
+        }
+    }
+    let mult = if ctl.gen_opt.full_counts || raw {
+        1.0
+    } else {
+        gex_info.gex_mults[li]
+    };
+    let mut x = Vec::<(usize, f64)>::new();
+    for (f, n) in row {
+        if let Some(g) = to_global[li][f] {
+            if n > 0.0 {
+                x.push((g, n * mult));
+            }
+        }
+    }
+    x
+}
+
+// Find the gene expression features across all datasets, and the map from the features of each
+// dataset to them.
+
+pub fn gene_index(gex_info: &GexInfo) -> (Vec<String>, Vec<Vec<Option<usize>>>) {
+    let n = gex_info.gex_features.len();
+    let mut features = Vec::<String>::new();
+    for li in 0..n {
+        for f in 0..gex_info.gex_features[li].len() {
+            if gex_info.is_gex[li][f] {
+                features.push(gex_info.gex_features[li][f].clone());
+            }
+        }
+    }
+    unique_sort(&mut features);
+    let mut to_global = vec![Vec::<Option<usize>>::new(); n];
+    for li in 0..n {
+        for f in 0..gex_info.gex_features[li].len() {
+            let mut g = None;
+            if gex_info.is_gex[li][f] {
+                g = Some(bin_position(&features, &gex_info.gex_features[li][f]) as usize);
+            }
+            to_global[li].push(g);
+        }
+    }
+    (features, to_global)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+pub fn differential_expression(
+    ctl: &EncloneControl,
+    exacts: &Vec<Vec<usize>>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    out_datas: &Vec<Vec<HashMap<String, String>>>,
+    gex_info: &GexInfo,
+    groups: &Vec<Vec<(i32, String)>>,
+    stable_ids: &Vec<String>,
+    h5_data: &Vec<(usize, Vec<u32>, Vec<u32>)>,
+    d_readers: &Vec<Option<Reader>>,
+    ind_readers: &Vec<Option<Reader>>,
+) -> Result<(), String> {
+    let n = ctl.origin_info.n();
+    if (0..n).all(|li| ctl.origin_info.gex_path[li].is_empty()) {
+        return Err("\nDE requires gene expression data.\n".to_string());
+    }
+
+    // Define the test and control clonotypes.
+
+    let mut all = Vec::<usize>::new();
+    for i in 0..groups.len() {
+        for j in 0..groups[i].len() {
+            all.push(groups[i][j].0 as usize);
+        }
+    }
+    unique_sort(&mut all);
+    let set = |x: &ClonotypeSet, arg: &str| {
+        in_set(
+            x,
+            arg,
+            exacts,
+            exact_clonotypes,
+            out_datas,
+            groups,
+            stable_ids,
+        )
+    };
+    let tests = match &ctl.gen_opt.de_test {
+        Some(x) => set(x, "DE_TEST")?,
+        None => all.clone(),
+    };
+    let controls = match &ctl.gen_opt.de_control {
+        Some(x) => Some(set(x, "DE_CONTROL")?),
+        None if ctl.gen_opt.de_control_vdj => Some(all),
+        None => None,
+    };
+
+    // Define the test and control cells, as positions in the gene expression barcode lists.
+
+    let cells_of = |x: &Vec<usize>| {
+        let mut cells = Vec::<(usize, usize)>::new();
+        for &o in x.iter() {
+            for &u in exacts[o].iter() {
+                for clone in exact_clonotypes[u].clones.iter() {
+                    let li = clone[0].dataset_index;
+                    let p = bin_position(&gex_info.gex_barcodes[li], &clone[0].barcode);
+                    if p >= 0 {
+                        cells.push((li, p as usize));
+                    }
+                }
+            }
+        }
+        unique_sort(&mut cells);
+        cells
+    };
+    let test_cells = cells_of(&tests);
+    let test_set = test_cells.iter().cloned().collect::<HashSet<_>>();
+    let mut control_cells = match &controls {
+        Some(x) => cells_of(x),
+        None => {
+            let mut cells = Vec::<(usize, usize)>::new();
+            for li in 0..n {
+                for p in 0..gex_info.gex_barcodes[li].len() {
+                    cells.push((li, p));
+                }
+            }
+            cells
+        }
+    };
+    control_cells.retain(|x| !test_set.contains(x));
+    if test_cells.is_empty() {
+        return Err("\nDE failed, because there are no cells in the test set.\n".to_string());
+    }
+    if control_cells.is_empty() {
+        return Err("\nDE failed, because there are no cells in the control set.\n".to_string());
+    }
+
+    // Index the gene expression features across datasets.
+
+    let (features, to_global) = gene_index(gex_info);
+
+    // Gather the nonzero normalized counts for each gene, in the test and control sets.
+
+    let nf = features.len();
+    let mut values = vec![(Vec::<f64>::new(), Vec::<f64>::new()); nf];
+    for pass in 1..=2 {
+        let cells = if pass == 1 {
+            &test_cells
+        } else {
+            &control_cells
+        };
+        let mut rows = vec![Vec::<(usize, f64)>::new(); cells.len()];
+        rows.par_iter_mut().enumerate().for_each(|(i, r)| {
+            let (li, p) = cells[i];
+            *r = cell_counts(
+                ctl,
+                gex_info,
+                li,
+                p,
+                &to_global,
+                h5_data,
+                d_readers,
+                ind_readers,
+                false,
+            );
+        });
+        for r in rows.iter() {
+            for (g, v) in r.iter() {
+                if pass == 1 {
+                    values[*g].0.push(*v);
+                } else {
+                    values[*g].1.push(*v);
+                }
+            }
+        }
+    }
+
+    // Test each gene.
+
+    let (nt, nc) = (test_cells.len(), control_cells.len());
+    let mut results = Vec::<(usize, f64, f64, f64, f64)>::new();
+    for g in 0..nf {
+        if !values[g].0.is_empty() || !values[g].1.is_empty() {
+            results.push((g, 0.0, 0.0, 0.0, 1.0));
+        }
+    }
+    results.par_iter_mut().for_each(|res| {
+        let (test, control) = &values[res.0];
+        res.1 = test.iter().sum::<f64>() / nt as f64;
+        res.2 = control.iter().sum::<f64>() / nc as f64;
+        res.3 = ((res.1 + 1.0) / (res.2 + 1.0)).log2();
+        res.4 = wilcoxon(test, control, nt, nc);
+    });
+    let p = results.iter().map(|x| x.4).collect::<Vec<f64>>();
+    let q = benjamini_hochberg(&p);
+    let mut ids = (0..results.len()).collect::<Vec<usize>>();
+    ids.sort_by(|&a, &b| {
+        results[a]
+            .4
+            .partial_cmp(&results[b].4)
+            .unwrap()
+            .then(results[b].3.abs().partial_cmp(&results[a].3.abs()).unwrap())
+            .then(results[a].0.cmp(&results[b].0))
+    });
+
+    // Write the results.
+
+    let filename = &ctl.gen_opt.de_filename;
+    let sep = if filename.ends_with(".csv") {
+        ","
+    } else {
+        "\t"
+    };
+    let mut out: Box<dyn Write> = if filename == "stdout" {
+        Box::new(std::io::stdout())
+    } else {
+        let f = File::create(&filename);
+        if f.is_err() {
+            return Err(format!(
+                "\nThe file {} in your DE argument could not be created.\n",
+                filename
+            ));
+        }
+        Box::new(BufWriter::new(f.unwrap()))
+    };
+    let header = [
+        "id",
+        "name",
+        "test_mean",
+        "control_mean",
+        "log2_fold_change",
+        "p",
+        "p_adj",
+    ];
+    let mut lines = vec![header.join(sep)];
+    for &i in ids.iter() {
+        let r = &results[i];
+        let f = &features[r.0];
+        lines.push(
+            [
+                f.before("\t").to_string(),
+                f.between("\t", "\t").to_string(),
+                format!("{:.3}", r.1),
+                format!("{:.3}", r.2),
+                format!("{:.3}", r.3),
+                format!("{:.3e}", r.4),
+                format!("{:.3e}", q[i]),
+            ]
+            .join(sep),
+        );
+    }
+    for line in lines.iter() {
+        writeln!(out, "{}", line).unwrap();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_erfc() {
+        let examples = [
+            (0.0, 1.0),
+            (0.5, 0.4795001221869535),
+            (1.0, 0.15729920705028513),
+            (-1.0, 1.842700792949715),
+            (2.0, 0.004677734981047265),
+        ];
+        for (x, y) in examples.iter() {
+            assert!(((erfc(*x) - y) / y).abs() < 1.2e-7);
+        }
+    }
+
+    #[test]
+    fn test_wilcoxon() {
+        // The test set is 0, 1, 2, 2 and the control set is 0, 0, 2, 3, 3.  The rank sum of the
+        // test set is 18, so U = 8, versus a mean of 10, and with the tie correction, the
+        // variance is 15.41667.  With the continuity correction, z = 1.5 / sqrt(15.41667).
+
+        let p = wilcoxon(&[1.0, 2.0, 2.0], &[2.0, 3.0, 3.0], 4, 5);
+        assert!((p - 0.7024400589591436).abs() < 1.0e-6);
+
+        // The test is symmetric in the two sets.
+
+        let q = wilcoxon(&[2.0, 3.0, 3.0], &[1.0, 2.0, 2.0], 5, 4);
+        assert!((p - q).abs() < 1.0e-12);
+
+        // If all values are equal, the p value is one.
+
+        assert_eq!(wilcoxon(&[], &[], 3, 3), 1.0);
+    }
+
+    #[test]
+    fn test_benjamini_hochberg() {
+        let q = benjamini_hochberg(&[0.01, 0.04, 0.03, 0.005]);
+        let expected = [0.02, 0.04, 0.04, 0.02];
+        for i in 0..q.len() {
+            assert!((q[i] - expected[i]).abs() < 1.0e-12);
+        }
+        let q = benjamini_hochberg(&[0.01, 0.02, 0.9]);
+        let expected = [0.03, 0.03, 0.9];
+        for i in 0..q.len() {
+            assert!((q[i] - expected[i]).abs() < 1.0e-12);
+        }
+    }
+}
@@ -5,28 +5,34 @@
 //
 // To keep compilation time down, this crate should not reach into the enclone crate.
 
+use crate::airr::{airr_headers, generate_airr_output};
 use crate::align_n::align_n;
 use crate::clustal::print_clustal;
 use crate::fasta::generate_fasta;
+use crate::ml_tree::print_ml_tree;
 use crate::parseable::generate_parseable_output;
+use crate::parsimony::print_parsimony_tree;
 use crate::phylip::print_phylip;
 use crate::plot::plot_clonotypes;
+use crate::plot_embed::plot_embed;
 use crate::plot_points::plot_points;
 use crate::print_stats::print_stats;
 use crate::requirements::test_requirements;
 use crate::sim_mat_plot::sim_mat_plot;
 use crate::tree::print_tree;
+use crate::tree_svg::tree_svg;
+use crate::uca::print_uca;
 use ansi_escape::ansi_to_html::{
     compress_ansi_escapes, convert_text_with_ansi_escapes_to_html,
     convert_text_with_ansi_escapes_to_svg,
 };
 use ansi_escape::{emit_bold_escape, emit_eight_bit_color_escape, emit_end_escape};
 use enclone_core::combine_group_pics::combine_group_pics;
-use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype, GexInfo};
+use enclone_core::defs::{abbr_var, ColInfo, EncloneControl, ExactClonotype, GexInfo};
 use enclone_core::mammalian_fixed_len::mammalian_fixed_len_peer_groups;
 use enclone_core::print_tools::font_face_in_css;
 use enclone_core::set_speakers::set_speakers;
-use enclone_core::version_string;
+use enclone_core::{airr_clone_filename, version_string};
 use enclone_proto::types::DonorReferenceItem;
 use io_utils::{fwrite, fwriteln, open_for_write_new};
 use itertools::Itertools;
@@ -34,7 +40,7 @@ use std::cmp::max;
 use std::collections::HashMap;
 use std::env;
 use std::fs::File;
-use std::io::{stdout, Write};
+use std::io::{sink, stdout, BufWriter, Write};
 use std::path::Path;
 use std::time::Instant;
 use string_utils::{stringme, strme, TextUtils};
@@ -53,7 +59,9 @@ pub fn group_and_print_clonotypes(
     exact_clonotypes: &Vec<ExactClonotype>,
     ctl: &EncloneControl,
     out_datas: &mut Vec<Vec<HashMap<String, String>>>,
+    stable_ids: &Vec<String>,
     join_info: &Vec<(usize, usize, bool, Vec<u8>)>,
+    join_ids: &HashMap<(usize, String), usize>,
     gex_info: &GexInfo,
     vdj_cells: &Vec<Vec<String>>,
     fate: &Vec<HashMap<String, String>>,
@@ -100,11 +108,7 @@ pub fn group_and_print_clonotypes(
     }
     let mut pcols2 = Vec::<String>::new();
     for i in 0..pcols.len() {
-        if pcols[i].contains(':') {
-            pcols2.push(pcols[i].before(":").to_string());
-        } else {
-            pcols2.push(pcols[i].clone());
-        }
+        pcols2.push(abbr_var(&pcols[i]).0);
     }
     pcols = pcols2;
     let mut pcols_show = pcols.clone();
@@ -140,6 +144,67 @@ pub fn group_and_print_clonotypes(
         }
     };
 
+    // Set up for parsimony tree FASTA output.
+
+    #[allow(bare_trait_objects)]
+    let mut ptout = match ctl.gen_opt.tree_pars_fasta.as_str() {
+        "" => (Box::new(stdout()) as Box<Write>),
+        "stdout" => (Box::new(stdout()) as Box<Write>),
+        _ => {
+            let path = Path::new(&ctl.gen_opt.tree_pars_fasta);
+            Box::new(BufWriter::new(File::create(&path).unwrap())) as Box<Write>
+        }
+    };
+
+    // Set up for maximum likelihood tree posterior output.
+
+    #[allow(bare_trait_objects)]
+    let mut mlout = match ctl.gen_opt.tree_ml_post.as_str() {
+        "" => (Box::new(stdout()) as Box<Write>),
+        "stdout" => (Box::new(stdout()) as Box<Write>),
+        _ => {
+            let path = Path::new(&ctl.gen_opt.tree_ml_post);
+            Box::new(BufWriter::new(File::create(&path).unwrap())) as Box<Write>
+        }
+    };
+    if !ctl.gen_opt.tree_ml_post.is_empty() && ctl.gen_opt.tree_ml_post != *"stdout" {
+        fwriteln!(mlout, "group,clonotype,node,chain,pos,map,A,C,G,T");
+    }
+
+    // Set up for unmutated common ancestor FASTA output.
+
+    #[allow(bare_trait_objects)]
+    let mut ucaout = match ctl.gen_opt.uca_fasta.as_str() {
+        "" => (Box::new(stdout()) as Box<Write>),
+        "stdout" => (Box::new(stdout()) as Box<Write>),
+        _ => {
+            let path = Path::new(&ctl.gen_opt.uca_fasta);
+            Box::new(BufWriter::new(File::create(&path).unwrap())) as Box<Write>
+        }
+    };
+
+    // Set up for AIRR output.
+
+    #[allow(bare_trait_objects)]
+    let (mut airr_out, mut airr_clone_out) = match ctl.gen_opt.airr.as_str() {
+        "" => (
+            Box::new(sink()) as Box<Write>,
+            Box::new(sink()) as Box<Write>,
+        ),
+        _ => {
+            let f = &ctl.gen_opt.airr;
+            (
+                Box::new(BufWriter::new(File::create(f).unwrap())) as Box<Write>,
+                Box::new(BufWriter::new(
+                    File::create(&airr_clone_filename(f)).unwrap(),
+                )) as Box<Write>,
+            )
+        }
+    };
+    if !ctl.gen_opt.airr.is_empty() {
+        airr_headers(&mut airr_out, &mut airr_clone_out);
+    }
+
     // Set up for clustal output.
 
     let (mut clustal_aa, mut clustal_dna) = (None, None);
@@ -518,6 +583,54 @@ pub fn group_and_print_clonotypes(
                 &mut glog,
             );
 
+            // Generate parsimony tree output (options TREE_PARS and TREE_PARS_FASTA).
+
+            print_parsimony_tree(
+                i,
+                j,
+                oo,
+                exacts,
+                rsi,
+                exact_clonotypes,
+                ctl,
+                refdata,
+                dref,
+                &mut glog,
+                &mut ptout,
+            );
+
+            // Generate maximum likelihood tree output (options TREE_ML and TREE_ML_POST).
+
+            print_ml_tree(
+                i,
+                j,
+                oo,
+                exacts,
+                rsi,
+                exact_clonotypes,
+                ctl,
+                refdata,
+                dref,
+                &mut glog,
+                &mut mlout,
+            );
+
+            // Generate unmutated common ancestor output (option UCA_FASTA).
+
+            print_uca(
+                i,
+                j,
+                oo,
+                exacts,
+                rsi,
+                exact_clonotypes,
+                ctl,
+                refdata,
+                dref,
+                &mut glog,
+                &mut ucaout,
+            );
+
             // Generate peer group output.
 
             if !ctl.gen_opt.peer_group_filename.is_empty() {
@@ -622,6 +735,23 @@ pub fn group_and_print_clonotypes(
                 &mut faaout,
             );
 
+            // Generate AIRR output.
+
+            generate_airr_output(
+                i,
+                j,
+                oo,
+                exacts,
+                rsi,
+                exact_clonotypes,
+                ctl,
+                refdata,
+                dref,
+                stable_ids,
+                &mut airr_out,
+                &mut airr_clone_out,
+            );
+
             // Generate parseable output.
 
             generate_parseable_output(
@@ -629,6 +759,8 @@ pub fn group_and_print_clonotypes(
                 exact_clonotypes,
                 ctl,
                 out_datas,
+                stable_ids,
+                join_ids,
                 group_ncells,
                 i,
                 j,
@@ -682,6 +814,20 @@ pub fn group_and_print_clonotypes(
 
     sim_mat_plot(ctl, groups, out_datas, svgs);
 
+    // Execute TREE_SVG.
+
+    tree_svg(
+        ctl,
+        refdata,
+        exacts,
+        rsi,
+        exact_clonotypes,
+        out_datas,
+        dref,
+        groups,
+        svgs,
+    )?;
+
     // Execute PLOT_XY.
 
     if !ctl.plot_opt.plot_xy_filename.is_empty() {
@@ -735,6 +881,12 @@ pub fn group_and_print_clonotypes(
         }
     }
 
+    // Execute PLOT_EMBED.
+
+    if !ctl.plot_opt.plot_embed_filename.is_empty() {
+        plot_embed(ctl, exacts, exact_clonotypes, gex_info, groups, stable_ids)?;
+    }
+
     // Finish CLUSTAL.
 
     if clustal_aa.is_some() {
@@ -1,5 +1,6 @@
 // Copyright (c) 2021 10x Genomics, Inc. All rights reserved.
 
+pub mod airr;
 pub mod align_n;
 pub mod alluvial_fb;
 pub mod assign_cell_color;
@@ -8,6 +9,7 @@ pub mod circles_to_svg;
 pub mod clustal;
 pub mod colors;
 pub mod convert_svg_to_png;
+pub mod de;
 pub mod display_tree;
 pub mod fasta;
 pub mod fate;
@@ -17,17 +19,21 @@ pub mod grouper;
 pub mod hex;
 pub mod keeper_group;
 pub mod legend;
+pub mod ml_tree;
 pub mod neighbor;
 pub mod newick;
 pub mod pack_circles;
 pub mod parseable;
+pub mod parsimony;
 pub mod phylip;
 pub mod plot;
+pub mod plot_embed;
 pub mod plot_points;
 pub mod plot_utils;
 pub mod polygon;
 pub mod print_dataset_vars;
 pub mod print_stats;
+pub mod pseudobulk;
 pub mod requirements;
 pub mod sens_spec;
 pub mod sim_mat_plot;
@@ -35,6 +41,8 @@ pub mod string_width;
 pub mod tail;
 pub mod ticks;
 pub mod tree;
+pub mod tree_svg;
+pub mod uca;
 
 use string_utils::TextUtils;
 
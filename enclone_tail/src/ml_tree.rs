@@ -0,0 +1,630 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Generate maximum likelihood lineage trees (options TREE_ML and TREE_ML_POST).
+//
+// The input for each clonotype is the alignment of the nucleotide V..J sequences of its exact
+// subclonotypes, with chains concatenated, which is also exported by PHYLIP_DNA, together with the
+// germline, which roots the tree (see parsimony.rs).
+//
+// Substitution model.  Substitutions follow the Kimura two-parameter model, with
+// transition/transversion ratio kappa, and are scaled at each site by a mutability, in the spirit
+// of the S5F model.  Rather than using a table of 5-mer mutabilities, we use the canonical
+// somatic hypermutation motifs, evaluated on the germline, with the junction region filled in by
+// the most common base of the exact subclonotypes:
+// - WRC/GYW (AID hotspots, at the C or G): HOT_AID;
+// - WA/TW (polymerase eta hotspots, at the A or T): HOT_POLH;
+// - SYC/GRS (coldspots, at the C or G): COLD;
+// - anything else: 1.
+// The mutabilities are normalized to have mean one, so that branch lengths are in expected
+// substitutions per site.  Because the mutabilities are evaluated on the germline, they do not
+// change as mutations accumulate, unlike a fully context-dependent model.  Gaps (missing chains)
+// and the germline junction region are treated as unknown.
+//
+// Tree search.  We start from the parsimony tree, and optimize the branch lengths, and then
+// kappa.  Then, for clonotypes having at most MAX_NNI exact subclonotypes, we make nearest
+// neighbor interchanges that increase the likelihood, reoptimizing the length of the central
+// branch for each, and then reoptimize all the branch lengths.
+//
+// Ancestral states.  For each inferred ancestor, and the germline, we compute the marginal
+// posterior probability of each base at each site, and the most probable base.
+
+use crate::parsimony::{
+    complete_lineage_tree, lineage_alignment, parsimony_tree, postorder, print_lineage_tree,
+    vertex_cells,
+};
+use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype};
+use enclone_proto::types::DonorReferenceItem;
+use io_utils::fwriteln;
+use rayon::prelude::*;
+use std::io::Write;
+use vdj_ann::refx::RefData;
+
+const HOT_AID: f64 = 4.0;
+const HOT_POLH: f64 = 2.0;
+const COLD: f64 = 0.25;
+const KAPPA_INIT: f64 = 2.0;
+const LEN_INIT: f64 = 0.01;
+const MIN_LEN: f64 = 1.0e-6;
+const MAX_LEN: f64 = 2.0;
+const MAX_NNI: usize = 100;
+const PASSES: usize = 10;
+const GOLDEN_ITERATIONS: usize = 40;
+
+const BASES: &[u8; 4] = b"ACGT";
+
+// Partial likelihoods, for each site and base.
+
+type Partial = Vec<[f64; 4]>;
+
+// A germline-rooted tree.  The root (germline) is vertex 0, and has one child.  The length of
+// the edge above each vertex is in len.
+
+struct Tree {
+    children: Vec<Vec<usize>>,
+    parent: Vec<usize>,
+    len: Vec<f64>,
+}
+
+struct Model {
+    kappa: f64,
+    rates: Vec<f64>,
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Compute the mutability of each site, from the germline context.  The context of a site does not
+// extend beyond the chain containing it.
+
+fn mutabilities(context: &[u8], sites: &Vec<(usize, usize)>) -> Vec<f64> {
+    let at = |p: usize, d: isize| -> u8 {
+        let q = p as isize + d;
+        if q < 0 || q as usize >= context.len() || sites[q as usize].0 != sites[p].0 {
+            b'N'
+        } else {
+            context[q as usize]
+        }
+    };
+    let w = |b: u8| b == b'A' || b == b'T';
+    let r = |b: u8| b == b'A' || b == b'G';
+    let y = |b: u8| b == b'C' || b == b'T';
+    let s = |b: u8| b == b'C' || b == b'G';
+    let mut rates = (0..context.len())
+        .map(|p| match context[p] {
+            b'C' if w(at(p, -2)) && r(at(p, -1)) => HOT_AID,
+            b'C' if s(at(p, -2)) && y(at(p, -1)) => COLD,
+            b'G' if y(at(p, 1)) && w(at(p, 2)) => HOT_AID,
+            b'G' if r(at(p, 1)) && s(at(p, 2)) => COLD,
+            b'A' if w(at(p, -1)) => HOT_POLH,
+            b'T' if w(at(p, 1)) => HOT_POLH,
+            _ => 1.0,
+        })
+        .collect::<Vec<f64>>();
+    let mean = rates.iter().sum::<f64>() / rates.len() as f64;
+    for x in rates.iter_mut() {
+        *x /= mean;
+    }
+    rates
+}
+
+// Compute the Kimura two-parameter transition probability matrix for a branch of length t,
+// where bases are indexed as A, C, G, T, so that a and a ^ 2 differ by a transition.  The total
+// substitution rate is one.
+
+fn transition_probs(t: f64, kappa: f64) -> [[f64; 4]; 4] {
+    let beta = 1.0 / (kappa + 2.0);
+    let alpha = kappa * beta;
+    let e1 = (-4.0 * beta * t).exp();
+    let e2 = (-2.0 * (alpha + beta) * t).exp();
+    let mut p = [[0.25 - 0.25 * e1; 4]; 4];
+    for a in 0..4 {
+        p[a][a] = 0.25 + 0.25 * e1 + 0.5 * e2;
+        p[a][a ^ 2] = 0.25 + 0.25 * e1 - 0.5 * e2;
+    }
+    p
+}
+
+// Propagate partial likelihoods across a branch of length t.
+
+fn propagate(x: &Partial, t: f64, model: &Model) -> Partial {
+    x.iter()
+        .enumerate()
+        .map(|(s, y)| {
+            let p = transition_probs(model.rates[s] * t, model.kappa);
+            let mut z = [0.0; 4];
+            for a in 0..4 {
+                for b in 0..4 {
+                    z[a] += p[a][b] * y[b];
+                }
+            }
+            z
+        })
+        .collect()
+}
+
+// Multiply partial likelihoods into x.
+
+fn multiply(x: &mut Partial, y: &Partial) {
+    for s in 0..x.len() {
+        for a in 0..4 {
+            x[s][a] *= y[s][a];
+        }
+    }
+}
+
+// Scale partial likelihoods at each site to have maximum one, adding the logs of the scale
+// factors to scale.
+
+fn normalize(x: &mut Partial, scale: &mut Vec<f64>) {
+    for s in 0..x.len() {
+        let m = x[s].iter().cloned().fold(0.0, f64::max);
+        if m > 0.0 {
+            for a in 0..4 {
+                x[s][a] /= m;
+            }
+            scale[s] += m.ln();
+        }
+    }
+}
+
+// Maximize a function on an interval by golden section search.
+
+fn golden_max(f: impl Fn(f64) -> f64, mut lo: f64, mut hi: f64) -> f64 {
+    let g = (5.0_f64.sqrt() - 1.0) / 2.0;
+    let (mut x1, mut x2) = (hi - g * (hi - lo), lo + g * (hi - lo));
+    let (mut f1, mut f2) = (f(x1), f(x2));
+    for _ in 0..GOLDEN_ITERATIONS {
+        if f1 < f2 {
+            lo = x1;
+            x1 = x2;
+            f1 = f2;
+            x2 = lo + g * (hi - lo);
+            f2 = f(x2);
+        } else {
+            hi = x2;
+            x2 = x1;
+            f2 = f1;
+            x1 = hi - g * (hi - lo);
+            f1 = f(x1);
+        }
+    }
+    (lo + hi) / 2.0
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Compute, for each vertex other than the root, the partial likelihoods of the subtree below it,
+// and the logs of their scale factors.
+
+fn down_pass(tree: &Tree, leaves: &Vec<Partial>, model: &Model) -> (Vec<Partial>, Vec<Vec<f64>>) {
+    let nv = tree.children.len();
+    let nsites = leaves[0].len();
+    let mut down = vec![Partial::new(); nv];
+    let mut scale = vec![vec![0.0; nsites]; nv];
+    for v in postorder(&tree.children) {
+        if v == 0 {
+            continue;
+        }
+        if tree.children[v].is_empty() {
+            down[v] = leaves[v].clone();
+        } else {
+            let mut x = vec![[1.0; 4]; nsites];
+            let mut sc = vec![0.0; nsites];
+            for c in tree.children[v].iter() {
+                multiply(&mut x, &propagate(&down[*c], tree.len[*c], model));
+                for s in 0..nsites {
+                    sc[s] += scale[*c][s];
+                }
+            }
+            normalize(&mut x, &mut sc);
+            down[v] = x;
+            scale[v] = sc;
+        }
+    }
+    (down, scale)
+}
+
+// Compute, for each vertex v other than the root, the partial likelihoods of the part of the tree
+// that is not below v, at the top of the edge above v.  These are not scaled consistently.
+
+fn up_pass(tree: &Tree, down: &Vec<Partial>, leaves: &Vec<Partial>, model: &Model) -> Vec<Partial> {
+    let nv = tree.children.len();
+    let mut up = vec![Partial::new(); nv];
+    let mut order = postorder(&tree.children);
+    order.reverse();
+    for v in order {
+        if v == 0 {
+            continue;
+        }
+        let u = tree.parent[v];
+        if u == 0 {
+            up[v] = leaves[0].iter().map(|x| x.map(|y| 0.25 * y)).collect();
+        } else {
+            let mut x = propagate(&up[u], tree.len[u], model);
+            for w in tree.children[u].iter() {
+                if *w != v {
+                    multiply(&mut x, &propagate(&down[*w], tree.len[*w], model));
+                }
+            }
+            let mut sc = vec![0.0; x.len()];
+            normalize(&mut x, &mut sc);
+            up[v] = x;
+        }
+    }
+    up
+}
+
+// Compute the log likelihood of the tree.
+
+fn log_likelihood(tree: &Tree, leaves: &Vec<Partial>, model: &Model) -> f64 {
+    let (down, scale) = down_pass(tree, leaves, model);
+    let c = tree.children[0][0];
+    let x = propagate(&down[c], tree.len[c], model);
+    (0..x.len())
+        .map(|s| {
+            (0..4)
+                .map(|a| 0.25 * leaves[0][s][a] * x[s][a])
+                .sum::<f64>()
+                .ln()
+                + scale[c][s]
+        })
+        .sum()
+}
+
+// Compute the log likelihood of the tree as a function of the length of one edge, up to a
+// constant, given the partial likelihoods above and below the edge.
+
+fn edge_log_likelihood(up: &Partial, down: &Partial, t: f64, model: &Model) -> f64 {
+    let x = propagate(down, t, model);
+    (0..x.len())
+        .map(|s| (0..4).map(|a| up[s][a] * x[s][a]).sum::<f64>().ln())
+        .sum()
+}
+
+// Optimize the branch lengths.  In each pass, each length is optimized, given the others, and
+// the pass is accepted if it increases the likelihood.  Return the log likelihood.
+
+fn optimize_lengths(tree: &mut Tree, leaves: &Vec<Partial>, model: &Model) -> f64 {
+    let mut ll = log_likelihood(tree, leaves, model);
+    for _ in 0..PASSES {
+        let (down, _) = down_pass(tree, leaves, model);
+        let up = up_pass(tree, &down, leaves, model);
+        let len = (0..tree.len.len())
+            .into_par_iter()
+            .map(|v| {
+                if v == 0 {
+                    0.0
+                } else {
+                    golden_max(
+                        |t| edge_log_likelihood(&up[v], &down[v], t, model),
+                        MIN_LEN,
+                        MAX_LEN,
+                    )
+                }
+            })
+            .collect::<Vec<f64>>();
+        let old = std::mem::replace(&mut tree.len, len);
+        let ll2 = log_likelihood(tree, leaves, model);
+        if ll2 <= ll {
+            tree.len = old;
+            break;
+        }
+        let done = ll2 - ll < 1.0e-3;
+        ll = ll2;
+        if done {
+            break;
+        }
+    }
+    ll
+}
+
+// Make nearest neighbor interchanges that increase the likelihood.  For an edge u --> v between
+// ancestors, where v has children a and b and u has another child s, we try exchanging s with a
+// or b.  Return the log likelihood.
+
+fn interchange(tree: &mut Tree, leaves: &Vec<Partial>, model: &Model, mut ll: f64) -> f64 {
+    let n = leaves.len() - 1;
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for v in n + 1..tree.children.len() {
+            let u = tree.parent[v];
+            if u == 0 {
+                continue;
+            }
+            for k in 0..2 {
+                let a = tree.children[v][k];
+                let s = *tree.children[u].iter().find(|x| **x != v).unwrap();
+                let swap = |tree: &mut Tree, x: usize, y: usize| {
+                    let p = tree.children[u].iter().position(|z| *z == x).unwrap();
+                    tree.children[u][p] = y;
+                    let p = tree.children[v].iter().position(|z| *z == y).unwrap();
+                    tree.children[v][p] = x;
+                    tree.parent[y] = u;
+                    tree.parent[x] = v;
+                };
+                swap(tree, s, a);
+                let (down, _) = down_pass(tree, leaves, model);
+                let up = up_pass(tree, &down, leaves, model);
+                let old = tree.len[v];
+                tree.len[v] = golden_max(
+                    |t| edge_log_likelihood(&up[v], &down[v], t, model),
+                    MIN_LEN,
+                    MAX_LEN,
+                );
+                let ll2 = log_likelihood(tree, leaves, model);
+                if ll2 > ll + 1.0e-6 {
+                    ll = ll2;
+                    improved = true;
+                } else {
+                    tree.len[v] = old;
+                    swap(tree, a, s);
+                }
+            }
+        }
+    }
+    ll
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+pub fn print_ml_tree(
+    i: usize,
+    j: usize,
+    oo: usize,
+    exacts: &Vec<Vec<usize>>,
+    rsi: &Vec<ColInfo>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+    logx: &mut Vec<u8>,
+    mlout: &mut Box<dyn std::io::Write>,
+) {
+    if !ctl.gen_opt.tree_ml && ctl.gen_opt.tree_ml_post.is_empty() {
+        return;
+    }
+
+    // Define the alignment, the partial likelihoods of the exact subclonotypes and the germline,
+    // and the model.
+
+    let n = exacts[oo].len();
+    let (seqs, sites, chains) =
+        lineage_alignment(oo, exacts, rsi, exact_clonotypes, ctl, refdata, dref);
+    let leaves = seqs
+        .iter()
+        .map(|x| {
+            x.iter()
+                .map(|b| match BASES.iter().position(|c| c == b) {
+                    Some(a) => {
+                        let mut y = [0.0; 4];
+                        y[a] = 1.0;
+                        y
+                    }
+                    None => [1.0; 4],
+                })
+                .collect::<Partial>()
+        })
+        .collect::<Vec<_>>();
+    let mut context = seqs[0].clone();
+    for p in 0..context.len() {
+        if !BASES.contains(&context[p]) {
+            let mut counts = [0; 4];
+            for x in seqs[1..].iter() {
+                if let Some(a) = BASES.iter().position(|c| *c == x[p]) {
+                    counts[a] += 1;
+                }
+            }
+            let m = (0..4).max_by_key(|a| (counts[*a], 4 - a)).unwrap();
+            if counts[m] > 0 {
+                context[p] = BASES[m];
+            }
+        }
+    }
+    let mut model = Model {
+        kappa: KAPPA_INIT,
+        rates: mutabilities(&context, &sites),
+    };
+
+    // Find the tree.
+
+    let (children, parent) = parsimony_tree(&seqs);
+    let nv = children.len();
+    let mut tree = Tree {
+        children,
+        parent,
+        len: vec![LEN_INIT; nv],
+    };
+    optimize_lengths(&mut tree, &leaves, &model);
+    model.kappa = golden_max(
+        |kappa| {
+            let m = Model {
+                kappa,
+                rates: model.rates.clone(),
+            };
+            log_likelihood(&tree, &leaves, &m)
+        },
+        0.1,
+        20.0,
+    );
+    let mut ll = optimize_lengths(&mut tree, &leaves, &model);
+    if n <= MAX_NNI {
+        ll = interchange(&mut tree, &leaves, &model, ll);
+        ll = optimize_lengths(&mut tree, &leaves, &model);
+    }
+
+    // Compute posterior probabilities and most probable bases.
+
+    let (down, _) = down_pass(&tree, &leaves, &model);
+    let up = up_pass(&tree, &down, &leaves, &model);
+    let mut post = vec![Partial::new(); nv];
+    for v in 0..nv {
+        let mut x = if v == 0 {
+            let c = tree.children[0][0];
+            let mut x = up[c].clone();
+            multiply(&mut x, &propagate(&down[c], tree.len[c], &model));
+            x
+        } else {
+            let mut x = propagate(&up[v], tree.len[v], &model);
+            multiply(&mut x, &down[v]);
+            x
+        };
+        for y in x.iter_mut() {
+            let s = y.iter().sum::<f64>();
+            for a in 0..4 {
+                y[a] /= s;
+            }
+        }
+        post[v] = x;
+    }
+    let best = |y: &[f64; 4]| {
+        (0..4)
+            .max_by(|a, b| y[*a].partial_cmp(&y[*b]).unwrap())
+            .unwrap()
+    };
+    let states = post
+        .iter()
+        .map(|x| x.iter().map(|y| BASES[best(y)]).collect::<Vec<u8>>())
+        .collect::<Vec<_>>();
+
+    // Name the vertices, and find the mutations on each edge.
+
+    let cells = vertex_cells(oo, exacts, exact_clonotypes, nv);
+    let lt = complete_lineage_tree(
+        tree.children.clone(),
+        tree.parent.clone(),
+        seqs,
+        states,
+        &sites,
+        &chains,
+        cells,
+    );
+    let (order, names, states) = (&lt.order, &lt.names, &lt.states);
+
+    // Output the tree.
+
+    if ctl.gen_opt.tree_ml {
+        fwriteln!(
+            logx,
+            "\nmaximum likelihood tree, log likelihood = {:.2}, kappa = {:.2}",
+            ll,
+            model.kappa
+        );
+        let labels = tree
+            .len
+            .iter()
+            .map(|x| format!("{:.5}", x))
+            .collect::<Vec<_>>();
+        let mut lengths = labels.clone();
+        lengths[0].clear();
+        let mean_post = (0..nv)
+            .map(|v| {
+                if v == 0 || v > n {
+                    let x = post[v].iter().map(|y| y[best(y)]).sum::<f64>() / post[v].len() as f64;
+                    format!("{:.3}", x)
+                } else {
+                    String::new()
+                }
+            })
+            .collect::<Vec<_>>();
+        let extra = [("length", lengths), ("posterior", mean_post)];
+        print_lineage_tree(&lt, &tree.len, &labels, &extra, ctl.pretty, logx);
+    }
+
+    // Output the posterior probabilities for the germline and the inferred ancestors.  The CSV
+    // header is written once, by group.rs if the output is to a file, and otherwise before the
+    // first clonotype.
+
+    if !ctl.gen_opt.tree_ml_post.is_empty() {
+        let stdout = ctl.gen_opt.tree_ml_post == *"stdout";
+        let mut data = Vec::<u8>::new();
+        if stdout && i == 0 && j == 0 {
+            fwriteln!(data, "group,clonotype,node,chain,pos,map,A,C,G,T");
+        }
+        for v in order.iter() {
+            if *v == 0 || *v > n {
+                for (p, &(m, pos)) in sites.iter().enumerate() {
+                    let y = &post[*v][p];
+                    fwriteln!(
+                        data,
+                        "{},{},{},{},{},{},{:.4},{:.4},{:.4},{:.4}",
+                        i + 1,
+                        j + 1,
+                        names[*v],
+                        m + 1,
+                        pos + 1,
+                        states[*v][p] as char,
+                        y[0],
+                        y[1],
+                        y[2],
+                        y[3]
+                    );
+                }
+            }
+        }
+        if stdout {
+            logx.append(&mut data);
+        } else {
+            mlout.write_all(&data).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transition_probs() {
+        for &t in [0.0, 1.0e-4, 0.01, 0.3, 2.0, 50.0].iter() {
+            for &kappa in [0.5, 1.0, 2.0, 10.0].iter() {
+                let p = transition_probs(t, kappa);
+                for a in 0..4 {
+                    assert!((p[a].iter().sum::<f64>() - 1.0).abs() < 1.0e-12);
+                    assert!(p[a].iter().all(|x| *x >= 0.0));
+                }
+            }
+        }
+        assert!((transition_probs(0.0, 2.0)[1][1] - 1.0).abs() < 1.0e-12);
+    }
+
+    #[test]
+    fn test_log_likelihood() {
+        // The germline (A) has one child, an ancestor, whose children are two sequences, A and
+        // G, at a single site, with kappa = 2, so that the transition rate alpha is 1/2 and the
+        // transversion rate beta is 1/4.  Summing over the base x of the ancestor, the
+        // likelihood is 1/4 * sum_x P(A -> x, t3) * P(x -> A, t1) * P(x -> G, t2), where A -> G
+        // is a transition, and A -> C, A -> T, C -> G and T -> G are transversions.  For
+        // branch lengths t1 = 0.1, t2 = 0.2 and t3 = 0.05, this is 0.0184099...
+
+        let one = |a: usize| {
+            let mut x = [0.0; 4];
+            x[a] = 1.0;
+            vec![x]
+        };
+        let leaves = vec![one(0), one(0), one(2)];
+        let tree = Tree {
+            children: vec![vec![3], vec![], vec![], vec![1, 2]],
+            parent: vec![0, 3, 3, 0],
+            len: vec![0.0, 0.1, 0.2, 0.05],
+        };
+        let model = Model {
+            kappa: 2.0,
+            rates: vec![1.0],
+        };
+        let (alpha, beta) = (0.5, 0.25);
+        let same = |t: f64| {
+            0.25 + 0.25 * (-4.0 * beta * t).exp() + 0.5 * (-2.0 * (alpha + beta) * t).exp()
+        };
+        let ts = |t: f64| {
+            0.25 + 0.25 * (-4.0 * beta * t).exp() - 0.5 * (-2.0 * (alpha + beta) * t).exp()
+        };
+        let tv = |t: f64| 0.25 - 0.25 * (-4.0 * beta * t).exp();
+        let (t1, t2, t3) = (0.1, 0.2, 0.05);
+        let lik = 0.25
+            * (same(t3) * same(t1) * ts(t2)
+                + ts(t3) * ts(t1) * same(t2)
+                + 2.0 * tv(t3) * tv(t1) * tv(t2));
+        assert!((lik - 0.018409885855872).abs() < 1.0e-12);
+        assert!((log_likelihood(&tree, &leaves, &model) - lik.ln()).abs() < 1.0e-9);
+    }
+}
@@ -14,12 +14,39 @@
 // The single input argument should be a symmetric n x n matrix, n >= 1.
 // The output is a vector of 2n-3 edges, represented as (v, w, distance).
 //
-// Note that this algorithm is O(n^4).
+// There are two implementations, which produce the same tree, except possibly when there are
+// ties (or near ties, up to rounding) in the choice of neighbors to join.  The canonical
+// implementation is O(n^3).  For n above RAPID_MIN, we instead use the RapidNJ algorithm, which
+// keeps each row of the distance matrix sorted, so as to bound the values of the Q matrix, and
+// thus usually avoids examining most of it.  It is still O(n^3) in the worst case, but is
+// typically much faster.  See:
+//
+// Simonsen M., Mailund T., Pedersen C.N.S. (2008). Rapid neighbour-joining. In: Algorithms in
+// Bioinformatics, WABI 2008. Lecture Notes in Computer Science 5251: 113-122.
 
 use rayon::prelude::*;
 
+const RAPID_MIN: usize = 1000;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
 pub fn neighbor_joining(d: &Vec<Vec<f64>>) -> Vec<(usize, usize, f64)> {
-    let (mut d, mut d2) = (d.clone(), d.clone());
+    neighbor_joining_with_min(d, RAPID_MIN)
+}
+
+fn neighbor_joining_with_min(d: &Vec<Vec<f64>>, rapid_min: usize) -> Vec<(usize, usize, f64)> {
+    if d.len() > rapid_min {
+        rapid_neighbor_joining(d)
+    } else {
+        canonical_neighbor_joining(d)
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Check the input distance matrix.
+
+fn check_distances(d: &Vec<Vec<f64>>) {
     let n0 = d.len();
     assert!(n0 >= 1);
     for i in 0..n0 {
@@ -30,6 +57,14 @@ pub fn neighbor_joining(d: &Vec<Vec<f64>>) -> Vec<(usize, usize, f64)> {
             assert_eq!(d[i][j], d[j][i]);
         }
     }
+}
+
+// The canonical implementation.
+
+pub fn canonical_neighbor_joining(d: &Vec<Vec<f64>>) -> Vec<(usize, usize, f64)> {
+    check_distances(d);
+    let (mut d, mut d2) = (d.clone(), d.clone());
+    let n0 = d.len();
     if n0 == 1 {
         return Vec::new();
     } else if n0 == 2 {
@@ -42,40 +77,29 @@ pub fn neighbor_joining(d: &Vec<Vec<f64>>) -> Vec<(usize, usize, f64)> {
     let mut edges = vec![(0, 0, 0.0); 2 * n0 - 3];
     let mut q = vec![vec![0.0; n0]; n0];
     for n in (3..=n0).rev() {
-        // Do the big part of the calculation, which drives the overall complexity.  We parallelize
-        // if n is large.  Possibly it would be just as good to always parallelize, and the
-        // boundary condition (n <= 100) has not been optimized.
+        // Compute the Q matrix, using row sums of the distance matrix.  This drives the overall
+        // complexity, so we parallelize if n is large.  The boundary condition (n <= 100) has
+        // not been optimized.
 
+        let r = (0..n)
+            .map(|i| d[i][0..n].iter().sum::<f64>())
+            .collect::<Vec<f64>>();
         if n <= 100 {
             for i in 0..n {
                 for j in i + 1..n {
-                    q[i][j] = (n - 2) as f64 * d[i][j];
-                    for k in 0..n {
-                        q[i][j] -= d[i][k] + d[j][k];
-                    }
-                    q[j][i] = q[i][j];
+                    q[i][j] = (n - 2) as f64 * d[i][j] - r[i] - r[j];
                 }
             }
         } else {
-            let mut z = vec![(0, vec![0.0; n]); n];
-            for i in 0..n {
-                z[i].0 = i;
-            }
-            z.par_iter_mut().for_each(|res| {
-                let i = res.0;
+            q[0..n].par_iter_mut().enumerate().for_each(|(i, qi)| {
                 for j in i + 1..n {
-                    res.1[j] = (n - 2) as f64 * d[i][j];
-                    // This loop drives the overall complexity of the algorithm.
-                    for k in 0..n {
-                        res.1[j] -= d[i][k] + d[j][k];
-                    }
+                    qi[j] = (n - 2) as f64 * d[i][j] - r[i] - r[j];
                 }
             });
-            for i in 0..n {
-                for j in i + 1..n {
-                    q[i][j] = z[i].1[j];
-                    q[j][i] = q[i][j];
-                }
+        }
+        for i in 0..n {
+            for j in i + 1..n {
+                q[j][i] = q[i][j];
             }
         }
 
@@ -92,11 +116,7 @@ pub fn neighbor_joining(d: &Vec<Vec<f64>>) -> Vec<(usize, usize, f64)> {
                 }
             }
         }
-        let mut df = (n - 2) as f64 * d[f][g];
-        for k in 0..n {
-            df += d[f][k] - d[g][k];
-        }
-        df /= (2 * (n - 2)) as f64;
+        let df = ((n - 2) as f64 * d[f][g] + r[f] - r[g]) / (2 * (n - 2)) as f64;
         let dg = d[f][g] - df;
         let vnew = n0 + (n0 - n);
         edges[2 * (n0 - n)] = (verts[f], vnew, df);
@@ -141,3 +161,222 @@ pub fn neighbor_joining(d: &Vec<Vec<f64>>) -> Vec<(usize, usize, f64)> {
     }
     edges
 }
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// The RapidNJ implementation.  Each vertex that has not yet been joined occupies a slot, and
+// when two vertices are joined, the new vertex takes the slot of the first.  For each slot, we
+// keep the distances to the other slots, sorted, as of the time that its vertex was created.
+// Then each pair of vertices appears in the sorted row of at least one of them, namely the one
+// that was created later.  Entries for slots that have since been reused are skipped.
+
+pub fn rapid_neighbor_joining(d: &Vec<Vec<f64>>) -> Vec<(usize, usize, f64)> {
+    check_distances(d);
+    let mut d = d.clone();
+    let n0 = d.len();
+    if n0 == 1 {
+        return Vec::new();
+    } else if n0 == 2 {
+        return vec![(0, 1, d[0][1])];
+    }
+    let mut verts = (0..n0).collect::<Vec<usize>>();
+    let mut alive = vec![true; n0];
+    let mut created = vec![0; n0];
+    let mut r = (0..n0)
+        .map(|i| d[i].iter().sum::<f64>())
+        .collect::<Vec<f64>>();
+    let mut sorted = (0..n0)
+        .into_par_iter()
+        .map(|i| {
+            let mut x = (0..n0)
+                .filter(|&j| j != i)
+                .map(|j| (d[i][j], j))
+                .collect::<Vec<_>>();
+            x.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            x
+        })
+        .collect::<Vec<_>>();
+    let mut edges = vec![(0, 0, 0.0); 2 * n0 - 3];
+    for n in (3..=n0).rev() {
+        // Find the pair of vertices that minimizes Q.  For a given row i, the Q values of the
+        // remaining entries are bounded below using the current entry and the maximum row sum.
+
+        let rmax = (0..n0)
+            .filter(|&i| alive[i])
+            .map(|i| r[i])
+            .fold(f64::MIN, f64::max);
+        let nm2 = (n - 2) as f64;
+        let mut best = (f64::MAX, n0, n0);
+        for i in 0..n0 {
+            if !alive[i] {
+                continue;
+            }
+            for &(dij, j) in sorted[i].iter() {
+                if nm2 * dij - r[i] - rmax > best.0 {
+                    break;
+                }
+                if !alive[j] || created[j] > created[i] {
+                    continue;
+                }
+                // Break ties by slot indices.  Slots are never reordered, so this is the same
+                // order as that of the compacted indices in the canonical implementation.  But
+                // row sums are updated incrementally here, so values that tie exactly in one
+                // implementation may differ by rounding in the other.
+
+                let q = (nm2 * dij - r[i] - r[j], i.min(j), i.max(j));
+                if q.0 < best.0 || (q.0 == best.0 && (q.1, q.2) < (best.1, best.2)) {
+                    best = q;
+                }
+            }
+        }
+        let (f, g) = (best.1, best.2);
+
+        // Join them.
+
+        let df = (nm2 * d[f][g] + r[f] - r[g]) / (2.0 * nm2);
+        let dg = d[f][g] - df;
+        let vnew = n0 + (n0 - n);
+        edges[2 * (n0 - n)] = (verts[f], vnew, df);
+        edges[2 * (n0 - n) + 1] = (verts[g], vnew, dg);
+        alive[g] = false;
+        sorted[g].clear();
+        let mut rf = 0.0;
+        for k in 0..n0 {
+            if alive[k] && k != f {
+                let dk = (d[f][k] + d[g][k] - d[f][g]) / 2.0;
+                r[k] += dk - d[f][k] - d[g][k];
+                rf += dk;
+                d[f][k] = dk;
+                d[k][f] = dk;
+            }
+        }
+        r[f] = rf;
+        verts[f] = vnew;
+        created[f] = n0 - n + 1;
+        let mut x = (0..n0)
+            .filter(|&k| alive[k] && k != f)
+            .map(|k| (d[f][k], k))
+            .collect::<Vec<_>>();
+        x.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        sorted[f] = x;
+        if n == 3 {
+            let last = (0..n0).filter(|&k| alive[k]).collect::<Vec<usize>>();
+            edges[2 * n0 - 4] = (verts[last[0]], verts[last[1]], d[last[0]][last[1]]);
+        }
+    }
+    for i in 0..edges.len() {
+        edges[i].2 = edges[i].2.max(0.0);
+    }
+    edges
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Generate a random symmetric distance matrix.  Entries are drawn from a large range, so
+    // that there are no ties in the choice of neighbors to join.
+
+    fn random_distances(n: usize, seed: &mut u64) -> Vec<Vec<f64>> {
+        let mut d = vec![vec![0.0; n]; n];
+        for i in 0..n {
+            for j in i + 1..n {
+                *seed = seed
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                d[i][j] = 1.0 + (*seed >> 11) as f64 / (1u64 << 53) as f64 * 100.0;
+                d[j][i] = d[i][j];
+            }
+        }
+        d
+    }
+
+    // Check that the edges are those of a tree on 2n-2 vertices, in which edge 2k and 2k+1 join
+    // two existing vertices (children) to the new vertex n+k (parent), and the last edge joins
+    // the two vertices that remain.
+
+    fn check_edges(n: usize, edges: &[(usize, usize, f64)]) {
+        assert_eq!(edges.len(), 2 * n - 3);
+        let mut has_parent = vec![false; 2 * n - 2];
+        for (k, e) in edges[0..2 * n - 4].iter().enumerate() {
+            assert_eq!(e.1, n + k / 2);
+            assert!(e.0 < e.1);
+            assert!(!has_parent[e.0]);
+            has_parent[e.0] = true;
+            assert!(e.2 >= 0.0);
+        }
+        let last = edges[2 * n - 4];
+        assert!(!has_parent[last.0] && !has_parent[last.1] && last.0 != last.1);
+        assert_eq!(has_parent.iter().filter(|&&x| !x).count(), 2);
+    }
+
+    // Represent a tree by its splits: for each edge, the set of leaves on the side of the edge
+    // that does not contain leaf 0, together with the length of the edge.
+
+    fn splits(n: usize, edges: &[(usize, usize, f64)]) -> Vec<(Vec<usize>, f64)> {
+        let nv = 2 * n - 2;
+        let mut adj = vec![Vec::<(usize, usize)>::new(); nv];
+        for (k, e) in edges.iter().enumerate() {
+            adj[e.0].push((e.1, k));
+            adj[e.1].push((e.0, k));
+        }
+        let mut below = vec![Vec::<usize>::new(); nv];
+        let mut order = vec![(0, nv)];
+        let mut parent_edge = vec![edges.len(); nv];
+        let mut i = 0;
+        while i < order.len() {
+            let (v, p) = order[i];
+            for &(w, k) in adj[v].iter() {
+                if w != p {
+                    parent_edge[w] = k;
+                    order.push((w, v));
+                }
+            }
+            i += 1;
+        }
+        assert_eq!(order.len(), nv);
+        let mut s = Vec::new();
+        for &(v, p) in order.iter().rev() {
+            if v < n {
+                below[v].push(v);
+            }
+            if p < nv {
+                let mut x = below[v].clone();
+                x.sort_unstable();
+                s.push((x.clone(), edges[parent_edge[v]].2));
+                below[p].append(&mut x);
+            }
+        }
+        s.sort_by(|a, b| a.0.cmp(&b.0));
+        s
+    }
+
+    // Compare the two implementations.  They need not join vertices in the same order, since
+    // there are always ties at n = 4, but they should yield the same tree.
+
+    #[test]
+    fn test_rapid_neighbor_joining() {
+        let mut seed = 1;
+        for n in (1..=40).chain([101, 150]) {
+            for _ in 0..3 {
+                let d = random_distances(n, &mut seed);
+                let e1 = neighbor_joining_with_min(&d, usize::MAX);
+                let e2 = neighbor_joining_with_min(&d, 0);
+                if n == 1 {
+                    assert!(e1.is_empty() && e2.is_empty());
+                    continue;
+                }
+                check_edges(n, &e1);
+                check_edges(n, &e2);
+                let (s1, s2) = (splits(n, &e1), splits(n, &e2));
+                assert_eq!(s1.len(), s2.len());
+                for (x1, x2) in s1.iter().zip(s2.iter()) {
+                    assert_eq!(x1.0, x2.0);
+                    assert!((x1.1 - x2.1).abs() <= 1.0e-6 * (1.0 + x1.1.abs()));
+                }
+            }
+        }
+    }
+}
@@ -9,12 +9,15 @@ use std::collections::HashMap;
 use std::io::Write;
 use string_utils::strme;
 use tables::print_tabular;
+use vector_utils::unique_sort;
 
 pub fn generate_parseable_output(
     exacts: &Vec<Vec<usize>>,
     exact_clonotypes: &Vec<ExactClonotype>,
     ctl: &EncloneControl,
     out_datas: &mut Vec<Vec<HashMap<String, String>>>,
+    stable_ids: &Vec<String>,
+    join_ids: &HashMap<(usize, String), usize>,
     group_ncells: usize,
     i: usize,
     j: usize,
@@ -29,7 +32,23 @@ pub fn generate_parseable_output(
         for m in 0..out_datas[oo].len() {
             out_datas[oo][m].insert("group_id".to_string(), format!("{}", i + 1));
             out_datas[oo][m].insert("group_ncells".to_string(), format!("{}", group_ncells));
-            out_datas[oo][m].insert("clonotype_id".to_string(), format!("{}", j + 1));
+            let clonotype_id = if ctl.gen_opt.stable_clonotype_ids {
+                stable_ids[oo].clone()
+            } else {
+                format!("{}", j + 1)
+            };
+            out_datas[oo][m].insert("clonotype_id".to_string(), clonotype_id);
+            out_datas[oo][m].insert("stable_id".to_string(), stable_ids[oo].clone());
+            if pcols.iter().any(|x| x == "join_id") {
+                let mut ids = exact_clonotypes[exacts[oo][m]]
+                    .clones
+                    .iter()
+                    .filter_map(|c| join_ids.get(&(c[0].dataset_index, c[0].barcode.clone())))
+                    .collect::<Vec<_>>();
+                unique_sort(&mut ids);
+                out_datas[oo][m]
+                    .insert("join_id".to_string(), format!("{}", ids.iter().format(",")));
+            }
         }
         if !ctl.parseable_opt.pno_header {
             if ctl.parseable_opt.pout == *"stdout" && (!ctl.gen_opt.noprint || (i == 0 && j == 0)) {
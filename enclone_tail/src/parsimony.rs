@@ -0,0 +1,868 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Generate maximum parsimony lineage trees (options TREE_PARS and TREE_PARS_FASTA).
+//
+// For each clonotype, the sequence of an exact subclonotype is its nucleotide V..J sequences,
+// concatenated across chains, with gaps for chains that are missing.  The tree is rooted at a
+// germline sequence, which is the donor reference V (or the universal reference V, if there is no
+// donor allele), followed by the reference J, and which is undefined in the junction region.
+//
+// The tree is found by stepwise addition, in which exact subclonotypes are added in order of
+// increasing distance from the germline, each on the edge that minimizes the Fitch parsimony
+// length of the tree, followed by leaf reattachment and nearest neighbor interchanges, which are
+// made while they reduce the length.  This is in the spirit of dnapars, but the tree is not
+// guaranteed to be a most parsimonious tree.  Because each step of stepwise addition computes
+// the Fitch sets of the entire tree, for clonotypes having more than MAX_NNI exact
+// subclonotypes, we instead add each exact subclonotype on the edge above the closest exact
+// subclonotype that has already been added, and do not try to improve the tree.  Ancestral sequences
+// are then reconstructed by the Fitch algorithm, which also fills in the junction region of the
+// germline.
+//
+// Finally, edges having no mutations are collapsed, so that an exact subclonotype that is
+// identical to an inferred ancestor takes the place of the ancestor, and inferred ancestors that
+// are identical to their parent are removed.  Gaps (missing chains) are treated as matching
+// anything.
+
+use crate::display_tree::display_tree;
+use crate::newick::newick;
+use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype};
+use enclone_proto::types::DonorReferenceItem;
+use io_utils::{fwrite, fwriteln};
+use rayon::prelude::*;
+use std::io::Write;
+use string_utils::strme;
+use tables::print_tabular_vbox;
+use vdj_ann::refx::RefData;
+
+const MAX_NNI: usize = 200;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Represent a base as a set of bases, encoded as a bit vector.  Anything other than A, C, G or T
+// is represented as the set of all bases.
+
+fn base_set(b: u8) -> u8 {
+    match b {
+        b'A' => 1,
+        b'C' => 2,
+        b'G' => 4,
+        b'T' => 8,
+        _ => 15,
+    }
+}
+
+// Pick a base from a nonempty set of bases.
+
+fn set_base(s: u8) -> u8 {
+    if s & 1 != 0 {
+        b'A'
+    } else if s & 2 != 0 {
+        b'C'
+    } else if s & 4 != 0 {
+        b'G'
+    } else {
+        b'T'
+    }
+}
+
+// Combine two vectors of sets of bases, as in the Fitch algorithm, adding the number of sites at
+// which they are disjoint to cost.
+
+fn fitch_combine(a: &[u8], b: &[u8], cost: &mut usize) -> Vec<u8> {
+    let mut x = Vec::<u8>::with_capacity(a.len());
+    for p in 0..a.len() {
+        let s = a[p] & b[p];
+        if s != 0 {
+            x.push(s);
+        } else {
+            x.push(a[p] | b[p]);
+            *cost += 1;
+        }
+    }
+    x
+}
+
+// List the vertices of a tree rooted at vertex zero, with each vertex after its children.
+
+pub fn postorder(children: &Vec<Vec<usize>>) -> Vec<usize> {
+    let mut order = Vec::<usize>::new();
+    let mut stack = vec![0];
+    while let Some(v) = stack.pop() {
+        order.push(v);
+        stack.extend(children[v].iter());
+    }
+    order.reverse();
+    order
+}
+
+// Fitch downpass.  Return the parsimony length of the tree, and for each vertex, the sets of
+// bases for the subtree below it.  The root has fixed sets, namely those of the germline.
+
+fn fitch_down(children: &Vec<Vec<usize>>, leaves: &Vec<Vec<u8>>) -> (usize, Vec<Vec<u8>>) {
+    let mut cost = 0;
+    let mut down = vec![Vec::<u8>::new(); children.len()];
+    for v in postorder(children) {
+        if v == 0 {
+            if !children[0].is_empty() {
+                fitch_combine(&leaves[0], &down[children[0][0]], &mut cost);
+            }
+            down[0] = leaves[0].clone();
+        } else if children[v].is_empty() {
+            down[v] = leaves[v].clone();
+        } else {
+            let mut x = down[children[v][0]].clone();
+            for c in children[v][1..].iter() {
+                x = fitch_combine(&x, &down[*c], &mut cost);
+            }
+            down[v] = x;
+        }
+    }
+    (cost, down)
+}
+
+// For each vertex v other than the root, find the sets of bases that would be placed on the edge
+// above v, were the tree to be rerooted there.  Attaching a new leaf to that edge increases the
+// parsimony length by the number of sites at which the leaf is not in these sets.
+
+fn edge_sets(children: &Vec<Vec<usize>>, parent: &Vec<usize>, down: &Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let mut up = vec![Vec::<u8>::new(); children.len()];
+    let mut order = postorder(children);
+    order.reverse();
+    for v in order {
+        if v == 0 {
+            continue;
+        }
+        let u = parent[v];
+        if u == 0 {
+            up[v] = down[0].clone();
+        } else {
+            let mut x = up[u].clone();
+            for w in children[u].iter() {
+                if *w != v {
+                    x = fitch_combine(&x, &down[*w], &mut 0);
+                }
+            }
+            up[v] = x;
+        }
+    }
+    (0..children.len())
+        .map(|v| {
+            if v == 0 {
+                Vec::new()
+            } else {
+                fitch_combine(&up[v], &down[v], &mut 0)
+            }
+        })
+        .collect()
+}
+
+// Replace a child of a vertex.
+
+fn replace_child(children: &mut Vec<Vec<usize>>, u: usize, old: usize, new: usize) {
+    let p = children[u].iter().position(|x| *x == old).unwrap();
+    children[u][p] = new;
+}
+
+// Find the edge on which attaching a leaf x would least increase the parsimony length.  The edge
+// is represented by the vertex below it.
+
+fn best_edge(
+    children: &Vec<Vec<usize>>,
+    parent: &Vec<usize>,
+    leaves: &Vec<Vec<u8>>,
+    x: usize,
+) -> usize {
+    let (_, down) = fitch_down(children, leaves);
+    let edges = edge_sets(children, parent, &down);
+    (1..children.len())
+        .into_par_iter()
+        .filter(|&v| !edges[v].is_empty())
+        .map(|v| {
+            let d = (0..leaves[x].len())
+                .filter(|&p| leaves[x][p] & edges[v][p] == 0)
+                .count();
+            (d, v)
+        })
+        .min()
+        .unwrap()
+        .1
+}
+
+// Attach a leaf x to the edge above v, by making it and v the children of a vertex w.
+
+fn attach(children: &mut Vec<Vec<usize>>, parent: &mut Vec<usize>, v: usize, x: usize, w: usize) {
+    let u = parent[v];
+    replace_child(children, u, v, w);
+    children[w] = vec![v, x];
+    parent[w] = u;
+    parent[v] = w;
+    parent[x] = w;
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Find a parsimony tree.  The input is the sequences, with the germline first, and the output
+// is the children and parent of each vertex, where the first n + 1 vertices are the input
+// sequences, and the rest are ancestors.  The root (germline) has one child.
+
+pub fn parsimony_tree(seqs: &Vec<Vec<u8>>) -> (Vec<Vec<usize>>, Vec<usize>) {
+    let n = seqs.len() - 1;
+    let leaves = seqs
+        .iter()
+        .map(|s| s.iter().map(|b| base_set(*b)).collect::<Vec<u8>>())
+        .collect::<Vec<_>>();
+    let mut children = vec![Vec::<usize>::new(); 2 * n];
+    let mut parent = vec![0; 2 * n];
+
+    // Add the sequences in order of increasing distance from the germline.
+
+    let mut order = (1..=n)
+        .map(|i| {
+            let d = (0..leaves[0].len())
+                .filter(|&p| leaves[0][p] & leaves[i][p] == 0)
+                .count();
+            (d, i)
+        })
+        .collect::<Vec<_>>();
+    order.sort_unstable();
+    children[0].push(order[0].1);
+    for (k, &(_, x)) in order.iter().enumerate().skip(1) {
+        let v = if n <= MAX_NNI {
+            best_edge(&children, &parent, &leaves, x)
+        } else {
+            order[0..k]
+                .par_iter()
+                .map(|&(_, y)| {
+                    let d = (0..leaves[x].len())
+                        .filter(|&p| leaves[x][p] & leaves[y][p] == 0)
+                        .count();
+                    (d, y)
+                })
+                .min()
+                .unwrap()
+                .1
+        };
+        attach(&mut children, &mut parent, v, x, n + k);
+    }
+
+    // Improve the tree, while this reduces its length.  First we remove each leaf x and
+    // reattach it where it least increases the length, which cannot increase the length.  Then
+    // we make nearest neighbor interchanges: for an edge u --> v between ancestors, where v has
+    // children a and b and u has another child s, we try exchanging s with a or b, and keep the
+    // exchange if it reduces the length.
+
+    if n <= MAX_NNI {
+        let mut cost = fitch_down(&children, &leaves).0;
+        loop {
+            let start = cost;
+            for x in 1..=n {
+                let w = parent[x];
+                if w == 0 {
+                    continue;
+                }
+                let s = *children[w].iter().find(|y| **y != x).unwrap();
+                let g = parent[w];
+                replace_child(&mut children, g, w, s);
+                parent[s] = g;
+                children[w].clear();
+                let v = best_edge(&children, &parent, &leaves, x);
+                attach(&mut children, &mut parent, v, x, w);
+            }
+            cost = fitch_down(&children, &leaves).0;
+            for v in n + 1..children.len() {
+                let u = parent[v];
+                if u == 0 {
+                    continue;
+                }
+                for k in 0..2 {
+                    let a = children[v][k];
+                    let s = *children[u].iter().find(|x| **x != v).unwrap();
+                    replace_child(&mut children, u, s, a);
+                    replace_child(&mut children, v, a, s);
+                    parent[a] = u;
+                    parent[s] = v;
+                    let c = fitch_down(&children, &leaves).0;
+                    if c < cost {
+                        cost = c;
+                    } else {
+                        replace_child(&mut children, u, a, s);
+                        replace_child(&mut children, v, s, a);
+                        parent[a] = v;
+                        parent[s] = u;
+                    }
+                }
+            }
+            if cost >= start {
+                break;
+            }
+        }
+    }
+    (children, parent)
+}
+
+// Reconstruct the sequences of the vertices of a tree, by the Fitch algorithm.
+
+fn reconstruct(
+    children: &Vec<Vec<usize>>,
+    parent: &Vec<usize>,
+    seqs: &Vec<Vec<u8>>,
+) -> Vec<Vec<u8>> {
+    let leaves = seqs
+        .iter()
+        .map(|s| s.iter().map(|b| base_set(*b)).collect::<Vec<u8>>())
+        .collect::<Vec<_>>();
+    let (_, down) = fitch_down(children, &leaves);
+    let mut states = vec![Vec::<u8>::new(); children.len()];
+    let mut order = postorder(children);
+    order.reverse();
+    for v in order {
+        if v == 0 {
+            let c = children[0][0];
+            states[0] = (0..down[0].len())
+                .map(|p| {
+                    let s = down[0][p] & down[c][p];
+                    set_base(if s != 0 { s } else { down[0][p] })
+                })
+                .collect();
+        } else {
+            let u = parent[v];
+            states[v] = (0..down[v].len())
+                .map(|p| {
+                    if down[v][p] & base_set(states[u][p]) != 0 {
+                        states[u][p]
+                    } else {
+                        set_base(down[v][p])
+                    }
+                })
+                .collect();
+        }
+    }
+    states
+}
+
+// Collapse the edges of a tree that have no mutations, given the sequences of its vertices,
+// where the first n + 1 vertices are the germline and the exact subclonotypes.  An ancestor
+// having a child exact subclonotype with the same sequence is replaced by it, and otherwise an
+// ancestor having the same sequence as its parent is removed.  Removed vertices are left with
+// no children, and are not reachable from the root.
+
+fn collapse_edges(
+    children: &mut Vec<Vec<usize>>,
+    parent: &mut Vec<usize>,
+    states: &Vec<Vec<u8>>,
+    n: usize,
+) {
+    let nv = children.len();
+    let mut alive = vec![true; nv];
+    loop {
+        let mut changed = false;
+        for w in n + 1..nv {
+            if !alive[w] {
+                continue;
+            }
+            let u = parent[w];
+            if let Some(x) = children[w]
+                .iter()
+                .find(|x| **x <= n && states[**x] == states[w])
+            {
+                let x = *x;
+                let others = children[w]
+                    .iter()
+                    .filter(|c| **c != x)
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for c in others.iter() {
+                    parent[*c] = x;
+                }
+                children[x].extend(others);
+                replace_child(children, u, w, x);
+                parent[x] = u;
+            } else if states[u] == states[w] {
+                let others = children[w].clone();
+                for c in others.iter() {
+                    parent[*c] = u;
+                }
+                children[u].retain(|c| *c != w);
+                children[u].extend(others);
+            } else {
+                continue;
+            }
+            children[w].clear();
+            alive[w] = false;
+            changed = true;
+        }
+        if !changed {
+            break;
+        }
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Define the sequences of the exact subclonotypes in a clonotype, preceded by the germline, and
+// the chain and position on the chain of each site, and the chain types.  This is the alignment
+// used for PHYLIP_DNA, with gaps for missing chains, together with the germline, which is N in
+// the junction region.
+
+pub fn lineage_alignment(
+    oo: usize,
+    exacts: &Vec<Vec<usize>>,
+    rsi: &Vec<ColInfo>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+) -> (Vec<Vec<u8>>, Vec<(usize, usize)>, Vec<String>) {
+    let n = exacts[oo].len();
+    let cols = rsi[oo].mat.len();
+    let mut seqs = vec![Vec::<u8>::new(); n + 1];
+    let mut sites = Vec::<(usize, usize)>::new();
+    let mut chains = vec![String::new(); cols];
+    for m in 0..cols {
+        let len = rsi[oo].seq_del_lens[m];
+        for k in 0..n {
+            match rsi[oo].mat[m][k] {
+                Some(r) => {
+                    let ex = &exact_clonotypes[exacts[oo][k]];
+                    seqs[k + 1].extend(ex.share[r].seq_del_amino.iter());
+                    chains[m] = ex.share[r].chain_type.clone();
+                }
+                None => seqs[k + 1].append(&mut vec![b'-'; len]),
+            }
+        }
+        let mut vref = refdata.refs[rsi[oo].vids[m]].to_ascii_vec();
+        if rsi[oo].vpids[m].is_some() {
+            vref = dref[rsi[oo].vpids[m].unwrap()].nt_sequence.clone();
+        }
+        let jref = refdata.refs[rsi[oo].jids[m]].to_ascii_vec();
+        let vlen = vref.len().saturating_sub(ctl.heur.ref_v_trim);
+        let jlen = jref.len().saturating_sub(ctl.heur.ref_j_trim);
+        for p in 0..len {
+            let b = if p < vlen {
+                vref[p]
+            } else if p + jlen >= len {
+                jref[jref.len() - (len - p)]
+            } else {
+                b'N'
+            };
+            seqs[0].push(b);
+            sites.push((m, p));
+        }
+    }
+    (seqs, sites, chains)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// A lineage tree for a clonotype, with edges having no mutations collapsed.  The vertices are
+// indexed as for parsimony_tree, and order lists the vertices that remain, in preorder.  For each
+// vertex, we give its name, its number of cells, and the mutations on the edge to it from its
+// parent.
+
+pub struct LineageTree {
+    pub children: Vec<Vec<usize>>,
+    pub parent: Vec<usize>,
+    pub order: Vec<usize>,
+    pub names: Vec<String>,
+    pub cells: Vec<usize>,
+    pub muts: Vec<Vec<String>>,
+    pub seqs: Vec<Vec<u8>>,
+    pub states: Vec<Vec<u8>>,
+}
+
+// Find the maximum parsimony lineage tree for a clonotype.
+
+pub fn lineage_tree(
+    oo: usize,
+    exacts: &Vec<Vec<usize>>,
+    rsi: &Vec<ColInfo>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+) -> LineageTree {
+    let n = exacts[oo].len();
+    let (seqs, sites, chains) =
+        lineage_alignment(oo, exacts, rsi, exact_clonotypes, ctl, refdata, dref);
+
+    // Find the tree and the ancestral sequences.
+
+    let (mut children, mut parent) = parsimony_tree(&seqs);
+    let states = reconstruct(&children, &parent, &seqs);
+
+    // Collapse edges having no mutations.
+
+    collapse_edges(&mut children, &mut parent, &states, n);
+    let nv = children.len();
+
+    let cells = vertex_cells(oo, exacts, exact_clonotypes, nv);
+    complete_lineage_tree(children, parent, seqs, states, &sites, &chains, cells)
+}
+
+// Find the number of cells at each vertex of a lineage tree for a clonotype, which is zero for
+// the germline and the inferred ancestors.
+
+pub fn vertex_cells(
+    oo: usize,
+    exacts: &Vec<Vec<usize>>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    nv: usize,
+) -> Vec<usize> {
+    let n = exacts[oo].len();
+    (0..nv)
+        .map(|v| {
+            if (1..=n).contains(&v) {
+                exact_clonotypes[exacts[oo][v - 1]].ncells()
+            } else {
+                0
+            }
+        })
+        .collect()
+}
+
+// Complete a lineage tree, given its structure, the input sequences, and the sequences of all
+// its vertices, by naming the vertices and finding the mutations on each edge.  Sites at which
+// an exact subclonotype is not A, C, G or T (for example, because a chain is missing) are
+// ignored for the edge to it.
+
+pub fn complete_lineage_tree(
+    children: Vec<Vec<usize>>,
+    parent: Vec<usize>,
+    seqs: Vec<Vec<u8>>,
+    states: Vec<Vec<u8>>,
+    sites: &Vec<(usize, usize)>,
+    chains: &Vec<String>,
+    cells: Vec<usize>,
+) -> LineageTree {
+    let n = seqs.len() - 1;
+    let nv = children.len();
+    let mut order = postorder(&children);
+    order.reverse();
+    let mut names = vec![String::new(); nv];
+    let mut count = 0;
+    for v in order.iter() {
+        names[*v] = if *v == 0 {
+            "germline".to_string()
+        } else if *v <= n {
+            format!("{}", v)
+        } else {
+            count += 1;
+            format!("A{}", count)
+        };
+    }
+    let mut muts = vec![Vec::<String>::new(); nv];
+    for v in order.iter() {
+        if *v > 0 {
+            let u = parent[*v];
+            for (p, &(m, pos)) in sites.iter().enumerate() {
+                if *v <= n && base_set(seqs[*v][p]) == 15 {
+                    continue;
+                }
+                if states[u][p] != states[*v][p] {
+                    muts[*v].push(format!(
+                        "{}:{}{}{}",
+                        chains[m],
+                        states[u][p] as char,
+                        pos + 1,
+                        states[*v][p] as char
+                    ));
+                }
+            }
+        }
+    }
+    LineageTree {
+        children,
+        parent,
+        order,
+        names,
+        cells,
+        muts,
+        seqs,
+        states,
+    }
+}
+
+// Print a lineage tree as a picture, in Newick format, and as a table.  The edge above each
+// vertex v is drawn with length lens[v], and labeled labels[v] in the Newick output.  The table
+// has a row for each vertex, and columns node, parent and cells, then the extra columns, which
+// are given as a name and an entry for each vertex, and then mutations.
+
+pub fn print_lineage_tree(
+    tree: &LineageTree,
+    lens: &[f64],
+    labels: &[String],
+    extra: &[(&str, Vec<String>)],
+    pretty: bool,
+    logx: &mut Vec<u8>,
+) {
+    let n = tree.seqs.len() - 1;
+    let (order, names, cells) = (&tree.order, &tree.names, &tree.cells);
+    let mut index = vec![0; tree.parent.len()];
+    let mut vnames = Vec::<String>::new();
+    for v in order.iter() {
+        index[*v] = vnames.len();
+        if (1..=n).contains(v) {
+            if pretty {
+                vnames.push(format!("[01m[31m{}[0m [{} cells]", names[*v], cells[*v]));
+            } else {
+                vnames.push(format!("{} [{} cells]", names[*v], cells[*v]));
+            }
+        } else {
+            vnames.push(names[*v].clone());
+        }
+    }
+    let mut edges = Vec::<(usize, usize, f64)>::new();
+    let mut sedges = Vec::<(usize, usize, String)>::new();
+    for v in order.iter() {
+        if *v > 0 {
+            let (x, y) = (index[tree.parent[*v]], index[*v]);
+            edges.push((x, y, lens[*v]));
+            sedges.push((x, y, labels[*v].clone()));
+        }
+    }
+    fwrite!(logx, "\n{}", display_tree(&vnames, &edges, 0, 100));
+    let nnames = order.iter().map(|v| names[*v].clone()).collect::<Vec<_>>();
+    fwriteln!(logx, "\n{}", newick(&nnames, 0, &sedges));
+    let mut rows = Vec::<Vec<String>>::new();
+    let mut row = vec![
+        "node".to_string(),
+        "parent".to_string(),
+        "cells".to_string(),
+    ];
+    row.extend(extra.iter().map(|x| x.0.to_string()));
+    row.push("mutations".to_string());
+    let ncols = row.len();
+    rows.push(row);
+    rows.push(vec!["\\hline".to_string(); ncols]);
+    for v in order.iter() {
+        let mut row = vec![names[*v].clone()];
+        if *v == 0 {
+            row.append(&mut vec![String::new(); 2]);
+        } else {
+            row.push(names[tree.parent[*v]].clone());
+            row.push(format!("{}", cells[*v]));
+        }
+        row.extend(extra.iter().map(|x| x.1[*v].clone()));
+        row.push(tree.muts[*v].join(","));
+        rows.push(row);
+    }
+    let mut just = b"l|l|r".to_vec();
+    for _ in extra.iter() {
+        just.append(&mut b"|r".to_vec());
+    }
+    just.append(&mut b"|l".to_vec());
+    let mut log = String::new();
+    print_tabular_vbox(&mut log, &rows, 2, &just, false, false);
+    fwrite!(logx, "\n{}", log);
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+pub fn print_parsimony_tree(
+    i: usize,
+    j: usize,
+    oo: usize,
+    exacts: &Vec<Vec<usize>>,
+    rsi: &Vec<ColInfo>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+    logx: &mut Vec<u8>,
+    ptout: &mut Box<dyn std::io::Write>,
+) {
+    if !ctl.gen_opt.tree_pars && ctl.gen_opt.tree_pars_fasta.is_empty() {
+        return;
+    }
+
+    let n = exacts[oo].len();
+    let cols = rsi[oo].mat.len();
+    let tree = lineage_tree(oo, exacts, rsi, exact_clonotypes, ctl, refdata, dref);
+    let LineageTree {
+        parent,
+        order,
+        names,
+        cells,
+        seqs,
+        states,
+        ..
+    } = &tree;
+
+    // Output the tree.
+
+    if ctl.gen_opt.tree_pars {
+        let len = tree.muts.iter().map(|x| x.len()).sum::<usize>();
+        fwriteln!(logx, "\nparsimony tree, length = {}", len);
+        let lens = tree.muts.iter().map(|x| x.len() as f64).collect::<Vec<_>>();
+        let labels = tree
+            .muts
+            .iter()
+            .map(|x| format!("{}", x.len()))
+            .collect::<Vec<_>>();
+        print_lineage_tree(&tree, &lens, &labels, &[], ctl.pretty, logx);
+    }
+
+    // Output the sequences, as FASTA.  For the exact subclonotypes, these are the observed
+    // sequences, and chains that are missing are omitted.
+
+    if !ctl.gen_opt.tree_pars_fasta.is_empty() {
+        let mut fasta = Vec::<u8>::new();
+        for v in order.iter() {
+            let seq = if (1..=n).contains(v) {
+                &seqs[*v]
+            } else {
+                &states[*v]
+            };
+            let mut start = 0;
+            for m in 0..cols {
+                let len = rsi[oo].seq_del_lens[m];
+                if *v == 0 || *v > n || rsi[oo].mat[m][*v - 1].is_some() {
+                    let parent_name = if *v == 0 {
+                        "none"
+                    } else {
+                        names[parent[*v]].as_str()
+                    };
+                    fwriteln!(
+                        fasta,
+                        ">group{}.clonotype{}.{}.chain{} parent={} cells={}",
+                        i + 1,
+                        j + 1,
+                        names[*v],
+                        m + 1,
+                        parent_name,
+                        cells[*v]
+                    );
+                    fwriteln!(fasta, "{}", strme(&seq[start..start + len]));
+                }
+                start += len;
+            }
+        }
+        if ctl.gen_opt.tree_pars_fasta == *"stdout" {
+            logx.append(&mut fasta);
+        } else {
+            ptout.write_all(&fasta).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sets(seqs: &[&str]) -> Vec<Vec<u8>> {
+        seqs.iter()
+            .map(|s| s.bytes().map(base_set).collect())
+            .collect()
+    }
+
+    // Check that a tree from parsimony_tree is a binary tree on all its vertices, rooted at the
+    // germline, which has one child, and having the input sequences as leaves.
+
+    fn check_tree(children: &Vec<Vec<usize>>, parent: &Vec<usize>, n: usize) {
+        assert_eq!(children.len(), 2 * n);
+        assert_eq!(children[0].len(), 1);
+        let mut order = postorder(children);
+        order.sort_unstable();
+        assert_eq!(order, (0..2 * n).collect::<Vec<_>>());
+        for v in 1..2 * n {
+            assert_eq!(children[v].len(), if v <= n { 0 } else { 2 });
+            assert!(children[parent[v]].contains(&v));
+        }
+    }
+
+    #[test]
+    fn test_fitch_down() {
+        let leaves = sets(&["AAAA", "ACAA", "ACGA", ""]);
+        let children = vec![vec![3], vec![], vec![], vec![1, 2]];
+        let (cost, down) = fitch_down(&children, &leaves);
+        assert_eq!(cost, 2);
+        assert_eq!(down[3], vec![1, 2, 5, 1]);
+        assert_eq!(down[0], leaves[0]);
+
+        // The germline undefined (N) at a site matches anything.
+
+        let leaves = sets(&["ANAA", "ACAA", "ACGA", ""]);
+        assert_eq!(fitch_down(&children, &leaves).0, 1);
+    }
+
+    #[test]
+    fn test_parsimony_tree() {
+        // The optimum is a perfect phylogeny, with each mutation occurring once: CC in 1, 2, 3
+        // and 5, then G in 2 and 5, G in 3, C in 5 and TT in 4, for a length of seven.  In any
+        // optimal tree, 2 and 5 are siblings.
+
+        let seqs = [
+            "AAAAAAA", "CCAAAAA", "CCGAAAA", "CCAGAAA", "AAAATTA", "CCGAAAC",
+        ];
+        let seqs = seqs
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let (children, parent) = parsimony_tree(&seqs);
+        check_tree(&children, &parent, seqs.len() - 1);
+        let leaves = sets(&seqs.iter().map(|s| strme(s)).collect::<Vec<_>>());
+        assert_eq!(fitch_down(&children, &leaves).0, 7);
+
+        assert_eq!(parent[2], parent[5]);
+    }
+
+    #[test]
+    fn test_parsimony_tree_large() {
+        // Above MAX_NNI, a tree is still found.
+
+        let n = MAX_NNI + 1;
+        let mut seqs = vec![vec![b'A'; 16]];
+        for i in 1..=n {
+            let mut s = vec![b'A'; 16];
+            for (p, b) in s.iter_mut().enumerate() {
+                if (i >> (p % 8)) & 1 == 1 {
+                    *b = if p < 8 { b'C' } else { b'G' };
+                }
+            }
+            seqs.push(s);
+        }
+        let (children, parent) = parsimony_tree(&seqs);
+        check_tree(&children, &parent, n);
+    }
+
+    #[test]
+    fn test_collapse_edges() {
+        // An ancestor that is identical to its parent is removed.
+
+        let mut children = vec![vec![3], vec![], vec![], vec![1, 2]];
+        let mut parent = vec![0, 3, 3, 0];
+        let states = ["AA", "CA", "AG", "AA"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        collapse_edges(&mut children, &mut parent, &states, 2);
+        assert_eq!(children, vec![vec![1, 2], vec![], vec![], vec![]]);
+        assert_eq!((parent[1], parent[2]), (0, 0));
+
+        // An ancestor that is identical to a child exact subclonotype is replaced by it.
+
+        let mut children = vec![vec![3], vec![], vec![], vec![1, 2]];
+        let mut parent = vec![0, 3, 3, 0];
+        let states = ["AA", "CA", "CG", "CA"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        collapse_edges(&mut children, &mut parent, &states, 2);
+        assert_eq!(children, vec![vec![1], vec![2], vec![], vec![]]);
+        assert_eq!((parent[1], parent[2]), (0, 1));
+
+        // Collapsing proceeds through chains of ancestors.
+
+        let mut children = vec![vec![5], vec![], vec![], vec![], vec![1, 2], vec![4, 3]];
+        let mut parent = vec![0, 4, 4, 5, 5, 0];
+        let states = ["AAA", "CAA", "AAG", "TAA", "AAA", "AAA"]
+            .iter()
+            .map(|s| s.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        collapse_edges(&mut children, &mut parent, &states, 3);
+        let mut top = children[0].clone();
+        top.sort_unstable();
+        assert_eq!(top, vec![1, 2, 3]);
+        assert!(children[4].is_empty() && children[5].is_empty());
+    }
+}
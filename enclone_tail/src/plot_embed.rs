@@ -0,0 +1,229 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Execute PLOT_EMBED=xvar,yvar,filename, which shows clonal structure on a transcriptional
+// embedding.  Every gene expression cell having values for xvar and yvar is drawn in light grey.
+// Then VDJ cells are drawn on top, with the top n clonotypes (by number of plotted cells) each
+// in a distinct color, and cells in other clonotypes in dark grey.  Options:
+// - top:n sets the number of distinctly colored clonotypes (default 10);
+// - group colors by clonotype group rather than clonotype;
+// - edges connects cells in each distinctly colored clonotype by a minimum spanning tree.
+//
+// The variables xvar and yvar are typically defined by OBS or BC, e.g. umap1 and umap2.  In
+// addition, pca1, pca2, ... may be used to refer to the PCA coordinates that come with the gene
+// expression data.
+
+use crate::colors::default_colors;
+use crate::convert_svg_to_png::convert_svg_to_png;
+use crate::plot_points::plot_points_and_edges;
+use enclone_core::defs::{EncloneControl, ExactClonotype, GexInfo};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use string_utils::TextUtils;
+
+// Don't draw edges for clonotypes having more cells than this, as the time to compute the
+// minimum spanning tree is quadratic in the number of cells.
+
+const MAX_EDGE_CELLS: usize = 5_000;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Get the values of a variable for the cells in a dataset.  Values that are not finite are
+// omitted, so that the coordinates can be sorted.
+
+fn embed_values(
+    ctl: &EncloneControl,
+    gex_info: &GexInfo,
+    li: usize,
+    var: &str,
+) -> HashMap<String, f32> {
+    let mut vals = HashMap::<String, f32>::new();
+    if li < ctl.origin_info.alt_bc_fields.len() {
+        for x in ctl.origin_info.alt_bc_fields[li].iter() {
+            if x.0 == var {
+                for (bc, v) in x.1.iter() {
+                    if let Ok(v) = v.parse::<f32>() {
+                        if v.is_finite() {
+                            vals.insert(bc.clone(), v);
+                        }
+                    }
+                }
+                return vals;
+            }
+        }
+    }
+    if var.starts_with("pca") && var.after("pca").parse::<usize>().is_ok() {
+        let k = var.after("pca").force_usize();
+        if k >= 1 && li < gex_info.pca.len() {
+            for (bc, v) in gex_info.pca[li].iter() {
+                if v.len() >= k && (v[k - 1] as f32).is_finite() {
+                    vals.insert(bc.clone(), v[k - 1] as f32);
+                }
+            }
+        }
+    }
+    vals
+}
+
+// Find the edges of a minimum spanning tree on a set of points, using Prim's algorithm.
+
+fn spanning_tree(p: &Vec<(f32, f32)>) -> Vec<(usize, usize)> {
+    let n = p.len();
+    let mut edges = Vec::<(usize, usize)>::new();
+    if n == 0 {
+        return edges;
+    }
+    let dist = |i: usize, j: usize| (p[i].0 - p[j].0).powi(2) + (p[i].1 - p[j].1).powi(2);
+    let mut in_tree = vec![false; n];
+    let mut best = vec![(f32::MAX, 0); n];
+    in_tree[0] = true;
+    for j in 1..n {
+        best[j] = (dist(0, j), 0);
+    }
+    for _ in 1..n {
+        let mut next = None;
+        for j in 0..n {
+            if !in_tree[j] && (next.is_none() || best[j].0 < best[next.unwrap()].0) {
+                next = Some(j);
+            }
+        }
+        let j = next.unwrap();
+        in_tree[j] = true;
+        edges.push((best[j].1, j));
+        for k in 0..n {
+            if !in_tree[k] && dist(j, k) < best[k].0 {
+                best[k] = (dist(j, k), j);
+            }
+        }
+    }
+    edges
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+pub fn plot_embed(
+    ctl: &EncloneControl,
+    exacts: &Vec<Vec<usize>>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    gex_info: &GexInfo,
+    groups: &Vec<Vec<(i32, String)>>,
+    stable_ids: &Vec<String>,
+) -> Result<(), String> {
+    let (xvar, yvar) = (&ctl.plot_opt.plot_embed_xvar, &ctl.plot_opt.plot_embed_yvar);
+    const BACKGROUND: (u8, u8, u8) = (210, 210, 210);
+    const OTHER: (u8, u8, u8) = (110, 110, 110);
+
+    // Get coordinates, and plot all cells that have them.
+
+    let mut coords = Vec::<HashMap<String, (f32, f32)>>::new();
+    let mut points = Vec::<(u32, (u8, u8, u8), f32, f32)>::new();
+    for li in 0..ctl.origin_info.n() {
+        let x = embed_values(ctl, gex_info, li, xvar);
+        let y = embed_values(ctl, gex_info, li, yvar);
+        let mut c = HashMap::<String, (f32, f32)>::new();
+        for (bc, vx) in x.iter() {
+            if let Some(vy) = y.get(bc) {
+                c.insert(bc.clone(), (*vx, *vy));
+            }
+        }
+        let mut cells = c.values().cloned().collect::<Vec<_>>();
+        cells.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for (vx, vy) in cells {
+            points.push((2, BACKGROUND, vx, vy));
+        }
+        coords.push(c);
+    }
+
+    // Find the coordinates of the VDJ cells in each clonotype or group.
+
+    let mut units = Vec::<(String, Vec<(f32, f32)>)>::new();
+    for i in 0..groups.len() {
+        for j in 0..groups[i].len() {
+            let o = groups[i][j].0 as usize;
+            if !ctl.plot_opt.plot_embed_group || j == 0 {
+                let name = if ctl.plot_opt.plot_embed_group {
+                    format!("group {}", i + 1)
+                } else if ctl.gen_opt.stable_clonotype_ids {
+                    stable_ids[o].clone()
+                } else {
+                    format!("{}.{}", i + 1, j + 1)
+                };
+                units.push((name, Vec::new()));
+            }
+            let p = &mut units.last_mut().unwrap().1;
+            for &u in exacts[o].iter() {
+                for clone in exact_clonotypes[u].clones.iter() {
+                    let li = clone[0].dataset_index;
+                    if let Some(&xy) = coords[li].get(&clone[0].barcode) {
+                        p.push(xy);
+                    }
+                }
+            }
+        }
+    }
+    units.retain(|x| !x.1.is_empty());
+    units.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
+
+    // Plot the VDJ cells, with the top clonotypes last, so they are not covered up.
+
+    let colors = default_colors();
+    let ntop = ctl.plot_opt.plot_embed_top.min(units.len());
+    for u in units[ntop..].iter() {
+        for xy in u.1.iter() {
+            points.push((3, OTHER, xy.0, xy.1));
+        }
+    }
+    let mut edges = Vec::<((f32, f32), (f32, f32), (u8, u8, u8))>::new();
+    let mut legend = Vec::<((u8, u8, u8), String)>::new();
+    for k in (0..ntop).rev() {
+        let c = &colors[k % colors.len()];
+        let c = (c[0], c[1], c[2]);
+        let p = &units[k].1;
+        for xy in p.iter() {
+            points.push((4, c, xy.0, xy.1));
+        }
+        if ctl.plot_opt.plot_embed_edges && p.len() <= MAX_EDGE_CELLS {
+            for e in spanning_tree(p) {
+                edges.push((p[e.0], p[e.1], c));
+            }
+        }
+    }
+    for k in 0..ntop {
+        let c = &colors[k % colors.len()];
+        legend.push((
+            (c[0], c[1], c[2]),
+            format!("{} ({})", units[k].0, units[k].1.len()),
+        ));
+    }
+    if ntop < units.len() {
+        legend.push((OTHER, "other".to_string()));
+    }
+
+    // Make and save the plot.
+
+    let mut svg = String::new();
+    plot_points_and_edges(
+        &points, &edges, &legend, xvar, yvar, &mut svg, false, None, None, None, None, None, None,
+    )?;
+    let filename = &ctl.plot_opt.plot_embed_filename;
+    if filename == "stdout" {
+        print!("{}", svg);
+    } else {
+        let f = File::create(&filename);
+        if f.is_err() {
+            return Err(format!(
+                "\nThe file {} in your PLOT_EMBED argument could not be created.\n",
+                filename
+            ));
+        }
+        let mut f = f.unwrap();
+        if filename.ends_with(".png") {
+            let width = ctl.plot_opt.png_width.unwrap_or(2000);
+            f.write_all(&convert_svg_to_png(svg.as_bytes(), width as u32))
+                .unwrap();
+        } else {
+            f.write_all(svg.as_bytes()).unwrap();
+        }
+    }
+    Ok(())
+}
@@ -13,6 +13,12 @@
 // If symmetric = true, produce a square plot having the same range and tic marks on both axes.
 //
 // points = {(point size, point color, x, y)}
+//
+// plot_points_and_edges additionally draws line segments, underneath the points, and a legend
+// showing the given colors and names.
+//
+// edges = {((x1, y1), (x2, y2), color)}
+// legend = {(color, name)}
 
 use crate::string_width::arial_width;
 use crate::ticks::ticks;
@@ -25,6 +31,38 @@ pub fn plot_points(
     yvar: &str,
     svg: &mut String,
     symmetric: bool,
+    title: Option<String>,
+    xlow: Option<f32>,
+    xhigh: Option<f32>,
+    ylow: Option<f32>,
+    yhigh: Option<f32>,
+    margin: Option<u32>,
+) -> Result<(), String> {
+    plot_points_and_edges(
+        points,
+        &Vec::new(),
+        &Vec::new(),
+        xvar,
+        yvar,
+        svg,
+        symmetric,
+        title,
+        xlow,
+        xhigh,
+        ylow,
+        yhigh,
+        margin,
+    )
+}
+
+pub fn plot_points_and_edges(
+    points: &Vec<(u32, (u8, u8, u8), f32, f32)>,
+    edges: &Vec<((f32, f32), (f32, f32), (u8, u8, u8))>,
+    legend: &Vec<((u8, u8, u8), String)>,
+    xvar: &str,
+    yvar: &str,
+    svg: &mut String,
+    symmetric: bool,
     // title may be specified:
     title: Option<String>,
     // plot boundaries may be specified:
@@ -182,6 +220,14 @@ pub fn plot_points(
         .y_desc(yvar)
         .draw()
         .unwrap();
+    for e in edges.iter() {
+        chart
+            .draw_series(LineSeries::new(
+                vec![e.0, e.1],
+                &RGBColor(e.2 .0, e.2 .1, e.2 .2),
+            ))
+            .unwrap();
+    }
 
     let mut i = 0;
     while i < points.len() {
@@ -206,5 +252,23 @@ pub fn plot_points(
             .unwrap();
         i = j;
     }
+    if !legend.is_empty() {
+        for (c, name) in legend.iter() {
+            let c = RGBColor(c.0, c.1, c.2);
+            chart
+                .draw_series(std::iter::empty::<Circle<(f32, f32), u32>>())
+                .unwrap()
+                .label(name)
+                .legend(move |(x, y)| Circle::new((x, y), 5, c.filled()));
+        }
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::UpperRight)
+            .background_style(&WHITE.mix(0.8))
+            .border_style(&BLACK)
+            .label_font((font, 15).into_font())
+            .draw()
+            .unwrap();
+    }
     Ok(())
 }
@@ -5,7 +5,7 @@
 use crate::alluvial_fb::*;
 use crate::fate::print_fate;
 use crate::print_dataset_vars::print_dataset_vars;
-// use crate::sens_spec::*;
+use crate::sens_spec::sens_spec;
 use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype, GexInfo};
 use enclone_core::median::median;
 use io_utils::{fwrite, fwriteln};
@@ -124,7 +124,6 @@ pub fn print_stats(
 
     // Print summary stats.
 
-    // let sens_spec_stats = sens_spec(&exacts, &exact_clonotypes, &refdata);
     let mut ncells = 0;
     *nclono2 = 0;
     *two_chain = 0;
@@ -481,7 +480,10 @@ pub fn print_stats(
                 "   • adjusted cell-cell merges (quadratic) = {}",
                 add_commas(adjusted)
             );
-            // fwriteln!(logx, "\n{}", sens_spec_stats);
+        }
+        if ctl.gen_opt.sens_spec {
+            fwriteln!(logx, "   • join scorer = {}", ctl.join_alg_opt.scorer);
+            fwrite!(logx, "{}", sens_spec(exacts, exact_clonotypes, refdata));
         }
         fwriteln!(logx, "   • number of cells having 1 chain = {}", n1);
         fwriteln!(logx, "   • number of cells having 2 or 3 chains = {}", n23);
@@ -0,0 +1,298 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Execute PSEUDOBULK=path, which writes pseudobulk gene expression count matrices, for use by
+// tools such as DESeq2.  For each clonotype that is printed (or each group, if PSEUDOBULK_GROUP
+// is specified), the raw counts of each gene are summed over the gene expression cells in the
+// clonotype.  Counts are not scaled by gene expression multipliers, as DESeq2 requires raw
+// counts.
+//
+// If path ends with .csv, the matrix is written as a CSV file, with a line for each gene, and
+// fields id, name, and then one field for each clonotype, named by its id.  Metadata are written
+// to the same path, with .csv replaced by _metadata.csv.  Otherwise path is a directory, which is
+// created if need be, and the matrix is written there in MEX format, as matrix.mtx, features.tsv
+// and barcodes.tsv, where the barcodes are the clonotype ids, and the metadata are written to
+// metadata.csv.
+//
+// The metadata file has a line for each clonotype, with these fields:
+// id       = clonotype id, as in the matrix
+// ncells   = number of cells in the clonotype
+// ngex     = number of these cells that have gene expression data
+// donors   = donors of the cells, with counts, e.g. d1:10;d2:1
+// origins  = origins of the cells, with counts
+// isotypes = constant regions of the heavy (or TRB or TRD) chains of the cells, with counts.
+
+use crate::de::{cell_counts, gene_index};
+use enclone_core::defs::{EncloneControl, ExactClonotype, GexInfo};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Write};
+use string_utils::TextUtils;
+use vdj_ann::refx::RefData;
+use vector_utils::{bin_position, reverse_sort};
+
+#[cfg(target_os = "windows")]
+use hdf5::Reader;
+#[cfg(not(target_os = "windows"))]
+use hdf5x::Reader;
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Format counts of names as name:count, separated by semicolons, in decreasing order of count.
+
+fn format_counts(x: &HashMap<String, usize>) -> String {
+    let mut y = x.iter().map(|(k, v)| (*v, k.clone())).collect::<Vec<_>>();
+    reverse_sort(&mut y);
+    y.iter()
+        .map(|(v, k)| format!("{}:{}", k, v))
+        .collect::<Vec<String>>()
+        .join(";")
+}
+
+// Create a file for writing.
+
+fn create(path: &str) -> Result<BufWriter<File>, String> {
+    match File::create(&path) {
+        Ok(f) => Ok(BufWriter::new(f)),
+        Err(_) => Err(format!(
+            "\nThe file {} for your PSEUDOBULK argument could not be created.\n",
+            path
+        )),
+    }
+}
+
+// Sum sparse rows of gene expression counts, as (feature index, count), yielding the nonzero
+// sums, in order by feature index.
+
+fn sum_counts(
+    nfeatures: usize,
+    rows: impl Iterator<Item = Vec<(usize, f64)>>,
+) -> Vec<(usize, u64)> {
+    let mut x = vec![0_u64; nfeatures];
+    for row in rows {
+        for (g, v) in row {
+            x[g] += v as u64;
+        }
+    }
+    x.into_iter().enumerate().filter(|(_, v)| *v > 0).collect()
+}
+
+// Write the matrix as CSV, with a line for each feature, and a field for each id.
+
+fn write_csv_matrix(
+    f: &mut impl Write,
+    features: &[String],
+    ids: &[String],
+    sums: &[Vec<(usize, u64)>],
+) {
+    writeln!(f, "id,name,{}", ids.join(",")).unwrap();
+
+    // Stream the rows, advancing through the sparse sums of all ids in step, rather
+    // than creating a dense matrix, which could be very large.
+
+    let mut pos = vec![0; ids.len()];
+    for g in 0..features.len() {
+        let mut vals = Vec::<String>::with_capacity(ids.len());
+        for (k, s) in sums.iter().enumerate() {
+            if pos[k] < s.len() && s[pos[k]].0 == g {
+                vals.push(s[pos[k]].1.to_string());
+                pos[k] += 1;
+            } else {
+                vals.push("0".to_string());
+            }
+        }
+        writeln!(
+            f,
+            "{},{},{}",
+            features[g].before("\t"),
+            features[g].between("\t", "\t"),
+            vals.join(",")
+        )
+        .unwrap();
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+pub fn pseudobulk(
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    exacts: &Vec<Vec<usize>>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    gex_info: &GexInfo,
+    groups: &Vec<Vec<(i32, String)>>,
+    stable_ids: &Vec<String>,
+    h5_data: &Vec<(usize, Vec<u32>, Vec<u32>)>,
+    d_readers: &Vec<Option<Reader>>,
+    ind_readers: &Vec<Option<Reader>>,
+) -> Result<(), String> {
+    let n = ctl.origin_info.n();
+    if (0..n).all(|li| ctl.origin_info.gex_path[li].is_empty()) {
+        return Err("\nPSEUDOBULK requires gene expression data.\n".to_string());
+    }
+
+    // Define the clonotypes or groups, and their cells and metadata.
+
+    let mut ids = Vec::<String>::new();
+    let mut cells = Vec::<Vec<(usize, usize)>>::new();
+    let mut meta = Vec::<(usize, [HashMap<String, usize>; 3])>::new();
+    for i in 0..groups.len() {
+        for j in 0..groups[i].len() {
+            let o = groups[i][j].0 as usize;
+            if !ctl.gen_opt.pseudobulk_group || j == 0 {
+                let id = if ctl.gen_opt.pseudobulk_group {
+                    format!("{}", i + 1)
+                } else if ctl.gen_opt.stable_clonotype_ids {
+                    stable_ids[o].clone()
+                } else {
+                    format!("{}.{}", i + 1, j + 1)
+                };
+                ids.push(id);
+                cells.push(Vec::new());
+                meta.push((0, Default::default()));
+            }
+            let (c, m) = (cells.last_mut().unwrap(), meta.last_mut().unwrap());
+            for &u in exacts[o].iter() {
+                let ex = &exact_clonotypes[u];
+                for clone in ex.clones.iter() {
+                    let li = clone[0].dataset_index;
+                    let p = bin_position(&gex_info.gex_barcodes[li], &clone[0].barcode);
+                    if p >= 0 {
+                        c.push((li, p as usize));
+                    }
+                    m.0 += 1;
+                    if let Some(d) = clone[0].donor_index {
+                        *m.1[0]
+                            .entry(ctl.origin_info.donor_list[d].clone())
+                            .or_insert(0) += 1;
+                    }
+                    if let Some(d) = clone[0].origin_index {
+                        *m.1[1]
+                            .entry(ctl.origin_info.origin_list[d].clone())
+                            .or_insert(0) += 1;
+                    }
+                }
+                for x in ex.share.iter() {
+                    if x.left && x.c_ref_id.is_some() {
+                        *m.1[2]
+                            .entry(refdata.name[x.c_ref_id.unwrap()].clone())
+                            .or_insert(0) += ex.ncells();
+                    }
+                }
+            }
+        }
+    }
+
+    // Sum the counts.
+
+    let (features, to_global) = gene_index(gex_info);
+    let mut sums = vec![Vec::<(usize, u64)>::new(); ids.len()];
+    sums.par_iter_mut().enumerate().for_each(|(k, s)| {
+        *s = sum_counts(
+            features.len(),
+            cells[k].iter().map(|&(li, p)| {
+                cell_counts(
+                    ctl,
+                    gex_info,
+                    li,
+                    p,
+                    &to_global,
+                    h5_data,
+                    d_readers,
+                    ind_readers,
+                    true,
+                )
+            }),
+        );
+    });
+
+    // Write the matrix.
+
+    let path = &ctl.gen_opt.pseudobulk;
+    let meta_path;
+    if path.ends_with(".csv") {
+        meta_path = format!("{}_metadata.csv", path.rev_before(".csv"));
+        let mut f = create(path)?;
+        write_csv_matrix(&mut f, &features, &ids, &sums);
+    } else {
+        if create_dir_all(&path).is_err() {
+            return Err(format!(
+                "\nThe directory {} for your PSEUDOBULK argument could not be created.\n",
+                path
+            ));
+        }
+        meta_path = format!("{}/metadata.csv", path);
+        let mut f = create(&format!("{}/features.tsv", path))?;
+        for x in features.iter() {
+            writeln!(f, "{}", x).unwrap();
+        }
+        let mut f = create(&format!("{}/barcodes.tsv", path))?;
+        for id in ids.iter() {
+            writeln!(f, "{}", id).unwrap();
+        }
+        let mut f = create(&format!("{}/matrix.mtx", path))?;
+        let nnz = sums.iter().map(|s| s.len()).sum::<usize>();
+        writeln!(f, "%%MatrixMarket matrix coordinate integer general").unwrap();
+        writeln!(f, "%").unwrap();
+        writeln!(f, "{} {} {}", features.len(), ids.len(), nnz).unwrap();
+        for (k, s) in sums.iter().enumerate() {
+            for &(g, v) in s.iter() {
+                writeln!(f, "{} {} {}", g + 1, k + 1, v).unwrap();
+            }
+        }
+    }
+
+    // Write the metadata.
+
+    let mut f = create(&meta_path)?;
+    writeln!(f, "id,ncells,ngex,donors,origins,isotypes").unwrap();
+    for k in 0..ids.len() {
+        writeln!(
+            f,
+            "{},{},{},{},{},{}",
+            ids[k],
+            meta[k].0,
+            cells[k].len(),
+            format_counts(&meta[k].1[0]),
+            format_counts(&meta[k].1[1]),
+            format_counts(&meta[k].1[2]),
+        )
+        .unwrap();
+    }
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Three genes and two groups, the first having two cells and the second having one cell,
+    // in which only the third gene is expressed.
+
+    #[test]
+    fn test_pseudobulk_sums() {
+        let features = vec![
+            "ENSG1\tA\tGene Expression".to_string(),
+            "ENSG2\tB\tGene Expression".to_string(),
+            "ENSG3\tC\tGene Expression".to_string(),
+        ];
+        let cells = vec![
+            vec![vec![(0, 1.0), (2, 3.0)], vec![(0, 4.0), (2, 2.0)]],
+            vec![vec![(2, 7.0)]],
+        ];
+        let sums = cells
+            .iter()
+            .map(|c| sum_counts(features.len(), c.iter().cloned()))
+            .collect::<Vec<_>>();
+        assert_eq!(sums, vec![vec![(0, 5), (2, 5)], vec![(2, 7)]]);
+        let ids = vec!["1.1".to_string(), "2.1".to_string()];
+        let mut f = Vec::<u8>::new();
+        write_csv_matrix(&mut f, &features, &ids, &sums);
+        assert_eq!(
+            String::from_utf8(f).unwrap(),
+            "id,name,1.1,2.1\nENSG1,A,5,0\nENSG2,B,0,0\nENSG3,C,5,7\n"
+        );
+    }
+}
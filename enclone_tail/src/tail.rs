@@ -2,7 +2,9 @@
 
 // Group and print clonotypes.  For now, limited grouping functionality.
 
+use crate::de::differential_expression;
 use crate::group::group_and_print_clonotypes;
+use crate::pseudobulk::pseudobulk;
 use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype, GexInfo};
 use enclone_core::median::median_f64;
 use enclone_proto::types::DonorReferenceItem;
@@ -34,7 +36,9 @@ pub fn tail_code(
     exact_clonotypes: &Vec<ExactClonotype>,
     ctl: &EncloneControl,
     mut out_datas: &mut Vec<Vec<HashMap<String, String>>>,
+    stable_ids: &Vec<String>,
     join_info: &Vec<(usize, usize, bool, Vec<u8>)>,
+    join_ids: &HashMap<(usize, String), usize>,
     gex_info: &GexInfo,
     vdj_cells: &Vec<Vec<String>>,
     fate: &Vec<HashMap<String, String>>,
@@ -62,7 +66,9 @@ pub fn tail_code(
         exact_clonotypes,
         ctl,
         &mut out_datas,
+        stable_ids,
         join_info,
+        join_ids,
         gex_info,
         vdj_cells,
         fate,
@@ -332,6 +338,40 @@ pub fn tail_code(
         print!("{}", strme(&log));
     }
 
+    // Do differential expression.
+
+    if !ctl.gen_opt.de_filename.is_empty() {
+        differential_expression(
+            ctl,
+            exacts,
+            exact_clonotypes,
+            out_datas,
+            gex_info,
+            groups,
+            stable_ids,
+            h5_data,
+            d_readers,
+            ind_readers,
+        )?;
+    }
+
+    // Write pseudobulk matrices.
+
+    if !ctl.gen_opt.pseudobulk.is_empty() {
+        pseudobulk(
+            ctl,
+            refdata,
+            exacts,
+            exact_clonotypes,
+            gex_info,
+            groups,
+            stable_ids,
+            h5_data,
+            d_readers,
+            ind_readers,
+        )?;
+    }
+
     // Print top genes.
 
     if ctl.gen_opt.top_genes {
@@ -0,0 +1,642 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Render lineage trees as SVG (option TREE_SVG=filename).
+//
+// For each clonotype that is printed, we draw its maximum parsimony lineage tree, as for
+// TREE_PARS, in a panel, and the panels are stacked vertically, in the order in which the
+// clonotypes are printed.  Branch lengths are numbers of mutations.  The filename may be stdout,
+// or gui, for enclone visual.
+//
+// The layout is rectangular, with the germline on the left, or circular, with the germline at
+// the center, as specified by TREE_SVG_LAYOUT=rect|circular (default rect).  Each exact
+// subclonotype is a pie chart, whose area is proportional to its number of cells, and which shows
+// the colors of its cells.  Inferred ancestors are small gray dots.  If TREE_SVG_MUTS is
+// specified, each branch is labeled by its mutations.
+//
+// The cells are colored as specified by TREE_SVG_COLOR, which may be
+// - dataset                                  by dataset
+// - origin[,origin1,color1,...]              by origin, using default colors if none given
+// - iso[,color0,color1,...,colorn]           by isotype, as for PLOT_BY_ISOTYPE_COLOR
+// - var,name[,minmax,min,max]                by values of a variable, using the turbo scheme.
+// If TREE_SVG_COLOR is not specified, cells are colored as for PLOT (e.g. by the color field in
+// META), and black otherwise.
+
+use crate::assign_cell_color::{assign_cell_color, VAR_HIGH, VAR_LOW};
+use crate::colors::{default_colors, reorder_color_list, TURBO_SRGB_BYTES};
+use crate::legend::add_legend_for_color_by_variable;
+use crate::parsimony::{lineage_tree, LineageTree};
+use crate::string_width::arial_width;
+use crate::{set_svg_height, set_svg_width, substitute_enclone_color, BOUNDARY};
+use enclone_core::cell_color::CellColor;
+use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype, PlotOpt};
+use enclone_proto::types::DonorReferenceItem;
+use io_utils::{fwrite, open_for_write_new};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::io::Write;
+use string_utils::TextUtils;
+use vdj_ann::refx::RefData;
+use vector_utils::{unique_sort, VecUtils};
+
+const MAX_RADIUS: f64 = 12.0; // radius of the largest node
+const MIN_RADIUS: f64 = 3.0; // minimum radius of a node having cells
+const ROW: f64 = 20.0; // minimum vertical space for a leaf, in the rectangular layout
+const TREE_WIDTH: f64 = 600.0; // width of a tree, in the rectangular layout
+const TREE_RADIUS: f64 = 250.0; // minimum radius of a tree, in the circular layout
+const FONT_SIZE: f64 = 12.0;
+const MUT_FONT_SIZE: f64 = 9.0;
+const MAX_MUT_LABELS: usize = 3; // maximum number of mutations shown on a branch
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Find the color of a cell, and its label for the legend, which is empty if the coloring scheme
+// does not have a categorical legend.  The color is an SVG color, except that it may be
+// "undefined" for coloring by a variable.
+
+fn cell_color(
+    ctl: &EncloneControl,
+    plot_opt: &PlotOpt,
+    refdata: &RefData,
+    const_names: &Vec<String>,
+    palette: &Vec<Vec<u8>>,
+    exacts: &Vec<Vec<usize>>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    out_datas: &Vec<Vec<HashMap<String, String>>>,
+    i: usize,
+    j: usize,
+    k: usize,
+) -> (String, String) {
+    let ex = &exact_clonotypes[exacts[i][j]];
+    let mut label = String::new();
+    let mut color;
+    if let CellColor::BySample(ref x) = plot_opt.tree_svg_color {
+        color = "black".to_string();
+        label = "undetermined".to_string();
+        if let Some(o) = ex.clones[k][0].origin_index {
+            label = ctl.origin_info.origin_list[o].clone();
+            if x.specification.is_empty() {
+                let c = &palette[o % palette.len()];
+                color = format!("rgb({},{},{})", c[0], c[1], c[2]);
+            } else if let Some(c) = x.specification.get(&label) {
+                color = c.clone();
+            }
+        }
+    } else {
+        color = assign_cell_color(
+            ctl,
+            plot_opt,
+            refdata,
+            const_names,
+            0,
+            exacts,
+            exact_clonotypes,
+            out_datas,
+            i,
+            j,
+            k,
+        );
+        if color.starts_with("turbo-pre-") {
+            let c = &TURBO_SRGB_BYTES[color.after("turbo-pre-").force_usize()];
+            color = format!("rgb({},{},{})", c[0], c[1], c[2]);
+        } else if color.starts_with("default-pre-") {
+            let c = &palette[color.after("default-pre-").force_usize() % palette.len()];
+            color = format!("rgb({},{},{})", c[0], c[1], c[2]);
+        }
+        match plot_opt.tree_svg_color {
+            CellColor::ByDataset(_) => {
+                label = ctl.origin_info.dataset_id[ex.clones[k][0].dataset_index].clone();
+            }
+            CellColor::ByIsotype(_) => {
+                let mut crefs = ex
+                    .share
+                    .iter()
+                    .filter(|x| x.left)
+                    .map(|x| x.c_ref_id)
+                    .collect::<Vec<_>>();
+                unique_sort(&mut crefs);
+                label = "undetermined".to_string();
+                if crefs.solo() && crefs[0].is_some() {
+                    label = refdata.name[crefs[0].unwrap()].clone();
+                }
+            }
+            _ => {}
+        }
+    }
+    substitute_enclone_color(&mut color);
+    (color, label)
+}
+
+// Make a pie chart for a node, from the counts of its cells having each color.
+
+fn pie(x: f64, y: f64, r: f64, colors: &Vec<(String, usize)>) -> String {
+    let fill = |c: &String| {
+        if c == "undefined" {
+            "white".to_string()
+        } else {
+            c.clone()
+        }
+    };
+    let mut svg = String::new();
+    if colors.solo() {
+        svg += &format!(
+            "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\" fill=\"{}\" />\n",
+            x,
+            y,
+            r,
+            fill(&colors[0].0)
+        );
+    } else {
+        let total = colors.iter().map(|c| c.1).sum::<usize>() as f64;
+        let mut a = -PI / 2.0;
+        for (c, count) in colors.iter() {
+            let b = a + 2.0 * PI * *count as f64 / total;
+            svg += &format!(
+                "<path d=\"M {:.2} {:.2} L {:.2} {:.2} A {:.2} {:.2} 0 {} 1 {:.2} {:.2} Z\" \
+                 fill=\"{}\" />\n",
+                x,
+                y,
+                x + r * a.cos(),
+                y + r * a.sin(),
+                r,
+                r,
+                if b - a > PI { 1 } else { 0 },
+                x + r * b.cos(),
+                y + r * b.sin(),
+                fill(c)
+            );
+            a = b;
+        }
+    }
+    svg += &format!(
+        "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\" fill=\"none\" stroke=\"black\" \
+         stroke-width=\"0.5\" />\n",
+        x, y, r
+    );
+    svg
+}
+
+// Define the label for the mutations on a branch.
+
+fn mut_label(muts: &Vec<String>) -> String {
+    if muts.len() <= MAX_MUT_LABELS {
+        muts.join(", ")
+    } else {
+        format!(
+            "{}, +{}",
+            muts[0..MAX_MUT_LABELS].join(", "),
+            muts.len() - MAX_MUT_LABELS
+        )
+    }
+}
+
+// Escape text for use in SVG, since titles, names and labels may contain arbitrary characters.
+
+fn xml_escape(x: &str) -> String {
+    let mut y = String::new();
+    for c in x.chars() {
+        match c {
+            '&' => y += "&amp;",
+            '<' => y += "&lt;",
+            '>' => y += "&gt;",
+            '"' => y += "&quot;",
+            '\'' => y += "&apos;",
+            _ => y.push(c),
+        }
+    }
+    y
+}
+
+// Draw a tree in a panel, returning the SVG for the panel, and its width and height.
+
+fn tree_panel(
+    title: &str,
+    tree: &LineageTree,
+    colors: &Vec<Vec<(String, usize)>>,
+    max_cells: usize,
+    circular: bool,
+    show_muts: bool,
+) -> (String, f64, f64) {
+    let nv = tree.parent.len();
+    let radius = (0..nv)
+        .map(|v| {
+            let c = tree.cells[v] as f64;
+            if c == 0.0 {
+                2.0
+            } else {
+                (MAX_RADIUS * (c / max_cells as f64).sqrt()).max(MIN_RADIUS)
+            }
+        })
+        .collect::<Vec<f64>>();
+
+    // Find the distance of each vertex from the germline, and assign consecutive slots to the
+    // leaves, which are contiguous for each subtree, since the order is a preorder.  Then place
+    // each internal vertex midway between its extreme children.
+
+    let mut depth = vec![0.0; nv];
+    let mut pos = vec![0.0; nv];
+    let mut total = 0.0;
+    for &v in tree.order.iter() {
+        if v > 0 {
+            depth[v] = depth[tree.parent[v]] + tree.muts[v].len() as f64;
+        }
+        if tree.children[v].is_empty() {
+            let h = ROW.max(2.0 * radius[v] + 4.0);
+            pos[v] = total + h / 2.0;
+            total += h;
+        }
+    }
+    for &v in tree.order.iter().rev() {
+        if !tree.children[v].is_empty() {
+            let p = tree.children[v].iter().map(|c| pos[*c]);
+            let (low, high) = (
+                p.clone().fold(f64::MAX, f64::min),
+                p.fold(f64::MIN, f64::max),
+            );
+            pos[v] = (low + high) / 2.0;
+        }
+    }
+    let max_depth = depth.iter().cloned().fold(1.0, f64::max);
+    let max_label = tree
+        .order
+        .iter()
+        .map(|v| arial_width(&tree.names[*v], FONT_SIZE))
+        .fold(0.0, f64::max);
+    let top = BOUNDARY as f64 + FONT_SIZE + 10.0;
+
+    // Define the coordinates of points, given the distance from the germline, and the position.
+
+    let (width, height, cx, cy, circ);
+    if circular {
+        circ = TREE_RADIUS.max(total / (2.0 * PI));
+        let pad = MAX_RADIUS + 4.0 + max_label;
+        cx = BOUNDARY as f64 + pad + circ;
+        cy = top + pad + circ;
+        width = 2.0 * (BOUNDARY as f64 + pad + circ);
+        height = cy + circ + pad + BOUNDARY as f64;
+    } else {
+        circ = 0.0;
+        cx = BOUNDARY as f64 + MAX_RADIUS;
+        cy = top;
+        width = cx + TREE_WIDTH + MAX_RADIUS + 4.0 + max_label + BOUNDARY as f64;
+        height = top + total + BOUNDARY as f64;
+    }
+    let theta = |p: f64| 2.0 * PI * p / total;
+    let point = |d: f64, p: f64| {
+        if circular {
+            let r = circ * d / max_depth;
+            (cx + r * theta(p).cos(), cy + r * theta(p).sin())
+        } else {
+            (cx + TREE_WIDTH * d / max_depth, cy + p)
+        }
+    };
+
+    // Draw the title and the branches.
+
+    let mut svg = format!(
+        "<text x=\"{}\" y=\"{}\" font-family=\"Arial\" font-size=\"{}\">{}</text>\n",
+        BOUNDARY,
+        BOUNDARY as f64 + FONT_SIZE,
+        FONT_SIZE,
+        xml_escape(title)
+    );
+    let line = |p1: (f64, f64), p2: (f64, f64)| {
+        format!(
+            "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\" stroke=\"black\" \
+             stroke-width=\"1\" />\n",
+            p1.0, p1.1, p2.0, p2.1
+        )
+    };
+    for &u in tree.order.iter() {
+        let kids = &tree.children[u];
+        if kids.is_empty() {
+            continue;
+        }
+        let p = kids.iter().map(|c| pos[*c]).chain(std::iter::once(pos[u]));
+        let (low, high) = (
+            p.clone().fold(f64::MAX, f64::min),
+            p.fold(f64::MIN, f64::max),
+        );
+        if !circular {
+            svg += &line(point(depth[u], low), point(depth[u], high));
+        } else if depth[u] > 0.0 && high > low {
+            let r = circ * depth[u] / max_depth;
+            let (p1, p2) = (point(depth[u], low), point(depth[u], high));
+            svg += &format!(
+                "<path d=\"M {:.2} {:.2} A {:.2} {:.2} 0 {} 1 {:.2} {:.2}\" fill=\"none\" \
+                 stroke=\"black\" stroke-width=\"1\" />\n",
+                p1.0,
+                p1.1,
+                r,
+                r,
+                if theta(high) - theta(low) > PI { 1 } else { 0 },
+                p2.0,
+                p2.1
+            );
+        }
+        for &v in kids.iter() {
+            svg += &line(point(depth[u], pos[v]), point(depth[v], pos[v]));
+        }
+    }
+
+    // Label the branches by their mutations.
+
+    if show_muts {
+        for &v in tree.order.iter() {
+            if v > 0 && !tree.muts[v].is_empty() {
+                let (x, y) = point((depth[tree.parent[v]] + depth[v]) / 2.0, pos[v]);
+                svg += &format!(
+                    "<text x=\"{:.2}\" y=\"{:.2}\" font-family=\"Arial\" font-size=\"{}\" \
+                     text-anchor=\"middle\" fill=\"rgb(120,120,120)\">{}</text>\n",
+                    x,
+                    y - 3.0,
+                    MUT_FONT_SIZE,
+                    mut_label(&tree.muts[v])
+                );
+            }
+        }
+    }
+
+    // Draw the nodes.
+
+    for &v in tree.order.iter() {
+        let (x, y) = point(depth[v], pos[v]);
+        if v == 0 {
+            svg += &format!(
+                "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"6\" height=\"6\" fill=\"black\" />\n\
+                 <text x=\"{:.2}\" y=\"{:.2}\" font-family=\"Arial\" font-size=\"{}\">\
+                 germline</text>\n",
+                x - 3.0,
+                y - 3.0,
+                x + 5.0,
+                y - 5.0,
+                FONT_SIZE
+            );
+        } else if tree.cells[v] == 0 {
+            svg += &format!(
+                "<circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"{:.2}\" fill=\"rgb(150,150,150)\" />\n",
+                x, y, radius[v]
+            );
+        } else {
+            svg += &pie(x, y, radius[v], &colors[v]);
+            let (mut tx, mut ty, mut anchor) = (x + radius[v] + 4.0, y + FONT_SIZE / 3.0, "start");
+            if circular {
+                let (c, s) = (theta(pos[v]).cos(), theta(pos[v]).sin());
+                tx = x + (radius[v] + 4.0) * c;
+                ty = y + (radius[v] + 4.0) * s + FONT_SIZE / 3.0;
+                if c < 0.0 {
+                    anchor = "end";
+                }
+            }
+            svg += &format!(
+                "<text x=\"{:.2}\" y=\"{:.2}\" font-family=\"Arial\" font-size=\"{}\" \
+                 text-anchor=\"{}\">{}</text>\n",
+                tx,
+                ty,
+                FONT_SIZE,
+                anchor,
+                xml_escape(&tree.names[v])
+            );
+        }
+    }
+    (svg, width, height)
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+pub fn tree_svg(
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    exacts: &Vec<Vec<usize>>,
+    rsi: &Vec<ColInfo>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    out_datas: &Vec<Vec<HashMap<String, String>>>,
+    dref: &Vec<DonorReferenceItem>,
+    groups: &Vec<Vec<(i32, String)>>,
+    svgs: &mut Vec<String>,
+) -> Result<(), String> {
+    let filename = &ctl.plot_opt.tree_svg_file;
+    if filename.is_empty() {
+        return Ok(());
+    }
+    if groups.is_empty() {
+        return Err("\nThere are no clonotypes for TREE_SVG, giving up.\n".to_string());
+    }
+
+    // Set up the coloring scheme.
+
+    let mut plot_opt = ctl.plot_opt.clone();
+    plot_opt.cell_color = plot_opt.tree_svg_color.clone();
+    plot_opt.plot_by_isotype = false;
+    plot_opt.plot_by_isotype_color.clear();
+    plot_opt.plot_by_mark = false;
+    let mut const_names = Vec::<String>::new();
+    for id in refdata.cs.iter() {
+        if refdata.rtype[*id] == 0 {
+            const_names.push(refdata.name[*id].clone());
+        }
+    }
+    unique_sort(&mut const_names);
+    let mut palette = default_colors();
+    match plot_opt.tree_svg_color {
+        CellColor::ByIsotype(ref x) => {
+            if !ctl.gen_opt.bcr {
+                return Err("\nTREE_SVG_COLOR=iso can only be used with BCR data.\n".to_string());
+            }
+            if !x.color.is_empty() && x.color.len() < const_names.len() + 1 {
+                return Err(format!(
+                    "\nUsing TREE_SVG_COLOR=iso, you specified {} colors, but there are {} \
+                     constant region\nnames, and one more color is needed for the \
+                     \"undetermined\" case.  Please add more colors.\n",
+                    x.color.len(),
+                    const_names.len()
+                ));
+            }
+            if x.color.is_empty() && const_names.len() > 12 {
+                return Err(
+                    "\nTREE_SVG_COLOR=iso without colors only works if there are at \
+                     most 12 constant region names.\n"
+                        .to_string(),
+                );
+            }
+            plot_opt.plot_by_isotype = true;
+            plot_opt.plot_by_isotype_color = x.color.clone();
+        }
+        CellColor::ByDataset(_) => {
+            palette.truncate(ctl.origin_info.n().min(256));
+            reorder_color_list(&mut palette);
+        }
+        CellColor::BySample(_) => {
+            palette.truncate(ctl.origin_info.origin_list.len().clamp(1, 256));
+            reorder_color_list(&mut palette);
+        }
+        _ => {}
+    }
+
+    // Find the trees and the colors of their nodes.
+
+    let mut trees = Vec::<(String, LineageTree, Vec<Vec<(String, usize)>>)>::new();
+    let mut all_colors = Vec::<String>::new();
+    let mut legend = Vec::<(String, String)>::new();
+    for i in 0..groups.len() {
+        for j in 0..groups[i].len() {
+            let oo = groups[i][j].0 as usize;
+            let tree = lineage_tree(oo, exacts, rsi, exact_clonotypes, ctl, refdata, dref);
+            let mut colors = vec![Vec::<(String, usize)>::new(); tree.parent.len()];
+            for (l, &u) in exacts[oo].iter().enumerate() {
+                let mut counts = HashMap::<String, usize>::new();
+                for k in 0..exact_clonotypes[u].clones.len() {
+                    let (color, label) = cell_color(
+                        ctl,
+                        &plot_opt,
+                        refdata,
+                        &const_names,
+                        &palette,
+                        exacts,
+                        exact_clonotypes,
+                        out_datas,
+                        oo,
+                        l,
+                        k,
+                    );
+                    *counts.entry(color.clone()).or_insert(0) += 1;
+                    if !label.is_empty() {
+                        legend.push((label, color.clone()));
+                    }
+                    all_colors.push(color);
+                }
+                let mut counts = counts.into_iter().collect::<Vec<_>>();
+                counts.sort();
+                colors[l + 1] = counts;
+            }
+            trees.push((format!("[{}.{}]", i + 1, j + 1), tree, colors));
+        }
+    }
+    unique_sort(&mut legend);
+    let max_cells = trees
+        .iter()
+        .map(|t| t.1.cells.iter().cloned().max().unwrap_or(0))
+        .max()
+        .unwrap_or(1)
+        .max(1);
+
+    // Draw the trees, stacked vertically.
+
+    let circular = ctl.plot_opt.tree_svg_layout == "circular";
+    let (mut body, mut width, mut height) = (String::new(), 0.0_f64, 0.0);
+    for (title, tree, colors) in trees.iter() {
+        let (panel, w, h) = tree_panel(
+            title,
+            tree,
+            colors,
+            max_cells,
+            circular,
+            ctl.plot_opt.tree_svg_muts,
+        );
+        body += &format!(
+            "<g transform=\"translate(0,{:.2})\">\n{}</g>\n",
+            height, panel
+        );
+        width = width.max(w);
+        height += h;
+    }
+    let mut svg = format!(
+        "<svg version=\"1.1\"\n\
+         baseProfile=\"full\"\n\
+         width=\"{:.2}\" height=\"{:.2}\"\n\
+         xmlns=\"http://www.w3.org/2000/svg\">\n\
+         <rect x=\"0\" y=\"0\" width=\"100%\" height=\"100%\" fill=\"white\" />\n\
+         {}</svg>",
+        width, height, body
+    );
+
+    // Add the legend.
+
+    if let CellColor::ByVariableValue(ref x) = plot_opt.cell_color {
+        let low = VAR_LOW
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|y| y.0 == x.var)
+            .map(|y| y.1);
+        let high = VAR_HIGH
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|y| y.0 == x.var)
+            .map(|y| y.1);
+        if low == Some(f64::MAX) && high == Some(f64::MIN) {
+            return Err(
+                "\nVariable used for color in TREE_SVG has no numeric values.\n".to_string(),
+            );
+        }
+        add_legend_for_color_by_variable(&plot_opt, &mut svg, &all_colors, width, height);
+        VAR_LOW.lock().unwrap().retain(|y| y.0 != x.var);
+        VAR_HIGH.lock().unwrap().retain(|y| y.0 != x.var);
+    } else if !legend.is_empty() {
+        let font_size = 14.0;
+        let row = font_size + BOUNDARY as f64 / 2.0;
+        let max_width = legend
+            .iter()
+            .map(|x| arial_width(&x.0, font_size))
+            .fold(0.0, f64::max);
+        let (legend_xstart, legend_ystart) = (width + 10.0, BOUNDARY as f64);
+        let legend_width = BOUNDARY as f64 * 2.5 + max_width;
+        let legend_height = row * legend.len() as f64 + BOUNDARY as f64;
+        svg = svg.rev_before("<").to_string();
+        svg += &format!(
+            "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"{:.2}\" height=\"{:.2}\" \
+             style=\"fill:white;stroke:black;stroke-width:2\" />\n",
+            legend_xstart, legend_ystart, legend_width, legend_height
+        );
+        for (i, (label, color)) in legend.iter().enumerate() {
+            let y = legend_ystart + BOUNDARY as f64 * 2.5 + row * i as f64;
+            svg += &format!(
+                "<text x=\"{:.2}\" y=\"{:.2}\" font-family=\"Arial\" font-size=\"{}\">{}</text>\n\
+                 <circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"4\" fill=\"{}\" />\n",
+                legend_xstart + BOUNDARY as f64 * 2.0,
+                y,
+                font_size,
+                xml_escape(label),
+                legend_xstart + BOUNDARY as f64,
+                y - BOUNDARY as f64 / 2.0,
+                color
+            );
+        }
+        set_svg_width(&mut svg, legend_xstart + legend_width + BOUNDARY as f64);
+        set_svg_height(
+            &mut svg,
+            height.max(legend_ystart + legend_height + BOUNDARY as f64),
+        );
+        svg += "</svg>";
+    }
+
+    // Output the svg.
+
+    if filename == "stdout" || filename == "gui_stdout" {
+        for line in svg.lines() {
+            println!("{}", line);
+        }
+    } else if filename == "gui" {
+        svgs.push(svg);
+    } else {
+        let mut f = open_for_write_new![&filename];
+        fwrite!(f, "{}", svg);
+    }
+    Ok(())
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(xml_escape("3.1"), "3.1");
+        assert_eq!(
+            xml_escape("<a & 'b' \"c\">"),
+            "&lt;a &amp; &apos;b&apos; &quot;c&quot;&gt;"
+        );
+    }
+}
@@ -0,0 +1,69 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+//
+// Generate FASTA output for the unmutated common ancestor of each clonotype (option UCA_FASTA).
+
+use enclone_core::defs::{ColInfo, EncloneControl, ExactClonotype};
+use enclone_core::uca::uca;
+use enclone_proto::types::DonorReferenceItem;
+use io_utils::fwriteln;
+use itertools::Itertools;
+use std::io::Write;
+use string_utils::strme;
+use vdj_ann::refx::RefData;
+
+pub fn print_uca(
+    i: usize,
+    j: usize,
+    oo: usize,
+    exacts: &Vec<Vec<usize>>,
+    rsi: &Vec<ColInfo>,
+    exact_clonotypes: &Vec<ExactClonotype>,
+    ctl: &EncloneControl,
+    refdata: &RefData,
+    dref: &Vec<DonorReferenceItem>,
+    logx: &mut Vec<u8>,
+    ucaout: &mut Box<dyn std::io::Write>,
+) {
+    if ctl.gen_opt.uca_fasta.is_empty() {
+        return;
+    }
+
+    // Output one record per chain, naming the genes that were used.
+
+    let mut fasta = Vec::<u8>::new();
+    for m in 0..rsi[oo].mat.len() {
+        let (dna, ds) = uca(
+            m,
+            &exacts[oo],
+            exact_clonotypes,
+            &rsi[oo],
+            refdata,
+            dref,
+            ctl,
+        );
+        let d = if ds.is_empty() {
+            "none".to_string()
+        } else {
+            ds.iter()
+                .map(|d| refdata.name[*d].clone())
+                .format(":")
+                .to_string()
+        };
+        fwriteln!(
+            fasta,
+            ">group{}.clonotype{}.uca.chain{} {} {} {}",
+            i + 1,
+            j + 1,
+            m + 1,
+            refdata.name[rsi[oo].vids[m]],
+            d,
+            refdata.name[rsi[oo].jids[m]]
+        );
+        fwriteln!(fasta, "{}", strme(&dna));
+    }
+    if ctl.gen_opt.uca_fasta == *"stdout" {
+        logx.append(&mut fasta);
+    } else {
+        ucaout.write_all(&fasta).unwrap();
+    }
+}
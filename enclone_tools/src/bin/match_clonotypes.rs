@@ -0,0 +1,509 @@
+// Copyright (c) 2022 10X Genomics, Inc. All rights reserved.
+
+// Match the clonotypes in two PROTO files made by enclone on the same data, for example with
+// different versions of enclone or different JOIN options, and report how they differ.
+//
+// usage: match_clonotypes old.proto new.proto [JSON=summary.json] [SHOW_MATCHES]
+//
+// Clonotypes are matched by shared cells, where a cell is given by its dataset and barcode, or
+// by the barcode alone if either PROTO file lacks datasets, as older ones do: an old and a new
+// clonotype are linked if they share a cell, and the connected components of this relation
+// define events:
+// • match:   one old and one new clonotype, which may have gained or lost cells
+// • split:   one old clonotype whose cells are now in more than one new clonotype
+// • merge:   more than one old clonotype whose cells are now in one new clonotype
+// • complex: more than one old and more than one new clonotype
+// • lost:    an old clonotype none of whose cells are in a new clonotype
+// • gained:  a new clonotype none of whose cells are in an old clonotype.
+// Then lost and gained clonotypes are matched by chain sequence, first requiring that all
+// chains be the same, and then that just the lead chains be the same, where a chain is given by
+// its V and J genes and CDR3 DNA sequence.  Each gained clonotype is matched at most once, and
+// lost clonotypes having the same key are paired with gained ones in order.  These become events
+// of type seq_match.
+//
+// For each event, the cells gained and lost are those in the new (resp. old) clonotypes that
+// are not in the old (resp. new) clonotypes, and the lead chain is the first chain of the
+// largest clonotype.  Events other than matches with no change are printed, unless
+// SHOW_MATCHES is specified, in which case all are printed.  If JSON is specified, a summary
+// having counts and all the events is written there.
+
+use enclone_proto::proto_io::read_proto;
+use enclone_proto::types::{ClonotypeChain, EncloneOutputs};
+use equiv::EquivRel;
+use io_utils::{fwriteln, open_for_write_new};
+use itertools::Itertools;
+use pretty_trace::PrettyTrace;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::Write;
+use string_utils::TextUtils;
+
+// A clonotype, as seen by this program.
+
+struct Clono {
+    id: String,                   // one-based index in the file, or stable id if present
+    cells: Vec<(String, String)>, // (dataset, barcode), dataset empty if not known
+    barcodes: Vec<String>,        // sorted cell keys, as set by set_cell_keys
+    lead: String,                 // lead chain key
+    chains: Vec<String>,          // sorted chain keys
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Define the key of a chain, which is V gene, J gene and CDR3 DNA sequence.
+
+fn chain_key(x: &ClonotypeChain, names: &[String]) -> String {
+    let cdr3 = &x.nt_sequence[x.cdr3_start as usize..x.cdr3_end as usize];
+    format!(
+        "{} {} {}",
+        names[x.v_idx as usize],
+        names[x.j_idx as usize],
+        String::from_utf8_lossy(cdr3)
+    )
+}
+
+// Load the clonotypes from a PROTO file.
+
+fn load_clonotypes(path: &str) -> (Vec<Clono>, bool) {
+    let outs: EncloneOutputs = read_proto(path).unwrap_or_else(|e| {
+        eprintln!("\nFailed to read the PROTO file {}: {}.\n", path, e);
+        std::process::exit(1);
+    });
+    get_clonotypes(&outs)
+}
+
+// Get the clonotypes from enclone outputs, and determine if the datasets of all cells are known.
+
+fn get_clonotypes(outs: &EncloneOutputs) -> (Vec<Clono>, bool) {
+    let names = outs
+        .universal_reference
+        .items
+        .iter()
+        .map(|x| x.display_name.clone())
+        .collect::<Vec<_>>();
+    let mut clonos = Vec::<Clono>::new();
+    let mut has_datasets = true;
+    for (i, c) in outs.clonotypes.iter().enumerate() {
+        let mut cells = Vec::<(String, String)>::new();
+        for ex in c.exact_clonotypes.iter() {
+            if ex.cell_datasets.len() != ex.cell_barcodes.len() {
+                has_datasets = false;
+            }
+            for (k, b) in ex.cell_barcodes.iter().enumerate() {
+                let dataset = ex.cell_datasets.get(k).cloned().unwrap_or_default();
+                cells.push((dataset, b.clone()));
+            }
+        }
+        let mut chains = c
+            .chains
+            .iter()
+            .map(|x| chain_key(x, &names))
+            .collect::<Vec<_>>();
+        let lead = chains.first().cloned().unwrap_or_default();
+        chains.sort();
+        clonos.push(Clono {
+            id: c.stable_id.clone().unwrap_or_else(|| format!("{}", i + 1)),
+            cells,
+            barcodes: Vec::new(),
+            lead,
+            chains,
+        });
+    }
+    (clonos, has_datasets)
+}
+
+// Set the keys of the cells in each clonotype, which are dataset/barcode if use_datasets, and
+// otherwise just the barcode.
+
+fn set_cell_keys(clonos: &mut [Vec<Clono>; 2], use_datasets: bool) {
+    for c in clonos.iter_mut().flatten() {
+        c.barcodes = c
+            .cells
+            .iter()
+            .map(|(d, b)| {
+                if use_datasets {
+                    format!("{}/{}", d, b)
+                } else {
+                    b.clone()
+                }
+            })
+            .collect();
+        c.barcodes.sort();
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Describe an event, given the indices of the old and new clonotypes in it.
+
+fn make_event(kind: &str, old: &[usize], new: &[usize], clonos: &[Vec<Clono>; 2]) -> Value {
+    let mut cells = vec![HashSet::<&String>::new(); 2];
+    let mut leads = vec![String::new(); 2];
+    for (v, x) in [old, new].iter().enumerate() {
+        let mut max_cells = 0;
+        for &i in x.iter() {
+            let c = &clonos[v][i];
+            cells[v].extend(c.barcodes.iter());
+            if c.barcodes.len() > max_cells {
+                max_cells = c.barcodes.len();
+                leads[v] = c.lead.clone();
+            }
+        }
+    }
+    let shared = cells[0].intersection(&cells[1]).count();
+    let kind = if kind == "match" && (cells[0] != cells[1] || leads[0] != leads[1]) {
+        "changed"
+    } else {
+        kind
+    };
+    json!({
+        "type": kind,
+        "old": old.iter().map(|&i| clonos[0][i].id.clone()).collect::<Vec<_>>(),
+        "new": new.iter().map(|&i| clonos[1][i].id.clone()).collect::<Vec<_>>(),
+        "old_cells": cells[0].len(),
+        "new_cells": cells[1].len(),
+        "shared_cells": shared,
+        "lost_cells": cells[0].len() - shared,
+        "gained_cells": cells[1].len() - shared,
+        "old_lead": leads[0],
+        "new_lead": leads[1],
+        "lead_changed": !old.is_empty() && !new.is_empty() && leads[0] != leads[1],
+    })
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+// Find the events relating old and new clonotypes.
+
+fn find_events(clonos: &[Vec<Clono>; 2]) -> Vec<Value> {
+    let n = [clonos[0].len(), clonos[1].len()];
+
+    // Link old and new clonotypes that share a cell.  Nodes are the old clonotypes, followed
+    // by the new clonotypes.
+
+    let mut to_new = HashMap::<&String, usize>::new();
+    for (j, c) in clonos[1].iter().enumerate() {
+        for b in c.barcodes.iter() {
+            to_new.insert(b, j);
+        }
+    }
+    let mut e = EquivRel::new((n[0] + n[1]) as i32);
+    for (i, c) in clonos[0].iter().enumerate() {
+        for b in c.barcodes.iter() {
+            if let Some(j) = to_new.get(b) {
+                e.join(i as i32, (n[0] + j) as i32);
+            }
+        }
+    }
+    let mut reps = Vec::<i32>::new();
+    e.orbit_reps(&mut reps);
+    let mut events = Vec::<Value>::new();
+    let (mut lost, mut gained) = (Vec::<usize>::new(), Vec::<usize>::new());
+    for r in reps.iter() {
+        let mut o = Vec::<i32>::new();
+        e.orbit(*r, &mut o);
+        o.sort_unstable();
+        let old = o
+            .iter()
+            .filter(|&&x| (x as usize) < n[0])
+            .map(|&x| x as usize)
+            .collect::<Vec<_>>();
+        let new = o
+            .iter()
+            .filter(|&&x| (x as usize) >= n[0])
+            .map(|&x| x as usize - n[0])
+            .collect::<Vec<_>>();
+        let kind = match (old.len(), new.len()) {
+            (0, _) => {
+                gained.push(new[0]);
+                continue;
+            }
+            (_, 0) => {
+                lost.push(old[0]);
+                continue;
+            }
+            (1, 1) => "match",
+            (1, _) => "split",
+            (_, 1) => "merge",
+            _ => "complex",
+        };
+        events.push(make_event(kind, &old, &new, clonos));
+    }
+
+    // Match lost and gained clonotypes by sequence, first using all chains, then lead chains.
+
+    let mut used = vec![vec![false; n[0]], vec![false; n[1]]];
+    for pass in 1..=2 {
+        let key = |c: &Clono| {
+            if pass == 1 {
+                c.chains.iter().format(";").to_string()
+            } else {
+                c.lead.clone()
+            }
+        };
+        let mut to_gained = HashMap::<String, Vec<usize>>::new();
+        for &j in gained.iter() {
+            if !used[1][j] {
+                to_gained.entry(key(&clonos[1][j])).or_default().push(j);
+            }
+        }
+        for &i in lost.iter() {
+            if used[0][i] {
+                continue;
+            }
+            if let Some(js) = to_gained.get(&key(&clonos[0][i])) {
+                if let Some(&j) = js.iter().find(|&&j| !used[1][j]) {
+                    used[0][i] = true;
+                    used[1][j] = true;
+                    events.push(make_event("seq_match", &[i], &[j], clonos));
+                }
+            }
+        }
+    }
+    for &i in lost.iter() {
+        if !used[0][i] {
+            events.push(make_event("lost", &[i], &[], clonos));
+        }
+    }
+    for &j in gained.iter() {
+        if !used[1][j] {
+            events.push(make_event("gained", &[], &[j], clonos));
+        }
+    }
+    events
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+fn main() {
+    PrettyTrace::new().on();
+    let args: Vec<String> = env::args().collect();
+    let mut paths = Vec::<String>::new();
+    let mut json_path = None;
+    let mut show_matches = false;
+    for arg in args.iter().skip(1) {
+        if arg.starts_with("JSON=") {
+            json_path = Some(arg.after("JSON=").to_string());
+        } else if arg == "SHOW_MATCHES" {
+            show_matches = true;
+        } else {
+            paths.push(arg.clone());
+        }
+    }
+    if paths.len() != 2 {
+        eprintln!(
+            "\nusage: match_clonotypes old.proto new.proto [JSON=summary.json] [SHOW_MATCHES]\n"
+        );
+        std::process::exit(1);
+    }
+    let (old, old_has_datasets) = load_clonotypes(&paths[0]);
+    let (new, new_has_datasets) = load_clonotypes(&paths[1]);
+    let mut clonos = [old, new];
+    set_cell_keys(&mut clonos, old_has_datasets && new_has_datasets);
+    let n = [clonos[0].len(), clonos[1].len()];
+    let events = find_events(&clonos);
+
+    // Summarize.
+
+    let mut counts = HashMap::<String, usize>::new();
+    let (mut cells_lost, mut cells_gained, mut lead_changes) = (0, 0, 0);
+    for x in events.iter() {
+        *counts
+            .entry(x["type"].as_str().unwrap().to_string())
+            .or_default() += 1;
+        cells_lost += x["lost_cells"].as_u64().unwrap();
+        cells_gained += x["gained_cells"].as_u64().unwrap();
+        if x["lead_changed"].as_bool().unwrap() {
+            lead_changes += 1;
+        }
+    }
+    let kinds = [
+        "match",
+        "changed",
+        "split",
+        "merge",
+        "complex",
+        "seq_match",
+        "lost",
+        "gained",
+    ];
+    println!("\nold clonotypes = {}, new clonotypes = {}", n[0], n[1]);
+    for k in kinds.iter() {
+        println!("{} = {}", k, counts.get(*k).unwrap_or(&0));
+    }
+    println!(
+        "cells lost = {}, cells gained = {}",
+        cells_lost, cells_gained
+    );
+    println!("lead chain changes = {}", lead_changes);
+    println!("\nEVENTS\n");
+    for x in events.iter() {
+        if x["type"] == "match" && !show_matches {
+            continue;
+        }
+        let ids = |v: &Value| {
+            v.as_array()
+                .unwrap()
+                .iter()
+                .map(|x| x.as_str().unwrap().to_string())
+                .format(",")
+                .to_string()
+        };
+        println!(
+            "{} old=[{}] new=[{}] cells: old={} new={} lost={} gained={}",
+            x["type"].as_str().unwrap(),
+            ids(&x["old"]),
+            ids(&x["new"]),
+            x["old_cells"],
+            x["new_cells"],
+            x["lost_cells"],
+            x["gained_cells"],
+        );
+        if x["lead_changed"].as_bool().unwrap() {
+            println!(
+                "    lead chain {} ==> {}",
+                x["old_lead"].as_str().unwrap(),
+                x["new_lead"].as_str().unwrap()
+            );
+        }
+    }
+    println!();
+    if let Some(json_path) = json_path {
+        let summary = json!({
+            "old": paths[0],
+            "new": paths[1],
+            "old_clonotypes": n[0],
+            "new_clonotypes": n[1],
+            "counts": kinds.iter().map(|k| (k.to_string(), *counts.get(*k).unwrap_or(&0)))
+                .collect::<HashMap<_, _>>(),
+            "cells_lost": cells_lost,
+            "cells_gained": cells_gained,
+            "lead_changes": lead_changes,
+            "events": events,
+        });
+        let mut f = open_for_write_new![&json_path];
+        fwriteln!(f, "{}", serde_json::to_string_pretty(&summary).unwrap());
+    }
+}
+
+// ▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓▓
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use enclone_proto::types::{
+        Clonotype, ExactSubClonotype, UniversalReference, UniversalReferenceItem,
+    };
+
+    // Make enclone outputs having one chain per clonotype, given for each clonotype its barcodes
+    // and CDR3 sequence.  If dataset is specified, it is the dataset of all cells.
+
+    fn outputs(clonotypes: &[(&[&str], &str)], dataset: Option<&str>) -> EncloneOutputs {
+        let items = ["IGHV3-23", "IGHJ4"]
+            .iter()
+            .map(|x| UniversalReferenceItem {
+                display_name: x.to_string(),
+                ..Default::default()
+            })
+            .collect();
+        let clonotypes = clonotypes
+            .iter()
+            .map(|(barcodes, cdr3)| Clonotype {
+                chains: vec![ClonotypeChain {
+                    nt_sequence: cdr3.as_bytes().to_vec(),
+                    v_idx: 0,
+                    j_idx: 1,
+                    cdr3_start: 0,
+                    cdr3_end: cdr3.len() as u32,
+                    ..Default::default()
+                }],
+                exact_clonotypes: vec![ExactSubClonotype {
+                    cell_barcodes: barcodes.iter().map(|b| b.to_string()).collect(),
+                    cell_datasets: match dataset {
+                        Some(d) => vec![d.to_string(); barcodes.len()],
+                        None => Vec::new(),
+                    },
+                    ..Default::default()
+                }],
+                frequency: barcodes.len() as u32,
+                ..Default::default()
+            })
+            .collect();
+        EncloneOutputs {
+            universal_reference: UniversalReference { items },
+            clonotypes,
+            ..Default::default()
+        }
+    }
+
+    fn kinds(events: &[Value]) -> Vec<String> {
+        let mut k = events
+            .iter()
+            .map(|x| x["type"].as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        k.sort();
+        k
+    }
+
+    // Compare an old PROTO file lacking datasets to a new one having them.  The first clonotype
+    // is unchanged, the second gains a cell, the third is replaced by a clonotype on new cells
+    // having the same sequence, and a fourth clonotype is new.
+
+    #[test]
+    fn test_old_vs_new_proto() {
+        let old = outputs(
+            &[
+                (&["AAAC-1", "AAAG-1"], "TGTGCGAGATGG"),
+                (&["CCCA-1"], "TGTGCGAAATGG"),
+                (&["GGGA-1"], "TGTGCGCGATGG"),
+            ],
+            None,
+        );
+        let new = outputs(
+            &[
+                (&["AAAC-1", "AAAG-1"], "TGTGCGAGATGG"),
+                (&["CCCA-1", "CCCT-1"], "TGTGCGAAATGG"),
+                (&["TTTA-1"], "TGTGCGCGATGG"),
+                (&["TTTC-1"], "TGTGCGTTATGG"),
+            ],
+            Some("d1"),
+        );
+        let (old, old_has_datasets) = get_clonotypes(&old);
+        let (new, new_has_datasets) = get_clonotypes(&new);
+        assert!(!old_has_datasets);
+        assert!(new_has_datasets);
+        let mut clonos = [old, new];
+        set_cell_keys(&mut clonos, old_has_datasets && new_has_datasets);
+        assert_eq!(clonos[1][0].barcodes, vec!["AAAC-1", "AAAG-1"]);
+        let events = find_events(&clonos);
+        assert_eq!(
+            kinds(&events),
+            vec!["changed", "gained", "match", "seq_match"]
+        );
+        let changed = events.iter().find(|x| x["type"] == "changed").unwrap();
+        assert_eq!(changed["old"], json!(["2"]));
+        assert_eq!(changed["new"], json!(["2"]));
+        assert_eq!(changed["gained_cells"], 1);
+        let seq_match = events.iter().find(|x| x["type"] == "seq_match").unwrap();
+        assert_eq!(seq_match["old"], json!(["3"]));
+        assert_eq!(seq_match["new"], json!(["3"]));
+        let gained = events.iter().find(|x| x["type"] == "gained").unwrap();
+        assert_eq!(gained["new"], json!(["4"]));
+    }
+
+    // If both PROTO files have datasets, the same barcode in different datasets is not the
+    // same cell.
+
+    #[test]
+    fn test_datasets() {
+        let old = outputs(&[(&["AAAC-1"], "TGTGCGAGATGG")], Some("d1"));
+        let new = outputs(&[(&["AAAC-1"], "TGTGCGAAATGG")], Some("d2"));
+        let (old, _) = get_clonotypes(&old);
+        let (new, _) = get_clonotypes(&new);
+        let mut clonos = [old, new];
+        set_cell_keys(&mut clonos, true);
+        assert_eq!(clonos[0][0].barcodes, vec!["d1/AAAC-1"]);
+        assert_eq!(kinds(&find_events(&clonos)), vec!["gained", "lost"]);
+    }
+}
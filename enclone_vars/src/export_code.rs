@@ -548,6 +548,7 @@ pub fn export_code(level: usize) -> Vec<(String, String)> {
         use enclone_core::defs::*;
         use enclone_core::median::*;
         use enclone_core::opt_d::*;
+        use enclone_core::uca::*;
         use enclone_proto::types::*;
         use itertools::Itertools;
         use stats_utils::*;
@@ -761,6 +762,7 @@ pub fn export_code(level: usize) -> Vec<(String, String)> {
             ind_readers: &Vec<Option<Reader>>,
             h5_data: &Vec<(usize, Vec<u32>, Vec<u32>)>,
             alt_bcs: &Vec<String>,
+            stable_id: &str,
         ) -> Result<bool, String> {
 
             let clonotype_id = exacts[u];
@@ -768,12 +770,7 @@ pub fn export_code(level: usize) -> Vec<(String, String)> {
             let mat = &rsi.mat;
             let cols = varmat[0].len();
             let verbose = ctl.gen_opt.row_fill_verbose;
-            let mut vname = var.clone();
-            let mut abbr = var.clone();
-            if var.contains(':') {
-                abbr = var.before(":").to_string();
-                vname = var.after(":").to_string();
-            }
+            let (abbr, vname) = abbr_var(var);
 
             macro_rules! speak {
                 ($u:expr, $var:expr, $val:expr) => {